@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use sp_core::crypto::Ss58Codec;
+
+use lazy_static::lazy_static;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap};
+
+use crate::engine::EngineOptions;
+use crate::users::{dynamic_to_account, AccountId};
+
+lazy_static! {
+  // Account -> label.  Used by the printers to show labels instead of addresses.
+  static ref ACCOUNT_LABELS: RwLock<HashMap<AccountId, String>> = RwLock::new(HashMap::new());
+}
+
+/// Get the label of a known account.
+pub fn account_label(acc: &AccountId) -> Option<String> {
+  ACCOUNT_LABELS.read().unwrap().get(acc).cloned()
+}
+
+/// Format an account as `label (address)` if it has a label.
+pub fn format_account(acc: &AccountId) -> String {
+  match account_label(acc) {
+    Some(label) => format!("{} ({})", label, acc),
+    None => acc.to_string(),
+  }
+}
+
+/// Wrap a value to debug print it with labels for known accounts.
+pub struct Labeled<'a>(pub &'a Dynamic);
+
+impl<'a> std::fmt::Debug for Labeled<'a> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let val = self.0;
+    if let Some(acc) = val.read_lock::<AccountId>() {
+      f.write_str(&format_account(&acc))
+    } else if let Some(arr) = val.read_lock::<Vec<Dynamic>>() {
+      f.debug_list().entries(arr.iter().map(Labeled)).finish()
+    } else if let Some(map) = val.read_lock::<RMap>() {
+      f.debug_map()
+        .entries(map.iter().map(|(k, v)| (k, Labeled(v))))
+        .finish()
+    } else {
+      write!(f, "{:?}", val)
+    }
+  }
+}
+
+pub struct InnerAddressBook {
+  path: PathBuf,
+  labels: RwLock<BTreeMap<String, AccountId>>,
+}
+
+impl InnerAddressBook {
+  fn load(path: PathBuf) -> Result<Self, Box<EvalAltResult>> {
+    let mut labels = BTreeMap::new();
+    if path.exists() {
+      let file = File::open(&path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+      let entries: BTreeMap<String, String> = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Failed to parse address book {:?}: {}", path, e))?;
+      for (label, addr) in entries {
+        let acc = AccountId::from_string(&addr)
+          .map_err(|e| format!("Invalid address for label '{}': {:?}", label, e))?;
+        labels.insert(label, acc);
+      }
+      log::info!("Loaded {} addresses from {:?}", labels.len(), path);
+    }
+    let book = Self {
+      path,
+      labels: RwLock::new(labels),
+    };
+    book.update_account_labels();
+    Ok(book)
+  }
+
+  fn update_account_labels(&self) {
+    let labels = self.labels.read().unwrap();
+    let mut accounts = ACCOUNT_LABELS.write().unwrap();
+    accounts.clear();
+    for (label, acc) in labels.iter() {
+      accounts.insert(acc.clone(), label.clone());
+    }
+  }
+
+  fn save(&self) -> Result<(), Box<EvalAltResult>> {
+    let entries = self
+      .labels
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(label, acc)| (label.clone(), acc.to_string()))
+      .collect::<BTreeMap<_, _>>();
+    let file =
+      File::create(&self.path).map_err(|e| format!("Failed to create {:?}: {}", self.path, e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &entries)
+      .map_err(|e| format!("Failed to write address book {:?}: {}", self.path, e))?;
+    Ok(())
+  }
+}
+
+/// Labeled addresses, persisted to a JSON file.
+#[derive(Clone)]
+pub struct AddressBook(Arc<InnerAddressBook>);
+
+impl AddressBook {
+  pub fn load(path: &str) -> Result<Self, Box<EvalAltResult>> {
+    Ok(Self(Arc::new(InnerAddressBook::load(path.into())?)))
+  }
+
+  pub fn set(&mut self, label: &str, addr: Dynamic) -> Result<(), Box<EvalAltResult>> {
+    let acc = dynamic_to_account(addr)?;
+    self.0.labels.write().unwrap().insert(label.into(), acc);
+    self.0.update_account_labels();
+    self.0.save()
+  }
+
+  pub fn get(&mut self, label: &str) -> Dynamic {
+    match self.0.labels.read().unwrap().get(label) {
+      Some(acc) => Dynamic::from(acc.clone()),
+      None => Dynamic::UNIT,
+    }
+  }
+
+  pub fn remove(&mut self, label: &str) -> Result<bool, Box<EvalAltResult>> {
+    let removed = self.0.labels.write().unwrap().remove(label).is_some();
+    if removed {
+      self.0.update_account_labels();
+      self.0.save()?;
+    }
+    Ok(removed)
+  }
+
+  fn label(&mut self, acc: AccountId) -> Dynamic {
+    account_label(&acc).map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+  }
+
+  fn labels(&mut self) -> RMap {
+    self
+      .0
+      .labels
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(label, acc)| (label.into(), Dynamic::from(acc.clone())))
+      .collect()
+  }
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<AddressBook>("AddressBook")
+    .register_result_fn("set", AddressBook::set)
+    .register_fn("get", AddressBook::get)
+    .register_result_fn("remove", AddressBook::remove)
+    .register_fn("label", AddressBook::label)
+    .register_get("labels", AddressBook::labels)
+    .register_fn("format_account", |acc: &mut AccountId| format_account(acc));
+}
+
+pub fn init_engine(engine: &mut Engine, opts: &EngineOptions) -> Result<AddressBook, Box<EvalAltResult>> {
+  register(engine);
+  AddressBook::load(&opts.addrbook_file)
+}
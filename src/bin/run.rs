@@ -1,6 +1,11 @@
 use sub_script::engine::*;
+use sub_script::error::error_message;
+use sub_script::pretty::pretty;
+use sub_script::profile;
+use sub_script::summary;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Result};
 use structopt::StructOpt;
@@ -8,52 +13,416 @@ use structopt::StructOpt;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "sub-script")]
 struct Opt {
+  /// Node url: `ws://`, `wss://`, `ipc:///path/node.sock` or `light://chainspec.json`
+  /// (needs the `light` feature).
   #[structopt(short, env = "NODE_URL", default_value = "ws://127.0.0.1:9944")]
   url: String,
 
+  /// Standard types schema.  Comma-separated files or `https://` URLs.
   #[structopt(short, env = "SUBSTRATE_TYPES", default_value = "init_types.json")]
   substrate_types: String,
 
+  /// Custom chain types schema.  Comma-separated files or `https://` URLs.
   #[structopt(short, env = "CUSTOM_TYPES", default_value = "schema.json")]
   custom_types: String,
 
-  #[structopt(name = "SCRIPT", parse(from_os_str))]
-  script: PathBuf,
+  /// Directory for schemas downloaded from URLs.  Copies less than a day old are reused.
+  #[structopt(long, env = "SCHEMA_CACHE_DIR", default_value = ".schema_cache")]
+  schema_cache_dir: String,
 
+  /// Trace-level logging of type paths and byte offsets during encode/decode.
+  #[structopt(long)]
+  codec_trace: bool,
+
+  /// CA bundle (PEM) used to verify `wss://` server certificates.
+  #[structopt(long, env = "TLS_CA_FILE")]
+  tls_ca_file: Option<String>,
+
+  /// Client certificate chain (PEM) for `wss://` connections.
+  #[structopt(long, env = "TLS_CERT_FILE")]
+  tls_cert_file: Option<String>,
+
+  /// Client private key (PEM) for `wss://` connections.
+  #[structopt(long, env = "TLS_KEY_FILE")]
+  tls_key_file: Option<String>,
+
+  /// Don't verify `wss://` server certificates (self-signed certs).
+  #[structopt(long)]
+  tls_insecure: bool,
+
+  /// Number of parallel connections per RPC endpoint.
+  #[structopt(long, env = "RPC_POOL_SIZE", default_value = "1")]
+  rpc_pool_size: usize,
+
+  /// Seconds to wait for a RPC method response (0 to wait forever).
+  #[structopt(long, env = "RPC_TIMEOUT", default_value = "120")]
+  rpc_timeout: u64,
+
+  /// Maximum size of a WebSocket message in bytes.  Large metadata or storage pages need more.
+  #[structopt(long, env = "WS_MAX_MESSAGE_SIZE", default_value = "67108864")]
+  ws_max_message_size: usize,
+
+  /// Maximum size of an incoming WebSocket frame in bytes (0 for no limit).
+  #[structopt(long, env = "WS_MAX_FRAGMENT_SIZE", default_value = "0")]
+  ws_max_fragment_size: usize,
+
+  /// Split outgoing WebSocket messages into frames of this size in bytes.
+  #[structopt(long, env = "WS_FRAGMENT_SIZE", default_value = "65535")]
+  ws_fragment_size: usize,
+
+  /// Maximum number of blocks to cache (0 to disable).
+  #[structopt(long, env = "BLOCK_CACHE_SIZE", default_value = "1000")]
+  block_cache_size: usize,
+
+  /// Maximum number of block events to cache (0 to disable).
+  #[structopt(long, env = "EVENT_CACHE_SIZE", default_value = "1000")]
+  event_cache_size: usize,
+
+  /// Maximum number of script operations each spawned task can run (0 for no limit).
+  #[structopt(long, env = "TASK_MAX_OPERATIONS", default_value = "0")]
+  task_max_operations: u64,
+
+  /// Maximum number of operations per script run (0 for no limit).
+  #[structopt(long, env = "MAX_OPERATIONS", default_value = "0")]
+  max_operations: u64,
+
+  /// Maximum function call depth (0 for the default).
+  #[structopt(long, env = "MAX_CALL_LEVELS", default_value = "0")]
+  max_call_levels: usize,
+
+  /// Maximum string length (0 for no limit).
+  #[structopt(long, env = "MAX_STRING_SIZE", default_value = "0")]
+  max_string_size: usize,
+
+  /// Maximum array length (0 for no limit).
+  #[structopt(long, env = "MAX_ARRAY_SIZE", default_value = "0")]
+  max_array_size: usize,
+
+  /// Maximum number of object map properties (0 for no limit).
+  #[structopt(long, env = "MAX_MAP_SIZE", default_value = "0")]
+  max_map_size: usize,
+
+  /// Seconds the script can run before it is cancelled (0 for no limit).
+  #[structopt(long, env = "SCRIPT_TIMEOUT", default_value = "0")]
+  script_timeout: u64,
+
+  /// Load a plugin: a Rhai module file (`name.rhai` is available as `name::`) or a native library.
+  #[structopt(long = "plugin", env = "SUB_SCRIPT_PLUGINS", use_delimiter = true)]
+  plugins: Vec<String>,
+
+  /// JSON file of labeled addresses (`ADDRBOOK`).
+  #[structopt(long, env = "ADDRBOOK_FILE", default_value = "addrbook.json")]
+  addrbook: String,
+
+  /// Profile the script and RPC calls.  Writes flame graph "folded stacks" to this file.
+  #[structopt(long)]
+  profile: Option<String>,
+
+  /// Output format of the script's final value: `text` or `json`.
+  #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+  output: String,
+
+  /// Write a JSON summary of the run (RPC calls, extrinsics, errors, timings) to this file.
+  #[structopt(long, env = "RUN_SUMMARY")]
+  summary: Option<String>,
+
+  /// Print per-method RPC request counts, errors and latencies at exit.
+  #[structopt(long)]
+  rpc_stats: bool,
+
+  /// Re-run the script when the file changes.  The RPC connections and types are kept loaded.
+  #[structopt(long)]
+  watch: bool,
+
+  /// Evaluate inline script code instead of a file.  All positional arguments are script args.
+  #[structopt(short, long = "eval")]
+  eval: Option<String>,
+
+  /// More scripts (or directories) to run after `SCRIPT`, in order, with the same engine and
+  /// scope.  Can be repeated.  Directories run all their `.rhai` files (sorted by name).
+  #[structopt(long = "script", parse(from_os_str))]
+  more_scripts: Vec<PathBuf>,
+
+  /// Script to run.  `docs [FILE]` writes a markdown reference of the chain's metadata instead.
+  /// A directory runs all its `.rhai` files.
+  #[structopt(name = "SCRIPT", parse(from_os_str), required_unless = "eval")]
+  script: Option<PathBuf>,
+
+  /// Script args.
   #[structopt(name = "arg")]
   args: Vec<String>,
 }
 
+/// Expand a directory into its `.rhai` files, sorted by name.
+fn expand_scripts(path: PathBuf) -> Result<Vec<Script>> {
+  if !path.is_dir() {
+    return Ok(vec![Script::File(path)]);
+  }
+  let mut files = std::fs::read_dir(&path)?
+    .map(|entry| entry.map(|e| e.path()))
+    .collect::<std::io::Result<Vec<_>>>()?;
+  files.retain(|f| f.is_file() && f.extension().map_or(false, |ext| ext == "rhai"));
+  files.sort();
+  if files.is_empty() {
+    return Err(anyhow!("No .rhai scripts in directory {:?}", path));
+  }
+  Ok(files.into_iter().map(Script::File).collect())
+}
+
 impl Opt {
   fn into_engine_opts(self) -> EngineOptions {
     EngineOptions {
       url: self.url,
       substrate_types: self.substrate_types,
       custom_types: self.custom_types,
+      schema_cache_dir: self.schema_cache_dir,
+      codec_trace: self.codec_trace,
+      tls_ca_file: self.tls_ca_file,
+      tls_cert_file: self.tls_cert_file,
+      tls_key_file: self.tls_key_file,
+      tls_insecure: self.tls_insecure,
+      rpc_pool_size: self.rpc_pool_size,
+      rpc_timeout: self.rpc_timeout,
+      ws_max_message_size: self.ws_max_message_size,
+      ws_max_fragment_size: self.ws_max_fragment_size,
+      ws_fragment_size: self.ws_fragment_size,
+      block_cache_size: self.block_cache_size,
+      event_cache_size: self.event_cache_size,
+      task_max_operations: self.task_max_operations,
+      max_operations: self.max_operations,
+      max_call_levels: self.max_call_levels,
+      max_string_size: self.max_string_size,
+      max_array_size: self.max_array_size,
+      max_map_size: self.max_map_size,
+      script_timeout: self.script_timeout,
+      plugins: self.plugins,
+      addrbook_file: self.addrbook,
       args: self.args,
     }
   }
 }
 
+/// How often `--watch` checks the script for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The script to run: a file or inline code (`-e`).
+enum Script {
+  File(PathBuf),
+  Inline(String),
+}
+
+impl Script {
+  fn eval(&self, engine: &SharedEngine, scope: &mut Scope) -> Result<Dynamic, Box<EvalAltResult>> {
+    match self {
+      Self::File(path) => engine.eval_file_with_scope(scope, path.clone()),
+      Self::Inline(code) => {
+        let ast = engine.compile(code)?;
+        engine.eval_ast_with_scope(scope, &ast)
+      }
+    }
+  }
+
+  fn eprint_error(&self, err: EvalAltResult) {
+    match self {
+      Self::File(path) => eprint_script_error(path, err),
+      Self::Inline(code) => eprint_error(code, err),
+    }
+  }
+
+  fn name(&self) -> String {
+    match self {
+      Self::File(path) => path.to_string_lossy().into(),
+      Self::Inline(_) => "<inline>".into(),
+    }
+  }
+
+  fn modified(&self) -> Option<SystemTime> {
+    match self {
+      Self::File(path) => std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+      Self::Inline(_) => None,
+    }
+  }
+}
+
+fn modified(scripts: &[Script]) -> Vec<Option<SystemTime>> {
+  scripts.iter().map(Script::modified).collect()
+}
+
+/// Wait for a script file to change.  Returns `false` if the script was cancelled.
+fn wait_for_change(scripts: &[Script], last: &[Option<SystemTime>]) -> bool {
+  eprintln!("Watching {} script(s) for changes (Ctrl-C to stop)...", scripts.len());
+  while !is_cancelled() {
+    std::thread::sleep(WATCH_INTERVAL);
+    if modified(scripts) != last {
+      return true;
+    }
+  }
+  false
+}
+
+/// `sub-script docs [FILE]`, unless there is a script named `docs`.
+fn is_docs_mode(opt: &Opt) -> bool {
+  opt.eval.is_none()
+    && opt
+      .script
+      .as_ref()
+      .map_or(false, |s| s == Path::new("docs") && !s.exists())
+}
+
+/// Write the metadata markdown reference to `file` (stdout if not set).
+fn write_docs(engine: &SharedEngine, file: Option<&String>) -> Result<()> {
+  let markdown = engine.metadata().to_markdown();
+  match file {
+    Some(file) => {
+      std::fs::write(file, markdown)?;
+      eprintln!("Metadata docs written to: {}", file);
+    }
+    None => print!("{}", markdown),
+  }
+  Ok(())
+}
+
+/// Run the script and print its result.  Returns the script error, if it failed.
+fn run_script(
+  engine: &SharedEngine,
+  script: &Script,
+  scope: &mut Scope,
+  json_output: bool,
+) -> Result<Option<String>> {
+  let res = summary::phase(&script.name(), || {
+    profile::scope(|| "main".into(), || script.eval(engine, scope))
+  });
+  let script_error = res.as_ref().err().map(|err| error_message(err));
+  match res {
+    Ok(res) if json_output => {
+      println!("{}", serde_json::to_string_pretty(&dynamic_to_json(&res))?);
+    }
+    // Show the value of one-liners.
+    Ok(res) if matches!(script, Script::Inline(_)) && !res.is::<()>() => {
+      println!("{}", pretty(&res));
+    }
+    Err(err) => {
+      if json_output {
+        let err_json = serde_json::json!({ "error": error_message(&err) });
+        println!("{}", serde_json::to_string_pretty(&err_json)?);
+      }
+      script.eprint_error(*err);
+    }
+    _ => (),
+  }
+  Ok(script_error)
+}
+
 fn main() -> Result<()> {
   dotenv::dotenv().ok();
-  env_logger::init();
+  // Count warnings/errors for the run summary.
+  let logger = env_logger::Builder::from_default_env().build();
+  log::set_max_level(logger.filter());
+  log::set_boxed_logger(Box::new(summary::CountingLogger(logger)))?;
 
-  let opt = Opt::from_args();
+  let mut opt = Opt::from_args();
+  let docs_mode = is_docs_mode(&opt);
 
-  let script = opt.script.clone();
+  let scripts = match opt.eval.take() {
+    Some(code) => {
+      // With inline code, the first positional argument is a script arg.
+      if let Some(arg) = opt.script.take() {
+        opt.args.insert(0, arg.to_string_lossy().into());
+      }
+      vec![Script::Inline(code)]
+    }
+    None => {
+      let script = opt.script.take().ok_or_else(|| anyhow!("Missing SCRIPT"))?;
+      let mut scripts = expand_scripts(script)?;
+      for path in opt.more_scripts.drain(..) {
+        scripts.extend(expand_scripts(path)?);
+      }
+      scripts
+    }
+  };
+  let json_output = opt.output == "json";
+  let profile_file = opt.profile.clone();
+  let summary_file = opt.summary.clone();
+  let rpc_stats = opt.rpc_stats;
+  let watch = opt.watch;
+  profile::set_profile(profile_file.is_some());
 
   let engine_opts = opt.into_engine_opts();
-  let engine =
-    init_engine(&engine_opts).map_err(|e| anyhow!("Failed to initial engine: {:?}", e))?;
+  let engine = summary::phase("init", || init_engine(&engine_opts))
+    .map_err(|e| anyhow!("Failed to initial engine: {:?}", e))?;
 
-  let mut scope = engine.args_to_scope(&engine_opts.args[..]);
+  if docs_mode {
+    return write_docs(&engine, engine_opts.args.first());
+  }
 
-  match engine.run_file_with_scope(&mut scope, script.clone()) {
-    Err(err) => {
-      eprint_script_error(&script, *err);
+  // First Ctrl-C cancels the script, the second one aborts.
+  let cancel_engine = engine.clone();
+  ctrlc::set_handler(move || {
+    if is_cancelled() {
+      eprintln!("Aborting.");
+      std::process::exit(130);
     }
-    _ => (),
+    eprintln!("Cancelling script (press Ctrl-C again to abort)...");
+    cancel_engine.cancel();
+  })?;
+
+  // Scripts blocked on the node don't run operations, cancel them when the timeout passes.
+  if engine_opts.script_timeout > 0 && !watch {
+    let timeout = Duration::from_secs(engine_opts.script_timeout);
+    let watchdog_engine = engine.clone();
+    std::thread::spawn(move || {
+      std::thread::sleep(timeout);
+      eprintln!("Script timeout ({:?}), cancelling.", timeout);
+      watchdog_engine.cancel();
+    });
+  }
+
+  let watch = watch && scripts.iter().any(|s| matches!(s, Script::File(_)));
+  let mut script_error;
+  loop {
+    let last_modified = modified(&scripts);
+    // Scripts share the scope, so later scripts can use the variables of earlier ones.
+    let mut scope = engine.args_to_scope(&engine_opts.args[..]);
+    script_error = None;
+    for script in &scripts {
+      // Stop at the first failed script.
+      script_error = run_script(&engine, script, &mut scope, json_output)?;
+      if script_error.is_some() || is_cancelled() {
+        break;
+      }
+    }
+    if !watch || !wait_for_change(&scripts, &last_modified) {
+      break;
+    }
+    eprintln!("Script changed, re-running.");
+  }
+
+  if let Some(profile_file) = profile_file {
+    profile::write_folded(&profile_file)?;
+    eprintln!("Profile written to: {}", profile_file);
+    for (name, time) in profile::totals().into_iter().take(10) {
+      eprintln!("{:>12.3?} {}", time, name);
+    }
+  }
+
+  if rpc_stats {
+    eprint!("{}", engine.rpc_manager().stats().report());
+  }
+
+  if let Some(summary_file) = summary_file {
+    let script_error = if is_cancelled() {
+      Some(script_error.unwrap_or_else(|| "Cancelled".into()))
+    } else {
+      script_error
+    };
+    let names = scripts.iter().map(Script::name).collect::<Vec<_>>();
+    summary::write_summary(&summary_file, &names.join(", "), script_error)?;
+    eprintln!("Run summary written to: {}", summary_file);
+  }
+
+  if is_cancelled() {
+    engine.shutdown();
+    std::process::exit(130);
   }
 
   Ok(())
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use rhai::{Dynamic, Map as RMap, INT};
+
+struct InnerLru<K, V> {
+  tick: u64,
+  entries: HashMap<K, (u64, V)>,
+  // Least recently used first.
+  order: BTreeMap<u64, K>,
+}
+
+/// Size-bounded LRU cache.  A capacity of `0` disables caching.
+pub struct LruCache<K, V> {
+  capacity: usize,
+  inner: Mutex<InnerLru<K, V>>,
+  hits: AtomicU64,
+  misses: AtomicU64,
+  evictions: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      inner: Mutex::new(InnerLru {
+        tick: 0,
+        entries: HashMap::new(),
+        order: BTreeMap::new(),
+      }),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+      evictions: AtomicU64::new(0),
+    }
+  }
+
+  pub fn get(&self, key: &K) -> Option<V> {
+    let mut inner = self.inner.lock().unwrap();
+    inner.tick += 1;
+    let tick = inner.tick;
+    let val = match inner.entries.get_mut(key) {
+      Some((used, val)) => {
+        let old = *used;
+        *used = tick;
+        Some((old, val.clone()))
+      }
+      None => None,
+    };
+    match val {
+      Some((old, val)) => {
+        // Move to most recently used.
+        inner.order.remove(&old);
+        inner.order.insert(tick, key.clone());
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(val)
+      }
+      None => {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+      }
+    }
+  }
+
+  pub fn insert(&self, key: K, val: V) {
+    if self.capacity == 0 {
+      return;
+    }
+    let mut inner = self.inner.lock().unwrap();
+    inner.tick += 1;
+    let tick = inner.tick;
+    if let Some((old, _)) = inner.entries.insert(key.clone(), (tick, val)) {
+      inner.order.remove(&old);
+    }
+    inner.order.insert(tick, key);
+    // Evict least recently used entries.
+    while inner.entries.len() > self.capacity {
+      let oldest = match inner.order.keys().next() {
+        Some(oldest) => *oldest,
+        None => break,
+      };
+      if let Some(key) = inner.order.remove(&oldest) {
+        inner.entries.remove(&key);
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.lock().unwrap().entries.len()
+  }
+
+  pub fn clear(&self) {
+    let mut inner = self.inner.lock().unwrap();
+    inner.entries.clear();
+    inner.order.clear();
+  }
+
+  /// Cache statistics: `#{size, capacity, hits, misses, evictions}`.
+  pub fn stats(&self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("size".into(), Dynamic::from(self.len() as INT));
+    map.insert("capacity".into(), Dynamic::from(self.capacity as INT));
+    let hits = self.hits.load(Ordering::Relaxed) as INT;
+    map.insert("hits".into(), Dynamic::from(hits));
+    let misses = self.misses.load(Ordering::Relaxed) as INT;
+    map.insert("misses".into(), Dynamic::from(misses));
+    let evictions = self.evictions.load(Ordering::Relaxed) as INT;
+    map.insert("evictions".into(), Dynamic::from(evictions));
+    map
+  }
+}
@@ -1,10 +1,9 @@
-use std::any::TypeId;
 use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use hex::FromHex;
 
-use frame_metadata::RuntimeMetadataPrefixed;
 use parity_scale_codec::{Compact, Decode, Encode};
 use sp_core::{
   crypto::{set_default_ss58_version, Ss58AddressFormat},
@@ -14,30 +13,145 @@ use sp_core::{
 };
 use sp_runtime::{
   generic::{self, Era},
-  traits, MultiSignature,
+  MultiSignature,
 };
 use sp_version::RuntimeVersion;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Value};
 
-use dashmap::DashMap;
+use rust_decimal::{
+  prelude::{FromPrimitive, ToPrimitive},
+  Decimal,
+};
 
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use dashmap::DashMap;
 
-use rhai::serde::from_dynamic;
-use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::plugin::NativeCallContext;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map as RMap, INT};
 
-use crate::metadata::{EncodedCall, Metadata};
+use crate::addrbook::Labeled;
+use crate::cache::LruCache;
+use crate::engine::{is_cancelled, EngineOptions};
+use crate::error::{error_message, ErrorKind, ScriptError};
+use crate::metadata::{EncodedArgs, EncodedCall, Metadata};
 use crate::rpc::*;
+use crate::summary;
 use crate::types::{TypeLookup, TypeRef};
-use crate::users::{AccountId, User};
+use crate::users::{dynamic_to_account, AccountId, User};
+
+/// Decode a hex string with optional `0x` prefix.
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, Box<EvalAltResult>> {
+  let hex = hex.strip_prefix("0x").unwrap_or(hex);
+  Ok(Vec::from_hex(hex).map_err(|e| e.to_string())?)
+}
 
 pub type TxHash = H256;
 pub type BlockHash = H256;
 
 pub type GenericAddress = sp_runtime::MultiAddress<AccountId, ()>;
 
+/// Block header.
+///
+/// The number is kept as a `u64`, so chains with `u32` or `u64` block numbers are supported.
+/// It is SCALE encoded as the chain's `Header` type does (`encode_with`).  The header hash
+/// is always provided by the node, so the chain's hasher doesn't matter here.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockHeader {
+  pub parent_hash: BlockHash,
+  #[serde(
+    serialize_with = "serialize_block_number",
+    deserialize_with = "deserialize_block_number"
+  )]
+  pub number: u64,
+  pub state_root: BlockHash,
+  pub extrinsics_root: BlockHash,
+  pub digest: generic::Digest<BlockHash>,
+}
+
+fn serialize_block_number<S: Serializer>(number: &u64, s: S) -> Result<S::Ok, S::Error> {
+  s.serialize_str(&format!("0x{:x}", number))
+}
+
+/// Block numbers are hex strings in the RPC responses, but some nodes use plain integers.
+fn deserialize_block_number<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Number {
+    Hex(String),
+    Int(u64),
+  }
+  match Number::deserialize(d)? {
+    Number::Hex(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16)
+      .map_err(|e| D::Error::custom(format!("Invalid block number {}: {}", hex, e))),
+    Number::Int(number) => Ok(number),
+  }
+}
+
+/// Block number from a script `INT`, negative numbers are rejected.
+pub fn block_number_from_int(number: INT) -> Result<u64, Box<EvalAltResult>> {
+  Ok(u64::try_from(number).map_err(|_| format!("Invalid block number: {}", number))?)
+}
+
+/// Block numbers are `INT`, unless they don't fit.
+pub fn block_number_to_dynamic(number: u64) -> Dynamic {
+  match i64::try_from(number) {
+    Ok(number) => Dynamic::from(number as INT),
+    Err(_) => Dynamic::from_decimal(Decimal::from(number)),
+  }
+}
+
+impl BlockHeader {
+  /// SCALE encode the header.  The number is encoded with `number_ty` (the `number` field
+  /// of the chain's `Header` type), or as a `Compact<u32>` like Substrate's default header.
+  pub fn encode_with(&self, number_ty: Option<&TypeRef>) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    let mut data = self.parent_hash.encode();
+    match number_ty {
+      Some(number_ty) => data.extend(number_ty.encode(block_number_to_dynamic(self.number))?),
+      None => {
+        let number = u32::try_from(self.number)
+          .map_err(|_| format!("Block number {} doesn't fit in a u32", self.number))?;
+        Compact(number).encode_to(&mut data);
+      }
+    }
+    self.state_root.encode_to(&mut data);
+    self.extrinsics_root.encode_to(&mut data);
+    self.digest.encode_to(&mut data);
+    Ok(data)
+  }
+
+  fn parent_hash(&mut self) -> BlockHash {
+    self.parent_hash
+  }
+
+  fn number(&mut self) -> Dynamic {
+    block_number_to_dynamic(self.number)
+  }
+
+  fn state_root(&mut self) -> BlockHash {
+    self.state_root
+  }
+
+  fn extrinsics_root(&mut self) -> BlockHash {
+    self.extrinsics_root
+  }
+
+  fn digest_logs(&mut self) -> Vec<Dynamic> {
+    self
+      .digest
+      .logs
+      .iter()
+      .map(|log| Dynamic::from(format!("0x{}", hex::encode(log.encode()))))
+      .collect()
+  }
+
+  fn to_string(&mut self) -> String {
+    format!("{:?}", self)
+  }
+}
+
 pub type AdditionalSigned = (u32, u32, BlockHash, BlockHash, (), (), ());
 
 #[derive(Clone, Debug, Encode, Decode)]
@@ -49,12 +163,111 @@ impl Extra {
   }
 }
 
+/// Get a required integer field from a script options map.
+fn map_get_int(map: &RMap, name: &str) -> Result<INT, Box<EvalAltResult>> {
+  map
+    .get(name)
+    .ok_or_else(|| format!("Missing field '{}'", name))?
+    .as_int()
+    .map_err(|_| format!("Expected integer for field '{}'", name).into())
+}
+
+/// Convert a `BlockHash` or hex string into a `BlockHash`.
+pub fn dynamic_to_hash(val: Dynamic) -> Result<BlockHash, Box<EvalAltResult>> {
+  if val.is::<BlockHash>() {
+    return Ok(val.cast::<BlockHash>());
+  }
+  let hex = val
+    .into_immutable_string()
+    .map_err(|t| format!("Expected BlockHash or hex string, got {}", t))?;
+  let bytes = decode_hex(&hex)?;
+  if bytes.len() != 32 {
+    Err(format!("Expected 32 byte hash, got {} bytes", bytes.len()))?;
+  }
+  Ok(BlockHash::from_slice(&bytes))
+}
+
+/// Response of `state_getReadProof`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReadProof {
+  pub at: BlockHash,
+  pub proof: Vec<sp_core::Bytes>,
+}
+
+impl ReadProof {
+  pub fn to_map(&self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("at".into(), Dynamic::from(self.at));
+    let proof = self
+      .proof
+      .iter()
+      .map(|node| Dynamic::from(format!("0x{}", hex::encode(&node.0))))
+      .collect::<Vec<_>>();
+    map.insert("proof".into(), Dynamic::from(proof));
+    map
+  }
+}
+
+/// Everything needed to sign an extrinsic without a node connection.
+#[derive(Clone, Debug)]
+pub struct OfflineSignOptions {
+  pub era: Era,
+  pub nonce: u32,
+  pub additional: AdditionalSigned,
+}
+
+impl OfflineSignOptions {
+  /// Parse options: `#{nonce, spec_version, transaction_version, genesis_hash, era, block_hash}`.
+  ///
+  /// `era` defaults to immortal and `block_hash` (era checkpoint) defaults to the genesis hash.
+  pub fn from_map(map: RMap) -> Result<Self, Box<EvalAltResult>> {
+    let nonce = map_get_int(&map, "nonce")? as u32;
+    let spec_version = map_get_int(&map, "spec_version")? as u32;
+    let tx_version = map_get_int(&map, "transaction_version")? as u32;
+    let genesis_hash = dynamic_to_hash(
+      map
+        .get("genesis_hash")
+        .cloned()
+        .ok_or_else(|| format!("Missing field 'genesis_hash'"))?,
+    )?;
+    let block_hash = match map.get("block_hash") {
+      Some(hash) if !hash.is::<()>() => dynamic_to_hash(hash.clone())?,
+      _ => genesis_hash,
+    };
+    let era = match map.get("era") {
+      Some(era) if !era.is::<()>() => era
+        .clone()
+        .try_cast::<Era>()
+        .ok_or_else(|| format!("Expected Era for field 'era'"))?,
+      _ => Era::Immortal,
+    };
+    Ok(Self {
+      era,
+      nonce,
+      additional: (
+        spec_version,
+        tx_version,
+        genesis_hash,
+        block_hash,
+        (),
+        (),
+        (),
+      ),
+    })
+  }
+}
+
 pub struct SignedPayload<'a>((&'a EncodedCall, &'a Extra, AdditionalSigned));
 
 impl<'a> SignedPayload<'a> {
   pub fn new(call: &'a EncodedCall, extra: &'a Extra, additional: AdditionalSigned) -> Self {
     Self((call, extra, additional))
   }
+
+  /// The full payload, before hashing.
+  pub fn raw_payload(&self) -> Vec<u8> {
+    self.0.encode()
+  }
 }
 
 impl<'a> Encode for SignedPayload<'a> {
@@ -69,6 +282,9 @@ impl<'a> Encode for SignedPayload<'a> {
   }
 }
 
+/// Default number of blocks fetched concurrently by `scan_blocks`.
+const DEFAULT_SCAN_CONCURRENCY: u64 = 8;
+
 /// Current version of the `UncheckedExtrinsic` format.
 pub const EXTRINSIC_VERSION: u8 = 4;
 
@@ -86,6 +302,19 @@ impl ExtrinsicV4 {
     }
   }
 
+  pub fn sign_call(
+    user: &User,
+    extra: Extra,
+    additional: AdditionalSigned,
+    call: EncodedCall,
+  ) -> Self {
+    let payload = SignedPayload::new(&call, &extra, additional);
+
+    let sig = payload.using_encoded(|p| user.pair.sign(p));
+
+    Self::signed(user.acc(), sig.into(), extra, call)
+  }
+
   pub fn unsigned(call: EncodedCall) -> Self {
     Self {
       signature: None,
@@ -99,7 +328,11 @@ impl ExtrinsicV4 {
     hex
   }
 
-  pub fn decode_call(call_ty: &TypeRef, xt: &mut &[u8]) -> Result<Dynamic, Box<EvalAltResult>> {
+  pub fn decode_call(
+    call_ty: &TypeRef,
+    extra_ty: Option<&TypeRef>,
+    xt: &mut &[u8],
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
     // Decode Vec length.
     let _len: Compact<u32> = Decode::decode(xt).map_err(|e| e.to_string())?;
     // Version and signed flag.
@@ -110,7 +343,7 @@ impl ExtrinsicV4 {
     }
 
     if is_signed {
-      let _sig: (GenericAddress, MultiSignature, Extra) = Decode::decode(xt).map_err(|e| e.to_string())?;
+      decode_signer(xt, extra_ty)?;
     }
 
     call_ty.decode(xt.to_vec())
@@ -137,9 +370,226 @@ impl Encode for ExtrinsicV4 {
   }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// `System.Account` value.  Older runtimes don't have the reference counters.
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct AccountInfo {
   pub nonce: u32,
+  #[serde(default)]
+  pub consumers: u32,
+  #[serde(default)]
+  pub providers: u32,
+  #[serde(default)]
+  pub sufficients: u32,
+  // Read from the named fields, so it works with all `AccountData` layouts.
+  #[serde(skip)]
+  pub data: AccountBalance,
+}
+
+impl AccountInfo {
+  fn nonce(&mut self) -> INT {
+    self.nonce as INT
+  }
+
+  fn consumers(&mut self) -> INT {
+    self.consumers as INT
+  }
+
+  fn providers(&mut self) -> INT {
+    self.providers as INT
+  }
+
+  fn sufficients(&mut self) -> INT {
+    self.sufficients as INT
+  }
+
+  fn data(&mut self) -> AccountBalance {
+    self.data.clone()
+  }
+
+  pub fn to_map(&mut self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("nonce".into(), Dynamic::from(self.nonce()));
+    map.insert("consumers".into(), Dynamic::from(self.consumers()));
+    map.insert("providers".into(), Dynamic::from(self.providers()));
+    map.insert("sufficients".into(), Dynamic::from(self.sufficients()));
+    map.insert("data".into(), Dynamic::from(self.data.to_map()));
+    map
+  }
+
+  fn to_string(&mut self) -> String {
+    format!("{:?}", self)
+  }
+}
+
+/// Balances of an account in token units.
+#[derive(Clone, Debug, Default)]
+pub struct AccountBalance {
+  pub free: Decimal,
+  pub reserved: Decimal,
+  pub frozen: Decimal,
+}
+
+impl AccountBalance {
+  /// Read the `data` field of a `System.Account` value decoded with the chain's `AccountInfo`
+  /// type (`info_ty`).
+  ///
+  /// `AccountData` has either `misc_frozen, fee_frozen` or `frozen, flags` after `free` and
+  /// `reserved`.  Fields not decoded in token units are scaled with `scale`.
+  fn from_info(
+    info: &Dynamic,
+    info_ty: &TypeRef,
+    scale: Decimal,
+  ) -> Result<Self, Box<EvalAltResult>> {
+    let data = info
+      .read_lock::<RMap>()
+      .and_then(|info| info.get("data").cloned())
+      .and_then(|data| data.try_cast::<RMap>())
+      .ok_or_else(|| format!("Expected AccountInfo with a data field: {:?}", info))?;
+    let data_ty = info_ty.field_type("data");
+    let amount = |name: &str| -> Result<Decimal, Box<EvalAltResult>> {
+      let val = match data.get(name) {
+        Some(val) => val,
+        None => return Ok(Decimal::ZERO),
+      };
+      let amount = match val.as_int() {
+        Ok(num) => Decimal::from(num),
+        Err(_) => val
+          .as_decimal()
+          .map_err(|_| format!("Expected an integer for AccountData.{}: {:?}", name, val))?,
+      };
+      // The chain's `Balance` type is already decoded in token units.
+      let is_scaled = data_ty
+        .as_ref()
+        .and_then(|ty| ty.field_type(name))
+        .map_or(false, |ty| ty.has_custom_decode());
+      Ok(if is_scaled { amount } else { amount / scale })
+    };
+    let frozen = if data.contains_key("frozen") {
+      amount("frozen")?
+    } else {
+      amount("misc_frozen")?.max(amount("fee_frozen")?)
+    };
+    Ok(Self {
+      free: amount("free")?,
+      reserved: amount("reserved")?,
+      frozen,
+    })
+  }
+
+  fn free(&mut self) -> Decimal {
+    self.free
+  }
+
+  fn reserved(&mut self) -> Decimal {
+    self.reserved
+  }
+
+  fn frozen(&mut self) -> Decimal {
+    self.frozen
+  }
+
+  fn to_string(&mut self) -> String {
+    format!("{:?}", self)
+  }
+
+  pub fn to_map(&self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("free".into(), Dynamic::from_decimal(self.free));
+    map.insert("reserved".into(), Dynamic::from_decimal(self.reserved));
+    map.insert("frozen".into(), Dynamic::from_decimal(self.frozen));
+    map
+  }
+}
+
+/// Most token decimals supported, `Decimal` has 28 digits of precision.
+pub const MAX_TOKEN_DECIMALS: u32 = 28;
+
+/// Token decimals and symbol of a chain, from the chain properties.
+#[derive(Clone, Debug, Default)]
+pub struct TokenUnits {
+  pub decimals: u32,
+  pub symbol: String,
+}
+
+impl TokenUnits {
+  /// Token units with `decimals` decimal places.  Fails if the plancks per token
+  /// don't fit in a `Decimal` (more than `MAX_TOKEN_DECIMALS`).
+  pub fn new(decimals: u32, symbol: String) -> Result<Self, Box<EvalAltResult>> {
+    if decimals > MAX_TOKEN_DECIMALS {
+      Err(format!(
+        "Too many token decimals: {} (max {})",
+        decimals, MAX_TOKEN_DECIMALS
+      ))?;
+    }
+    Ok(Self { decimals, symbol })
+  }
+
+  fn from_props(props: Option<&ChainProperties>) -> Result<Self, Box<EvalAltResult>> {
+    match props {
+      Some(p) => Self::new(p.token_decimals, p.token_symbol.clone()),
+      None => Ok(Self::default()),
+    }
+  }
+
+  /// Plancks per token.
+  pub fn scale(&self) -> Result<Decimal, Box<EvalAltResult>> {
+    Ok(
+      10u128
+        .checked_pow(self.decimals)
+        .and_then(Decimal::from_u128)
+        .ok_or_else(|| format!("Too many token decimals: {}", self.decimals))?,
+    )
+  }
+
+  /// Convert a token amount into plancks.  Returns an `INT` when it fits, otherwise a `Decimal`.
+  pub fn to_plancks(&self, value: Decimal) -> Result<Dynamic, Box<EvalAltResult>> {
+    let plancks = self.checked_plancks(value)?;
+    Ok(match plancks.to_i64() {
+      Some(val) => Dynamic::from(val as INT),
+      None => Dynamic::from_decimal(plancks.normalize()),
+    })
+  }
+
+  /// Convert a token amount into plancks, for encoding a `u128` balance.
+  pub fn to_plancks_u128(&self, value: Decimal) -> Result<u128, Box<EvalAltResult>> {
+    Ok(
+      self
+        .checked_plancks(value)?
+        .to_u128()
+        .ok_or_else(|| format!("Balance too large: {}", value))?,
+    )
+  }
+
+  fn checked_plancks(&self, value: Decimal) -> Result<Decimal, Box<EvalAltResult>> {
+    let plancks = value
+      .checked_mul(self.scale()?)
+      .ok_or_else(|| format!("Balance too large: {}", value))?;
+    if plancks.is_sign_negative() {
+      Err(format!("Balance can't be negative: {}", value))?;
+    }
+    if !plancks.fract().is_zero() {
+      Err(format!(
+        "Balance {} has more than {} decimals",
+        value, self.decimals
+      ))?;
+    }
+    Ok(plancks)
+  }
+
+  /// Convert plancks into a token amount.
+  pub fn from_plancks(&self, plancks: Decimal) -> Result<Decimal, Box<EvalAltResult>> {
+    Ok((plancks / self.scale()?).normalize())
+  }
+
+  /// Format a token amount with the token symbol, e.g. `1.5 DOT`.
+  pub fn format_balance(&self, value: Decimal) -> String {
+    let value = value.normalize();
+    if self.symbol.is_empty() {
+      value.to_string()
+    } else {
+      format!("{} {}", value, self.symbol)
+    }
+  }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -157,6 +607,71 @@ pub enum TransactionStatus {
   Invalid,
 }
 
+/// Calls that can only be included as inherents.
+const INHERENT_CALLS: &[(&str, &str)] = &[
+  ("Timestamp", "set"),
+  ("Authorship", "set_uncles"),
+  ("FinalityTracker", "final_hint"),
+  ("ParaInherent", "enter"),
+  ("ParachainSystem", "set_validation_data"),
+  ("AuthorInherent", "kick_off_authorship"),
+];
+
+/// Get the pallet and call names of a decoded call.
+fn call_names(call: &Dynamic) -> Option<(String, String)> {
+  let pallet = call.read_lock::<RMap>()?;
+  let (pallet_name, call) = pallet.iter().next()?;
+  let call = call.read_lock::<RMap>()?;
+  let (call_name, _) = call.iter().next()?;
+  Some((pallet_name.to_string(), call_name.to_string()))
+}
+
+/// Check the signed flag of an encoded extrinsic.
+fn is_signed_extrinsic(mut xt: &[u8]) -> bool {
+  let _len: Result<Compact<u32>, _> = Decode::decode(&mut xt);
+  xt.first().map_or(false, |version| version & 0b1000_0000 != 0)
+}
+
+/// Decode the signer, signature and signed extra of a signed extrinsic.  Returns the signer
+/// and the nonce (`()` if the chain doesn't have the `CheckNonce` extension).
+///
+/// The signed extra is decoded with the `SignedExtra` type from the metadata's signed
+/// extensions, or the default `Extra` layout if the metadata doesn't have their types.
+fn decode_signer(
+  xt: &mut &[u8],
+  extra_ty: Option<&TypeRef>,
+) -> Result<(GenericAddress, Dynamic), Box<EvalAltResult>> {
+  let (address, _sig): (GenericAddress, MultiSignature) =
+    Decode::decode(xt).map_err(|e| e.to_string())?;
+  let nonce = match extra_ty.filter(|ty| !ty.is_unresolved()) {
+    Some(extra_ty) => {
+      let extra = extra_ty.decode_value(xt, false).map_err(|e| e.to_string())?;
+      extra
+        .try_cast::<RMap>()
+        .and_then(|mut extra| extra.remove("CheckNonce"))
+        .unwrap_or_default()
+    }
+    None => {
+      let extra: Extra = Decode::decode(xt).map_err(|e| e.to_string())?;
+      Dynamic::from(extra.1 .0 as INT)
+    }
+  };
+  Ok((address, nonce))
+}
+
+/// Decode the signer and nonce of a signed extrinsic.
+fn decode_signature(
+  mut xt: &[u8],
+  extra_ty: Option<&TypeRef>,
+) -> Option<(GenericAddress, Dynamic)> {
+  let _len: Compact<u32> = Decode::decode(&mut xt).ok()?;
+  let version: u8 = Decode::decode(&mut xt).ok()?;
+  if version & 0b1000_0000 == 0 {
+    return None;
+  }
+  decode_signer(&mut xt, extra_ty).ok()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignedBlock {
   block: Block,
@@ -166,12 +681,22 @@ pub struct SignedBlock {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
   extrinsics: Vec<String>,
-  header: generic::Header<u32, traits::BlakeTwo256>,
+  header: BlockHeader,
   #[serde(skip)]
   call_ty: Option<TypeRef>,
+  #[serde(skip)]
+  extra_ty: Option<TypeRef>,
 }
 
 impl Block {
+  pub fn extrinsics(&self) -> &[String] {
+    &self.extrinsics
+  }
+
+  pub fn header(&self) -> &BlockHeader {
+    &self.header
+  }
+
   pub fn find_extrinsic(&self, xthex: &str) -> Option<usize> {
     self.extrinsics.iter().position(|xt| xt == xthex)
   }
@@ -187,7 +712,7 @@ impl Block {
               if xthex.starts_with("0x") {
                 hex::decode(&xthex[2..]).ok()
                   .map(|xt| {
-                    ExtrinsicV4::decode_call(call_ty, &mut &xt[..])
+                    ExtrinsicV4::decode_call(call_ty, self.extra_ty.as_ref(), &mut &xt[..])
                       .map_err(|e| eprintln!("Call decode failed: {:?}", e))
                       .ok()
                   })
@@ -203,12 +728,101 @@ impl Block {
     }).collect::<Vec<_>>())
   }
 
+  /// Decode all extrinsics.  Returns `index`, `signed`, `signer`, `nonce`, `inherent`,
+  /// `call` and `xthex` for each extrinsic.
+  ///
+  /// The leading unsigned extrinsics are inherents, if their call is a known inherent
+  /// (or can't be decoded).
+  fn decode_extrinsics(&self) -> Vec<(bool, RMap)> {
+    let mut decoded = Vec::with_capacity(self.extrinsics.len());
+    let mut has_transactions = false;
+    for (idx, xthex) in self.extrinsics.iter().enumerate() {
+      let xt = xthex
+        .strip_prefix("0x")
+        .and_then(|xt| hex::decode(xt).ok())
+        .unwrap_or_default();
+      let signed = is_signed_extrinsic(&xt);
+      let call = self
+        .call_ty
+        .as_ref()
+        .and_then(|call_ty| {
+          ExtrinsicV4::decode_call(call_ty, self.extra_ty.as_ref(), &mut &xt[..])
+            .map_err(|e| log::warn!("Call decode failed: {:?}", e))
+            .ok()
+        });
+      let is_inherent = !signed
+        && !has_transactions
+        && call.as_ref().and_then(call_names).map_or(true, |(pallet, call)| {
+          INHERENT_CALLS
+            .iter()
+            .any(|(p, c)| *p == pallet && *c == call)
+        });
+      has_transactions |= !is_inherent;
+      let (signer, nonce) = match decode_signature(&xt, self.extra_ty.as_ref()) {
+        Some((GenericAddress::Id(acc), nonce)) => (Dynamic::from(acc), nonce),
+        Some((_, nonce)) => (Dynamic::UNIT, nonce),
+        None => (Dynamic::UNIT, Dynamic::UNIT),
+      };
+      let mut map = RMap::new();
+      map.insert("index".into(), Dynamic::from(idx as INT));
+      map.insert("signed".into(), Dynamic::from(signed));
+      map.insert("signer".into(), signer);
+      map.insert("nonce".into(), nonce);
+      map.insert("inherent".into(), Dynamic::from(is_inherent));
+      map.insert(
+        "call".into(),
+        call.unwrap_or_else(|| Dynamic::from(xthex.clone())),
+      );
+      map.insert("xthex".into(), Dynamic::from(xthex.clone()));
+      decoded.push((is_inherent, map));
+    }
+    decoded
+  }
+
+  fn filter_extrinsics(&self, inherent: bool) -> Vec<Dynamic> {
+    self
+      .decode_extrinsics()
+      .into_iter()
+      .filter(|(is_inherent, _)| *is_inherent == inherent)
+      .map(|(_, map)| Dynamic::from(map))
+      .collect()
+  }
+
+  pub fn inherents(&mut self) -> Vec<Dynamic> {
+    self.filter_extrinsics(true)
+  }
+
+  pub fn transactions(&mut self) -> Vec<Dynamic> {
+    self.filter_extrinsics(false)
+  }
+
+  /// All decoded extrinsics, in block order.
+  pub fn decoded_extrinsics(&mut self) -> Vec<Dynamic> {
+    self
+      .decode_extrinsics()
+      .into_iter()
+      .map(|(_, map)| Dynamic::from(map))
+      .collect()
+  }
+
+  fn extrinsics_hex(&mut self) -> Vec<Dynamic> {
+    self.extrinsics.iter().cloned().map(Dynamic::from).collect()
+  }
+
   pub fn parent(&mut self) -> BlockHash {
     self.header.parent_hash
   }
 
-  pub fn block_number(&mut self) -> i64 {
-    self.header.number as i64
+  pub fn block_number(&mut self) -> Dynamic {
+    block_number_to_dynamic(self.header.number)
+  }
+
+  fn state_root(&mut self) -> BlockHash {
+    self.header.state_root
+  }
+
+  fn get_header(&mut self) -> BlockHeader {
+    self.header.clone()
   }
 
   pub fn to_string(&mut self) -> String {
@@ -223,7 +837,7 @@ pub enum Phase {
   Initialization,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct EventRecord {
   pub phase: Phase,
   pub name: String,
@@ -231,6 +845,18 @@ pub struct EventRecord {
   pub topics: Vec<BlockHash>,
 }
 
+impl std::fmt::Debug for EventRecord {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // Show the labels of known accounts.
+    f.debug_struct("EventRecord")
+      .field("phase", &self.phase)
+      .field("name", &self.name)
+      .field("args", &Labeled(&self.args))
+      .field("topics", &self.topics)
+      .finish()
+  }
+}
+
 impl EventRecord {
   pub fn name(&mut self) -> String {
     self.name.clone()
@@ -295,6 +921,18 @@ impl EventRecords {
         .collect::<Result<Vec<EventRecord>, _>>()?,
     ))
   }
+
+  /// Convert decoded block events (`()` if the block has none) into `EventRecord` values.
+  pub fn to_records(val: Dynamic) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    if val.is::<()>() {
+      return Ok(Vec::new());
+    }
+    Ok(Self::from_dynamic(val)?.0.into_iter().map(Dynamic::from).collect())
+  }
+
+  pub fn records(&self) -> &[EventRecord] {
+    &self.0
+  }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -309,37 +947,85 @@ pub struct InnerClient {
   rpc: RpcHandler,
   runtime_version: RuntimeVersion,
   genesis_hash: BlockHash,
+  chain_name: String,
+  // Raw `system_properties`.
+  properties: Value,
   metadata: Metadata,
   event_records: TypeRef,
   account_info: TypeRef,
   call_ty: TypeRef,
-  cached_blocks: DashMap<BlockHash, Block>,
-  cached_events: DashMap<BlockHash, Dynamic>,
+  // Signed extensions of extrinsics.
+  extra_ty: TypeRef,
+  header_ty: TypeRef,
+  session_keys: TypeRef,
+  token_units: TokenUnits,
+  cached_blocks: LruCache<BlockHash, Block>,
+  cached_events: LruCache<BlockHash, Dynamic>,
+  // Block number -> hash, only for finalized blocks (they can't be re-orged).
+  cached_hashes: LruCache<u64, BlockHash>,
+  // Highest finalized block number seen so far.
+  finalized_number: AtomicU64,
+  // Account index -> account, from the `Indices` pallet.
+  cached_indices: DashMap<u32, Option<AccountId>>,
+  // Submitted extrinsics that haven't been included in a block yet.
+  pending_xts: DashMap<RequestToken, String>,
+  // Mortal era period for submitted extrinsics, 0 for immortal.
+  era_period: AtomicU64,
 }
 
 impl InnerClient {
   pub fn new(
     rpc: RpcHandler,
     lookup: &TypeLookup,
+    opts: &EngineOptions,
   ) -> Result<Arc<Self>, Box<EvalAltResult>> {
     let runtime_version = Self::rpc_get_runtime_version(&rpc)?;
+    // Select the schema's versioned types before decoding the metadata.
+    lookup.set_spec_version(runtime_version.spec_version)?;
     let genesis_hash = Self::rpc_get_genesis_hash(&rpc)?;
     let runtime_metadata = Self::rpc_get_runtime_metadata(&rpc)?;
-    let metadata = Metadata::from_runtime_metadata(runtime_metadata, lookup)?;
+    let metadata = Metadata::decode(&runtime_metadata, lookup)?;
+    let chain_name: String = rpc.call_method("system_chain", json!([]))?.unwrap_or_default();
+    let properties: Value = rpc
+      .call_method("system_properties", json!([]))?
+      .unwrap_or_default();
+    let chain_props: Option<ChainProperties> = if properties.is_null() {
+      None
+    } else {
+      Some(
+        serde_json::from_value(properties.clone())
+          .map_err(|e| format!("Failed to parse chain properties: {}", e))?,
+      )
+    };
+    let token_units = TokenUnits::from_props(chain_props.as_ref())?;
 
     let event_records = lookup.resolve("EventRecords");
     let account_info = lookup.resolve("AccountInfo");
     let call_ty = lookup.resolve("Call");
+    let extra_ty = lookup.resolve("SignedExtra");
+    let header_ty = lookup.resolve("Header");
+    let session_keys = lookup.resolve("SessionKeys");
     Ok(Arc::new(Self {
       rpc,
       runtime_version,
       genesis_hash,
+      chain_name,
+      properties,
       metadata,
       event_records,
       account_info,
       call_ty,
-      cached_blocks: DashMap::new(),
-      cached_events: DashMap::new(),
+      extra_ty,
+      header_ty,
+      session_keys,
+      token_units,
+      cached_blocks: LruCache::new(opts.block_cache_size),
+      cached_events: LruCache::new(opts.event_cache_size),
+      cached_hashes: LruCache::new(opts.block_cache_size),
+      finalized_number: AtomicU64::new(0),
+      cached_indices: DashMap::new(),
+      pending_xts: DashMap::new(),
+      era_period: AtomicU64::new(0),
     }))
   }
 
@@ -368,19 +1054,69 @@ impl InnerClient {
   /// Get metadata from rpc node.
   fn rpc_get_runtime_metadata(
     rpc: &RpcHandler,
-  ) -> Result<RuntimeMetadataPrefixed, Box<EvalAltResult>> {
+  ) -> Result<Vec<u8>, Box<EvalAltResult>> {
     let hex: String = rpc
       .call_method("state_getMetadata", json!([]))?
       .ok_or_else(|| format!("Failed to get Metadata from node."))?;
 
-    let bytes = Vec::from_hex(&hex[2..]).map_err(|e| e.to_string())?;
-    Ok(RuntimeMetadataPrefixed::decode(&mut bytes.as_slice()).map_err(|e| e.to_string())?)
+    decode_hex(&hex)
   }
 
   pub fn get_transaction_version(&self) -> i64 {
     self.runtime_version.transaction_version as i64
   }
 
+  /// Runtime version of the node, when the client connected.
+  pub fn runtime_version(&self) -> RMap {
+    let version = &self.runtime_version;
+    let mut map = RMap::new();
+    map.insert("spec_name".into(), Dynamic::from(version.spec_name.to_string()));
+    map.insert("impl_name".into(), Dynamic::from(version.impl_name.to_string()));
+    map.insert("authoring_version".into(), Dynamic::from(version.authoring_version as INT));
+    map.insert("spec_version".into(), Dynamic::from(version.spec_version as INT));
+    map.insert("impl_version".into(), Dynamic::from(version.impl_version as INT));
+    map.insert("transaction_version".into(), Dynamic::from(version.transaction_version as INT));
+    map
+  }
+
+  pub fn genesis_hash(&self) -> BlockHash {
+    self.genesis_hash
+  }
+
+  /// Type of the block number in the chain's `Header` type, if the metadata has one.
+  pub fn block_number_type(&self) -> Option<TypeRef> {
+    self.header_ty.field_type("number")
+  }
+
+  pub fn chain_name(&self) -> String {
+    self.chain_name.clone()
+  }
+
+  pub fn properties(&self) -> Result<Dynamic, Box<EvalAltResult>> {
+    to_dynamic(&self.properties)
+  }
+
+  /// Block and event cache statistics.
+  pub fn cache_stats(&self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("blocks".into(), Dynamic::from(self.cached_blocks.stats()));
+    map.insert("events".into(), Dynamic::from(self.cached_events.stats()));
+    map.insert("hashes".into(), Dynamic::from(self.cached_hashes.stats()));
+    map
+  }
+
+  pub fn clear_caches(&self) {
+    self.cached_blocks.clear();
+    self.cached_events.clear();
+    self.cached_hashes.clear();
+    self.cached_indices.clear();
+  }
+
+  /// Extrinsics that were submitted, but haven't been included in a block yet.
+  pub fn pending_extrinsics(&self) -> Vec<String> {
+    self.pending_xts.iter().map(|xt| xt.value().clone()).collect()
+  }
+
   pub fn get_metadata(&self) -> Metadata {
     self.metadata.clone()
   }
@@ -398,8 +1134,90 @@ impl InnerClient {
   }
 
   /// Get block hash.
+  ///
+  /// Hashes of finalized blocks are cached.  Finality is checked against the highest
+  /// finalized block seen so far, the finalized head is only requested if none was seen yet.
   pub fn get_block_hash(&self, block_number: u64) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
-    Self::rpc_get_block_hash(&self.rpc, block_number)
+    if let Some(hash) = self.cached_hashes.get(&block_number) {
+      return Ok(Some(hash));
+    }
+    let hash = Self::rpc_get_block_hash(&self.rpc, block_number)?;
+    if let Some(hash) = hash {
+      let mut finalized = self.finalized_number.load(Ordering::Relaxed);
+      if finalized == 0 {
+        finalized = self.get_finalized_block_number()?;
+      }
+      if block_number <= finalized {
+        self.cached_hashes.insert(block_number, hash);
+      }
+    }
+    Ok(hash)
+  }
+
+  /// Resolve a block number to it's hash, failing if the block doesn't exist yet.
+  pub fn block_hash_at(&self, block_number: u64) -> Result<BlockHash, Box<EvalAltResult>> {
+    self
+      .get_block_hash(block_number)?
+      .ok_or_else(|| format!("Block #{} not found", block_number).into())
+  }
+
+  /// Get the hash of the best block.
+  pub fn get_best_block_hash(&self) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
+    self.rpc.call_method("chain_getBlockHash", json!([]))
+  }
+
+  /// Get the number of the best block.
+  pub fn get_best_block_number(&self) -> Result<u64, Box<EvalAltResult>> {
+    let header: BlockHeader = self
+      .rpc
+      .call_method("chain_getHeader", json!([]))?
+      .ok_or_else(|| format!("Failed to get best header from node."))?;
+    Ok(header.number)
+  }
+
+  /// Get the hash and number of the last finalized block.
+  pub fn get_finalized_head(&self) -> Result<(BlockHash, u64), Box<EvalAltResult>> {
+    let hash: BlockHash = self
+      .rpc
+      .call_method("chain_getFinalizedHead", json!([]))?
+      .ok_or_else(|| format!("Failed to get finalized head from node."))?;
+    let header: BlockHeader = self
+      .rpc
+      .call_method("chain_getHeader", json!([hash]))?
+      .ok_or_else(|| format!("Failed to get finalized header from node."))?;
+    self
+      .finalized_number
+      .fetch_max(header.number, Ordering::Relaxed);
+    Ok((hash, header.number))
+  }
+
+  /// Get the number of the last finalized block.
+  pub fn get_finalized_block_number(&self) -> Result<u64, Box<EvalAltResult>> {
+    Ok(self.get_finalized_head()?.1)
+  }
+
+  pub fn era_period(&self) -> u64 {
+    self.era_period.load(Ordering::Relaxed)
+  }
+
+  /// Use mortal eras of `period` blocks for submitted extrinsics.  `0` is immortal.
+  pub fn set_era_period(&self, period: u64) {
+    self.era_period.store(period, Ordering::Relaxed);
+  }
+
+  /// Era and signed extra for a new extrinsic.
+  ///
+  /// Mortal eras start at the last finalized block, which is also the checkpoint hash.
+  pub fn get_era(&self) -> Result<(Era, AdditionalSigned, u64), Box<EvalAltResult>> {
+    let mut additional = self.get_signed_extra();
+    match self.era_period() {
+      0 => Ok((Era::Immortal, additional, 0)),
+      period => {
+        let (hash, number) = self.get_finalized_head()?;
+        additional.3 = hash;
+        Ok((Era::mortal(period, number), additional, number))
+      }
+    }
   }
 
   pub fn get_block_by_number(&self, block_number: u64) -> Result<Option<Block>, Box<EvalAltResult>> {
@@ -412,12 +1230,13 @@ impl InnerClient {
     Ok(if let Some(hash) = hash {
       let block = self.cached_blocks.get(&hash);
       if block.is_some() {
-        block.as_deref().cloned()
+        block
       } else {
         let block = self
           .get_signed_block(Some(hash))?
           .map(|mut signed| {
             signed.block.call_ty = Some(self.call_ty.clone());
+            signed.block.extra_ty = Some(self.extra_ty.clone());
             signed.block
           });
         if let Some(block) = &block {
@@ -427,7 +1246,11 @@ impl InnerClient {
         block
       }
     } else {
-      self.get_signed_block(hash)?.map(|signed| signed.block)
+      self.get_signed_block(hash)?.map(|mut signed| {
+        signed.block.call_ty = Some(self.call_ty.clone());
+        signed.block.extra_ty = Some(self.extra_ty.clone());
+        signed.block
+      })
     })
   }
 
@@ -447,12 +1270,13 @@ impl InnerClient {
     prefix: &StorageKey,
     count: u32,
     start_key: Option<&StorageKey>,
+    at_block: Option<BlockHash>,
   ) -> Result<Vec<StorageKey>, Box<EvalAltResult>> {
     self
       .rpc
       .call_method(
         "state_getKeysPaged",
-        json!([prefix, count, start_key.unwrap_or(prefix)]),
+        json!([prefix, count, start_key.unwrap_or(prefix), at_block]),
       )
       .map(|res| res.unwrap_or_default())
   }
@@ -483,20 +1307,74 @@ impl InnerClient {
     self.rpc.get_responses(tokens.as_slice())
   }
 
-  pub fn get_storage_value(
+  pub fn get_storage_sizes(
     &self,
-    module: &str,
-    storage: &str,
+    keys: &[StorageKey],
     at_block: Option<BlockHash>,
-  ) -> Result<Option<StorageData>, Box<EvalAltResult>> {
-    let md = self.metadata.get_storage(module, storage)?;
-    let key = md.get_value_key()?;
-    self.get_storage_by_key(key, at_block)
-  }
-
-  pub fn get_storage_map(
-    &self,
-    module: &str,
+  ) -> Result<Vec<Option<u64>>, Box<EvalAltResult>> {
+    let tokens: Vec<RequestToken> = keys
+      .into_iter()
+      .map(|k| {
+        self
+          .rpc
+          .async_call_method("state_getStorageSize", json!([k, at_block]))
+      })
+      .collect::<Result<Vec<_>, Box<EvalAltResult>>>()?;
+    self.rpc.get_responses(tokens.as_slice())
+  }
+
+  pub fn get_child_storage_keys_paged(
+    &self,
+    child_key: &StorageKey,
+    prefix: &StorageKey,
+    count: u32,
+    start_key: Option<&StorageKey>,
+  ) -> Result<Vec<StorageKey>, Box<EvalAltResult>> {
+    self
+      .rpc
+      .call_method(
+        "childstate_getKeysPaged",
+        json!([child_key, prefix, count, start_key.unwrap_or(prefix)]),
+      )
+      .map(|res| res.unwrap_or_default())
+  }
+
+  pub fn get_child_storage_by_key(
+    &self,
+    child_key: &StorageKey,
+    key: StorageKey,
+    at_block: Option<BlockHash>,
+  ) -> Result<Option<StorageData>, Box<EvalAltResult>> {
+    self
+      .rpc
+      .call_method("childstate_getStorage", json!([child_key, key, at_block]))
+  }
+
+  pub fn get_read_proof(
+    &self,
+    keys: &[StorageKey],
+    at_block: Option<BlockHash>,
+  ) -> Result<ReadProof, Box<EvalAltResult>> {
+    self
+      .rpc
+      .call_method("state_getReadProof", json!([keys, at_block]))?
+      .ok_or_else(|| format!("Failed to get read proof").into())
+  }
+
+  pub fn get_storage_value(
+    &self,
+    module: &str,
+    storage: &str,
+    at_block: Option<BlockHash>,
+  ) -> Result<Option<StorageData>, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(module, storage)?;
+    let key = md.get_value_key()?;
+    self.get_storage_by_key(key, at_block)
+  }
+
+  pub fn get_storage_map(
+    &self,
+    module: &str,
     storage: &str,
     key: Vec<u8>,
     at_block: Option<BlockHash>,
@@ -519,6 +1397,80 @@ impl InnerClient {
     self.get_storage_by_key(key, at_block)
   }
 
+  /// Get the blocks and events of a range of block numbers, making all requests concurrently.
+  ///
+  /// Missing blocks are skipped.
+  pub fn get_blocks_with_events(
+    &self,
+    from: u64,
+    to: u64,
+  ) -> Result<Vec<(Block, Dynamic)>, Box<EvalAltResult>> {
+    let hash_tokens = (from..=to)
+      .map(|num| {
+        self
+          .rpc
+          .async_call_method("chain_getBlockHash", json!([num]))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let hashes: Vec<Option<BlockHash>> = self.rpc.get_responses(&hash_tokens)?;
+
+    let events_key = self
+      .metadata
+      .get_storage("System", "Events")?
+      .get_value_key()?;
+    // Only request the blocks and events that aren't cached.
+    // `Ok` holds a cached value, `Err` the token of the pending request.
+    let mut pending = Vec::new();
+    for hash in hashes.into_iter().flatten() {
+      let block = match self.cached_blocks.get(&hash) {
+        Some(block) => Ok(block),
+        None => Err(
+          self
+            .rpc
+            .async_call_method("chain_getBlock", json!([hash]))?,
+        ),
+      };
+      let events = match self.cached_events.get(&hash) {
+        Some(events) => Ok(events),
+        None => Err(
+          self
+            .rpc
+            .async_call_method("state_getStorage", json!([events_key, hash]))?,
+        ),
+      };
+      pending.push((hash, block, events));
+    }
+
+    let mut blocks = Vec::with_capacity(pending.len());
+    for (hash, block, events) in pending {
+      let events = match events {
+        Ok(events) => events,
+        Err(token) => {
+          let events = match self.rpc.get_response::<StorageData>(token)? {
+            Some(value) => self.event_records.decode(value.0)?,
+            None => Dynamic::UNIT,
+          };
+          self.cached_events.insert(hash, events.clone());
+          events
+        }
+      };
+      let block = match block {
+        Ok(block) => block,
+        Err(token) => match self.rpc.get_response::<SignedBlock>(token)? {
+          Some(mut signed) => {
+            signed.block.call_ty = Some(self.call_ty.clone());
+            signed.block.extra_ty = Some(self.extra_ty.clone());
+            self.cached_blocks.insert(hash, signed.block.clone());
+            signed.block
+          }
+          None => continue,
+        },
+      };
+      blocks.push((block, events));
+    }
+    Ok(blocks)
+  }
+
   fn get_block_events(&self, hash: Option<BlockHash>) -> Result<Dynamic, Box<EvalAltResult>> {
     match self.get_storage_value("System", "Events", hash)? {
       Some(value) => Ok(self.event_records.decode(value.0)?),
@@ -530,7 +1482,7 @@ impl InnerClient {
     if let Some(hash) = hash {
       let events = self.cached_events.get(&hash);
       if let Some(events) = events {
-        Ok(events.clone())
+        Ok(events)
       } else {
         let events = self.get_block_events(Some(hash))?;
         // Cache new events.
@@ -566,53 +1518,96 @@ impl InnerClient {
     }
   }
 
-  pub fn get_request_block_hash(
+  /// Account of an already resolved account index (doesn't make RPC calls).
+  pub fn cached_index(&self, index: u32) -> Option<AccountId> {
+    self.cached_indices.get(&index).and_then(|acc| acc.clone())
+  }
+
+  /// Get the account of an account index.  Chains without the `Indices` pallet have no indices.
+  pub fn lookup_index(&self, index: u32) -> Result<Option<AccountId>, Box<EvalAltResult>> {
+    if let Some(acc) = self.cached_indices.get(&index) {
+      return Ok(acc.clone());
+    }
+    if self.metadata.get_module("Indices").is_none() {
+      return Ok(None);
+    }
+    // The value is `(AccountId, Balance, bool)`.
+    let acc = match self.get_storage_map("Indices", "Accounts", index.encode(), None)? {
+      Some(value) => Some(AccountId::decode(&mut &value.0[..]).map_err(|e| e.to_string())?),
+      None => None,
+    };
+    self.cached_indices.insert(index, acc.clone());
+    Ok(acc)
+  }
+
+  pub fn get_balance(&self, account: AccountId) -> Result<AccountBalance, Box<EvalAltResult>> {
+    Ok(self.account_info(account)?.data)
+  }
+
+  /// Typed `System.Account` value.  Missing accounts return the default (all zero).
+  pub fn account_info(&self, account: AccountId) -> Result<AccountInfo, Box<EvalAltResult>> {
+    match self.get_account_info(account)? {
+      Some(value) => {
+        let mut info: AccountInfo = from_dynamic(&value)?;
+        info.data =
+          AccountBalance::from_info(&value, &self.account_info, self.token_units.scale()?)?;
+        Ok(info)
+      }
+      None => Ok(AccountInfo::default()),
+    }
+  }
+
+  /// Generate new session keys on the node (`author_rotateKeys`).
+  pub fn rotate_keys(&self) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    let hex: String = self
+      .rpc
+      .call_method("author_rotateKeys", json!([]))?
+      .ok_or_else(|| format!("Failed to rotate session keys."))?;
+    Ok(decode_hex(&hex)?)
+  }
+
+  /// Check if the node has the private keys for the session keys.
+  pub fn has_session_keys(&self, keys: Vec<u8>) -> Result<bool, Box<EvalAltResult>> {
+    let keys = format!("0x{}", hex::encode(keys));
+    Ok(
+      self
+        .rpc
+        .call_method("author_hasSessionKeys", json!([keys]))?
+        .unwrap_or(false),
+    )
+  }
+
+  /// Decode session keys using the chain's `SessionKeys` type.
+  pub fn decode_session_keys(&self, keys: Vec<u8>) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.session_keys.decode(keys)
+  }
+
+  /// Build a `Session.set_keys` call from the raw session keys.
+  pub fn set_keys_call(
+    &self,
+    keys: Vec<u8>,
+    proof: Vec<u8>,
+  ) -> Result<EncodedCall, Box<EvalAltResult>> {
+    let func = self.metadata.get_func("Session", "set_keys")?;
+    let mut data = EncodedArgs::new();
+    // The session keys are already SCALE encoded.
+    data.write(&keys);
+    data.encode(proof);
+    Ok(func.encode_raw_call(data))
+  }
+
+  /// Get the next status update of a submitted extrinsic.
+  pub fn get_tx_status(
     &self,
     token: RequestToken,
-  ) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
-    let hash = loop {
-      let status = self.rpc.get_update(token)?;
-      match status {
-        Some(TransactionStatus::InBlock(hash))
-        | Some(TransactionStatus::Finalized(hash))
-        | Some(TransactionStatus::FinalityTimeout(hash)) => {
-          break Some(hash);
-        }
-        Some(TransactionStatus::Future) => {
-          log::warn!("Transaction in future (maybe nonce issue)");
-        }
-        Some(TransactionStatus::Ready) => {
-          log::debug!("Transaction ready.");
-        }
-        Some(TransactionStatus::Broadcast(nodes)) => {
-          log::debug!("Transaction broadcast: {:?}", nodes);
-        }
-        Some(TransactionStatus::Retracted(hash)) => {
-          log::error!("Transaction retracted: {:?}", hash);
-        }
-        Some(TransactionStatus::Usurped(tx_hash)) => {
-          log::error!(
-            "Transaction was replaced by another in the pool: {:?}",
-            tx_hash
-          );
-          break None;
-        }
-        Some(TransactionStatus::Dropped) => {
-          log::error!("Transaction dropped.");
-          break None;
-        }
-        Some(TransactionStatus::Invalid) => {
-          log::error!("Transaction invalid.");
-          break None;
-        }
-        None => {
-          break None;
-        }
-      }
-    };
-    self.rpc.close_request(token)?;
+  ) -> Result<Option<TransactionStatus>, Box<EvalAltResult>> {
+    self.rpc.get_update(token)
+  }
 
-    Ok(hash)
+  /// Stop watching a submitted extrinsic.
+  pub fn close_tx_request(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
+    self.pending_xts.remove(&token);
+    self.rpc.close_request(token)
   }
 
   pub fn submit(&self, xthex: String) -> Result<(RequestToken, String), Box<EvalAltResult>> {
@@ -621,6 +1616,8 @@ impl InnerClient {
       json!([xthex]),
       "author_unwatchExtrinsic",
     )?;
+    self.pending_xts.insert(token, xthex.clone());
+    summary::extrinsic_submitted();
     Ok((token, xthex))
   }
 
@@ -629,12 +1626,9 @@ impl InnerClient {
     user: &User,
     call: EncodedCall,
   ) -> Result<(RequestToken, String), Box<EvalAltResult>> {
-    let extra = Extra::new(Era::Immortal, user.nonce);
-    let payload = SignedPayload::new(&call, &extra, self.get_signed_extra());
-
-    let sig = payload.using_encoded(|p| user.pair.sign(p));
-
-    let xt = ExtrinsicV4::signed(user.acc(), sig.into(), extra, call);
+    let (era, additional, _) = self.get_era()?;
+    let extra = Extra::new(era, user.nonce);
+    let xt = ExtrinsicV4::sign_call(user, extra, additional, call);
     let xthex = xt.to_hex();
 
     self.submit(xthex)
@@ -648,6 +1642,19 @@ impl InnerClient {
 
     self.submit(xthex)
   }
+
+  /// Sign and submit a call without watching it.  Returns the transaction hash.
+  pub fn submit_nowait(&self, user: &User, call: EncodedCall) -> Result<TxHash, Box<EvalAltResult>> {
+    let (era, additional, _) = self.get_era()?;
+    let extra = Extra::new(era, user.nonce);
+    let xthex = ExtrinsicV4::sign_call(user, extra, additional, call).to_hex();
+    let hash = self
+      .rpc
+      .call_method("author_submitExtrinsic", json!([xthex]))?
+      .ok_or_else(|| format!("Node didn't return a transaction hash"))?;
+    summary::extrinsic_submitted();
+    Ok(hash)
+  }
 }
 
 #[derive(Clone)]
@@ -656,9 +1663,13 @@ pub struct Client {
 }
 
 impl Client {
-  pub fn connect(rpc: RpcHandler, lookup: &TypeLookup) -> Result<Self, Box<EvalAltResult>> {
+  pub fn connect(
+    rpc: RpcHandler,
+    lookup: &TypeLookup,
+    opts: &EngineOptions,
+  ) -> Result<Self, Box<EvalAltResult>> {
     Ok(Self {
-      inner: InnerClient::new(rpc, lookup)?,
+      inner: InnerClient::new(rpc, lookup, opts)?,
     })
   }
 
@@ -666,6 +1677,38 @@ impl Client {
     self.inner.get_transaction_version()
   }
 
+  pub fn runtime_version(&self) -> RMap {
+    self.inner.runtime_version()
+  }
+
+  pub fn genesis_hash(&self) -> BlockHash {
+    self.inner.genesis_hash()
+  }
+
+  pub fn block_number_type(&self) -> Option<TypeRef> {
+    self.inner.block_number_type()
+  }
+
+  pub fn chain_name(&self) -> String {
+    self.inner.chain_name()
+  }
+
+  pub fn properties(&self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.inner.properties()
+  }
+
+  pub fn cache_stats(&self) -> RMap {
+    self.inner.cache_stats()
+  }
+
+  pub fn clear_caches(&self) {
+    self.inner.clear_caches()
+  }
+
+  pub fn pending_extrinsics(&self) -> Vec<String> {
+    self.inner.pending_extrinsics()
+  }
+
   pub fn get_metadata(&self) -> Metadata {
     self.inner.get_metadata()
   }
@@ -674,31 +1717,124 @@ impl Client {
     self.inner.get_signed_extra()
   }
 
+  pub fn decode_call(&self, call: &EncodedCall) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.inner.call_ty.decode(call.encode())
+  }
+
+  /// The bytes a signer signs for `call`, with a breakdown of the payload fields.
+  pub fn signing_payload(
+    &self,
+    call: &EncodedCall,
+    opts: &OfflineSignOptions,
+  ) -> Result<RMap, Box<EvalAltResult>> {
+    let extra = Extra::new(opts.era, opts.nonce);
+    let payload = SignedPayload::new(call, &extra, opts.additional);
+    let raw = payload.raw_payload();
+    let signed = payload.encode();
+    let (spec_version, tx_version, genesis_hash, block_hash, ..) = opts.additional;
+    let to_hex = |data: &[u8]| format!("0x{}", hex::encode(data));
+
+    let mut map = RMap::new();
+    map.insert("bytes".into(), Dynamic::from(to_hex(&signed)));
+    map.insert("payload".into(), Dynamic::from(to_hex(&raw)));
+    // Payloads longer than 256 bytes are hashed (blake2_256) before signing.
+    map.insert("hashed".into(), Dynamic::from(raw.len() > 256));
+    map.insert("call".into(), Dynamic::from(to_hex(&call.encode())));
+    map.insert("call_decoded".into(), self.decode_call(call)?);
+    map.insert("era".into(), Dynamic::from(opts.era));
+    map.insert("nonce".into(), Dynamic::from(opts.nonce as INT));
+    map.insert("tip".into(), Dynamic::from(0 as INT));
+    map.insert("spec_version".into(), Dynamic::from(spec_version as INT));
+    map.insert(
+      "transaction_version".into(),
+      Dynamic::from(tx_version as INT),
+    );
+    map.insert(
+      "genesis_hash".into(),
+      Dynamic::from(to_hex(genesis_hash.as_bytes())),
+    );
+    map.insert(
+      "block_hash".into(),
+      Dynamic::from(to_hex(block_hash.as_bytes())),
+    );
+    Ok(map)
+  }
+
   pub fn get_chain_properties(&self) -> Result<Option<ChainProperties>, Box<EvalAltResult>> {
     self.inner.get_chain_properties()
   }
 
+  pub fn token_units(&self) -> &TokenUnits {
+    &self.inner.token_units
+  }
+
   pub fn get_block_hash(&self, block_number: u64) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
     self.inner.get_block_hash(block_number)
   }
 
+  pub fn block_hash_at(&self, block_number: u64) -> Result<BlockHash, Box<EvalAltResult>> {
+    self.inner.block_hash_at(block_number)
+  }
+
   pub fn get_block(&self, hash: Option<BlockHash>) -> Result<Option<Block>, Box<EvalAltResult>> {
     self.inner.get_block(hash)
   }
 
+  pub fn get_best_block_hash(&self) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
+    self.inner.get_best_block_hash()
+  }
+
+  pub fn get_best_block_number(&self) -> Result<u64, Box<EvalAltResult>> {
+    self.inner.get_best_block_number()
+  }
+
   pub fn get_block_by_number(&self, block_number: u64) -> Result<Option<Block>, Box<EvalAltResult>> {
     self.inner.get_block_by_number(block_number)
   }
 
+  pub fn get_finalized_block_number(&self) -> Result<u64, Box<EvalAltResult>> {
+    self.inner.get_finalized_block_number()
+  }
+
+  pub fn get_finalized_head(&self) -> Result<(BlockHash, u64), Box<EvalAltResult>> {
+    self.inner.get_finalized_head()
+  }
+
+  pub fn get_era(&self) -> Result<(Era, AdditionalSigned, u64), Box<EvalAltResult>> {
+    self.inner.get_era()
+  }
+
+  /// Use mortal eras of `period` blocks (`0` for immortal) for submitted extrinsics.
+  ///
+  /// Returns the current `#{era, block_hash, block_number}`, which can also be used
+  /// as offline signing options.
+  pub fn era_mortal_auto(&mut self, period: INT) -> Result<RMap, Box<EvalAltResult>> {
+    if period < 0 {
+      Err(format!("Invalid era period: {}", period))?;
+    }
+    self.inner.set_era_period(period as u64);
+    let (era, additional, number) = self.get_era()?;
+    let mut map = RMap::new();
+    map.insert("era".into(), Dynamic::from(era));
+    map.insert("block_hash".into(), Dynamic::from(additional.3));
+    map.insert("block_number".into(), Dynamic::from(number as INT));
+    Ok(map)
+  }
+
+  pub fn rpc(&self) -> RpcHandler {
+    self.inner.rpc.clone()
+  }
+
   pub fn get_storage_keys_paged(
     &self,
     prefix: &StorageKey,
     count: u32,
     start_key: Option<&StorageKey>,
+    at_block: Option<BlockHash>,
   ) -> Result<Vec<StorageKey>, Box<EvalAltResult>> {
     self
       .inner
-      .get_storage_keys_paged(prefix, count, start_key)
+      .get_storage_keys_paged(prefix, count, start_key, at_block)
   }
 
   pub fn get_storage_by_key(
@@ -719,6 +1855,66 @@ impl Client {
       .get_storage_by_keys(keys, at_block)
   }
 
+  pub fn get_storage_sizes(
+    &self,
+    keys: &[StorageKey],
+    at_block: Option<BlockHash>,
+  ) -> Result<Vec<Option<u64>>, Box<EvalAltResult>> {
+    self.inner.get_storage_sizes(keys, at_block)
+  }
+
+  pub fn get_read_proof(
+    &self,
+    keys: &[StorageKey],
+    at_block: Option<BlockHash>,
+  ) -> Result<ReadProof, Box<EvalAltResult>> {
+    self.inner.get_read_proof(keys, at_block)
+  }
+
+  pub fn get_child_storage_keys_paged(
+    &self,
+    child_key: &StorageKey,
+    prefix: &StorageKey,
+    count: u32,
+    start_key: Option<&StorageKey>,
+  ) -> Result<Vec<StorageKey>, Box<EvalAltResult>> {
+    self
+      .inner
+      .get_child_storage_keys_paged(child_key, prefix, count, start_key)
+  }
+
+  pub fn get_child_storage_by_key(
+    &self,
+    child_key: &StorageKey,
+    key: StorageKey,
+    at_block: Option<BlockHash>,
+  ) -> Result<Option<StorageData>, Box<EvalAltResult>> {
+    self.inner.get_child_storage_by_key(child_key, key, at_block)
+  }
+
+  /// Read proof for storage keys (hex strings).  `at_block` can be `()` for the best block.
+  pub fn read_proof(
+    &self,
+    keys: Vec<Dynamic>,
+    at_block: Dynamic,
+  ) -> Result<RMap, Box<EvalAltResult>> {
+    let keys = keys
+      .into_iter()
+      .map(|key| {
+        let key = key
+          .into_immutable_string()
+          .map_err(|t| format!("Expected hex storage key, got {}", t))?;
+        Ok(StorageKey(decode_hex(&key)?))
+      })
+      .collect::<Result<Vec<_>, Box<EvalAltResult>>>()?;
+    let at_block = if at_block.is::<()>() {
+      None
+    } else {
+      Some(dynamic_to_hash(at_block)?)
+    };
+    Ok(self.get_read_proof(&keys, at_block)?.to_map())
+  }
+
   pub fn get_storage_value(
     &self,
     prefix: &str,
@@ -759,15 +1955,90 @@ impl Client {
     self.inner.get_events(block)
   }
 
+  /// Call `func(block, events)` for each block in `from..=to`, in order.
+  ///
+  /// `to` is clamped to the best block.  Blocks and events are fetched `concurrency` blocks
+  /// at a time.  Stops early if the callback returns `false`.  Returns the number of
+  /// processed blocks.
+  pub fn scan_blocks(
+    &self,
+    ctx: &NativeCallContext,
+    from: u64,
+    to: u64,
+    concurrency: u64,
+    func: FnPtr,
+  ) -> Result<INT, Box<EvalAltResult>> {
+    let concurrency = concurrency.max(1);
+    let to = to.min(self.get_best_block_number()?);
+    let mut count = 0;
+    let mut start = from;
+    while start <= to {
+      let end = to.min(start.saturating_add(concurrency - 1));
+      for (block, events) in self.inner.get_blocks_with_events(start, end)? {
+        if is_cancelled() {
+          return Ok(count);
+        }
+        let events = EventRecords::to_records(events)?;
+        let res: Dynamic =
+          func.call_raw(ctx, None, [Dynamic::from(block), Dynamic::from(events)])?;
+        count += 1;
+        if res.as_bool() == Ok(false) {
+          return Ok(count);
+        }
+      }
+      if end == u64::MAX {
+        break;
+      }
+      start = end + 1;
+    }
+    Ok(count)
+  }
+
   pub fn get_nonce(&self, account: AccountId) -> Result<Option<u32>, Box<EvalAltResult>> {
     self.inner.get_nonce(account)
   }
 
-  pub fn get_request_block_hash(
+  pub fn get_balance(&self, account: AccountId) -> Result<AccountBalance, Box<EvalAltResult>> {
+    self.inner.get_balance(account)
+  }
+
+  pub fn account_info(&self, account: AccountId) -> Result<AccountInfo, Box<EvalAltResult>> {
+    self.inner.account_info(account)
+  }
+
+  pub fn lookup_index(&self, index: u32) -> Result<Option<AccountId>, Box<EvalAltResult>> {
+    self.inner.lookup_index(index)
+  }
+
+  pub fn rotate_keys(&self) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    self.inner.rotate_keys()
+  }
+
+  pub fn has_session_keys(&self, keys: Vec<u8>) -> Result<bool, Box<EvalAltResult>> {
+    self.inner.has_session_keys(keys)
+  }
+
+  pub fn decode_session_keys(&self, keys: Vec<u8>) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.inner.decode_session_keys(keys)
+  }
+
+  pub fn set_keys_call(
+    &self,
+    keys: Vec<u8>,
+    proof: Vec<u8>,
+  ) -> Result<EncodedCall, Box<EvalAltResult>> {
+    self.inner.set_keys_call(keys, proof)
+  }
+
+  pub fn get_tx_status(
     &self,
     token: RequestToken,
-  ) -> Result<Option<BlockHash>, Box<EvalAltResult>> {
-    self.inner.get_request_block_hash(token)
+  ) -> Result<Option<TransactionStatus>, Box<EvalAltResult>> {
+    self.inner.get_tx_status(token)
+  }
+
+  pub fn close_tx_request(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
+    self.inner.close_tx_request(token)
   }
 
   fn call_results(&self, res: Result<(RequestToken, String), Box<EvalAltResult>>) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
@@ -794,14 +2065,76 @@ impl Client {
     self.call_results(self.inner.submit_unsigned(call))
   }
 
+  pub fn submit_nowait(&self, user: &User, call: EncodedCall) -> Result<TxHash, Box<EvalAltResult>> {
+    self.inner.submit_nowait(user, call)
+  }
+
   pub fn inner(&self) -> Arc<InnerClient> {
     self.inner.clone()
   }
 }
 
+/// What to wait for before a submitted extrinsic's block is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaitFor {
+  InBlock,
+  Finalized,
+}
+
+impl WaitFor {
+  pub fn parse(wait: &str) -> Result<Self, Box<EvalAltResult>> {
+    match wait {
+      "in_block" | "InBlock" => Ok(Self::InBlock),
+      "finalized" | "Finalized" => Ok(Self::Finalized),
+      _ => Err(format!("Unknown wait level '{}', expected 'in_block' or 'finalized'", wait))?,
+    }
+  }
+}
+
+/// Last known state of a submitted extrinsic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxState {
+  Pending,
+  InBlock,
+  Retracted,
+  Finalized,
+  FinalityTimeout,
+  Usurped,
+  Dropped,
+  Invalid,
+}
+
+impl TxState {
+  /// The node doesn't send more updates after these states.
+  fn is_final(&self) -> bool {
+    !matches!(self, Self::Pending | Self::InBlock | Self::Retracted)
+  }
+}
+
+/// Re-sign and re-submit an extrinsic.  Returns the new request token and extrinsic.
+pub type ResubmitFn =
+  Arc<dyn Fn() -> Result<(RequestToken, String), Box<EvalAltResult>> + Send + Sync>;
+
+/// Re-submit extrinsics that were rejected because of their nonce or replaced
+/// by another extrinsic (e.g. because of a nonce race).
+#[derive(Clone)]
+pub struct RetryPolicy {
+  pub max_retries: u32,
+  pub resubmit: ResubmitFn,
+}
+
 pub struct InnerCallResult {
   client: Client,
   token: RequestToken,
+  retry: Option<RetryPolicy>,
+  retries: u32,
+  // Set if the node rejected the extrinsic because of its nonce.
+  nonce_rejected: bool,
+  wait: WaitFor,
+  state: TxState,
+  // Set if the extrinsic's block was ever retracted.
+  retracted: bool,
+  counted: bool,
   hash: Option<BlockHash>,
   xthex: String,
   idx: Option<u32>,
@@ -813,6 +2146,13 @@ impl InnerCallResult {
     Self {
       client: client.clone(),
       token,
+      retry: None,
+      retries: 0,
+      nonce_rejected: false,
+      wait: WaitFor::InBlock,
+      state: TxState::Pending,
+      retracted: false,
+      counted: false,
       hash: None,
       xthex,
       idx: None,
@@ -820,16 +2160,160 @@ impl InnerCallResult {
     }
   }
 
-  fn get_block_hash(&mut self) -> Result<(), Box<EvalAltResult>> {
-    if self.hash.is_some() {
-      return Ok(());
+  fn is_done(&self, wait: WaitFor) -> bool {
+    match wait {
+      WaitFor::InBlock => self.state == TxState::InBlock || self.state.is_final(),
+      WaitFor::Finalized => self.state.is_final(),
     }
+  }
 
-    self.hash = self.client.get_request_block_hash(self.token)?;
+  fn set_hash(&mut self, hash: Option<BlockHash>) {
+    if self.hash != hash {
+      // The extrinsic moved to another block.
+      self.idx = None;
+      self.events = None;
+    }
+    self.hash = hash;
+  }
+
+  /// Process status updates until the extrinsic reaches `wait`.
+  ///
+  /// The subscription is kept open while in a block, so it is possible to wait
+  /// for finalization later.
+  fn wait_for(&mut self, wait: WaitFor) -> Result<(), Box<EvalAltResult>> {
+    while !self.is_done(wait) {
+      let status = match self.client.get_tx_status(self.token) {
+        Ok(status) => status,
+        // Let the retry policy re-submit with a fresh nonce.
+        Err(err) if self.retry.is_some() && is_nonce_error(&err) => {
+          log::warn!("Transaction rejected: {}", error_message(&err));
+          self.nonce_rejected = true;
+          Some(TransactionStatus::Invalid)
+        }
+        Err(err) => return Err(err),
+      };
+      match status {
+        Some(TransactionStatus::InBlock(hash)) => {
+          self.state = TxState::InBlock;
+          self.set_hash(Some(hash));
+        }
+        Some(TransactionStatus::Finalized(hash)) => {
+          self.state = TxState::Finalized;
+          self.set_hash(Some(hash));
+        }
+        Some(TransactionStatus::FinalityTimeout(hash)) => {
+          log::warn!("Transaction finality timeout: {:?}", hash);
+          self.state = TxState::FinalityTimeout;
+          self.set_hash(Some(hash));
+        }
+        Some(TransactionStatus::Retracted(hash)) => {
+          log::error!("Transaction retracted: {:?}", hash);
+          self.state = TxState::Retracted;
+          self.retracted = true;
+          self.set_hash(None);
+        }
+        Some(TransactionStatus::Future) => {
+          log::warn!("Transaction in future (maybe nonce issue)");
+        }
+        Some(TransactionStatus::Ready) => {
+          log::debug!("Transaction ready.");
+        }
+        Some(TransactionStatus::Broadcast(nodes)) => {
+          log::debug!("Transaction broadcast: {:?}", nodes);
+        }
+        Some(TransactionStatus::Usurped(tx_hash)) => {
+          log::error!(
+            "Transaction was replaced by another in the pool: {:?}",
+            tx_hash
+          );
+          self.state = TxState::Usurped;
+          self.set_hash(None);
+        }
+        Some(TransactionStatus::Dropped) => {
+          log::error!("Transaction dropped.");
+          self.state = TxState::Dropped;
+          self.set_hash(None);
+        }
+        Some(TransactionStatus::Invalid) => {
+          log::error!("Transaction invalid.");
+          self.state = TxState::Invalid;
+          self.set_hash(None);
+        }
+        None => {
+          // The subscription ended without a final state.
+          if self.state != TxState::InBlock {
+            self.state = TxState::Dropped;
+          } else {
+            self.state = TxState::FinalityTimeout;
+          }
+        }
+      }
+      if self.state.is_final() {
+        self.client.close_tx_request(self.token)?;
+        self.try_resubmit()?;
+      }
+    }
+    Ok(())
+  }
 
+  /// Re-submit the extrinsic if it was rejected because of its nonce or usurped,
+  /// and the retry policy allows it.  Other invalid or dropped extrinsics would fail again.
+  fn try_resubmit(&mut self) -> Result<(), Box<EvalAltResult>> {
+    let retry = match &self.retry {
+      Some(retry) if self.retries < retry.max_retries => retry.clone(),
+      _ => return Ok(()),
+    };
+    let nonce_rejected = std::mem::take(&mut self.nonce_rejected);
+    match self.state {
+      TxState::Usurped => (),
+      TxState::Invalid if nonce_rejected => (),
+      _ => return Ok(()),
+    }
+    self.retries += 1;
+    log::warn!(
+      "Transaction {:?}, re-submitting with a new nonce ({}/{})",
+      self.state,
+      self.retries,
+      retry.max_retries
+    );
+    let (token, xthex) = (retry.resubmit)()?;
+    self.token = token;
+    self.xthex = xthex;
+    self.state = TxState::Pending;
+    self.set_hash(None);
     Ok(())
   }
 
+  pub fn set_retry(&mut self, retry: RetryPolicy) {
+    self.retry = Some(retry);
+  }
+
+  fn retries(&mut self) -> INT {
+    self.retries as INT
+  }
+
+  fn get_block_hash(&mut self) -> Result<(), Box<EvalAltResult>> {
+    self.wait_for(self.wait)
+  }
+
+  pub fn set_wait(&mut self, wait: WaitFor) {
+    self.wait = wait;
+  }
+
+  pub fn wait_finalized(&mut self) -> Result<bool, Box<EvalAltResult>> {
+    self.wait_for(WaitFor::Finalized)?;
+    Ok(self.state == TxState::Finalized)
+  }
+
+  pub fn status(&mut self) -> Result<String, Box<EvalAltResult>> {
+    self.get_block_hash()?;
+    Ok(format!("{:?}", self.state))
+  }
+
+  pub fn was_retracted(&mut self) -> bool {
+    self.retracted
+  }
+
   pub fn is_in_block(&mut self) -> Result<bool, Box<EvalAltResult>> {
     self.get_block_hash()?;
     Ok(self.hash.is_some())
@@ -861,6 +2345,12 @@ impl InnerCallResult {
       }
       None => EventRecords::default(),
     };
+    // Only count the first time the result is loaded (not after moving to another block).
+    if !self.counted {
+      self.counted = true;
+      let success = events.0.iter().any(|ev| ev.name == "System.ExtrinsicSuccess");
+      summary::extrinsic_result(success);
+    }
 
     self.events = Some(events);
     Ok(())
@@ -904,6 +2394,29 @@ impl InnerCallResult {
     Ok(events.len() > 0)
   }
 
+  /// Throw a `Dispatch` error if the extrinsic failed.
+  pub fn ensure_success(&mut self) -> Result<(), Box<EvalAltResult>> {
+    let failed = self.events_filtered("System.ExtrinsicFailed")?;
+    let event = match failed.first().and_then(|ev| ev.read_lock::<EventRecord>()) {
+      Some(event) => event.clone(),
+      None => return Ok(()),
+    };
+    // The dispatch error is the first event argument.
+    let error = if let Some(args) = event.args.read_lock::<RMap>() {
+      args.get("dispatch_error").cloned()
+    } else if let Some(args) = event.args.read_lock::<Array>() {
+      args.first().cloned()
+    } else {
+      None
+    }
+    .unwrap_or_else(|| event.args.clone());
+    let mut err = ScriptError::new(ErrorKind::Dispatch, format!("Extrinsic failed: {}", error));
+    if let Some(module) = error.read_lock::<RMap>().and_then(|e| e.get("Module").cloned()) {
+      err = err.with_module_error(module);
+    }
+    Err(err)?
+  }
+
   pub fn block(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
     self.get_block_hash()?;
     match self.hash {
@@ -923,7 +2436,7 @@ impl InnerCallResult {
     let _ = self.get_block_hash();
     match &self.hash {
       Some(hash) => {
-        format!("InBlock: {:?}", hash)
+        format!("{:?}: {:?}", self.state, hash)
       }
       None => {
         format!("NoBlock")
@@ -932,6 +2445,15 @@ impl InnerCallResult {
   }
 }
 
+impl Drop for InnerCallResult {
+  fn drop(&mut self) {
+    // Stop watching extrinsics that haven't reached a final state.
+    if !self.state.is_final() {
+      let _ = self.client.close_tx_request(self.token);
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct ExtrinsicCallResult(Arc<RwLock<InnerCallResult>>);
 
@@ -944,6 +2466,31 @@ impl ExtrinsicCallResult {
     self.0.write().unwrap().is_in_block()
   }
 
+  /// Set what to wait for before the block and events are loaded.
+  pub fn set_wait(&mut self, wait: WaitFor) {
+    self.0.write().unwrap().set_wait(wait)
+  }
+
+  pub fn wait_finalized(&mut self) -> Result<bool, Box<EvalAltResult>> {
+    self.0.write().unwrap().wait_finalized()
+  }
+
+  pub fn set_retry(&mut self, retry: RetryPolicy) {
+    self.0.write().unwrap().set_retry(retry)
+  }
+
+  pub fn retries(&mut self) -> INT {
+    self.0.write().unwrap().retries()
+  }
+
+  pub fn status(&mut self) -> Result<String, Box<EvalAltResult>> {
+    self.0.write().unwrap().status()
+  }
+
+  pub fn was_retracted(&mut self) -> bool {
+    self.0.write().unwrap().was_retracted()
+  }
+
   pub fn block_hash(&mut self) -> Result<String, Box<EvalAltResult>> {
     self.0.write().unwrap().block_hash()
   }
@@ -964,6 +2511,10 @@ impl ExtrinsicCallResult {
     self.0.write().unwrap().is_success()
   }
 
+  pub fn ensure_success(&mut self) -> Result<(), Box<EvalAltResult>> {
+    self.0.write().unwrap().ensure_success()
+  }
+
   pub fn block(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
     self.0.write().unwrap().block()
   }
@@ -977,39 +2528,177 @@ impl ExtrinsicCallResult {
   }
 }
 
-pub fn init_engine(
-  rpc: &RpcHandler,
-  engine: &mut Engine,
-  lookup: &TypeLookup,
-) -> Result<Client, Box<EvalAltResult>> {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<Client>("Client")
     .register_result_fn("get_block_hash", |client: &mut Client, num: i64| {
-      match client.get_block_hash(num as u64)? {
+      match client.get_block_hash(block_number_from_int(num)?)? {
         Some(hash) => Ok(Dynamic::from(hash)),
         None => Ok(Dynamic::UNIT),
       }
     })
     .register_result_fn("get_block", |client: &mut Client, hash: Dynamic| {
-      match client.get_block(hash.try_cast::<BlockHash>())? {
+      // Also accept a block number.
+      let block = match hash.as_int() {
+        Ok(num) => client.get_block_by_number(block_number_from_int(num)?)?,
+        Err(_) => client.get_block(hash.try_cast::<BlockHash>())?,
+      };
+      match block {
         Some(block) => Ok(Dynamic::from(block)),
         None => Ok(Dynamic::UNIT),
       }
     })
     .register_result_fn("get_block_by_number", |client: &mut Client, num: i64| {
-      match client.get_block_by_number(num as u64)? {
+      match client.get_block_by_number(block_number_from_int(num)?)? {
         Some(block) => Ok(Dynamic::from(block)),
         None => Ok(Dynamic::UNIT),
       }
     })
+    .register_result_fn("get_finalized_block_number", |client: &mut Client| {
+      Ok(client.get_finalized_block_number()? as INT)
+    })
+    .register_result_fn(
+      "scan_blocks",
+      |ctx: NativeCallContext, client: &mut Client, from: INT, to: INT, func: FnPtr| {
+        let from = block_number_from_int(from)?;
+        let to = block_number_from_int(to)?;
+        client.scan_blocks(&ctx, from, to, DEFAULT_SCAN_CONCURRENCY, func)
+      },
+    )
+    .register_result_fn(
+      "scan_blocks",
+      |ctx: NativeCallContext,
+       client: &mut Client,
+       from: INT,
+       to: INT,
+       opts: RMap,
+       func: FnPtr| {
+        let concurrency = match opts.get("concurrency") {
+          Some(val) => val
+            .as_int()
+            .map_err(|_| format!("Expected integer for concurrency"))? as u64,
+          None => DEFAULT_SCAN_CONCURRENCY,
+        };
+        client.scan_blocks(
+          &ctx,
+          block_number_from_int(from)?,
+          block_number_from_int(to)?,
+          concurrency,
+          func,
+        )
+      },
+    )
+    .register_result_fn("balance", |client: &mut Client, account: Dynamic| {
+      Ok(client.get_balance(dynamic_to_account(account)?)?.to_map())
+    })
+    .register_result_fn("account_info", |client: &mut Client, account: Dynamic| {
+      client.account_info(dynamic_to_account(account)?)
+    })
+    .register_result_fn("to_plancks", |client: &mut Client, value: INT| {
+      client.token_units().to_plancks(Decimal::from(value))
+    })
+    .register_result_fn("to_plancks", |client: &mut Client, value: Decimal| {
+      client.token_units().to_plancks(value)
+    })
+    .register_result_fn("from_plancks", |client: &mut Client, plancks: INT| {
+      client.token_units().from_plancks(Decimal::from(plancks))
+    })
+    .register_result_fn("from_plancks", |client: &mut Client, plancks: Decimal| {
+      client.token_units().from_plancks(plancks)
+    })
+    .register_fn("format_balance", |client: &mut Client, value: INT| {
+      client.token_units().format_balance(Decimal::from(value))
+    })
+    .register_fn("format_balance", |client: &mut Client, value: Decimal| {
+      client.token_units().format_balance(value)
+    })
+    .register_result_fn("read_proof", |client: &mut Client, keys: Vec<Dynamic>| {
+      client.read_proof(keys, Dynamic::UNIT)
+    })
+    .register_result_fn(
+      "read_proof",
+      |client: &mut Client, keys: Vec<Dynamic>, at_block: Dynamic| {
+        client.read_proof(keys, at_block)
+      },
+    )
+    .register_result_fn("lookup_index", |client: &mut Client, index: INT| {
+      let index = u32::try_from(index).map_err(|_| format!("Invalid account index: {}", index))?;
+      Ok(client.lookup_index(index)?.map(Dynamic::from).unwrap_or(Dynamic::UNIT))
+    })
+    .register_get("token_symbol", |client: &mut Client| {
+      client.token_units().symbol.clone()
+    })
+    .register_get("token_decimals", |client: &mut Client| {
+      client.token_units().decimals as INT
+    })
     .register_fn("get_transaction_version", |client: &mut Client| client.get_transaction_version())
+    .register_get("runtime_version", |client: &mut Client| client.runtime_version())
+    .register_get("genesis_hash", |client: &mut Client| client.genesis_hash())
+    .register_result_fn("era_mortal_auto", Client::era_mortal_auto)
+    .register_get("era_period", |client: &mut Client| client.inner.era_period() as INT)
+    .register_get("chain_name", |client: &mut Client| client.chain_name())
+    .register_get_result("properties", |client: &mut Client| client.properties())
+    .register_fn("cache_stats", |client: &mut Client| client.cache_stats())
+    .register_fn("clear_caches", |client: &mut Client| client.clear_caches())
+    .register_fn("pending_extrinsics", |client: &mut Client| {
+      client
+        .pending_extrinsics()
+        .into_iter()
+        .map(Dynamic::from)
+        .collect::<Vec<Dynamic>>()
+    })
+    .register_result_fn("rotate_keys", |client: &mut Client| {
+      Ok(format!("0x{}", hex::encode(client.rotate_keys()?)))
+    })
+    .register_result_fn("has_session_keys", |client: &mut Client, keys: &str| {
+      client.has_session_keys(decode_hex(keys)?)
+    })
+    .register_result_fn("decode_session_keys", |client: &mut Client, keys: &str| {
+      client.decode_session_keys(decode_hex(keys)?)
+    })
+    .register_result_fn("set_keys_call", |client: &mut Client, keys: &str| {
+      client.set_keys_call(decode_hex(keys)?, vec![])
+    })
+    .register_result_fn("set_keys_call", |client: &mut Client, keys: &str, proof: &str| {
+      client.set_keys_call(decode_hex(keys)?, decode_hex(proof)?)
+    })
     .register_result_fn("submit_unsigned", Client::submit_unsigned)
-    .register_type_with_name::<BlockHash>("BlockHash")
-    .register_fn("to_string", |hash: &mut BlockHash| hash.to_string())
+    .register_result_fn("submit", |client: &mut Client, xthex: &str| {
+      client.submit(xthex.into())
+    })
+    .register_type_with_name::<BlockHeader>("BlockHeader")
+    .register_get("parent_hash", BlockHeader::parent_hash)
+    .register_get("number", BlockHeader::number)
+    .register_get("state_root", BlockHeader::state_root)
+    .register_get("extrinsics_root", BlockHeader::extrinsics_root)
+    .register_get("digest_logs", BlockHeader::digest_logs)
+    .register_fn("to_string", BlockHeader::to_string)
+    .register_type_with_name::<AccountInfo>("AccountInfo")
+    .register_get("nonce", AccountInfo::nonce)
+    .register_get("consumers", AccountInfo::consumers)
+    .register_get("providers", AccountInfo::providers)
+    .register_get("sufficients", AccountInfo::sufficients)
+    .register_get("data", AccountInfo::data)
+    .register_fn("to_map", AccountInfo::to_map)
+    .register_fn("to_string", AccountInfo::to_string)
+    .register_type_with_name::<AccountBalance>("AccountBalance")
+    .register_get("free", AccountBalance::free)
+    .register_get("reserved", AccountBalance::reserved)
+    .register_get("frozen", AccountBalance::frozen)
+    .register_fn("to_map", |balance: &mut AccountBalance| balance.to_map())
+    .register_fn("to_string", AccountBalance::to_string)
     .register_type_with_name::<Block>("Block")
     .register_fn("extrinsics_filtered", Block::extrinsics_filtered)
+    .register_get("inherents", Block::inherents)
+    .register_get("transactions", Block::transactions)
+    .register_get("extrinsics", Block::decoded_extrinsics)
+    .register_get("extrinsics_hex", Block::extrinsics_hex)
     .register_get("parent", Block::parent)
+    .register_get("parent_hash", Block::parent)
     .register_get("block_number", Block::block_number)
+    .register_get("number", Block::block_number)
+    .register_get("state_root", Block::state_root)
+    .register_get("header", Block::get_header)
     .register_fn("to_string", Block::to_string)
     .register_type_with_name::<EventRecords>("EventRecords")
     .register_fn("to_string", EventRecords::to_string)
@@ -1024,14 +2713,30 @@ pub fn init_engine(
     .register_get_result("block_hash", ExtrinsicCallResult::block_hash)
     .register_get_result("result", ExtrinsicCallResult::result)
     .register_get_result("is_success", ExtrinsicCallResult::is_success)
+    .register_result_fn("ensure_success", ExtrinsicCallResult::ensure_success)
     .register_get_result("is_in_block", ExtrinsicCallResult::is_in_block)
+    .register_result_fn("wait_finalized", ExtrinsicCallResult::wait_finalized)
+    .register_get_result("status", ExtrinsicCallResult::status)
+    .register_get("was_retracted", ExtrinsicCallResult::was_retracted)
+    .register_get("retries", ExtrinsicCallResult::retries)
     .register_get("xthex", ExtrinsicCallResult::xthex)
-    .register_fn("to_string", ExtrinsicCallResult::to_string);
+    .register_fn("to_string", ExtrinsicCallResult::to_string)
+    .register_result_fn("connect_client", |rpc: &mut RpcManager, url: &str| {
+      connect_client(rpc, url)
+    });
+}
 
-  let client = Client::connect(rpc.clone(), lookup)?;
+pub fn init_engine(
+  rpc: &RpcHandler,
+  engine: &mut Engine,
+  lookup: &TypeLookup,
+  opts: &EngineOptions,
+) -> Result<Client, Box<EvalAltResult>> {
+  register(engine);
 
-  // Get Chain properties.
-  let chain_props = client.get_chain_properties()?;
+  let client = Client::connect(rpc.clone(), lookup, opts)?;
+
+  let chain_props = init_chain_types(&client, lookup)?;
   // Set default ss58 format.
   let ss58_format = chain_props
     .as_ref()
@@ -1040,48 +2745,66 @@ pub fn init_engine(
     set_default_ss58_version(ss58_format);
   }
 
-  // Get the `tokenDecimals` value from the chain properties.
-  let token_decimals = chain_props.as_ref().map(|p| p.token_decimals).unwrap_or(0);
-  let balance_scale = 10u128.pow(token_decimals);
-  log::info!(
-    "token_deciamls: {:?}, balance_scale={:?}",
-    token_decimals,
-    balance_scale
-  );
-  lookup.custom_encode("Balance", TypeId::of::<INT>(), move |value, data| {
-    let mut val = value.cast::<INT>() as u128;
-    val *= balance_scale;
-    if data.is_compact() {
-      data.encode(Compact::<u128>(val));
-    } else {
-      data.encode(val);
+  Ok(client)
+}
+
+/// Connect a client to another chain.  Each chain gets it's own types.
+pub fn connect_client(rpc_manager: &RpcManager, url: &str) -> Result<Client, Box<EvalAltResult>> {
+  let opts = rpc_manager.engine_opts();
+  let rpc = rpc_manager.get_client(url)?;
+  let lookup = crate::types::new_lookup(opts)?;
+  let client = Client::connect(rpc, &lookup, opts)?;
+  init_chain_types(&client, &lookup)?;
+  Ok(client)
+}
+
+/// Setup chain specific type encodings.
+fn init_chain_types(
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<Option<ChainProperties>, Box<EvalAltResult>> {
+  // Get Chain properties.
+  let chain_props = client.get_chain_properties()?;
+
+  // Balances are in token units, scaled by the chain's `tokenDecimals`.
+  let token_decimals = client.token_units().decimals;
+  log::info!("token_decimals: {:?}", token_decimals);
+  lookup.set_decimals("Balance", token_decimals)?;
+
+  // Resolve account indices to accounts for display.
+  // The variants are from the chain's types (the index type can be `()`).
+  let variants = match lookup.resolve("MultiAddress").enum_variants() {
+    Some(variants) => variants,
+    None => {
+      log::warn!("MultiAddress isn't an enum, account indices won't be resolved.");
+      return Ok(chain_props);
     }
-    Ok(())
-  })?;
-  lookup.custom_encode("Balance", TypeId::of::<Decimal>(), move |value, data| {
-    let mut dec = value.cast::<Decimal>();
-    dec *= Decimal::from(balance_scale);
-    let val = dec
-      .to_u128()
-      .ok_or_else(|| format!("Expected unsigned integer"))?;
-    if data.is_compact() {
-      data.encode(Compact::<u128>(val));
-    } else {
-      data.encode(val);
+  };
+  // Weak ref, the client owns the types.
+  let inner = Arc::downgrade(&client.inner);
+  lookup.custom_decode("MultiAddress", move |mut input| {
+    let idx = input.read_byte()?;
+    let variant = variants
+      .get_by_idx(idx)
+      .ok_or("Error decoding MultiAddress, invalid variant.")?;
+    let val = match variant.type_ref() {
+      Some(type_ref) => type_ref.decode_value(&mut input, false)?,
+      None => Dynamic::UNIT,
+    };
+    let mut map = RMap::new();
+    if variant.name() == "Index" {
+      // Only use already resolved indices, decoding doesn't make RPC calls.
+      let acc = val
+        .as_int()
+        .ok()
+        .and_then(|index| inner.upgrade()?.cached_index(index as u32));
+      if let Some(acc) = acc {
+        map.insert("account".into(), Dynamic::from(acc));
+      }
     }
-    Ok(())
-  })?;
-  lookup.custom_decode("Balance", move |mut input| {
-    let mut val = Decimal::from(u128::decode(&mut input)?);
-    val /= Decimal::from(balance_scale);
-    Ok(Dynamic::from_decimal(val))
-  })?;
-  lookup.custom_decode("Compact<Balance>", move |mut input| {
-    let num = Compact::<u128>::decode(&mut input)?;
-    let mut val = Decimal::from(num.0);
-    val /= Decimal::from(balance_scale);
-    Ok(Dynamic::from_decimal(val))
+    map.insert(variant.name().into(), val);
+    Ok(Dynamic::from(map))
   })?;
 
-  Ok(client)
+  Ok(chain_props)
 }
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use parity_scale_codec::Decode;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use crate::client::{block_number_from_int, BlockHash, Client, EventRecord};
+
+/// Pallets that emit XCM message events.
+const XCM_PALLETS: &[&str] = &[
+  "XcmpQueue",
+  "DmpQueue",
+  "UmpQueue",
+  "Ump",
+  "ParachainSystem",
+  "PolkadotXcm",
+  "XcmPallet",
+  "MessageQueue",
+];
+
+/// Field names used for the message hash.
+const HASH_FIELDS: &[&str] = &["message_hash", "message_id"];
+
+/// Convert a decoded `[u8; 32]` into a hex string.
+fn hash_to_hex(val: &Dynamic) -> Option<String> {
+  let arr = val.read_lock::<Vec<Dynamic>>()?;
+  if arr.len() != 32 {
+    return None;
+  }
+  let bytes = arr
+    .iter()
+    .map(|b| b.as_int().ok().map(|b| b as u8))
+    .collect::<Option<Vec<u8>>>()?;
+  Some(format!("0x{}", hex::encode(bytes)))
+}
+
+/// Find the message hash in the event args.
+///
+/// Named hash fields are checked first, then the first 32 byte value.
+fn find_message_hash(args: &Dynamic) -> Option<String> {
+  if let Some(hash) = hash_to_hex(args) {
+    return Some(hash);
+  }
+  if let Some(map) = args.read_lock::<RMap>() {
+    for field in HASH_FIELDS {
+      if let Some(hash) = map.get(*field).and_then(find_message_hash) {
+        return Some(hash);
+      }
+    }
+    return map.values().find_map(find_message_hash);
+  }
+  if let Some(arr) = args.read_lock::<Vec<Dynamic>>() {
+    return arr.iter().find_map(find_message_hash);
+  }
+  None
+}
+
+fn is_xcm_event(event: &EventRecord) -> bool {
+  match event.name.split_once('.') {
+    Some((pallet, _)) => XCM_PALLETS.contains(&pallet),
+    None => false,
+  }
+}
+
+#[derive(Clone, Debug)]
+struct Sighting {
+  chain: String,
+  block: u64,
+  timestamp: u64,
+  event: String,
+}
+
+#[derive(Default)]
+struct InnerCorrelator {
+  chains: HashMap<String, Client>,
+  // First sighting of each message.
+  pending: HashMap<String, Sighting>,
+  deliveries: Vec<Dynamic>,
+}
+
+impl InnerCorrelator {
+  fn block_timestamp(client: &Client, hash: BlockHash) -> Result<u64, Box<EvalAltResult>> {
+    Ok(match client.get_storage_value("Timestamp", "Now", Some(hash))? {
+      Some(data) => u64::decode(&mut &data.0[..]).map_err(|e| e.to_string())?,
+      None => 0,
+    })
+  }
+
+  fn process_block(&mut self, chain: &str, block: u64) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let client = self
+      .chains
+      .get(chain)
+      .cloned()
+      .ok_or_else(|| format!("Unknown chain: {}", chain))?;
+    let hash = match client.get_block_hash(block)? {
+      Some(hash) => hash,
+      None => Err(format!("Block {} not found on chain {}", block, chain))?,
+    };
+    let events = client
+      .get_events(Some(hash))?
+      .try_cast::<Vec<Dynamic>>()
+      .ok_or("Expected Array")?
+      .into_iter()
+      .map(EventRecord::from_dynamic)
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut timestamp = None;
+    let mut delivered = Vec::new();
+    for event in events.iter().filter(|ev| is_xcm_event(ev)) {
+      let msg_hash = match find_message_hash(&event.args) {
+        Some(msg_hash) => msg_hash,
+        None => continue,
+      };
+      let timestamp = match timestamp {
+        Some(ts) => ts,
+        None => *timestamp.insert(Self::block_timestamp(&client, hash)?),
+      };
+      let sighting = Sighting {
+        chain: chain.into(),
+        block,
+        timestamp,
+        event: event.name.clone(),
+      };
+      match self.pending.get(&msg_hash) {
+        Some(sent) if sent.chain != chain => {
+          let sent = self.pending.remove(&msg_hash).unwrap();
+          let delivery = Dynamic::from(Self::delivery(&msg_hash, &sent, &sighting));
+          self.deliveries.push(delivery.clone());
+          delivered.push(delivery);
+        }
+        Some(_) => (),
+        None => {
+          self.pending.insert(msg_hash, sighting);
+        }
+      }
+    }
+    Ok(delivered)
+  }
+
+  fn delivery(msg_hash: &str, sent: &Sighting, recv: &Sighting) -> RMap {
+    let mut map = RMap::new();
+    map.insert("hash".into(), Dynamic::from(msg_hash.to_string()));
+    map.insert("from".into(), Dynamic::from(sent.chain.clone()));
+    map.insert("from_block".into(), Dynamic::from(sent.block as INT));
+    map.insert("from_event".into(), Dynamic::from(sent.event.clone()));
+    map.insert("to".into(), Dynamic::from(recv.chain.clone()));
+    map.insert("to_block".into(), Dynamic::from(recv.block as INT));
+    map.insert("to_event".into(), Dynamic::from(recv.event.clone()));
+    let latency = recv.timestamp.saturating_sub(sent.timestamp);
+    map.insert("latency_ms".into(), Dynamic::from(latency as INT));
+    map
+  }
+}
+
+/// Match XCM message events across chains by message hash.
+#[derive(Clone, Default)]
+pub struct XcmCorrelator(Arc<RwLock<InnerCorrelator>>);
+
+impl XcmCorrelator {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn add_chain(&mut self, name: &str, client: Client) {
+    self.0.write().unwrap().chains.insert(name.into(), client);
+  }
+
+  /// Process the XCM events of a block.  Returns the newly delivered messages.
+  pub fn process_block(&mut self, chain: &str, block: INT) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let block = block_number_from_int(block)?;
+    self.0.write().unwrap().process_block(chain, block)
+  }
+
+  fn deliveries(&mut self) -> Vec<Dynamic> {
+    self.0.read().unwrap().deliveries.clone()
+  }
+
+  fn pending(&mut self) -> RMap {
+    self
+      .0
+      .read()
+      .unwrap()
+      .pending
+      .iter()
+      .map(|(hash, sent)| {
+        let mut map = RMap::new();
+        map.insert("chain".into(), Dynamic::from(sent.chain.clone()));
+        map.insert("block".into(), Dynamic::from(sent.block as INT));
+        map.insert("event".into(), Dynamic::from(sent.event.clone()));
+        (hash.into(), Dynamic::from(map))
+      })
+      .collect()
+  }
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<XcmCorrelator>("XcmCorrelator")
+    .register_fn("new_xcm_correlator", XcmCorrelator::new)
+    .register_fn("add_chain", XcmCorrelator::add_chain)
+    .register_result_fn("process_block", XcmCorrelator::process_block)
+    .register_get("deliveries", XcmCorrelator::deliveries)
+    .register_get("pending", XcmCorrelator::pending);
+}
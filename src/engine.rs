@@ -1,22 +1,102 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
 
 use std::path::PathBuf;
 use std::{fs::File, io::Read};
 
-pub use rhai::{AST, Dynamic, Engine, EvalAltResult, Position, ParseError, Scope};
+use serde_json::{json, Value};
+
+use rhai::plugin::NativeCallContext;
+use rhai::{Array, FnPtr, ImmutableString, Map as RMap};
+
+pub use rhai::{AST, Dynamic, Engine, EvalAltResult, FuncArgs, Position, ParseError, Scope, INT};
 
 #[cfg(not(feature = "no_optimize"))]
 use rhai::OptimizationLevel;
 
-use crate::{client, metadata, plugins, rpc, storage, types, users};
+use crate::{addrbook, client, correlator, error, journal, metadata, payload, plugins, pretty, profile, rpc, ss58, storage, submitter, types, users, watcher};
+use crate::metadata::Metadata;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Check the script timeout every this many operations.
+const TIMEOUT_CHECK_OPS: u64 = 1024;
+
+/// Ask all running scripts to stop.
+///
+/// Scripts check the flag between operations and stop with a `Cancelled` error.
+/// The flag is cleared when a new run starts (`reset_cancelled`).
+pub fn cancel_scripts() {
+  CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Clear the cancel flag, so a new run isn't cancelled by an earlier `cancel_scripts`.
+pub fn reset_cancelled() {
+  CANCELLED.store(false, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+  CANCELLED.load(Ordering::SeqCst)
+}
+
+thread_local! {
+  // The engine and script running on this thread.
+  static CURRENT_SCRIPT: RefCell<Option<(SharedEngine, AST)>> = RefCell::new(None);
+}
+
+/// The engine and script running on the current thread.
+///
+/// Background tasks use it to call the script's closures.
+pub fn current_script() -> Option<(SharedEngine, AST)> {
+  CURRENT_SCRIPT.with(|s| s.borrow().clone())
+}
+
+fn with_current_script<R>(engine: &SharedEngine, ast: &AST, func: impl FnOnce() -> R) -> R {
+  let prev = CURRENT_SCRIPT.with(|s| s.replace(Some((engine.clone(), ast.clone()))));
+  let res = func();
+  CURRENT_SCRIPT.with(|s| *s.borrow_mut() = prev);
+  res
+}
 
 #[derive(Debug, Clone)]
 pub struct EngineOptions {
   pub url: String,
+  /// Comma-separated schema files or `https://` URLs.
   pub substrate_types: String,
   pub custom_types: String,
+  /// Directory for schemas downloaded from URLs.
+  pub schema_cache_dir: String,
+  pub codec_trace: bool,
+  pub tls_ca_file: Option<String>,
+  pub tls_cert_file: Option<String>,
+  pub tls_key_file: Option<String>,
+  pub tls_insecure: bool,
+  pub rpc_pool_size: usize,
+  pub rpc_timeout: u64,
+  /// WebSocket limits in bytes, see `rpc::WsLimits`.
+  pub ws_max_message_size: usize,
+  pub ws_max_fragment_size: usize,
+  pub ws_fragment_size: usize,
+  pub block_cache_size: usize,
+  pub event_cache_size: usize,
+  pub task_max_operations: u64,
+  /// Maximum number of operations per script run (0 for no limit).
+  pub max_operations: u64,
+  /// Maximum function call depth (0 for Rhai's default).
+  pub max_call_levels: usize,
+  /// Maximum length of strings, arrays and object maps (0 for no limit).
+  pub max_string_size: usize,
+  pub max_array_size: usize,
+  pub max_map_size: usize,
+  /// Seconds a script run can take (0 for no limit).
+  pub script_timeout: u64,
+  /// Plugins to load at runtime: Rhai module files or native libraries.
+  pub plugins: Vec<String>,
+  pub addrbook_file: String,
   pub args: Vec<String>,
 }
 
@@ -102,10 +182,62 @@ pub fn eprint_error(input: &str, mut err: EvalAltResult) {
 
   if pos.is_none() {
     // No position
-    eprintln!("{}", err);
+    eprintln!("{}", error::error_message(&err));
   } else {
     // Specific position
-    eprint_line(&lines, pos, &err.to_string())
+    eprint_line(&lines, pos, &error::error_message(&err))
+  }
+}
+
+/// Convert a script value into JSON.
+///
+/// Decimals are converted to strings to keep their precision.
+pub fn dynamic_to_json(val: &Dynamic) -> Value {
+  if val.is::<()>() {
+    Value::Null
+  } else if let Ok(b) = val.as_bool() {
+    Value::Bool(b)
+  } else if let Ok(num) = val.as_int() {
+    json!(num)
+  } else if let Ok(dec) = val.as_decimal() {
+    Value::String(dec.to_string())
+  } else if val.is::<ImmutableString>() {
+    Value::String(val.clone().cast::<ImmutableString>().to_string())
+  } else if val.is::<Array>() {
+    let arr = val.read_lock::<Array>().unwrap();
+    Value::Array(arr.iter().map(dynamic_to_json).collect())
+  } else if val.is::<RMap>() {
+    let map = val.read_lock::<RMap>().unwrap();
+    Value::Object(
+      map
+        .iter()
+        .map(|(k, v)| (k.to_string(), dynamic_to_json(v)))
+        .collect(),
+    )
+  } else if val.is::<Vec<u8>>() {
+    let data = val.read_lock::<Vec<u8>>().unwrap();
+    Value::String(format!("0x{}", hex::encode(&*data)))
+  } else if val.is::<client::BlockHash>() {
+    Value::String(format!("{:?}", val.clone().cast::<client::BlockHash>()))
+  } else if val.is::<sp_core::H160>() {
+    Value::String(format!("{:?}", val.clone().cast::<sp_core::H160>()))
+  } else if val.is::<sp_core::U256>() {
+    Value::String(val.clone().cast::<sp_core::U256>().to_string())
+  } else if val.is::<users::AccountId>() {
+    Value::String(val.clone().cast::<users::AccountId>().to_string())
+  } else if val.is::<users::SharedUser>() {
+    Value::String(val.clone().cast::<users::SharedUser>().acc().to_string())
+  } else if let Some(event) = val.read_lock::<client::EventRecord>() {
+    json!({
+      "name": event.name,
+      "phase": event.phase,
+      "args": dynamic_to_json(&event.args),
+      "topics": event.topics,
+    })
+  } else if let Some(header) = val.read_lock::<client::BlockHeader>() {
+    serde_json::to_value(&*header).unwrap_or(Value::Null)
+  } else {
+    Value::String(val.to_string())
   }
 }
 
@@ -127,36 +259,213 @@ impl TaskHandle {
   }
 }
 
+/// The currently loaded script of a long-running host.
+#[derive(Default)]
+struct LoadedScript {
+  version: u64,
+  ast: Option<AST>,
+}
+
+/// Shared state used to build a new engine for each spawned task.
+struct EngineTemplate {
+  globals: Arc<HashMap<String, Dynamic>>,
+  rpc_manager: rpc::RpcManager,
+  client: client::Client,
+  lookup: types::TypeLookup,
+  metadata: Metadata,
+  plugins: plugins::PluginRegistry,
+  task_max_operations: AtomicU64,
+}
+
+impl EngineTemplate {
+  /// Build a new engine.  Only the globals (clients, type lookup, users, etc..) are shared.
+  fn build_engine(&self) -> Result<Engine, Box<EvalAltResult>> {
+    let mut engine = new_engine(self.rpc_manager.engine_opts());
+    rpc::register(&mut engine);
+    types::register(&mut engine);
+    client::register(&mut engine);
+    users::register(&mut engine);
+    addrbook::register(&mut engine);
+    metadata::register(&mut engine);
+    // The module constants are already in the shared globals.
+    self.metadata.add_encode_calls(&mut engine, &mut HashMap::new())?;
+    storage::register(&mut engine);
+    register_all(&mut engine, &self.lookup);
+    plugins::register(&mut engine);
+    register_globals(&mut engine, self.globals.clone());
+    self.plugins.register(&mut engine);
+
+    match self.task_max_operations.load(Ordering::Relaxed) {
+      0 => (),
+      max => {
+        engine.set_max_operations(max);
+      }
+    }
+    Ok(engine)
+  }
+}
+
 #[derive(Clone)]
-pub struct SharedEngine(Arc<RwLock<Engine>>);
+pub struct SharedEngine(Arc<RwLock<Engine>>, Arc<RwLock<LoadedScript>>, Arc<EngineTemplate>);
 
 impl SharedEngine {
-  fn new(engine: Engine) -> Self {
-     Self(Arc::new(RwLock::new(engine)))
+  fn new(engine: Engine, template: Arc<EngineTemplate>) -> Self {
+     Self(Arc::new(RwLock::new(engine)), Default::default(), template)
+  }
+
+  /// New engine for a task, sharing the loaded script and globals.
+  pub fn new_task_engine(&self) -> Result<Self, Box<EvalAltResult>> {
+    let engine = self.2.build_engine()?;
+    Ok(Self(Arc::new(RwLock::new(engine)), self.1.clone(), self.2.clone()))
+  }
+
+  /// Cancel all running scripts.
+  ///
+  /// Subscriptions are closed to wake up scripts waiting on them.
+  pub fn cancel(&self) {
+    cancel_scripts();
+    let closed = self.2.rpc_manager.close_subscriptions();
+    log::info!("Closed {} subscriptions", closed);
+  }
+
+  /// Flush the logs and print the extrinsics that haven't been included in a block yet.
+  pub fn shutdown(&self) {
+    log::logger().flush();
+
+    let pending = self.2.client.pending_extrinsics();
+    if pending.len() > 0 {
+      eprintln!("{} extrinsics still in-flight:", pending.len());
+      for xthex in pending {
+        eprintln!("  {}", xthex);
+      }
+    }
+  }
+
+  /// Maximum number of operations a spawned task can run (0 for no limit).
+  pub fn task_max_operations(&self) -> u64 {
+    self.2.task_max_operations.load(Ordering::Relaxed)
+  }
+
+  pub fn set_task_max_operations(&self, max: u64) {
+    self.2.task_max_operations.store(max, Ordering::Relaxed);
+  }
+
+  /// Metadata of the connected chain.
+  pub fn metadata(&self) -> &Metadata {
+    &self.2.metadata
+  }
+
+  pub fn rpc_manager(&self) -> &rpc::RpcManager {
+    &self.2.rpc_manager
+  }
+
+  /// Swap in a new script.  Globals (client, users, metadata, caches) are kept.
+  ///
+  /// Returns the new script version.
+  pub fn reload(&self, ast: AST) -> u64 {
+    let mut script = self.1.write().unwrap();
+    script.version += 1;
+    script.ast = Some(ast);
+    log::info!("Loaded script version: {}", script.version);
+    script.version
+  }
+
+  pub fn reload_file(&self, path: PathBuf) -> Result<u64, Box<EvalAltResult>> {
+    let ast = self.compile_file(path)?;
+    Ok(self.reload(ast))
+  }
+
+  pub fn script_version(&self) -> u64 {
+    self.1.read().unwrap().version
+  }
+
+  fn current_script(&self) -> Result<(u64, AST), Box<EvalAltResult>> {
+    let script = self.1.read().unwrap();
+    match &script.ast {
+      Some(ast) => Ok((script.version, ast.clone())),
+      None => Err(format!("No script loaded"))?,
+    }
+  }
+
+  /// Create a scope for a script version.
+  fn versioned_scope(&self, version: u64, args: Dynamic) -> Scope {
+    let mut scope = self.new_scope(args);
+    scope.push_constant("SCRIPT_VERSION", version as INT);
+    scope
+  }
+
+  /// Run the currently loaded script.
+  pub fn run_current(&self, args: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+    let (version, ast) = self.current_script()?;
+    let mut scope = self.versioned_scope(version, args);
+    reset_cancelled();
+    self.eval_ast_with_scope(&mut scope, &ast)
+  }
+
+  /// Call a function from the currently loaded script.
+  pub fn call_current_fn(
+    &self,
+    name: &str,
+    args: impl FuncArgs,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let (version, ast) = self.current_script()?;
+    let mut scope = self.versioned_scope(version, Dynamic::UNIT);
+    with_current_script(self, &ast, || {
+      self.0.read().unwrap().call_fn(&mut scope, &ast, name, args)
+    })
+  }
+
+  /// Call a script function pointer (closure) from `ast`.
+  pub fn call_fn_ptr(
+    &self,
+    ast: &AST,
+    func: &FnPtr,
+    args: impl FuncArgs,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    with_current_script(self, ast, || {
+      func.call(&self.0.read().unwrap(), ast, args)
+    })
   }
 
   pub fn compile(&self, script: &str) -> Result<AST, Box<EvalAltResult>> {
+    if profile::is_profile() {
+      return Ok(self.0.read().unwrap().compile(profile::instrument_script(script))?);
+    }
     Ok(self.0.read().unwrap().compile(script)?)
   }
 
   pub fn compile_file(&self, path: PathBuf) -> Result<AST, Box<EvalAltResult>> {
     let (contents, filename) = read_script(&path)?;
+    let contents = if profile::is_profile() {
+      profile::instrument_script(&contents)
+    } else {
+      contents
+    };
     let mut ast = self.0.read().unwrap().compile(contents)?;
     ast.set_source(filename);
     Ok(ast)
   }
 
   pub fn run_ast_with_scope(&self, scope: &mut Scope, ast: &AST) -> Result<(), Box<EvalAltResult>> {
-    self.0.read().unwrap().run_ast_with_scope(scope, ast)
+    with_current_script(self, ast, || {
+      self.0.read().unwrap().run_ast_with_scope(scope, ast)
+    })
   }
 
   pub fn eval_ast_with_scope(&self, scope: &mut Scope, ast: &AST) -> Result<Dynamic, Box<EvalAltResult>> {
-    self.0.read().unwrap().eval_ast_with_scope(scope, ast)
+    with_current_script(self, ast, || {
+      self.0.read().unwrap().eval_ast_with_scope(scope, ast)
+    })
   }
 
   pub fn run_file_with_scope(&self, scope: &mut Scope, path: PathBuf) -> Result<(), Box<EvalAltResult>> {
     let ast = self.compile_file(path)?;
-    self.0.read().unwrap().run_ast_with_scope(scope, &ast)
+    self.run_ast_with_scope(scope, &ast)
+  }
+
+  pub fn eval_file_with_scope(&self, scope: &mut Scope, path: PathBuf) -> Result<Dynamic, Box<EvalAltResult>> {
+    let ast = self.compile_file(path)?;
+    self.eval_ast_with_scope(scope, &ast)
   }
 
   pub fn spawn_task(&mut self, script: &str) -> Result<TaskHandle, Box<EvalAltResult>> {
@@ -180,10 +489,12 @@ impl SharedEngine {
   }
 
   fn spawn_task_ast_args(&mut self, ast: AST, args: Dynamic) -> Result<TaskHandle, Box<EvalAltResult>> {
-    let engine = self.clone();
+    let parent = self.clone();
     let handle = spawn(move || {
+        // Each task gets it's own engine, so long running tasks don't block others.
+        let engine = parent.new_task_engine()?;
         let mut scope = engine.new_scope(args);
-        engine.eval_ast_with_scope(&mut scope, &ast)
+        profile::scope(|| "task".into(), || engine.eval_ast_with_scope(&mut scope, &ast))
     });
     Ok(TaskHandle::new(handle))
   }
@@ -207,32 +518,96 @@ impl SharedEngine {
   }
 }
 
-pub fn init_engine(opts: &EngineOptions) -> Result<SharedEngine, Box<EvalAltResult>> {
+/// Async facade for embedding the engine in tokio apps.
+///
+/// Scripts still run synchronously, but on tokio's blocking thread pool
+/// so they don't block the runtime's worker threads.
+#[cfg(feature = "async")]
+impl SharedEngine {
+  pub async fn run_file_async(
+    &self,
+    path: PathBuf,
+    args: Vec<String>,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let engine = self.clone();
+    tokio::task::spawn_blocking(move || {
+      let ast = engine.compile_file(path)?;
+      let mut scope = engine.args_to_scope(&args[..]);
+      reset_cancelled();
+      engine.eval_ast_with_scope(&mut scope, &ast)
+    })
+    .await
+    .map_err(|err| format!("Failed to join task: {:?}", err))?
+  }
+
+  pub async fn eval_async(&self, script: String, args: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+    let engine = self.clone();
+    tokio::task::spawn_blocking(move || {
+      let ast = engine.compile(&script)?;
+      let mut scope = engine.new_scope(args);
+      reset_cancelled();
+      engine.eval_ast_with_scope(&mut scope, &ast)
+    })
+    .await
+    .map_err(|err| format!("Failed to join task: {:?}", err))?
+  }
+}
+
+fn new_engine(opts: &EngineOptions) -> Engine {
   let mut engine = Engine::new();
-  let mut globals = HashMap::new();
 
   #[cfg(not(feature = "no_optimize"))]
   engine.set_optimization_level(OptimizationLevel::Full);
   engine.set_max_expr_depths(64, 64);
 
-  // Initialize types, client, users, metadata and plugins.
-  let rpc_manager = rpc::init_engine(&mut engine)?;
-  let rpc = rpc_manager.get_client(&opts.url)?;
+  // Resource limits.
+  if opts.max_operations > 0 {
+    engine.set_max_operations(opts.max_operations);
+  }
+  if opts.max_call_levels > 0 {
+    engine.set_max_call_levels(opts.max_call_levels);
+  }
+  engine
+    .set_max_string_size(opts.max_string_size)
+    .set_max_array_size(opts.max_array_size)
+    .set_max_map_size(opts.max_map_size);
 
-  let lookup = types::init_engine(&mut engine, &opts)?;
-  let client = client::init_engine(&rpc, &mut engine, &lookup)?;
-  let users = users::init_engine(&mut engine, &client);
-  let metadata = metadata::init_engine(&mut engine, &mut globals, &client, &lookup)?;
-  let storage = storage::init_engine(&mut engine, &client, &metadata);
-  plugins::init_engine(&mut engine, &mut globals, &client, &lookup)?;
+  let timeout = match opts.script_timeout {
+    0 => None,
+    secs => Some(Duration::from_secs(secs)),
+  };
+  let started = Mutex::new(Instant::now());
+  // Cooperative cancellation (Ctrl-C) and timeout.
+  engine.on_progress(move |ops| {
+    if is_cancelled() {
+      return Some("Cancelled".into());
+    }
+    if let Some(timeout) = timeout {
+      // The operation count starts over for each script run.
+      if ops == 1 {
+        *started.lock().unwrap() = Instant::now();
+      } else if ops % TIMEOUT_CHECK_OPS == 0 && started.lock().unwrap().elapsed() > timeout {
+        return Some(format!("Script timeout ({:?})", timeout).into());
+      }
+    }
+    None
+  });
 
-  // Setup globals for easy access.
-  globals.insert("CLIENT".into(), Dynamic::from(client));
-  globals.insert("RPC_MANAGER".into(), Dynamic::from(rpc_manager));
-  globals.insert("RPC".into(), Dynamic::from(rpc));
-  globals.insert("Types".into(), Dynamic::from(lookup));
-  globals.insert("STORAGE".into(), Dynamic::from(storage));
-  globals.insert("USER".into(), Dynamic::from(users));
+  engine
+}
+
+/// Log a message from a script.  The script's filename is the log target.
+fn script_log(ctx: &NativeCallContext, level: log::Level, msg: Dynamic) {
+  let target = ctx.source().unwrap_or("script");
+  log::log!(target: target, level, "{}", msg);
+}
+
+fn register_globals(engine: &mut Engine, globals: Arc<HashMap<String, Dynamic>>) {
+  if let Some(users) = globals.get("USER").and_then(|u| u.clone().try_cast::<users::Users>()) {
+    engine.register_result_fn("fund", move |account: Dynamic, amount: Dynamic| {
+      users.clone().fund(account, amount)
+    });
+  }
 
   // For easier access to globals.
   engine.on_var(move |name, _, _| {
@@ -246,8 +621,108 @@ pub fn init_engine(opts: &EngineOptions) -> Result<SharedEngine, Box<EvalAltResu
     .register_result_fn("spawn_task_args", SharedEngine::spawn_task_args)
     .register_result_fn("spawn_file_task", SharedEngine::spawn_file_task)
     .register_result_fn("spawn_file_task_args", SharedEngine::spawn_file_task_args)
+    .register_result_fn("reload_file", |engine: &mut SharedEngine, file: &str| {
+      Ok(engine.reload_file(file.into())? as INT)
+    })
+    .register_get("script_version", |engine: &mut SharedEngine| {
+      engine.script_version() as INT
+    })
+    .register_get("task_max_operations", |engine: &mut SharedEngine| {
+      engine.task_max_operations() as INT
+    })
+    .register_set("task_max_operations", |engine: &mut SharedEngine, max: INT| {
+      engine.set_task_max_operations(max.max(0) as u64)
+    })
+    .register_fn("is_cancelled", is_cancelled)
+    .register_fn("log_error", |ctx: NativeCallContext, msg: Dynamic| {
+      script_log(&ctx, log::Level::Error, msg)
+    })
+    .register_fn("log_warn", |ctx: NativeCallContext, msg: Dynamic| {
+      script_log(&ctx, log::Level::Warn, msg)
+    })
+    .register_fn("log_info", |ctx: NativeCallContext, msg: Dynamic| {
+      script_log(&ctx, log::Level::Info, msg)
+    })
+    .register_fn("log_debug", |ctx: NativeCallContext, msg: Dynamic| {
+      script_log(&ctx, log::Level::Debug, msg)
+    })
+    .register_fn("log_trace", |ctx: NativeCallContext, msg: Dynamic| {
+      script_log(&ctx, log::Level::Trace, msg)
+    })
+    .register_fn("to_json", |val: Dynamic| dynamic_to_json(&val).to_string())
+    .register_fn("to_json_pretty", |val: Dynamic| {
+      serde_json::to_string_pretty(&dynamic_to_json(&val)).unwrap_or_default()
+    })
+    .register_result_fn(
+      "profile",
+      |ctx: NativeCallContext, name: &str, func: FnPtr| {
+        profile::scope(|| name.to_string(), || func.call_raw(&ctx, None, []))
+      },
+    )
     .register_type_with_name::<TaskHandle>("TaskHandle")
     .register_result_fn("join", TaskHandle::join);
+}
+
+/// Register the modules that don't need a client, shared by the main and task engines.
+#[cfg_attr(not(feature = "simulate"), allow(unused_variables))]
+fn register_all(engine: &mut Engine, lookup: &types::TypeLookup) {
+  error::init_engine(engine);
+  payload::init_engine(engine);
+  pretty::init_engine(engine);
+  ss58::init_engine(engine);
+  submitter::init_engine(engine);
+  correlator::init_engine(engine);
+  journal::init_engine(engine);
+  watcher::init_engine(engine);
+  #[cfg(feature = "simulate")]
+  crate::simulate::init_engine(engine, lookup);
+}
+
+pub fn init_engine(opts: &EngineOptions) -> Result<SharedEngine, Box<EvalAltResult>> {
+  let mut engine = new_engine(opts);
+  let mut globals = HashMap::new();
+
+  // Initialize types, client, users, metadata and plugins.
+  let rpc_manager = rpc::init_engine(&mut engine, &opts)?;
+  let rpc = rpc_manager.get_client(&opts.url)?;
+
+  let lookup = types::init_engine(&mut engine, &opts)?;
+  let client = client::init_engine(&rpc, &mut engine, &lookup, &opts)?;
+  let users = users::init_engine(&mut engine, &client);
+  let addrbook = addrbook::init_engine(&mut engine, &opts)?;
+  let metadata = metadata::init_engine(&mut engine, &mut globals, &client, &lookup)?;
+  let storage = storage::init_engine(&mut engine, &client, &metadata);
+  register_all(&mut engine, &lookup);
+  plugins::init_engine(&mut engine, &mut globals, &client, &lookup)?;
+
+  // Setup globals for easy access.
+  globals.insert("CLIENT".into(), Dynamic::from(client.clone()));
+  globals.insert("RPC_MANAGER".into(), Dynamic::from(rpc_manager.clone()));
+  globals.insert("RPC".into(), Dynamic::from(rpc));
+  globals.insert("Types".into(), Dynamic::from(lookup.clone()));
+  globals.insert("STORAGE".into(), Dynamic::from(storage));
+  for name in users::DEV_ACCOUNTS {
+    let user = users.clone().get_user(name.to_string())?;
+    globals.insert(name.to_uppercase(), user);
+  }
+  globals.insert("USER".into(), Dynamic::from(users));
+  globals.insert("ADDRBOOK".into(), Dynamic::from(addrbook));
+  globals.insert("None".into(), Dynamic::from(types::OptionValue(None)));
+
+  let globals = Arc::new(globals);
+  register_globals(&mut engine, globals.clone());
+  // Loaded last, so plugin modules can use the globals.
+  let plugins = plugins::PluginRegistry::load(&mut engine, &opts.plugins)?;
+
+  let template = Arc::new(EngineTemplate {
+    globals,
+    rpc_manager,
+    client,
+    lookup,
+    metadata,
+    plugins,
+    task_max_operations: AtomicU64::new(opts.task_max_operations),
+  });
 
-  Ok(SharedEngine::new(engine))
+  Ok(SharedEngine::new(engine, template))
 }
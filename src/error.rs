@@ -0,0 +1,124 @@
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, Position};
+
+/// Where an error came from, so scripts can handle them differently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+  /// The node returned an error or the connection failed.
+  Rpc,
+  /// An RPC request didn't get a response in time.
+  Timeout,
+  /// A value couldn't be encoded as the expected type.
+  Encode,
+  /// Data from the chain couldn't be decoded.
+  Decode,
+  /// Unknown module, call, storage or type.
+  Metadata,
+  /// An extrinsic was included in a block, but failed.
+  Dispatch,
+}
+
+impl ErrorKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Rpc => "Rpc",
+      Self::Timeout => "Timeout",
+      Self::Encode => "Encode",
+      Self::Decode => "Decode",
+      Self::Metadata => "Metadata",
+      Self::Dispatch => "Dispatch",
+    }
+  }
+}
+
+/// Error value thrown to scripts.  Catch it with `try { } catch (err) { err.kind }`.
+#[derive(Clone, Debug)]
+pub struct ScriptError {
+  pub kind: ErrorKind,
+  pub message: String,
+  pub module_error: Option<Dynamic>,
+}
+
+impl ScriptError {
+  pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+    Self {
+      kind,
+      message: message.into(),
+      module_error: None,
+    }
+  }
+
+  pub fn with_module_error(mut self, module_error: Dynamic) -> Self {
+    self.module_error = Some(module_error);
+    self
+  }
+
+  /// Tag an error with `kind`.  Errors that already have a kind and
+  /// non-runtime errors (e.g. cancelled scripts) are kept as-is.
+  pub fn wrap(kind: ErrorKind, err: Box<EvalAltResult>) -> Box<EvalAltResult> {
+    let message = match &*err {
+      EvalAltResult::ErrorRuntime(val, _) if !val.is::<ScriptError>() => val.to_string(),
+      _ => return err,
+    };
+    Self::new(kind, message).into()
+  }
+
+  fn kind(&mut self) -> String {
+    self.kind.as_str().into()
+  }
+
+  fn message(&mut self) -> String {
+    self.message.clone()
+  }
+
+  fn module_error(&mut self) -> Dynamic {
+    self.module_error.clone().unwrap_or(Dynamic::UNIT)
+  }
+
+  fn to_map(&mut self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("kind".into(), Dynamic::from(self.kind()));
+    map.insert("message".into(), Dynamic::from(self.message()));
+    map.insert("module_error".into(), self.module_error());
+    map
+  }
+
+  fn to_string(&mut self) -> String {
+    format!("{}", self)
+  }
+}
+
+impl std::fmt::Display for ScriptError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+impl From<ScriptError> for Box<EvalAltResult> {
+  fn from(err: ScriptError) -> Self {
+    Box::new(EvalAltResult::ErrorRuntime(
+      Dynamic::from(err),
+      Position::NONE,
+    ))
+  }
+}
+
+/// Error message, showing the message of `ScriptError` values instead of their type name.
+pub fn error_message(err: &EvalAltResult) -> String {
+  match err {
+    EvalAltResult::ErrorRuntime(val, _) => match val.read_lock::<ScriptError>() {
+      Some(err) => err.to_string(),
+      None => err.to_string(),
+    },
+    _ => err.to_string(),
+  }
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<ScriptError>("ScriptError")
+    .register_get("kind", ScriptError::kind)
+    .register_get("message", ScriptError::message)
+    .register_get("module_error", ScriptError::module_error)
+    .register_fn("to_map", ScriptError::to_map)
+    .register_fn("to_string", ScriptError::to_string);
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use rhai::plugin::NativeCallContext;
+use rhai::serde::from_dynamic;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map as RMap, INT};
+
+use crate::client::{block_number_from_int, BlockHeader, Client};
+use crate::engine::is_cancelled;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct JournalSubscription {
+  method: String,
+  params: Value,
+  unsub: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct JournalState {
+  last_block: Option<u64>,
+  subscriptions: BTreeMap<String, JournalSubscription>,
+}
+
+pub struct InnerJournal {
+  path: PathBuf,
+  client: Client,
+  state: Mutex<JournalState>,
+}
+
+impl InnerJournal {
+  fn load(path: PathBuf, client: Client) -> Result<Self, Box<EvalAltResult>> {
+    let state = if path.exists() {
+      let file = File::open(&path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+      let state: JournalState = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Failed to parse journal {:?}: {}", path, e))?;
+      log::info!(
+        "Loaded journal {:?}: last_block={:?}, subscriptions={}",
+        path,
+        state.last_block,
+        state.subscriptions.len()
+      );
+      state
+    } else {
+      JournalState::default()
+    };
+    Ok(Self {
+      path,
+      client,
+      state: Mutex::new(state),
+    })
+  }
+
+  /// Write the journal to a temp file and rename it, so a crash never leaves a partial journal.
+  fn save(&self, state: &JournalState) -> Result<(), Box<EvalAltResult>> {
+    let tmp = self.path.with_extension("tmp");
+    let file = File::create(&tmp).map_err(|e| format!("Failed to create {:?}: {}", tmp, e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), state)
+      .map_err(|e| format!("Failed to write journal {:?}: {}", tmp, e))?;
+    fs::rename(&tmp, &self.path)
+      .map_err(|e| format!("Failed to replace journal {:?}: {}", self.path, e))?;
+    Ok(())
+  }
+
+  fn update<R>(&self, f: impl FnOnce(&mut JournalState) -> R) -> Result<R, Box<EvalAltResult>> {
+    let mut state = self.state.lock().unwrap();
+    let res = f(&mut state);
+    self.save(&state)?;
+    Ok(res)
+  }
+
+  fn last_block(&self) -> Option<u64> {
+    self.state.lock().unwrap().last_block
+  }
+
+  fn set_block(&self, block: u64) -> Result<(), Box<EvalAltResult>> {
+    self.update(|state| state.last_block = Some(block))
+  }
+}
+
+/// Persists the last processed block and the active subscriptions of a bot,
+/// so it can catch up on missed blocks after a restart.
+#[derive(Clone)]
+pub struct SubscriptionJournal(Arc<InnerJournal>);
+
+impl SubscriptionJournal {
+  pub fn load(client: Client, path: &str) -> Result<Self, Box<EvalAltResult>> {
+    Ok(Self(Arc::new(InnerJournal::load(path.into(), client)?)))
+  }
+
+  fn last_block(&mut self) -> Dynamic {
+    match self.0.last_block() {
+      Some(block) => Dynamic::from(block as INT),
+      None => Dynamic::UNIT,
+    }
+  }
+
+  fn set_block(&mut self, block: INT) -> Result<(), Box<EvalAltResult>> {
+    self.0.set_block(block_number_from_int(block)?)
+  }
+
+  /// Record a subscription and subscribe to it.
+  fn subscribe(
+    &mut self,
+    name: &str,
+    method: &str,
+    params: Dynamic,
+    unsub: &str,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let sub = JournalSubscription {
+      method: method.into(),
+      params: from_dynamic(&params)?,
+      unsub: unsub.into(),
+    };
+    let token = self
+      .0
+      .client
+      .rpc()
+      .subscribe(&sub.method, sub.params.clone(), &sub.unsub)?;
+    self.0.update(|state| state.subscriptions.insert(name.into(), sub))?;
+    Ok(Dynamic::from(token))
+  }
+
+  fn remove_subscription(&mut self, name: &str) -> Result<bool, Box<EvalAltResult>> {
+    self
+      .0
+      .update(|state| state.subscriptions.remove(name).is_some())
+  }
+
+  fn subscriptions(&mut self) -> RMap {
+    let state = self.0.state.lock().unwrap();
+    state
+      .subscriptions
+      .iter()
+      .map(|(name, sub)| {
+        let mut map = RMap::new();
+        map.insert("method".into(), Dynamic::from(sub.method.clone()));
+        map.insert("params".into(), Dynamic::from(sub.params.to_string()));
+        map.insert("unsub".into(), Dynamic::from(sub.unsub.clone()));
+        (name.into(), Dynamic::from(map))
+      })
+      .collect()
+  }
+
+  /// Re-subscribe to all recorded subscriptions.  Returns the new request tokens by name.
+  fn resubscribe(&mut self) -> Result<RMap, Box<EvalAltResult>> {
+    let subs = self.0.state.lock().unwrap().subscriptions.clone();
+    let rpc = self.0.client.rpc();
+    let mut tokens = RMap::new();
+    for (name, sub) in subs {
+      log::info!("Resubscribe '{}': {}", name, sub.method);
+      let token = rpc.subscribe(&sub.method, sub.params, &sub.unsub)?;
+      tokens.insert(name.into(), Dynamic::from(token));
+    }
+    Ok(tokens)
+  }
+
+  /// Pass blocks `from..=to` to the callback, recording each block after it was processed.
+  ///
+  /// Returns `false` if the callback asked to stop.
+  fn process_blocks(
+    &self,
+    ctx: &NativeCallContext,
+    func: &FnPtr,
+    from: u64,
+    to: u64,
+  ) -> Result<bool, Box<EvalAltResult>> {
+    for number in from..=to {
+      if is_cancelled() {
+        return Ok(false);
+      }
+      let block = self
+        .0
+        .client
+        .get_block_by_number(number)?
+        .ok_or_else(|| format!("Block {} not found", number))?;
+      let res: Dynamic = func.call_raw(ctx, None, [Dynamic::from(block)])?;
+      self.0.set_block(number)?;
+      if res.as_bool() == Ok(false) {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+
+  /// First block that hasn't been processed yet.  New journals start at `head`.
+  fn next_block(&self, head: u64) -> u64 {
+    self.0.last_block().map(|b| b + 1).unwrap_or(head)
+  }
+
+  /// Process all finalized blocks missed since the last recorded block.
+  ///
+  /// Returns the number of processed blocks.
+  fn catch_up(&mut self, ctx: &NativeCallContext, func: FnPtr) -> Result<INT, Box<EvalAltResult>> {
+    let head = self.0.client.get_finalized_block_number()?;
+    let from = self.next_block(head);
+    if from > head {
+      return Ok(0);
+    }
+    log::info!("Journal catch-up: blocks {}..={}", from, head);
+    self.process_blocks(ctx, &func, from, head)?;
+    Ok(self.0.last_block().map_or(0, |b| (b + 1).saturating_sub(from)) as INT)
+  }
+
+  /// Catch up on missed blocks, then follow new finalized blocks until
+  /// the callback returns `false` or the script is cancelled.
+  fn follow(&mut self, ctx: &NativeCallContext, func: FnPtr) -> Result<(), Box<EvalAltResult>> {
+    let rpc = self.0.client.rpc();
+    // Subscribe before catching up, so no blocks are missed in between.
+    let token = rpc.subscribe(
+      "chain_subscribeFinalizedHeads",
+      json!([]),
+      "chain_unsubscribeFinalizedHeads",
+    )?;
+    let res = (|| -> Result<(), Box<EvalAltResult>> {
+      let head = self.0.client.get_finalized_block_number()?;
+      let from = self.next_block(head);
+      if from <= head && !self.process_blocks(ctx, &func, from, head)? {
+        return Ok(());
+      }
+      while !is_cancelled() {
+        let header = match rpc.get_update::<BlockHeader>(token)? {
+          Some(header) => header,
+          None => break,
+        };
+        let head = header.number;
+        let from = self.next_block(head);
+        // Finalized heads can skip blocks, process the gap too.
+        if from <= head && !self.process_blocks(ctx, &func, from, head)? {
+          break;
+        }
+      }
+      Ok(())
+    })();
+    rpc.close_request(token)?;
+    res
+  }
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<SubscriptionJournal>("SubscriptionJournal")
+    .register_result_fn("journal", |client: &mut Client, path: &str| {
+      SubscriptionJournal::load(client.clone(), path)
+    })
+    .register_get("last_block", SubscriptionJournal::last_block)
+    .register_result_fn("set_block", SubscriptionJournal::set_block)
+    .register_result_fn("subscribe", SubscriptionJournal::subscribe)
+    .register_result_fn("remove_subscription", SubscriptionJournal::remove_subscription)
+    .register_get("subscriptions", SubscriptionJournal::subscriptions)
+    .register_result_fn("resubscribe", SubscriptionJournal::resubscribe)
+    .register_result_fn(
+      "catch_up",
+      |ctx: NativeCallContext, journal: &mut SubscriptionJournal, func: FnPtr| {
+        journal.catch_up(&ctx, func)
+      },
+    )
+    .register_result_fn(
+      "follow",
+      |ctx: NativeCallContext, journal: &mut SubscriptionJournal, func: FnPtr| {
+        journal.follow(&ctx, func)
+      },
+    );
+}
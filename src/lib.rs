@@ -1,18 +1,53 @@
 pub mod engine;
 pub use engine::*;
 
+pub mod error;
+pub use error::*;
+
 pub mod users;
 pub use users::*;
 
+pub mod addrbook;
+pub use addrbook::*;
+
 pub mod rpc;
 pub use rpc::*;
 
+pub mod cache;
+pub use cache::*;
+
 pub mod client;
 pub use client::*;
 
 pub mod types;
 pub use types::*;
 
+pub mod payload;
+pub use payload::*;
+
+pub mod pretty;
+
+pub mod profile;
+
+pub mod summary;
+
+pub mod ss58;
+
+pub mod submitter;
+pub use submitter::*;
+
+pub mod correlator;
+pub use correlator::*;
+
+pub mod journal;
+pub use journal::*;
+
+pub mod watcher;
+pub use watcher::*;
+
+#[cfg(feature = "simulate")]
+pub mod simulate;
+
 pub mod storage;
 pub use storage::*;
 
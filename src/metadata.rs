@@ -1,5 +1,5 @@
 use std::any::TypeId;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::{Deref, DerefMut};
 
 use frame_metadata::{
@@ -23,14 +23,19 @@ use scale_info::{
   TypeDef,
   Variant, Field,
 };
-use parity_scale_codec::{Encode, Output};
+use parity_scale_codec::{Decode, Encode, Output};
 use sp_core::{self, storage::StorageKey};
 
 use rhai::plugin::NativeCallContext;
 use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map as RMap, INT};
 
+use indexmap::map::IndexMap;
+
+use rust_decimal::Decimal;
+
 use crate::client::Client;
-use crate::types::{EnumVariants, TypeLookup, TypeMeta, TypeRef};
+use crate::error::{ErrorKind, ScriptError};
+use crate::types::{encode_nested, EnumVariants, TypeLookup, TypeMeta, TypeRef};
 
 #[cfg(feature = "v14")]
 use crate::types::{get_type_name, is_type_compact};
@@ -48,13 +53,74 @@ fn decode_meta<B: 'static, O: 'static>(
   }
 }
 
+/// Storage key types that can be chosen by any caller.
+const USER_CONTROLLED_KEYS: &[&str] = &[
+  "AccountId",
+  "LookupSource",
+  "MultiAddress",
+  "Vec<",
+  "BoundedVec<",
+  "Bytes",
+  "String",
+  "[u8",
+];
+
+fn is_user_controlled_key(ty_name: &str) -> bool {
+  USER_CONTROLLED_KEYS.iter().any(|key| ty_name.contains(key))
+}
+
+fn storage_finding(severity: &str, storage: &str, message: String) -> Dynamic {
+  let mut map = RMap::new();
+  map.insert("severity".into(), Dynamic::from(severity.to_string()));
+  map.insert("storage".into(), Dynamic::from(storage.to_string()));
+  map.insert("message".into(), Dynamic::from(message));
+  Dynamic::from(map)
+}
+
 #[derive(Clone)]
 pub struct Metadata {
+  version: u32,
   modules: HashMap<String, ModuleMetadata>,
   idx_map: HashMap<u8, String>,
 }
 
 impl Metadata {
+  fn new(version: u32) -> Self {
+    Self {
+      version,
+      modules: HashMap::new(),
+      idx_map: HashMap::new(),
+    }
+  }
+
+  /// Decode metadata from the raw `state_getMetadata` bytes.
+  ///
+  /// Unsupported metadata versions fallback to an empty metadata, in that
+  /// mode only raw RPC and the types from the user's schema are available.
+  pub fn decode(bytes: &[u8], lookup: &TypeLookup) -> Result<Self, Box<EvalAltResult>> {
+    // The metadata version is the enum index after the 4 byte magic prefix.
+    let version = bytes.get(4).cloned().unwrap_or_default() as u32;
+    if !Self::is_supported_version(version) {
+      log::warn!(
+        "Unsupported metadata version v{}.  Falling back to raw mode.",
+        version
+      );
+      return Ok(Self::new(version));
+    }
+    let metadata_prefixed =
+      RuntimeMetadataPrefixed::decode(&mut &bytes[..]).map_err(|e| e.to_string())?;
+    Self::from_runtime_metadata(metadata_prefixed, lookup)
+  }
+
+  pub fn is_supported_version(version: u32) -> bool {
+    match version {
+      12 => cfg!(feature = "v12"),
+      13 => cfg!(feature = "v13"),
+      14 => cfg!(feature = "v14"),
+      _ => false,
+    }
+  }
+
   pub fn from_runtime_metadata(
     metadata_prefixed: RuntimeMetadataPrefixed,
     lookup: &TypeLookup,
@@ -97,10 +163,7 @@ impl Metadata {
     md: frame_metadata::v12::RuntimeMetadataV12,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    let mut api_md = Self {
-      modules: HashMap::new(),
-      idx_map: HashMap::new(),
-    };
+    let mut api_md = Self::new(12);
 
     // Top-level event/error/call types.
     let mut mod_events = EnumVariants::new();
@@ -136,10 +199,7 @@ impl Metadata {
     md: frame_metadata::v13::RuntimeMetadataV13,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    let mut api_md = Self {
-      modules: HashMap::new(),
-      idx_map: HashMap::new(),
-    };
+    let mut api_md = Self::new(13);
 
     // Top-level event/error/call types.
     let mut mod_events = EnumVariants::new();
@@ -175,10 +235,7 @@ impl Metadata {
     md: frame_metadata::v14::RuntimeMetadataV14,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    let mut api_md = Self {
-      modules: HashMap::new(),
-      idx_map: HashMap::new(),
-    };
+    let mut api_md = Self::new(14);
 
     // Import types from registry.
     lookup.import_v14_types(&md.types)?;
@@ -192,7 +249,14 @@ impl Metadata {
     md.pallets
       .iter()
       .try_for_each(|m| -> Result<(), Box<EvalAltResult>> {
-        let m = ModuleMetadata::from_v14_meta(m, &md.types, lookup)?;
+        let m = match ModuleMetadata::from_v14_meta(m, &md.types, lookup) {
+          Ok(m) => m,
+          Err(err) => {
+            // Skip unsupported pallets, so the rest of the chain is still usable.
+            log::warn!("Skipping pallet {}: {}", m.name, err);
+            return Ok(());
+          }
+        };
         let name = m.name.clone();
         mod_events.insert_at(m.index, &name, m.event_ref.clone());
         mod_errors.insert_at(m.index, &name, m.error_ref.clone());
@@ -209,6 +273,14 @@ impl Metadata {
     let call_ref = lookup.insert_meta("Call", TypeMeta::Enum(mod_calls));
     lookup.insert("Call", call_ref);
 
+    // Signed extra of extrinsics: a struct of the signed extensions.
+    let mut extra = IndexMap::new();
+    for ext in &md.extrinsic.signed_extensions {
+      let ext_ty = NamedType::new_type(ext.ty.id(), &md.types, lookup)?;
+      extra.insert(ext.identifier.clone(), ext_ty.ty_meta);
+    }
+    lookup.insert_meta("SignedExtra", TypeMeta::Struct(extra));
+
     Ok(api_md)
   }
 
@@ -225,10 +297,70 @@ impl Metadata {
     Ok(())
   }
 
+  pub fn version(&mut self) -> INT {
+    self.version as INT
+  }
+
   fn modules(&mut self) -> Vec<Dynamic> {
     self.modules.values().cloned().map(Dynamic::from).collect()
   }
 
+  /// Case-insensitive search of call, storage, event, error and constant names.
+  ///
+  /// Returns a list of matches with `pallet`, `kind`, `name`, `signature` and `title`.
+  pub fn find(&self, query: &str) -> Vec<Dynamic> {
+    let query = query.to_lowercase();
+    let mut modules = self.modules.values().collect::<Vec<_>>();
+    modules.sort_by_key(|m| m.index);
+    let mut found = Vec::new();
+    for module in modules {
+      let mut add = |kind: &str, name: &str, signature: String, docs: &Docs| {
+        if !name.to_lowercase().contains(&query) {
+          return;
+        }
+        let mut map = RMap::new();
+        map.insert("pallet".into(), Dynamic::from(module.name.clone()));
+        map.insert("kind".into(), Dynamic::from(kind.to_string()));
+        map.insert("name".into(), Dynamic::from(name.to_string()));
+        map.insert("signature".into(), Dynamic::from(signature));
+        map.insert("title".into(), Dynamic::from(docs.summary()));
+        found.push(Dynamic::from(map));
+      };
+      for func in module.funcs.values() {
+        add("call", &func.name, func.signature(), &func.docs);
+      }
+      for md in module.storage.values() {
+        add("storage", &md.name, md.signature(), &md.docs);
+      }
+      for event in module.events.values() {
+        add("event", &event.name, event.signature(), &event.docs);
+      }
+      for error in module.errors.values() {
+        add("error", &error.name, error.name.clone(), &error.docs);
+      }
+      for constant in module.constants.values() {
+        add("constant", &constant.name, constant.signature(), &constant.docs);
+      }
+    }
+    found
+  }
+
+  /// Markdown reference of the pallets (ordered by index).
+  pub fn to_markdown(&self) -> String {
+    let mut out = format!("# Runtime metadata (V{})\n\n", self.version);
+    let mut modules = self.modules.values().collect::<Vec<_>>();
+    modules.sort_by_key(|m| m.index);
+    out.push_str("## Pallets\n\n");
+    for module in &modules {
+      out.push_str(&format!("- [{}](#{})\n", module.name, module.name.to_lowercase()));
+    }
+    out.push('\n');
+    for module in modules {
+      module.write_markdown(&mut out);
+    }
+    out
+  }
+
   pub fn get_module(&self, name: &str) -> Option<&ModuleMetadata> {
     self.modules.get(name)
   }
@@ -242,7 +374,48 @@ impl Metadata {
       self
         .get_module(module)
         .and_then(|m| m.get_storage(storage))
-        .ok_or_else(|| format!("Can't find storage: {}.{}", module, storage))?,
+        .ok_or_else(|| {
+          ScriptError::new(
+            ErrorKind::Metadata,
+            format!("Can't find storage: {}.{}", module, storage),
+          )
+        })?,
+    )
+  }
+
+  pub fn get_constant(
+    &self,
+    module: &str,
+    name: &str,
+  ) -> Result<&ConstMetadata, Box<EvalAltResult>> {
+    Ok(
+      self
+        .get_module(module)
+        .and_then(|m| m.get_constant(name))
+        .ok_or_else(|| {
+          ScriptError::new(
+            ErrorKind::Metadata,
+            format!("Can't find constant: {}.{}", module, name),
+          )
+        })?,
+    )
+  }
+
+  pub fn get_func(
+    &self,
+    module: &str,
+    func: &str,
+  ) -> Result<&FuncMetadata, Box<EvalAltResult>> {
+    Ok(
+      self
+        .get_module(module)
+        .and_then(|m| m.get_func(func))
+        .ok_or_else(|| {
+          ScriptError::new(
+            ErrorKind::Metadata,
+            format!("Can't find call: {}.{}", module, func),
+          )
+        })?,
     )
   }
 
@@ -255,12 +428,99 @@ impl Metadata {
       .map_or(Dynamic::UNIT, |module| module.find_error(err_idx))
   }
 
+  /// Audit the storage layout: prefix collisions and risky key hashers.
+  ///
+  /// Returns a list of findings with `severity` (`error`, `warning` or `info`),
+  /// `storage` and `message`.
+  pub fn check_storage(&mut self) -> Vec<Dynamic> {
+    let mut findings = Vec::new();
+    let modules = self.modules.iter().collect::<BTreeMap<_, _>>();
+
+    // Each module needs a unique storage prefix.
+    let mut prefixes = BTreeMap::<&str, Vec<&str>>::new();
+    for (name, module) in &modules {
+      if !module.storage_prefix.is_empty() {
+        prefixes
+          .entry(&module.storage_prefix)
+          .or_default()
+          .push(name.as_str());
+      }
+    }
+    for (prefix, names) in prefixes.iter().filter(|(_, names)| names.len() > 1) {
+      findings.push(storage_finding(
+        "error",
+        prefix,
+        format!("Storage prefix '{}' is used by modules: {}", prefix, names.join(", ")),
+      ));
+    }
+
+    // Check the full storage prefix keys and the key hashers.
+    let mut keys = BTreeMap::<Vec<u8>, Vec<String>>::new();
+    for module in modules.values() {
+      let storage = module.storage.values().collect::<BTreeMap<_, _>>();
+      for md in storage.values() {
+        let name = format!("{}.{}", md.prefix, md.name);
+        keys.entry(md.get_prefix_key()).or_default().push(name.clone());
+        let hashers = md.key_hasher.iter().flat_map(|h| h.type_hashers.iter());
+        for (ty, hasher) in hashers {
+          let user_key = is_user_controlled_key(&ty.name);
+          match hasher {
+            KeyHasherType::Identity if user_key => findings.push(storage_finding(
+              "warning",
+              &name,
+              format!(
+                "Identity hasher on user controlled key `{}`, keys can be chosen to unbalance the storage trie",
+                ty.name
+              ),
+            )),
+            KeyHasherType::Twox64Concat | KeyHasherType::Twox128 | KeyHasherType::Twox256
+              if user_key =>
+            {
+              findings.push(storage_finding(
+                "warning",
+                &name,
+                format!(
+                  "Non-cryptographic hasher {:?} on user controlled key `{}`",
+                  hasher, ty.name
+                ),
+              ))
+            }
+            _ => (),
+          }
+          match hasher {
+            KeyHasherType::Blake2_128
+            | KeyHasherType::Blake2_256
+            | KeyHasherType::Twox128
+            | KeyHasherType::Twox256 => findings.push(storage_finding(
+              "info",
+              &name,
+              format!(
+                "Hasher {:?} doesn't include the key `{}`, keys can't be decoded when iterating",
+                hasher, ty.name
+              ),
+            )),
+            _ => (),
+          }
+        }
+      }
+    }
+    for names in keys.values().filter(|names| names.len() > 1) {
+      findings.push(storage_finding(
+        "error",
+        &names[0],
+        format!("Storage key collision: {}", names.join(", ")),
+      ));
+    }
+
+    findings
+  }
+
   fn indexer_get(&mut self, name: String) -> Result<Dynamic, Box<EvalAltResult>> {
     let m = self
       .modules
       .get(&name)
       .cloned()
-      .ok_or_else(|| format!("Module {} not found", name))?;
+      .ok_or_else(|| ScriptError::new(ErrorKind::Metadata, format!("Module {} not found", name)))?;
     Ok(Dynamic::from(m))
   }
 }
@@ -282,6 +542,69 @@ pub struct ModuleMetadata {
 }
 
 impl ModuleMetadata {
+  fn write_markdown(&self, out: &mut String) {
+    out.push_str(&format!("## {}\n\nPallet index: {}\n\n", self.name, self.index));
+
+    let mut funcs = self.funcs.values().collect::<Vec<_>>();
+    funcs.sort_by_key(|f| f.func_idx);
+    if !funcs.is_empty() {
+      out.push_str("### Calls\n\n");
+      for func in funcs {
+        out.push_str(&format!("#### `{}`\n\n", func.signature()));
+        func.docs.write_markdown(out);
+      }
+    }
+
+    let mut storage = self.storage.values().collect::<Vec<_>>();
+    storage.sort_by(|a, b| a.name.cmp(&b.name));
+    if !storage.is_empty() {
+      out.push_str("### Storage\n\n");
+      for md in storage {
+        out.push_str(&format!("#### `{}`\n\n", md.signature()));
+        if let Some(hasher) = &md.key_hasher {
+          let keys = hasher
+            .type_hashers
+            .iter()
+            .map(|(ty, hasher)| format!("`{}` ({:?})", ty.name, hasher))
+            .collect::<Vec<_>>()
+            .join(", ");
+          out.push_str(&format!("Keys: {}\n\n", keys));
+        }
+        md.docs.write_markdown(out);
+      }
+    }
+
+    let mut events = self.events.values().collect::<Vec<_>>();
+    events.sort_by_key(|ev| ev.event_idx);
+    if !events.is_empty() {
+      out.push_str("### Events\n\n");
+      for event in events {
+        out.push_str(&format!("#### `{}`\n\n", event.signature()));
+        event.docs.write_markdown(out);
+      }
+    }
+
+    let mut errors = self.errors.values().collect::<Vec<_>>();
+    errors.sort_by_key(|err| err.error_idx);
+    if !errors.is_empty() {
+      out.push_str("### Errors\n\n");
+      for error in errors {
+        out.push_str(&format!("#### `{}`\n\n", error.name));
+        error.docs.write_markdown(out);
+      }
+    }
+
+    let mut constants = self.constants.values().collect::<Vec<_>>();
+    constants.sort_by(|a, b| a.name.cmp(&b.name));
+    if !constants.is_empty() {
+      out.push_str("### Constants\n\n");
+      for constant in constants {
+        out.push_str(&format!("#### `{}`\n\n", constant.signature()));
+        constant.docs.write_markdown(out);
+      }
+    }
+  }
+
   #[cfg(feature = "v12")]
   fn from_v12_meta(
     md: &frame_metadata::v12::ModuleMetadata,
@@ -526,13 +849,27 @@ impl ModuleMetadata {
       call_ref: None,
     };
 
+    // Namespace of the pallet's types.
+    let pallet_ty = md
+      .calls
+      .as_ref()
+      .map(|c| c.ty.id())
+      .or_else(|| md.event.as_ref().map(|e| e.ty.id()))
+      .or_else(|| md.error.as_ref().map(|e| e.ty.id()));
+    if let Some(namespace) = pallet_ty
+      .and_then(|id| types.resolve(id))
+      .and_then(|ty| ty.path().namespace().first())
+    {
+      lookup.add_namespace(mod_name, namespace);
+    }
+
     // Decode module functions.
     if let Some(calls) = &md.calls {
       // Module RawCall type.
       let mut raw_calls = EnumVariants::new();
 
       let call_ty = types.resolve(calls.ty.id())
-        .expect("Missing Pallet call type");
+        .ok_or_else(|| format!("Missing Pallet call type"))?;
       match call_ty.type_def() {
         TypeDef::Variant(v) => {
           v.variants().iter().try_for_each(
@@ -546,7 +883,7 @@ impl ModuleMetadata {
           )?;
         }
         _ => {
-          unimplemented!("Only Variant type supported for Pallet Call type.");
+          Err(format!("Only Variant type supported for Pallet Call type."))?;
         }
       }
       module.call_ref = Some(lookup.insert_meta(
@@ -575,7 +912,7 @@ impl ModuleMetadata {
       let mut raw_events = EnumVariants::new();
 
       let event_ty = types.resolve(events.ty.id())
-        .expect("Missing Pallet event type");
+        .ok_or_else(|| format!("Missing Pallet event type"))?;
       match event_ty.type_def() {
         TypeDef::Variant(v) => {
           v.variants().iter().try_for_each(
@@ -590,7 +927,7 @@ impl ModuleMetadata {
           )?;
         }
         _ => {
-          unimplemented!("Only Variant type supported for Pallet Event type.");
+          Err(format!("Only Variant type supported for Pallet Event type."))?;
         }
       }
       module.event_ref = Some(lookup.insert_meta(
@@ -616,7 +953,7 @@ impl ModuleMetadata {
 
       let extra_bytes = lookup.parse_type("[u8; 3]")?;
       let error_ty = types.resolve(error.ty.id())
-        .expect("Missing Pallet error type");
+        .ok_or_else(|| format!("Missing Pallet error type"))?;
       match error_ty.type_def() {
         TypeDef::Variant(v) => {
           v.variants().iter().try_for_each(
@@ -631,7 +968,7 @@ impl ModuleMetadata {
           )?;
         }
         _ => {
-          unimplemented!("Only Variant type supported for Pallet Error type.");
+          Err(format!("Only Variant type supported for Pallet Error type."))?;
         }
       }
       module.error_ref = Some(lookup.insert_meta(
@@ -704,6 +1041,14 @@ impl ModuleMetadata {
     self.storage.get(name)
   }
 
+  pub fn get_constant(&self, name: &str) -> Option<&ConstMetadata> {
+    self.constants.get(name)
+  }
+
+  pub fn get_func(&self, name: &str) -> Option<&FuncMetadata> {
+    self.funcs.get(name)
+  }
+
   fn to_string(&mut self) -> String {
     format!("ModuleMetadata: {}", self.name)
   }
@@ -717,7 +1062,13 @@ impl ModuleMetadata {
       if let Some(func) = self.funcs.get(&name) {
         Ok(Dynamic::from(func.clone()))
       } else {
-        Err(format!("Storage or function {} not found", name).into())
+        Err(
+          ScriptError::new(
+            ErrorKind::Metadata,
+            format!("Storage or function {} not found", name),
+          )
+          .into(),
+        )
       }
     }
   }
@@ -745,7 +1096,7 @@ impl NamedType {
     let ty = types.resolve(ty_id)
       .ok_or_else(|| format!("Failed to resolve type."))?;
     let name = get_type_name(ty, types, false);
-    let ty_meta = lookup.parse_type(&name)?;
+    let ty_meta = lookup.parse_type_with_id(&name, ty_id)?;
     let named = Self {
       name: name.into(),
       ty_meta,
@@ -775,7 +1126,7 @@ impl NamedType {
       }).unwrap_or_else(|| {
         get_type_name(ty, types, false)
       });
-    let ty_meta = lookup.parse_type(&name)?;
+    let ty_meta = lookup.parse_type_with_id(&name, md.ty().id())?;
     let named = Self {
       name: name.into(),
       ty_meta,
@@ -798,6 +1149,10 @@ impl NamedType {
     Ok(data.into_inner())
   }
 
+  pub fn is_option(&self) -> bool {
+    self.ty_meta.is_option()
+  }
+
   pub fn decode(&self, data: Vec<u8>) -> Result<Dynamic, Box<EvalAltResult>> {
     self.ty_meta.decode(data)
   }
@@ -886,7 +1241,8 @@ impl KeyHasher {
       0 => Err(format!("This storage isn't a map type."))?,
       1 => {
         let (ty, _) = &self.type_hashers[0];
-        ty.encode_value(key, &mut buf)?;
+        ty.encode_value(key, &mut buf)
+          .map_err(|e| ScriptError::wrap(ErrorKind::Encode, e))?;
       }
       _ => {
         Err(format!("This storage isn't a double map type."))?;
@@ -905,9 +1261,11 @@ impl KeyHasher {
     match self.type_hashers.len() {
       2 => {
         let (ty, _) = &self.type_hashers[0];
-        ty.encode_value(key1, &mut buf1)?;
+        ty.encode_value(key1, &mut buf1)
+          .map_err(|e| ScriptError::wrap(ErrorKind::Encode, e))?;
         let (ty, _) = &self.type_hashers[1];
-        ty.encode_value(key2, &mut buf2)?;
+        ty.encode_value(key2, &mut buf2)
+          .map_err(|e| ScriptError::wrap(ErrorKind::Encode, e))?;
       }
       _ => Err(format!("This storage isn't a double map type."))?,
     }
@@ -1007,6 +1365,34 @@ impl KeyHasher {
     Ok(StorageKey(buf))
   }
 
+  pub fn get_nmap_key(
+    &self,
+    buf: Vec<u8>,
+    keys: Vec<Dynamic>,
+  ) -> Result<StorageKey, Box<EvalAltResult>> {
+    let len = self.type_hashers.len();
+    if keys.len() != len {
+      Err(format!("This storage expects {} keys, got {}.", len, keys.len()))?;
+    }
+    self.get_nmap_prefix(buf, keys)
+  }
+
+  /// Prefix key for the first `keys.len()` keys of an NMap.
+  pub fn get_nmap_prefix(
+    &self,
+    mut buf: Vec<u8>,
+    keys: Vec<Dynamic>,
+  ) -> Result<StorageKey, Box<EvalAltResult>> {
+    let len = self.type_hashers.len();
+    if keys.len() > len {
+      Err(format!("This storage only has {} keys, got {}.", len, keys.len()))?;
+    }
+    for (idx, key) in keys.into_iter().enumerate() {
+      self.hash_key(&mut buf, idx, key)?;
+    }
+    Ok(StorageKey(buf))
+  }
+
   pub fn raw_map_key(
     &self,
     mut buf: Vec<u8>,
@@ -1059,17 +1445,27 @@ pub struct StorageMetadata {
   pub name: String,
   pub key_hasher: Option<KeyHasher>,
   pub value_ty: NamedType,
+  /// SCALE-encoded value returned by the runtime when the storage is empty.
+  pub default: Vec<u8>,
+  /// `OptionQuery` entries (`Optional` modifier) don't have a default value,
+  /// their `default` is an encoded `None`.
+  pub optional: bool,
   pub docs: Docs,
 }
 
 impl StorageMetadata {
+  /// `Name: ValueType`
+  pub fn signature(&self) -> String {
+    format!("{}: {}", self.name, self.value_ty.name)
+  }
+
   #[cfg(feature = "v12")]
   fn from_v12_meta(
     prefix: &str,
     md: &frame_metadata::v12::StorageEntryMetadata,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    use frame_metadata::v12::StorageEntryType;
+    use frame_metadata::v12::{StorageEntryModifier, StorageEntryType};
     let (key_hasher, value) = match &md.ty {
       StorageEntryType::Plain(value) => (None, value.clone()),
       StorageEntryType::Map {
@@ -1101,6 +1497,8 @@ impl StorageMetadata {
       name: decode_meta(&md.name)?.clone(),
       key_hasher,
       value_ty: NamedType::new(decode_meta(&value)?, lookup)?,
+      default: decode_meta(&md.default)?.clone(),
+      optional: matches!(md.modifier, StorageEntryModifier::Optional),
       docs: Docs::from_v12_meta(&md.documentation)?,
     };
 
@@ -1113,7 +1511,7 @@ impl StorageMetadata {
     md: &frame_metadata::v13::StorageEntryMetadata,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    use frame_metadata::v13::StorageEntryType;
+    use frame_metadata::v13::{StorageEntryModifier, StorageEntryType};
     let (key_hasher, value) = match &md.ty {
       StorageEntryType::Plain(value) => (None, value.clone()),
       StorageEntryType::Map {
@@ -1158,6 +1556,8 @@ impl StorageMetadata {
       name: decode_meta(&md.name)?.clone(),
       key_hasher,
       value_ty: NamedType::new(decode_meta(&value)?, lookup)?,
+      default: decode_meta(&md.default)?.clone(),
+      optional: matches!(md.modifier, StorageEntryModifier::Optional),
       docs: Docs::from_v13_meta(&md.documentation)?,
     };
 
@@ -1171,7 +1571,7 @@ impl StorageMetadata {
     types: &PortableRegistry,
     lookup: &TypeLookup,
   ) -> Result<Self, Box<EvalAltResult>> {
-    use frame_metadata::v14::StorageEntryType;
+    use frame_metadata::v14::{StorageEntryModifier, StorageEntryType};
     let (key_hasher, value) = match &md.ty {
       StorageEntryType::Plain(value) => (None, value.clone()),
       StorageEntryType::Map {
@@ -1186,10 +1586,24 @@ impl StorageMetadata {
             (Some(hasher), value.clone())
           }
           hashers => {
-            let ty = NamedType::new_type(key.id(), types, lookup)?;
+            // NMap keys are a tuple with one field per hasher.
+            let key_ty = types.resolve(key.id())
+              .ok_or_else(|| format!("Failed to resolve type."))?;
+            let key_ids = match key_ty.type_def() {
+              TypeDef::Tuple(t) if t.fields().len() == hashers.len() => {
+                t.fields().iter().map(|f| f.id()).collect::<Vec<_>>()
+              }
+              _ => vec![key.id(); hashers.len()],
+            };
+            let type_hashers = key_ids.into_iter()
+              .zip(hashers.iter())
+              .map(|(id, hasher)| {
+                let ty = NamedType::new_type(id, types, lookup)?;
+                Ok((ty, hasher.into()))
+              })
+              .collect::<Result<Vec<_>, Box<EvalAltResult>>>()?;
             let hasher = KeyHasher {
-              type_hashers: hashers.iter()
-                .map(|hasher| (ty.clone(), hasher.into())).collect(),
+              type_hashers,
             };
             (Some(hasher), value.clone())
           }
@@ -1201,6 +1615,8 @@ impl StorageMetadata {
       name: md.name.to_string(),
       key_hasher,
       value_ty: NamedType::new_type(value.id(), types, lookup)?,
+      default: md.default.clone(),
+      optional: matches!(md.modifier, StorageEntryModifier::Optional),
       docs: Docs::from_v14_meta(md.docs.as_slice()),
     };
 
@@ -1265,6 +1681,26 @@ impl StorageMetadata {
     }
   }
 
+  pub fn get_nmap_key(&self, keys: Vec<Dynamic>) -> Result<StorageKey, Box<EvalAltResult>> {
+    match &self.key_hasher {
+      Some(hasher) => {
+        let prefix = self.get_prefix_key();
+        hasher.get_nmap_key(prefix, keys)
+      }
+      None => Err(format!("This storage type doesn't have keys.").into()),
+    }
+  }
+
+  pub fn get_nmap_prefix(&self, keys: Vec<Dynamic>) -> Result<StorageKey, Box<EvalAltResult>> {
+    match &self.key_hasher {
+      Some(hasher) => {
+        let prefix = self.get_prefix_key();
+        hasher.get_nmap_prefix(prefix, keys)
+      }
+      None => Err(format!("This storage type doesn't have keys.").into()),
+    }
+  }
+
   pub fn raw_map_key(&self, key: Vec<u8>) -> Result<StorageKey, Box<EvalAltResult>> {
     match &self.key_hasher {
       Some(hasher) => {
@@ -1311,6 +1747,23 @@ impl StorageMetadata {
     self.value_ty.decode(data)
   }
 
+  /// Decode the default value.  Returns `()` for `OptionQuery` entries and if the
+  /// metadata doesn't have one.
+  pub fn decode_default(&self) -> Result<Dynamic, Box<EvalAltResult>> {
+    if self.optional || self.default.is_empty() {
+      return Ok(Dynamic::UNIT);
+    }
+    self.decode_value(self.default.clone())
+  }
+
+  fn default_value(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.decode_default()
+  }
+
+  fn is_optional(&mut self) -> bool {
+    self.optional
+  }
+
   fn name(&mut self) -> String {
     self.name.clone()
   }
@@ -1352,6 +1805,17 @@ pub struct EventMetadata {
 }
 
 impl EventMetadata {
+  /// `Name(ArgType, ...)`
+  pub fn signature(&self) -> String {
+    let args = self
+      .args
+      .iter()
+      .map(|ty| ty.name.as_str())
+      .collect::<Vec<_>>()
+      .join(", ");
+    format!("{}({})", self.name, args)
+  }
+
   #[cfg(feature = "v12")]
   fn from_v12_meta(
     mod_name: &str,
@@ -1499,10 +1963,16 @@ pub struct ConstMetadata {
   mod_name: String,
   name: String,
   const_ty: NamedType,
+  value: Vec<u8>,
   docs: Docs,
 }
 
 impl ConstMetadata {
+  /// `Name: Type`
+  pub fn signature(&self) -> String {
+    format!("{}: {}", self.name, self.const_ty.name)
+  }
+
   #[cfg(feature = "v12")]
   fn from_v12_meta(
     mod_name: &str,
@@ -1515,6 +1985,7 @@ impl ConstMetadata {
       mod_name: mod_name.into(),
       name: decode_meta(&md.name)?.clone(),
       const_ty,
+      value: decode_meta(&md.value)?.clone(),
       docs: Docs::from_v12_meta(&md.documentation)?,
     })
   }
@@ -1531,6 +2002,7 @@ impl ConstMetadata {
       mod_name: mod_name.into(),
       name: decode_meta(&md.name)?.clone(),
       const_ty,
+      value: decode_meta(&md.value)?.clone(),
       docs: Docs::from_v13_meta(&md.documentation)?,
     })
   }
@@ -1547,10 +2019,20 @@ impl ConstMetadata {
       mod_name: mod_name.into(),
       name: md.name.clone(),
       const_ty,
+      value: md.value.clone(),
       docs: Docs::from_v14_meta(&md.docs),
     })
   }
 
+  /// Decode the constant's value.
+  pub fn decode_value(&self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.const_ty.decode(self.value.clone())
+  }
+
+  fn value(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.decode_value()
+  }
+
   fn title(&mut self) -> String {
     self.docs.title()
   }
@@ -1647,6 +2129,20 @@ impl ErrorMetadata {
 pub struct EncodedCall(u8, u8, EncodedArgs);
 
 impl EncodedCall {
+  pub fn new(mod_idx: u8, func_idx: u8, args: EncodedArgs) -> Self {
+    Self(mod_idx, func_idx, args)
+  }
+
+  /// Build from an encoded call: module index, function index, args.
+  pub fn from_bytes(data: &[u8]) -> Result<Self, Box<EvalAltResult>> {
+    if data.len() < 2 {
+      Err(format!("Encoded call too short: {} bytes", data.len()))?;
+    }
+    let mut args = EncodedArgs::new();
+    args.write(&data[2..]);
+    Ok(Self(data[0], data[1], args))
+  }
+
   pub fn len(&mut self) -> i64 {
     2 + self.2.len()
   }
@@ -1739,6 +2235,17 @@ pub struct FuncMetadata {
 }
 
 impl FuncMetadata {
+  /// `name(arg: Type, ...)`
+  pub fn signature(&self) -> String {
+    let args = self
+      .args
+      .iter()
+      .map(|arg| format!("{}: {}", arg.name, arg.ty.name))
+      .collect::<Vec<_>>()
+      .join(", ");
+    format!("{}({})", self.name, args)
+  }
+
   #[cfg(feature = "v12")]
   fn from_v12_meta(
     mod_name: &str,
@@ -1856,14 +2363,27 @@ impl FuncMetadata {
     Ok((func, func_ref))
   }
 
+  /// Number of args without the trailing `Option<T>` args.
+  pub fn required_args(&self) -> usize {
+    let optional = self.args.iter().rev().take_while(|arg| arg.ty.is_option()).count();
+    self.args.len() - optional
+  }
+
   pub fn add_encode_calls(&self, engine: &mut Engine) -> Result<Dynamic, Box<EvalAltResult>> {
     let full_name = format!("{}_{}", self.mod_name, self.name);
-    let mut args = vec![TypeId::of::<RMap>(), TypeId::of::<FuncMetadata>()];
-    let args_len = self.args.len();
-    if args_len > 0 {
-      args.extend([TypeId::of::<Dynamic>()].repeat(args_len));
+    // Trailing `Option<T>` args can be left out.
+    for args_len in self.required_args()..=self.args.len() {
+      let mut args = vec![TypeId::of::<RMap>(), TypeId::of::<FuncMetadata>()];
+      if args_len > 0 {
+        args.extend([TypeId::of::<Dynamic>()].repeat(args_len));
+      }
+      engine.register_raw_fn(&full_name, &args, encode_call);
+    }
+    if self.args.len() > 0 {
+      // Named args: `Balances.transfer(#{ dest: bob, value: 100 })`.
+      let args = [TypeId::of::<RMap>(), TypeId::of::<FuncMetadata>(), TypeId::of::<RMap>()];
+      engine.register_raw_fn(&full_name, &args, encode_call);
     }
-    engine.register_raw_fn(&full_name, &args, encode_call);
 
     let mut encode_call = FnPtr::new(full_name)?;
     encode_call.add_curry(Dynamic::from(self.clone()));
@@ -1895,12 +2415,109 @@ impl FuncMetadata {
     self.docs.to_string()
   }
 
+  /// Build a call from already encoded arguments.
+  pub fn encode_raw_call(&self, data: EncodedArgs) -> EncodedCall {
+    EncodedCall(self.mod_idx, self.func_idx, data)
+  }
+
+  pub fn has_arg(&self, name: &str) -> bool {
+    self.args.iter().any(|arg| arg.name == name)
+  }
+
+  fn get_arg(&self, name: &str) -> Result<&FuncArg, Box<EvalAltResult>> {
+    Ok(
+      self
+        .args
+        .iter()
+        .find(|arg| arg.name == name)
+        .ok_or_else(|| format!("{}.{} has no argument `{}`", self.mod_name, self.name, name))?,
+    )
+  }
+
+  /// Encode the argument `name`, for calls where some arguments are encoded by hand.
+  pub fn encode_arg(
+    &self,
+    name: &str,
+    value: Dynamic,
+    data: &mut EncodedArgs,
+  ) -> Result<(), Box<EvalAltResult>> {
+    let arg = self.get_arg(name)?;
+    encode_nested(
+      || format!("{}.{}.{}", self.mod_name, self.name, arg.name),
+      || arg.encode_value(value, data),
+    )
+  }
+
+  /// Encode the integer argument `name` from an already scaled amount (e.g. an asset
+  /// amount in its own decimals), with the argument's width and compactness.
+  pub fn encode_raw_integer_arg(
+    &self,
+    name: &str,
+    raw: Decimal,
+    data: &mut EncodedArgs,
+  ) -> Result<(), Box<EvalAltResult>> {
+    let arg = self.get_arg(name)?;
+    encode_nested(
+      || format!("{}.{}.{}", self.mod_name, self.name, arg.name),
+      || arg.ty.ty_meta.encode_raw_integer(raw, data),
+    )
+  }
+
+  /// Encode a call from Rust, with the same argument handling as scripts.
+  pub fn encode_args(&self, mut args: Vec<Dynamic>) -> Result<EncodedCall, Box<EvalAltResult>> {
+    let params = args.iter_mut().collect::<Vec<_>>();
+    self.encode_call(&params)
+  }
+
   fn encode_call(&self, params: &[&mut Dynamic]) -> Result<EncodedCall, Box<EvalAltResult>> {
     let mut data = EncodedArgs::new();
-    self.encode_params(params, &mut data)?;
+    let res = match self.named_args(params) {
+      Some(named) => self.encode_named_params(named, &mut data),
+      None => self.encode_params(params, &mut data),
+    };
+    res.map_err(|e| ScriptError::wrap(ErrorKind::Encode, e))?;
     Ok(EncodedCall(self.mod_idx, self.func_idx, data))
   }
 
+  /// A single map with only arg names as keys is used as named args.
+  fn named_args(&self, params: &[&mut Dynamic]) -> Option<RMap> {
+    match params {
+      [param] => {
+        let map = param.read_lock::<RMap>()?;
+        let is_named = !map.is_empty()
+          && map
+            .keys()
+            .all(|key| self.args.iter().any(|arg| arg.name == key.as_str()));
+        if is_named {
+          Some(map.clone())
+        } else {
+          None
+        }
+      }
+      _ => None,
+    }
+  }
+
+  fn encode_named_params(
+    &self,
+    mut named: RMap,
+    data: &mut EncodedArgs,
+  ) -> Result<(), Box<EvalAltResult>> {
+    let required = self.required_args();
+    for (idx, arg) in self.args.iter().enumerate() {
+      let param = match named.remove(arg.name.as_str()) {
+        Some(param) => param,
+        None if idx >= required => Dynamic::UNIT,
+        None => Err(format!("Missing argument: {}", arg.name))?,
+      };
+      encode_nested(
+        || format!("{}.{}.{}", self.mod_name, self.name, arg.name),
+        || arg.encode_value(param, data),
+      )?;
+    }
+    Ok(())
+  }
+
   fn encode_params(
     &self,
     params: &[&mut Dynamic],
@@ -1912,10 +2529,18 @@ impl FuncMetadata {
     }
     for (idx, arg) in self.args.iter().enumerate() {
       if let Some(param) = params.get(idx).map(|p| (*p).clone()) {
-        arg.encode_value(param, data)?;
+        encode_nested(
+          || format!("{}.{}.args[{}]", self.mod_name, self.name, idx),
+          || arg.encode_value(param, data),
+        )?;
+      } else if idx >= self.required_args() {
+        // Missing optional parameter.
+        encode_nested(
+          || format!("{}.{}.args[{}]", self.mod_name, self.name, idx),
+          || arg.encode_value(Dynamic::UNIT, data),
+        )?;
       } else {
-        // TODO: Check if parameter is optional.
-        Err(format!("Too many parameters"))?
+        Err(format!("Missing parameter: {}", arg.name))?
       }
     }
     Ok(())
@@ -2033,7 +2658,20 @@ impl Docs {
     }
   }
 
-  pub fn title(&mut self) -> String {
+  fn write_markdown(&self, out: &mut String) {
+    if self.lines.is_empty() {
+      return;
+    }
+    for line in &self.lines {
+      // Doc comments keep the space after `///`.
+      out.push_str(line.strip_prefix(' ').unwrap_or(line));
+      out.push('\n');
+    }
+    out.push('\n');
+  }
+
+  /// First line of the docs.
+  pub fn summary(&self) -> String {
     self
       .lines
       .first()
@@ -2041,6 +2679,10 @@ impl Docs {
       .unwrap_or_default()
   }
 
+  pub fn title(&mut self) -> String {
+    self.summary()
+  }
+
   fn to_string(&mut self) -> String {
     self.lines.join("\n")
   }
@@ -2057,15 +2699,14 @@ fn encode_call(
   func.encode_call(&args[2..])
 }
 
-pub fn init_engine(
-  engine: &mut Engine,
-  globals: &mut HashMap<String, Dynamic>,
-  client: &Client,
-  lookup: &TypeLookup,
-) -> Result<Metadata, Box<EvalAltResult>> {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<Metadata>("Metadata")
     .register_get("modules", Metadata::modules)
+    .register_get("version", Metadata::version)
+    .register_fn("to_markdown", |md: &mut Metadata| md.to_markdown())
+    .register_fn("find", |md: &mut Metadata, query: &str| md.find(query))
+    .register_fn("check_storage", Metadata::check_storage)
     .register_fn(
       "find_error",
       |md: &mut Metadata, mod_idx: INT, err_idx: INT| md.find_error(mod_idx, err_idx),
@@ -2086,6 +2727,8 @@ pub fn init_engine(
     .register_get("name", StorageMetadata::name)
     .register_get("value_type_name", StorageMetadata::value_type_name)
     .register_get("hasher_name", StorageMetadata::hasher_name)
+    .register_get_result("default", StorageMetadata::default_value)
+    .register_get("is_optional", StorageMetadata::is_optional)
     .register_get("title", StorageMetadata::title)
     .register_get("docs", StorageMetadata::docs)
     .register_type_with_name::<FuncMetadata>("FuncMetadata")
@@ -2107,6 +2750,7 @@ pub fn init_engine(
     .register_get("docs", EventMetadata::docs)
     .register_type_with_name::<ConstMetadata>("ConstMetadata")
     .register_fn("to_string", ConstMetadata::to_string)
+    .register_get_result("value", ConstMetadata::value)
     .register_get("title", ConstMetadata::title)
     .register_get("docs", ConstMetadata::docs)
     .register_type_with_name::<ErrorMetadata>("ErrorMetadata")
@@ -2129,6 +2773,15 @@ pub fn init_engine(
     .register_type_with_name::<Docs>("Docs")
     .register_fn("to_string", Docs::to_string)
     .register_get("title", Docs::title);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<Metadata, Box<EvalAltResult>> {
+  register(engine);
 
   let metadata = client.get_metadata();
 
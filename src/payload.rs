@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::path::Path;
+
+use parity_scale_codec::{Decode, Encode};
+use sp_runtime::{generic::Era, MultiSignature};
+
+use serde::{Deserialize, Serialize};
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap};
+
+use crate::client::{
+  decode_hex, AdditionalSigned, BlockHash, Client, Extra, ExtrinsicCallResult, ExtrinsicV4,
+  OfflineSignOptions, SignedPayload,
+};
+use crate::metadata::EncodedCall;
+use crate::users::{AccountId, SharedUser};
+
+fn to_hex(data: &[u8]) -> String {
+  format!("0x{}", hex::encode(data))
+}
+
+/// Unsigned (or signed) extrinsic payload that can be passed between machines/people.
+///
+/// The genesis hash identifies the chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayloadFile {
+  pub call: String,
+  pub signer: AccountId,
+  pub era: String,
+  pub nonce: u32,
+  pub spec_version: u32,
+  pub transaction_version: u32,
+  pub genesis_hash: BlockHash,
+  pub block_hash: BlockHash,
+  pub signature: Option<String>,
+}
+
+impl PayloadFile {
+  pub fn new(call: &EncodedCall, signer: AccountId, opts: OfflineSignOptions) -> Self {
+    let (spec_version, transaction_version, genesis_hash, block_hash, ..) = opts.additional;
+    Self {
+      call: to_hex(&call.encode()),
+      signer,
+      era: to_hex(&opts.era.encode()),
+      nonce: opts.nonce,
+      spec_version,
+      transaction_version,
+      genesis_hash,
+      block_hash,
+      signature: None,
+    }
+  }
+
+  pub fn load(path: &str) -> Result<Self, Box<EvalAltResult>> {
+    let file = File::open(Path::new(path))
+      .map_err(|e| format!("Failed to open payload file {}: {}", path, e))?;
+    Ok(
+      serde_json::from_reader(file)
+        .map_err(|e| format!("Failed to parse payload file {}: {}", path, e))?,
+    )
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), Box<EvalAltResult>> {
+    let file = File::create(Path::new(path))
+      .map_err(|e| format!("Failed to create payload file {}: {}", path, e))?;
+    serde_json::to_writer_pretty(file, self)
+      .map_err(|e| format!("Failed to write payload file {}: {}", path, e))?;
+    Ok(())
+  }
+
+  fn call(&self) -> Result<EncodedCall, Box<EvalAltResult>> {
+    EncodedCall::from_bytes(&decode_hex(&self.call)?)
+  }
+
+  fn extra(&self) -> Result<Extra, Box<EvalAltResult>> {
+    let era = Era::decode(&mut &decode_hex(&self.era)?[..]).map_err(|e| e.to_string())?;
+    Ok(Extra::new(era, self.nonce))
+  }
+
+  fn additional(&self) -> AdditionalSigned {
+    (
+      self.spec_version,
+      self.transaction_version,
+      self.genesis_hash,
+      self.block_hash,
+      (),
+      (),
+      (),
+    )
+  }
+
+  /// The bytes that the signer needs to sign.
+  pub fn signing_payload(&self) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    let call = self.call()?;
+    let extra = self.extra()?;
+    Ok(SignedPayload::new(&call, &extra, self.additional()).using_encoded(|p| p.to_vec()))
+  }
+
+  pub fn attach_signature(&mut self, sig: &MultiSignature) {
+    self.signature = Some(to_hex(&sig.encode()));
+  }
+
+  /// Build the signed extrinsic.
+  pub fn to_extrinsic(&self) -> Result<ExtrinsicV4, Box<EvalAltResult>> {
+    let sig = self
+      .signature
+      .as_ref()
+      .ok_or_else(|| format!("Payload hasn't been signed"))?;
+    let sig =
+      MultiSignature::decode(&mut &decode_hex(sig)?[..]).map_err(|e| e.to_string())?;
+    Ok(ExtrinsicV4::signed(
+      self.signer.clone(),
+      sig,
+      self.extra()?,
+      self.call()?,
+    ))
+  }
+}
+
+fn export_unsigned(
+  client: &Client,
+  path: &str,
+  call: EncodedCall,
+  signer: AccountId,
+) -> Result<(), Box<EvalAltResult>> {
+  let nonce = client.get_nonce(signer.clone())?.unwrap_or(0);
+  let (era, additional, _) = client.get_era()?;
+  let opts = OfflineSignOptions {
+    era,
+    nonce,
+    additional,
+  };
+  PayloadFile::new(&call, signer, opts).save(path)
+}
+
+fn attach_signature(path: &str, sig: MultiSignature) -> Result<(), Box<EvalAltResult>> {
+  let mut payload = PayloadFile::load(path)?;
+  payload.attach_signature(&sig);
+  payload.save(path)
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_result_fn(
+      "export_unsigned",
+      |client: &mut Client, path: &str, call: EncodedCall, signer: AccountId| {
+        export_unsigned(client, path, call, signer)
+      },
+    )
+    .register_result_fn(
+      "export_unsigned",
+      |_client: &mut Client, path: &str, call: EncodedCall, signer: AccountId, opts: RMap| {
+        let opts = OfflineSignOptions::from_map(opts)?;
+        PayloadFile::new(&call, signer, opts).save(path)
+      },
+    )
+    .register_result_fn(
+      "payload_to_sign",
+      |_client: &mut Client, path: &str| PayloadFile::load(path)?.signing_payload(),
+    )
+    .register_result_fn(
+      "attach_signature",
+      |_client: &mut Client, path: &str, sig: MultiSignature| attach_signature(path, sig),
+    )
+    .register_result_fn(
+      "attach_signature",
+      |_client: &mut Client, path: &str, sig: &str| {
+        let sig =
+          MultiSignature::decode(&mut &decode_hex(sig)?[..]).map_err(|e| e.to_string())?;
+        attach_signature(path, sig)
+      },
+    )
+    .register_result_fn(
+      "submit_from_file",
+      |client: &mut Client, path: &str| -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+        let xt = PayloadFile::load(path)?.to_extrinsic()?;
+        client.submit(xt.to_hex())
+      },
+    )
+    .register_result_fn(
+      "sign_payload_file",
+      |user: &mut SharedUser, path: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+        let mut payload = PayloadFile::load(path)?;
+        if payload.signer != user.acc() {
+          Err(format!("Payload signer doesn't match user {}", user.acc()))?;
+        }
+        let sig = user.sign_data(payload.signing_payload()?);
+        payload.attach_signature(&sig);
+        payload.save(path)?;
+        Ok(Dynamic::from(sig))
+      },
+    );
+}
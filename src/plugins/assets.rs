@@ -0,0 +1,216 @@
+use std::convert::TryFrom;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use rust_decimal::Decimal;
+
+use crate::client::{Client, ExtrinsicCallResult, TokenUnits};
+use crate::metadata::{EncodedArgs, Metadata};
+use crate::types::{fixed_point_value, TypeLookup};
+use crate::users::SharedUser;
+
+/// `pallet-assets` calls and balances.  Amounts are in asset units, scaled by the asset's decimals.
+///
+/// The assets pallet's `Balance` type often shares the native `Balance` name, which is
+/// scaled by the native token decimals, so amounts skip the custom `Balance` encoding.
+#[derive(Clone)]
+pub struct Assets {
+  client: Client,
+  metadata: Metadata,
+  // Asset id -> decimals, from `Assets.Metadata` or `set_decimals`.
+  units: Arc<DashMap<INT, TokenUnits>>,
+  // Decimals of the native `Balance` type, if it is scaled.
+  native_units: Option<TokenUnits>,
+}
+
+/// Units of an asset with `decimals` decimal places.
+fn asset_units(decimals: INT) -> Result<TokenUnits, Box<EvalAltResult>> {
+  let decimals = u32::try_from(decimals).map_err(|_| format!("Invalid decimals: {}", decimals))?;
+  TokenUnits::new(decimals, String::new())
+}
+
+impl Assets {
+  pub fn new(client: Client, lookup: &TypeLookup) -> Self {
+    Self {
+      metadata: client.get_metadata(),
+      client,
+      units: Arc::new(DashMap::new()),
+      native_units: lookup
+        .decimals("Balance")
+        .and_then(|decimals| TokenUnits::new(decimals, String::new()).ok()),
+    }
+  }
+
+  fn units(&self, id: INT) -> Result<TokenUnits, Box<EvalAltResult>> {
+    if let Some(units) = self.units.get(&id) {
+      return Ok(units.clone());
+    }
+    let md = self.metadata.get_storage("Assets", "Metadata")?;
+    let key = md.get_map_key(Dynamic::from(id))?;
+    // Assets without metadata have no decimals.
+    let decimals = match self.client.get_storage_by_key(key, None)? {
+      Some(value) => md
+        .decode_value(value.0)?
+        .try_cast::<RMap>()
+        .and_then(|map| map.get("decimals").and_then(|d| d.as_int().ok()))
+        .unwrap_or(0),
+      None => 0,
+    };
+    let units = asset_units(decimals)?;
+    self.units.insert(id, units.clone());
+    Ok(units)
+  }
+
+  fn decimals(&mut self, id: INT) -> Result<INT, Box<EvalAltResult>> {
+    Ok(self.units(id)?.decimals as INT)
+  }
+
+  /// Override the decimals of an asset, e.g. for assets without metadata.
+  fn set_decimals(&mut self, id: INT, decimals: INT) -> Result<(), Box<EvalAltResult>> {
+    self.units.insert(id, asset_units(decimals)?);
+    Ok(())
+  }
+
+  /// Build and submit `Assets.<name>(id, <account_arg>, <amount_arg>)`.
+  ///
+  /// The amount is encoded with the argument's metadata type (e.g. `Compact<Balance>`).
+  fn submit_amount_call(
+    &self,
+    mut user: SharedUser,
+    name: &str,
+    id: INT,
+    (account_arg, account): (&str, Dynamic),
+    (amount_arg, amount): (&str, Dynamic),
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func("Assets", name)?;
+    let raw = self.units(id)?.to_plancks_u128(fixed_point_value(amount)?)?;
+    let mut data = EncodedArgs::new();
+    func.encode_arg("id", Dynamic::from(id), &mut data)?;
+    func.encode_arg(account_arg, account, &mut data)?;
+    func.encode_raw_integer_arg(amount_arg, Decimal::from(raw), &mut data)?;
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  pub fn create(
+    &mut self,
+    user: SharedUser,
+    id: INT,
+    admin: Dynamic,
+    min_balance: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    self.submit_amount_call(user, "create", id, ("admin", admin), ("min_balance", min_balance))
+  }
+
+  pub fn mint(
+    &mut self,
+    user: SharedUser,
+    id: INT,
+    beneficiary: Dynamic,
+    amount: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    self.submit_amount_call(user, "mint", id, ("beneficiary", beneficiary), ("amount", amount))
+  }
+
+  pub fn transfer(
+    &mut self,
+    user: SharedUser,
+    id: INT,
+    target: Dynamic,
+    amount: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    self.submit_amount_call(user, "transfer", id, ("target", target), ("amount", amount))
+  }
+
+  pub fn set_metadata(
+    &mut self,
+    mut user: SharedUser,
+    id: INT,
+    name: &str,
+    symbol: &str,
+    decimals: INT,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func("Assets", "set_metadata")?;
+    let mut data = EncodedArgs::new();
+    func.encode_arg("id", Dynamic::from(id), &mut data)?;
+    data.encode(name.as_bytes());
+    data.encode(symbol.as_bytes());
+    let decimals =
+      u8::try_from(decimals).map_err(|_| format!("Invalid decimals: {}", decimals))?;
+    data.encode(decimals);
+    // Reload the decimals on next use.
+    self.units.remove(&id);
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  /// Decoded `Assets.Metadata` of an asset, `()` if it has none.
+  pub fn metadata(&mut self, id: INT) -> Result<Dynamic, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage("Assets", "Metadata")?;
+    let key = md.get_map_key(Dynamic::from(id))?;
+    match self.client.get_storage_by_key(key, None)? {
+      Some(value) => md.decode_value(value.0),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Asset balance of `account` in asset units.
+  pub fn balance(&mut self, id: INT, account: Dynamic) -> Result<Decimal, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage("Assets", "Account")?;
+    let key = md.get_double_map_key(Dynamic::from(id), account)?;
+    let value = match self.client.get_storage_by_key(key, None)? {
+      Some(value) => md.decode_value(value.0)?,
+      None => return Ok(Decimal::ZERO),
+    };
+    // `AssetAccount` struct, or the older `AssetBalance`.
+    let balance = value
+      .try_cast::<RMap>()
+      .and_then(|mut account| account.remove("balance"))
+      .ok_or_else(|| format!("Missing balance in Assets.Account"))?;
+    let raw = match balance.as_int() {
+      Ok(raw) => Decimal::from(raw),
+      Err(_) => {
+        let balance = balance
+          .as_decimal()
+          .map_err(|_| format!("Expected a number for the asset balance"))?;
+        // Decoded with the native `Balance` scaling, undo it.
+        match &self.native_units {
+          Some(units) => Decimal::from(units.to_plancks_u128(balance)?),
+          None => balance,
+        }
+      }
+    };
+    self.units(id)?.from_plancks(raw)
+  }
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<Assets>("Assets")
+    .register_result_fn("decimals", Assets::decimals)
+    .register_result_fn("set_decimals", Assets::set_decimals)
+    .register_result_fn("create", Assets::create)
+    .register_result_fn("mint", Assets::mint)
+    .register_result_fn("transfer", Assets::transfer)
+    .register_result_fn("set_metadata", Assets::set_metadata)
+    .register_result_fn("metadata", Assets::metadata)
+    .register_result_fn("balance", Assets::balance);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
+
+  globals.insert(
+    "ASSETS".into(),
+    Dynamic::from(Assets::new(client.clone(), lookup)),
+  );
+
+  Ok(())
+}
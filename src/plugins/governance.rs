@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use parity_scale_codec::{Compact, Encode};
+
+use sp_core::{hashing::blake2_256, H256};
+
+use crate::client::{Client, ExtrinsicCallResult};
+use crate::metadata::{EncodedArgs, EncodedCall, Metadata};
+use crate::types::{TypeLookup, TypeRef};
+use crate::users::SharedUser;
+
+/// Calls up to this size are passed inline in a `Bounded` proposal, larger ones need a preimage.
+const MAX_INLINE_PROPOSAL: usize = 128;
+
+/// Highest conviction (`Locked6x`).
+const MAX_CONVICTION: INT = 6;
+
+/// Preimages, proposals, votes and referendum status for both the old `Democracy` pallet
+/// and OpenGov (`Referenda` + `ConvictionVoting`).
+#[derive(Clone)]
+pub struct Governance {
+  client: Client,
+  metadata: Metadata,
+  balance: TypeRef,
+  compact_balance: TypeRef,
+}
+
+impl Governance {
+  pub fn new(client: Client, lookup: &TypeLookup) -> Self {
+    Self {
+      metadata: client.get_metadata(),
+      client,
+      balance: lookup.resolve("Balance"),
+      compact_balance: lookup.resolve("Compact<Balance>"),
+    }
+  }
+
+  fn is_opengov(&mut self) -> bool {
+    self.metadata.get_module("Referenda").is_some()
+  }
+
+  /// Module with the referenda: `Referenda` (OpenGov) or `Democracy`.
+  fn referenda_module(&self) -> &'static str {
+    if self.metadata.get_module("Referenda").is_some() {
+      "Referenda"
+    } else {
+      "Democracy"
+    }
+  }
+
+  pub fn preimage_hash(call: &EncodedCall) -> H256 {
+    H256(blake2_256(&call.encode()))
+  }
+
+  /// Encode `call` as a `Bounded` proposal: inline for small calls, otherwise a preimage lookup.
+  fn encode_bounded(call: &EncodedCall, data: &mut EncodedArgs) {
+    let bytes = call.encode();
+    if bytes.len() <= MAX_INLINE_PROPOSAL {
+      data.encode(1u8); // Bounded::Inline
+      data.encode(&bytes);
+    } else {
+      data.encode(2u8); // Bounded::Lookup
+      data.encode(H256(blake2_256(&bytes)));
+      data.encode(bytes.len() as u32);
+    }
+  }
+
+  /// Note the preimage of `call` with the `Preimage` pallet (or `Democracy` on older chains).
+  pub fn note_preimage(
+    &mut self,
+    mut user: SharedUser,
+    call: EncodedCall,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self
+      .metadata
+      .get_func("Preimage", "note_preimage")
+      .or_else(|_| self.metadata.get_func("Democracy", "note_preimage"))?;
+    let mut data = EncodedArgs::new();
+    data.encode(call.encode());
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  /// Submit a public proposal to the `Democracy` pallet, with a `value` deposit.
+  ///
+  /// Older runtimes only take the preimage hash, so the preimage must be noted separately.
+  pub fn propose(
+    &mut self,
+    mut user: SharedUser,
+    call: EncodedCall,
+    value: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func("Democracy", "propose")?;
+    let mut data = EncodedArgs::new();
+    if func.has_arg("proposal_hash") {
+      data.encode(H256(blake2_256(&call.encode())));
+    } else {
+      Self::encode_bounded(&call, &mut data);
+    }
+    data.write(&self.compact_balance.encode(value)?);
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  /// Submit an OpenGov referendum for `call`, enacted `after` blocks once approved.
+  ///
+  /// `origin` is the chain's origin enum, e.g. `#{ system: "Root" }`.
+  pub fn submit(
+    &mut self,
+    mut user: SharedUser,
+    origin: Dynamic,
+    call: EncodedCall,
+    after: INT,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func("Referenda", "submit")?;
+    let mut data = EncodedArgs::new();
+    func.encode_arg("proposal_origin", origin, &mut data)?;
+    Self::encode_bounded(&call, &mut data);
+    data.encode(1u8); // DispatchTime::After
+    data.encode(after as u32);
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  /// Standard vote on a referendum, with `ConvictionVoting` if available.
+  ///
+  /// `conviction` is 0 (no lock, 0.1x votes) to 6 (`Locked6x`).
+  pub fn vote(
+    &mut self,
+    mut user: SharedUser,
+    index: INT,
+    aye: bool,
+    conviction: INT,
+    balance: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    if !(0..=MAX_CONVICTION).contains(&conviction) {
+      Err(format!(
+        "Invalid conviction {}, expected 0 to {}",
+        conviction, MAX_CONVICTION
+      ))?;
+    }
+    let func = self
+      .metadata
+      .get_func("ConvictionVoting", "vote")
+      .or_else(|_| self.metadata.get_func("Democracy", "vote"))?;
+    let mut data = EncodedArgs::new();
+    data.encode(Compact(index as u32));
+    data.encode(0u8); // AccountVote::Standard
+    // `Vote` is a single byte: the aye flag and the conviction.
+    let vote = (if aye { 0x80u8 } else { 0 }) | conviction as u8;
+    data.encode(vote);
+    data.write(&self.balance.encode(balance)?);
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  fn get_storage(
+    &self,
+    mod_name: &str,
+    storage_name: &str,
+    key: Option<Dynamic>,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = match key {
+      Some(key) => md.get_map_key(key)?,
+      None => md.get_value_key()?,
+    };
+    match self.client.get_storage_by_key(key, None)? {
+      Some(value) => md.decode_value(value.0),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Referendum info (ongoing status or outcome).  Returns `()` for unknown referenda.
+  pub fn referendum(&mut self, index: INT) -> Result<Dynamic, Box<EvalAltResult>> {
+    let storage = match self.referenda_module() {
+      "Referenda" => "ReferendumInfoFor",
+      _ => "ReferendumInfoOf",
+    };
+    self.get_storage(self.referenda_module(), storage, Some(Dynamic::from(index)))
+  }
+
+  /// Number of referenda created so far, also the index of the next one.
+  pub fn referendum_count(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.get_storage(self.referenda_module(), "ReferendumCount", None)
+  }
+
+  /// OpenGov track status: the number of deciding referenda and the queue waiting to decide.
+  pub fn track(&mut self, id: INT) -> Result<RMap, Box<EvalAltResult>> {
+    let mut map = RMap::new();
+    let deciding = self.get_storage("Referenda", "DecidingCount", Some(Dynamic::from(id)))?;
+    let queue = self.get_storage("Referenda", "TrackQueue", Some(Dynamic::from(id)))?;
+    map.insert("deciding".into(), deciding);
+    map.insert("queue".into(), queue);
+    Ok(map)
+  }
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<Governance>("Governance")
+    .register_get("is_opengov", Governance::is_opengov)
+    .register_fn("preimage_hash", |_: &mut Governance, call: EncodedCall| {
+      Governance::preimage_hash(&call)
+    })
+    .register_result_fn("note_preimage", Governance::note_preimage)
+    .register_result_fn("propose", Governance::propose)
+    .register_result_fn("submit", Governance::submit)
+    .register_result_fn("vote", Governance::vote)
+    .register_result_fn("referendum", Governance::referendum)
+    .register_result_fn("referendum_count", Governance::referendum_count)
+    .register_result_fn("track", Governance::track);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
+
+  globals.insert(
+    "GOVERNANCE".into(),
+    Dynamic::from(Governance::new(client.clone(), lookup)),
+  );
+
+  Ok(())
+}
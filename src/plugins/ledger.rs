@@ -5,19 +5,22 @@ use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::sync::{Arc, RwLock};
 
-use rhai::{Dynamic, Engine, EvalAltResult};
+use rhai::{Dynamic, Engine, EvalAltResult, INT};
 
 use sp_core::{ed25519, sr25519};
-use sp_runtime::generic;
 
 use ledger_apdu::{APDUAnswer, APDUCommand, APDUErrorCodes};
 
 use sp_core::Encode;
+use sp_runtime::MultiSignature;
 
-use crate::client::{Client, Extra, ExtrinsicCallResult, ExtrinsicV4, SignedPayload};
+use crate::client::{
+  Client, Extra, ExtrinsicCallResult, ExtrinsicV4, RetryPolicy, SignedPayload,
+};
+use crate::rpc::RequestToken;
 use crate::metadata::EncodedCall;
 use crate::types::TypeLookup;
-use crate::users::AccountId;
+use crate::users::{dynamic_to_bytes, AccountId};
 
 pub const MAX_PACKET_LEN: u32 = 10_000_000;
 
@@ -42,6 +45,9 @@ pub const SLIP0044_POLYMESH: u32 = 595;
 // APP
 pub const APP_POLYMESH: u8 = 0x91;
 
+/// Re-sign with a fresh nonce once if the node rejects an extrinsic because of its nonce.
+pub const NONCE_RETRIES: u32 = 1;
+
 pub trait LedgerSyncTransport: Send + Sync {
   fn send_cmd(&self, command: APDUCommand) -> Result<APDUAnswer, Box<EvalAltResult>>;
 }
@@ -198,13 +204,28 @@ impl SubstrateApp {
     let address = String::from_utf8_lossy(&res[32..len]);
     log::debug!("  -- address: {:?}", address);
 
+    self.refresh_nonce()?;
+    Ok(())
+  }
+
+  /// Reload the nonce from the chain, e.g. after the account was used from another signer.
+  pub fn refresh_nonce(&mut self) -> Result<u32, Box<EvalAltResult>> {
     self.nonce = self.client.get_nonce(self.account_id.clone())?.unwrap_or(0);
     log::debug!(
-      "  Leaded nonce[{}] for account: {:?}",
+      "  Loaded nonce[{}] for account: {:?}",
       self.nonce,
       self.account_id
     );
-    Ok(())
+    Ok(self.nonce)
+  }
+
+  pub fn get_nonce(&self) -> u32 {
+    self.nonce
+  }
+
+  /// Use `nonce` for the next extrinsic.
+  pub fn set_nonce(&mut self, nonce: u32) {
+    self.nonce = nonce;
   }
 
   pub fn get_account_id(&self) -> AccountId {
@@ -255,38 +276,64 @@ impl SubstrateApp {
     Ok(resp)
   }
 
-  pub fn submit_call(
-    &mut self,
-    call: EncodedCall,
-  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
-    let extra = Extra::new(generic::Era::Immortal, self.nonce);
-    let payload = SignedPayload::new(&call, &extra, self.client.get_signed_extra());
-
-    let signature = self.sign(payload.encode())?;
+  /// Sign `data` with the app's scheme.  The device shows the raw bytes for approval.
+  pub fn sign_raw(&self, data: Vec<u8>) -> Result<MultiSignature, Box<EvalAltResult>> {
+    let signature = self.sign(data)?;
     log::debug!(
       "signature res: len={}, sig_type={}, sig={:?}",
       signature.len(),
-      signature[0],
-      &signature[1..]
+      signature.get(0),
+      signature.get(1..)
     );
-    let sig = match self.scheme {
+    if signature.len() < 2 {
+      Err(format!("Ledger: invalid signature length: {}", signature.len()))?;
+    }
+    Ok(match self.scheme {
       SCHEME_ED25519 => ed25519::Signature::from_slice(&signature[1..]).into(),
       SCHEME_SR25519 => sr25519::Signature::from_slice(&signature[1..]).into(),
       scheme => {
         panic!("Unsupported signature scheme: {}", scheme);
       }
-    };
+    })
+  }
+
+  fn sign_and_submit(
+    &mut self,
+    call: EncodedCall,
+  ) -> Result<(RequestToken, String), Box<EvalAltResult>> {
+    let (era, additional, _) = self.client.get_era()?;
+    let extra = Extra::new(era, self.nonce);
+    let payload = SignedPayload::new(&call, &extra, additional);
+
+    let sig = self.sign_raw(payload.encode())?;
 
     let xt = ExtrinsicV4::signed(self.account_id.clone(), sig, extra, call);
     let xthex = xt.to_hex();
 
-    let res = self.client.submit(xthex)?;
+    let res = self.client.inner().submit(xthex)?;
 
     // Only update the nonce if the call was executed.
     self.nonce += 1;
 
     Ok(res)
   }
+
+  pub fn submit_call(
+    &mut self,
+    call: EncodedCall,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let (token, xthex) = self.sign_and_submit(call)?;
+    Ok(ExtrinsicCallResult::new(&self.client, token, xthex))
+  }
+
+  /// Reload the nonce, then sign and submit the call again.
+  fn resubmit_call(
+    &mut self,
+    call: EncodedCall,
+  ) -> Result<(RequestToken, String), Box<EvalAltResult>> {
+    self.refresh_nonce()?;
+    self.sign_and_submit(call)
+  }
 }
 
 #[derive(Clone)]
@@ -297,11 +344,42 @@ impl SharedApp {
     self.0.read().unwrap().get_account_id()
   }
 
+  fn nonce(&mut self) -> INT {
+    self.0.read().unwrap().get_nonce() as INT
+  }
+
+  pub fn refresh_nonce(&mut self) -> Result<INT, Box<EvalAltResult>> {
+    Ok(self.0.write().unwrap().refresh_nonce()? as INT)
+  }
+
+  pub fn set_nonce(&mut self, nonce: INT) -> Result<(), Box<EvalAltResult>> {
+    if nonce < 0 || nonce > u32::MAX as INT {
+      Err(format!("Invalid nonce: {}", nonce))?;
+    }
+    self.0.write().unwrap().set_nonce(nonce as u32);
+    Ok(())
+  }
+
+  /// Sign a non-extrinsic payload (e.g. an off-chain authorization), given as a `Blob`
+  /// or a string (`0x` prefixed hex or UTF-8 text).
+  pub fn sign_raw(&mut self, data: Dynamic) -> Result<MultiSignature, Box<EvalAltResult>> {
+    let data = dynamic_to_bytes(data)?;
+    self.0.read().unwrap().sign_raw(data)
+  }
+
+  /// Submit a call.  If it is dropped or rejected because of its nonce (e.g. the account
+  /// was used by another signer), the nonce is reloaded and the call signed again.
   pub fn submit_call(
     &mut self,
     call: EncodedCall,
   ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
-    self.0.write().unwrap().submit_call(call)
+    let mut res = self.0.write().unwrap().submit_call(call.clone())?;
+    let app = self.clone();
+    res.set_retry(RetryPolicy {
+      max_retries: NONCE_RETRIES,
+      resubmit: Arc::new(move || app.0.write().unwrap().resubmit_call(call.clone())),
+    });
+    Ok(res)
   }
 }
 
@@ -378,18 +456,26 @@ impl LedgerApps {
   }
 }
 
-pub fn init_engine(
-  engine: &mut Engine,
-  globals: &mut HashMap<String, Dynamic>,
-  client: &Client,
-  lookup: &TypeLookup,
-) -> Result<(), Box<EvalAltResult>> {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<SharedApp>("LedgerApp")
     .register_get("acc", SharedApp::acc)
+    .register_get("nonce", SharedApp::nonce)
+    .register_result_fn("refresh_nonce", SharedApp::refresh_nonce)
+    .register_result_fn("set_nonce", SharedApp::set_nonce)
+    .register_result_fn("sign_raw", SharedApp::sign_raw)
     .register_result_fn("submit", SharedApp::submit_call)
     .register_type_with_name::<LedgerApps>("LedgerApps")
     .register_result_fn("get_app", LedgerApps::get_app);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
 
   globals.insert(
     "LedgerApps".into(),
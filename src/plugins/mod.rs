@@ -5,18 +5,40 @@ use rhai::{Dynamic, Engine, EvalAltResult};
 use crate::client::Client;
 use crate::types::TypeLookup;
 
+pub mod assets;
+pub mod governance;
 pub mod ledger;
+pub mod nfts;
+pub mod scheduler;
+
+pub mod registry;
+pub use registry::PluginRegistry;
 
 #[cfg(feature = "polymesh")]
 pub mod polymesh;
 
+pub fn register(engine: &mut Engine) {
+  assets::register(engine);
+  governance::register(engine);
+  ledger::register(engine);
+  nfts::register(engine);
+  scheduler::register(engine);
+
+  #[cfg(feature = "polymesh")]
+  polymesh::register(engine);
+}
+
 pub fn init_engine(
   engine: &mut Engine,
   globals: &mut HashMap<String, Dynamic>,
   client: &Client,
   lookup: &TypeLookup,
 ) -> Result<(), Box<EvalAltResult>> {
+  assets::init_engine(engine, globals, client, lookup)?;
+  governance::init_engine(engine, globals, client, lookup)?;
   ledger::init_engine(engine, globals, client, lookup)?;
+  nfts::init_engine(engine, globals, client, lookup)?;
+  scheduler::init_engine(engine, globals, client, lookup)?;
 
   #[cfg(feature = "polymesh")]
   polymesh::init_engine(engine, globals, client, lookup)?;
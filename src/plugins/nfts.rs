@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use parity_scale_codec::Decode;
+
+use sp_core::storage::StorageKey;
+
+use crate::client::{Client, ExtrinsicCallResult};
+use crate::metadata::{EncodedArgs, Metadata};
+use crate::types::TypeLookup;
+use crate::users::{dynamic_to_account, SharedUser};
+
+/// Number of keys to fetch per request when listing items.
+const KEYS_PAGE_SIZE: u32 = 1000;
+
+/// Length of the `Blake2_128Concat` hash before each key.
+const BLAKE2_128_LEN: usize = 16;
+
+/// `AttributeNamespace::CollectionOwner` in the `Nfts` pallet.
+const NAMESPACE_COLLECTION_OWNER: u8 = 1;
+
+fn bytes_to_array(bytes: &[u8]) -> Array {
+  bytes.iter().map(|b| Dynamic::from(*b as INT)).collect()
+}
+
+/// Collections and items of the `Nfts` pallet, or the older `Uniques` pallet.
+///
+/// Collection and item ids are expected to be `u32`.
+#[derive(Clone)]
+pub struct Nfts {
+  client: Client,
+  metadata: Metadata,
+  pallet: &'static str,
+}
+
+impl Nfts {
+  pub fn new(client: Client) -> Self {
+    let metadata = client.get_metadata();
+    let pallet = if metadata.get_module("Nfts").is_some() {
+      "Nfts"
+    } else {
+      "Uniques"
+    };
+    Self {
+      client,
+      metadata,
+      pallet,
+    }
+  }
+
+  fn pallet(&mut self) -> String {
+    self.pallet.to_string()
+  }
+
+  fn get_value(
+    &self,
+    storage_name: &str,
+    keys: Vec<Dynamic>,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(self.pallet, storage_name)?;
+    let key = match keys.len() {
+      0 => md.get_value_key()?,
+      _ => md.get_nmap_key(keys)?,
+    };
+    match self.client.get_storage_by_key(key, None)? {
+      Some(value) => md.decode_value(value.0),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Decode the `u32` id after `prefix` from each key of a `Blake2_128Concat` map.
+  fn key_ids(&self, prefix: StorageKey) -> Result<Array, Box<EvalAltResult>> {
+    let mut ids = Array::new();
+    let mut start_key: Option<StorageKey> = None;
+    loop {
+      let keys = self
+        .client
+        .get_storage_keys_paged(&prefix, KEYS_PAGE_SIZE, start_key.as_ref(), None)?;
+      for key in &keys {
+        let mut tail = key
+          .0
+          .get(prefix.0.len() + BLAKE2_128_LEN..)
+          .ok_or_else(|| format!("Storage key too short: {} bytes", key.0.len()))?;
+        let id = u32::decode(&mut tail).map_err(|e| e.to_string())?;
+        ids.push(Dynamic::from(id as INT));
+      }
+      if keys.len() < KEYS_PAGE_SIZE as usize {
+        return Ok(ids);
+      }
+      start_key = keys.last().cloned();
+    }
+  }
+
+  /// Id the next `create_collection` will use (`Nfts` only).
+  pub fn next_collection_id(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.get_value("NextCollectionId", vec![])
+  }
+
+  /// Create a collection with the default config: all settings unlocked, no max supply
+  /// and only the issuer can mint.
+  ///
+  /// Only the `Nfts` pallet picks the collection id, use `Uniques.create(id, admin)` otherwise.
+  pub fn create_collection(
+    &mut self,
+    mut user: SharedUser,
+    admin: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    if self.pallet != "Nfts" {
+      Err(format!("create_collection needs the Nfts pallet, use Uniques.create(id, admin)"))?;
+    }
+    let func = self.metadata.get_func("Nfts", "create")?;
+    let mut data = EncodedArgs::new();
+    func.encode_arg("admin", admin, &mut data)?;
+    // CollectionConfig: settings, max_supply.
+    data.encode(0u64);
+    data.encode(None::<u32>);
+    // MintSettings: mint_type (Issuer), price, start_block, end_block, default_item_settings.
+    data.encode(0u8);
+    data.encode(0u8);
+    data.encode(0u8);
+    data.encode(0u8);
+    data.encode(0u64);
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  pub fn mint(
+    &mut self,
+    mut user: SharedUser,
+    collection: INT,
+    item: INT,
+    owner: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func(self.pallet, "mint")?;
+    // `Nfts.mint` has a trailing optional `witness_data`.
+    let call = func.encode_args(vec![Dynamic::from(collection), Dynamic::from(item), owner])?;
+    user.submit_call(call)
+  }
+
+  /// Set an attribute of a collection (`item` is `()`) or item, as the collection owner.
+  pub fn set_attribute(
+    &mut self,
+    mut user: SharedUser,
+    collection: INT,
+    item: Dynamic,
+    key: &str,
+    value: &str,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let func = self.metadata.get_func(self.pallet, "set_attribute")?;
+    let mut data = EncodedArgs::new();
+    func.encode_arg("collection", Dynamic::from(collection), &mut data)?;
+    func.encode_arg("maybe_item", item, &mut data)?;
+    if func.has_arg("namespace") {
+      data.encode(NAMESPACE_COLLECTION_OWNER);
+    }
+    data.encode(key.as_bytes());
+    data.encode(value.as_bytes());
+    user.submit_call(func.encode_raw_call(data))
+  }
+
+  /// Attribute value (as a string) set by the collection owner.  Returns `()` if not set.
+  pub fn attribute(
+    &mut self,
+    collection: INT,
+    item: Dynamic,
+    key: &str,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let mut keys = vec![Dynamic::from(collection), item];
+    if self.pallet == "Nfts" {
+      keys.push("CollectionOwner".into());
+    }
+    keys.push(Dynamic::from(bytes_to_array(key.as_bytes())));
+    let md = self.metadata.get_storage(self.pallet, "Attribute")?;
+    match self.client.get_storage_by_key(md.get_nmap_key(keys)?, None)? {
+      // `(value, deposit)`: only decode the value.
+      Some(data) => {
+        let value = Vec::<u8>::decode(&mut &data.0[..]).map_err(|e| e.to_string())?;
+        Ok(Dynamic::from(String::from_utf8_lossy(&value).to_string()))
+      }
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Owner of an item, `()` if the item doesn't exist.
+  pub fn owner(&mut self, collection: INT, item: INT) -> Result<Dynamic, Box<EvalAltResult>> {
+    let storage = match self.pallet {
+      "Nfts" => "Item",
+      _ => "Asset",
+    };
+    let details = self.get_value(storage, vec![Dynamic::from(collection), Dynamic::from(item)])?;
+    Ok(
+      details
+        .try_cast::<RMap>()
+        .and_then(|mut map| map.remove("owner"))
+        .unwrap_or(Dynamic::UNIT),
+    )
+  }
+
+  /// Ids of the items in `collection` owned by `account`.
+  pub fn items(&mut self, account: Dynamic, collection: INT) -> Result<Array, Box<EvalAltResult>> {
+    let account = Dynamic::from(dynamic_to_account(account)?);
+    let md = self.metadata.get_storage(self.pallet, "Account")?;
+    let prefix = md.get_nmap_prefix(vec![account, Dynamic::from(collection)])?;
+    self.key_ids(prefix)
+  }
+
+  /// Ids of the collections owned by `account`.
+  pub fn collections(&mut self, account: Dynamic) -> Result<Array, Box<EvalAltResult>> {
+    let account = Dynamic::from(dynamic_to_account(account)?);
+    let storage = match self.pallet {
+      "Nfts" => "CollectionAccount",
+      _ => "ClassAccount",
+    };
+    let md = self.metadata.get_storage(self.pallet, storage)?;
+    let prefix = md.get_double_map_prefix(account)?;
+    self.key_ids(prefix)
+  }
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<Nfts>("Nfts")
+    .register_get("pallet", Nfts::pallet)
+    .register_result_fn("next_collection_id", Nfts::next_collection_id)
+    .register_result_fn("create_collection", Nfts::create_collection)
+    .register_result_fn("mint", Nfts::mint)
+    .register_result_fn("set_attribute", Nfts::set_attribute)
+    .register_result_fn("attribute", Nfts::attribute)
+    .register_result_fn("owner", Nfts::owner)
+    .register_result_fn("items", Nfts::items)
+    .register_result_fn("collections", Nfts::collections);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  _lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
+
+  globals.insert("NFTS".into(), Dynamic::from(Nfts::new(client.clone())));
+
+  Ok(())
+}
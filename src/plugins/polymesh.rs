@@ -104,12 +104,7 @@ impl PolymeshUtils {
   }
 }
 
-pub fn init_engine(
-  engine: &mut Engine,
-  globals: &mut HashMap<String, Dynamic>,
-  client: &Client,
-  lookup: &TypeLookup,
-) -> Result<(), Box<EvalAltResult>> {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<PolymeshUtils>("PolymeshUtils")
     .register_result_fn(
@@ -141,6 +136,15 @@ pub fn init_engine(
       let s = String::from_utf8_lossy(ticker.as_slice());
       format!("{}", s)
     });
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
 
   let utils = PolymeshUtils::new(client.clone())?;
   globals.insert("PolymeshUtils".into(), Dynamic::from(utils.clone()));
@@ -0,0 +1,104 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rhai::{Engine, EvalAltResult, Module, Scope};
+
+#[cfg(feature = "dylib")]
+use libloading::{Library, Symbol};
+
+/// Symbol a native plugin exports to register its functions.
+///
+/// Native plugins must be built with the same compiler and `sub-script` version.
+#[cfg(feature = "dylib")]
+pub const NATIVE_PLUGIN_SYMBOL: &[u8] = b"sub_script_plugin_register";
+
+#[cfg(feature = "dylib")]
+type NativeRegisterFn = fn(&mut Engine);
+
+#[cfg(feature = "dylib")]
+struct NativePlugin {
+  // Keep the library loaded, `register` points into it.
+  _lib: Library,
+  register: NativeRegisterFn,
+}
+
+/// Plugins loaded at runtime: Rhai module files and (with the `dylib` feature) native libraries.
+#[derive(Clone, Default)]
+pub struct PluginRegistry {
+  modules: Vec<(String, Arc<Module>)>,
+  #[cfg(feature = "dylib")]
+  native: Vec<Arc<NativePlugin>>,
+}
+
+impl PluginRegistry {
+  /// Load the plugin files.  `.rhai` files become a static module named after the file,
+  /// everything else is loaded as a native plugin.
+  pub fn load(engine: &mut Engine, files: &[String]) -> Result<Self, Box<EvalAltResult>> {
+    let mut registry = Self::default();
+    for file in files {
+      let path = Path::new(file);
+      if path.extension().map_or(false, |ext| ext == "rhai") {
+        registry.load_module(engine, path)?;
+      } else {
+        registry.load_native(engine, path)?;
+      }
+    }
+    Ok(registry)
+  }
+
+  fn load_module(&mut self, engine: &mut Engine, path: &Path) -> Result<(), Box<EvalAltResult>> {
+    let name = path
+      .file_stem()
+      .map(|s| s.to_string_lossy().to_string())
+      .ok_or_else(|| format!("Invalid plugin module path: {:?}", path))?;
+    let ast = engine
+      .compile_file(path.into())
+      .map_err(|e| format!("Failed to load plugin module {:?}: {}", path, e))?;
+    let module = Module::eval_ast_as_new(Scope::new(), &ast, engine)
+      .map_err(|e| format!("Failed to initialize plugin module {:?}: {}", path, e))?;
+    let module = Arc::new(module);
+    engine.register_static_module(name.as_str(), module.clone());
+    log::info!("Loaded plugin module '{}' from {:?}", name, path);
+    self.modules.push((name, module));
+    Ok(())
+  }
+
+  #[cfg(feature = "dylib")]
+  fn load_native(&mut self, engine: &mut Engine, path: &Path) -> Result<(), Box<EvalAltResult>> {
+    let plugin = unsafe {
+      let lib = Library::new(path)
+        .map_err(|e| format!("Failed to load native plugin {:?}: {}", path, e))?;
+      let register: Symbol<NativeRegisterFn> = lib
+        .get(NATIVE_PLUGIN_SYMBOL)
+        .map_err(|e| format!("Native plugin {:?} has no register function: {}", path, e))?;
+      let register = *register;
+      NativePlugin {
+        _lib: lib,
+        register,
+      }
+    };
+    (plugin.register)(engine);
+    log::info!("Loaded native plugin {:?}", path);
+    self.native.push(Arc::new(plugin));
+    Ok(())
+  }
+
+  #[cfg(not(feature = "dylib"))]
+  fn load_native(&mut self, _engine: &mut Engine, path: &Path) -> Result<(), Box<EvalAltResult>> {
+    Err(format!(
+      "Can't load native plugin {:?}: built without the `dylib` feature",
+      path
+    ))?
+  }
+
+  /// Register the loaded plugins with another engine (e.g. for a spawned task).
+  pub fn register(&self, engine: &mut Engine) {
+    for (name, module) in &self.modules {
+      engine.register_static_module(name.as_str(), module.clone());
+    }
+    #[cfg(feature = "dylib")]
+    for plugin in &self.native {
+      (plugin.register)(engine);
+    }
+  }
+}
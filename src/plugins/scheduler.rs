@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use parity_scale_codec::Decode;
+
+use sp_core::storage::StorageKey;
+
+use crate::client::{BlockHash, Client};
+use crate::metadata::{EncodedCall, Metadata};
+use crate::types::TypeLookup;
+
+/// Number of `Agenda` keys to fetch per request.
+const KEYS_PAGE_SIZE: u32 = 1000;
+
+/// Length of the `Twox64Concat` hash before the block number in `Agenda` keys.
+const TWOX_64_LEN: usize = 8;
+
+/// Blocks used to estimate the block time.
+const BLOCK_TIME_SAMPLE: u64 = 100;
+
+/// Scheduled calls (`Scheduler.Agenda`) with their calls decoded, including calls
+/// only referenced by a preimage hash.
+#[derive(Clone)]
+pub struct Scheduler {
+  client: Client,
+  metadata: Metadata,
+}
+
+impl Scheduler {
+  pub fn new(client: Client) -> Self {
+    Self {
+      metadata: client.get_metadata(),
+      client,
+    }
+  }
+
+  /// Length of a preimage from its `RequestStatusFor` (or older `StatusFor`) entry.
+  fn preimage_len(&self, hash: &Dynamic) -> Result<Option<Dynamic>, Box<EvalAltResult>> {
+    for storage_name in &["RequestStatusFor", "StatusFor"] {
+      let md = match self.metadata.get_storage("Preimage", storage_name) {
+        Ok(md) => md,
+        Err(_) => continue,
+      };
+      let key = md.get_map_key(hash.clone())?;
+      let status = match self.client.get_storage_by_key(key, None)? {
+        Some(value) => md.decode_value(value.0)?,
+        None => continue,
+      };
+      // `#{ Unrequested: #{ len, .. } }` or `#{ Requested: #{ maybe_len, .. } }`.
+      let len = status
+        .try_cast::<RMap>()
+        .and_then(|status| status.values().next().cloned())
+        .and_then(|status| status.try_cast::<RMap>())
+        .and_then(|status| {
+          status
+            .get("len")
+            .or_else(|| status.get("maybe_len"))
+            .filter(|len| !len.is::<()>())
+            .cloned()
+        });
+      if len.is_some() {
+        return Ok(len);
+      }
+    }
+    Ok(None)
+  }
+
+  /// Bytes of a noted preimage.  `len` is part of the key on newer runtimes, if it isn't
+  /// known it is read from the preimage's status.
+  fn preimage(
+    &self,
+    hash: Dynamic,
+    len: Option<Dynamic>,
+  ) -> Result<Option<Vec<u8>>, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage("Preimage", "PreimageFor")?;
+    let len = match len {
+      Some(len) => Some(len),
+      None => self.preimage_len(&hash)?,
+    };
+    // Older runtimes only key the preimages by hash.
+    let key = match len.map(|len| md.get_map_key(Dynamic::from(vec![hash.clone(), len]))) {
+      Some(Ok(key)) => key,
+      _ => md.get_map_key(hash)?,
+    };
+    match self.client.get_storage_by_key(key, None)? {
+      Some(value) => Ok(Some(
+        Vec::<u8>::decode(&mut &value.0[..]).map_err(|e| e.to_string())?,
+      )),
+      None => Ok(None),
+    }
+  }
+
+  fn decode_bytes(&self, bytes: &[u8]) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.client.decode_call(&EncodedCall::from_bytes(bytes)?)
+  }
+
+  /// Decode the call of a `Scheduled` entry.  Returns `()` if the preimage is missing.
+  ///
+  /// Handles plain calls, `MaybeHashed` (`Value`/`Hash`) and `Bounded` (`Inline`/`Lookup`/`Legacy`).
+  fn decode_scheduled_call(&self, call: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+    let (name, value) = match call.read_lock::<RMap>() {
+      Some(map) if map.len() == 1 => map
+        .iter()
+        .next()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .unwrap(),
+      _ => return Ok(call),
+    };
+    let bytes = match name.as_str() {
+      "Value" => return Ok(value),
+      "Inline" => Some(
+        value
+          .try_cast::<Array>()
+          .ok_or_else(|| format!("Expected bytes for an inline call"))?
+          .into_iter()
+          .map(|b| b.as_int().unwrap_or_default() as u8)
+          .collect(),
+      ),
+      "Hash" => self.preimage(value, None)?,
+      "Lookup" | "Legacy" => {
+        let mut lookup = value
+          .try_cast::<RMap>()
+          .ok_or_else(|| format!("Expected a map for {}", name))?;
+        let hash = lookup.remove("hash").unwrap_or_default();
+        self.preimage(hash, lookup.remove("len"))?
+      }
+      // Already a decoded call (older runtimes).
+      _ => return Ok(call),
+    };
+    match bytes {
+      Some(bytes) => self.decode_bytes(&bytes),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Add `block`, `index` and `call_decoded` to the entries of an agenda.
+  fn agenda_entries(
+    &self,
+    block: INT,
+    agenda: Dynamic,
+    out: &mut Array,
+  ) -> Result<(), Box<EvalAltResult>> {
+    let agenda = match agenda.try_cast::<Array>() {
+      Some(agenda) => agenda,
+      None => return Ok(()),
+    };
+    for (index, entry) in agenda.into_iter().enumerate() {
+      let mut entry = match entry.try_cast::<RMap>() {
+        Some(entry) => entry,
+        // Cancelled or already dispatched.
+        None => continue,
+      };
+      let call = entry.get("call").cloned().unwrap_or_default();
+      entry.insert("call_decoded".into(), self.decode_scheduled_call(call)?);
+      entry.insert("block".into(), Dynamic::from(block));
+      entry.insert("index".into(), Dynamic::from(index as INT));
+      out.push(Dynamic::from(entry));
+    }
+    Ok(())
+  }
+
+  /// Calls scheduled at `block`.
+  pub fn agenda(&mut self, block: INT) -> Result<Array, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage("Scheduler", "Agenda")?;
+    let key = md.get_map_key(Dynamic::from(block))?;
+    let mut entries = Array::new();
+    if let Some(value) = self.client.get_storage_by_key(key, None)? {
+      self.agenda_entries(block, md.decode_value(value.0)?, &mut entries)?;
+    }
+    Ok(entries)
+  }
+
+  /// All scheduled calls, ordered by block.
+  pub fn scheduled(&mut self) -> Result<Array, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage("Scheduler", "Agenda")?;
+    let prefix = md.get_map_prefix()?;
+    let mut entries = Array::new();
+    let mut start_key: Option<StorageKey> = None;
+    loop {
+      let keys = self
+        .client
+        .get_storage_keys_paged(&prefix, KEYS_PAGE_SIZE, start_key.as_ref(), None)?;
+      let values = self.client.get_storage_by_keys(&keys, None)?;
+      for (key, value) in keys.iter().zip(values) {
+        let mut tail = key
+          .0
+          .get(prefix.0.len() + TWOX_64_LEN..)
+          .ok_or_else(|| format!("Storage key too short: {} bytes", key.0.len()))?;
+        let block = u32::decode(&mut tail).map_err(|e| e.to_string())? as INT;
+        if let Some(value) = value {
+          self.agenda_entries(block, md.decode_value(value.0)?, &mut entries)?;
+        }
+      }
+      if keys.len() < KEYS_PAGE_SIZE as usize {
+        break;
+      }
+      start_key = keys.last().cloned();
+    }
+    // Keys are ordered by their hash, not the block number.
+    entries.sort_by_key(|entry| {
+      entry
+        .read_lock::<RMap>()
+        .and_then(|entry| entry.get("block").and_then(|b| b.as_int().ok()))
+        .unwrap_or_default()
+    });
+    Ok(entries)
+  }
+
+  /// `Timestamp.Now` at a block.
+  fn timestamp_at(&self, hash: Option<BlockHash>) -> Result<u64, Box<EvalAltResult>> {
+    match self.client.get_storage_value("Timestamp", "Now", hash)? {
+      Some(value) => Ok(u64::decode(&mut &value.0[..]).map_err(|e| e.to_string())?),
+      None => Err(format!("Missing Timestamp.Now"))?,
+    }
+  }
+
+  /// When `block` is expected to execute: blocks left and an estimated time (in seconds),
+  /// using the average block time of the last finalized blocks.
+  pub fn eta(&mut self, block: INT) -> Result<RMap, Box<EvalAltResult>> {
+    let (head_hash, head) = self.client.get_finalized_head()?;
+    let blocks = (block as i64 - head as i64).max(0);
+    let sample = BLOCK_TIME_SAMPLE.min(head);
+    let block_ms = if sample > 0 {
+      let from = self.client.block_hash_at(head - sample)?;
+      let elapsed = self
+        .timestamp_at(Some(head_hash))?
+        .saturating_sub(self.timestamp_at(Some(from))?);
+      elapsed / sample
+    } else {
+      0
+    };
+    let mut map = RMap::new();
+    map.insert("head".into(), Dynamic::from(head as INT));
+    map.insert("blocks".into(), Dynamic::from(blocks as INT));
+    map.insert("block_time_ms".into(), Dynamic::from(block_ms as INT));
+    map.insert(
+      "seconds".into(),
+      Dynamic::from((blocks as u64 * block_ms / 1000) as INT),
+    );
+    Ok(map)
+  }
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<Scheduler>("Scheduler")
+    .register_result_fn("agenda", Scheduler::agenda)
+    .register_result_fn("scheduled", Scheduler::scheduled)
+    .register_result_fn("eta", Scheduler::eta);
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  globals: &mut HashMap<String, Dynamic>,
+  client: &Client,
+  _lookup: &TypeLookup,
+) -> Result<(), Box<EvalAltResult>> {
+  register(engine);
+
+  globals.insert(
+    "SCHEDULER".into(),
+    Dynamic::from(Scheduler::new(client.clone())),
+  );
+
+  Ok(())
+}
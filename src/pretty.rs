@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use sp_core::{H160, U256};
+
+use rhai::{Array, Dynamic, Engine, ImmutableString, Map as RMap};
+
+use crate::addrbook::format_account;
+use crate::client::{BlockHash, EventRecord, EventRecords};
+use crate::users::{AccountId, SharedUser};
+
+const INDENT: &str = "  ";
+
+/// Arrays/maps of simple values shorter than this are kept on one line.
+const MAX_INLINE_LEN: usize = 60;
+
+/// Integer arrays at least this long, with only byte values, are shown as hex.
+const MIN_HEX_BYTES: usize = 8;
+
+fn as_bytes(arr: &Array) -> Option<Vec<u8>> {
+  if arr.len() < MIN_HEX_BYTES {
+    return None;
+  }
+  arr
+    .iter()
+    .map(|v| v.as_int().ok().filter(|b| (0..=255).contains(b)).map(|b| b as u8))
+    .collect()
+}
+
+fn is_nested(val: &Dynamic) -> bool {
+  match val.read_lock::<Array>() {
+    Some(arr) => as_bytes(&arr).is_none(),
+    None => val.is::<RMap>(),
+  }
+}
+
+/// Format a value that isn't an array or map.
+fn pretty_scalar(val: &Dynamic) -> String {
+  if val.is::<()>() {
+    "()".into()
+  } else if let Some(s) = val.read_lock::<ImmutableString>() {
+    format!("{:?}", s.as_str())
+  } else if let Some(data) = val.read_lock::<Vec<u8>>() {
+    format!("0x{}", hex::encode(&*data))
+  } else if let Some(arr) = val.read_lock::<Array>() {
+    // Only byte arrays.
+    format!("0x{}", hex::encode(as_bytes(&arr).unwrap_or_default()))
+  } else if let Some(acc) = val.read_lock::<AccountId>() {
+    format_account(&acc)
+  } else if let Some(user) = val.read_lock::<SharedUser>() {
+    format_account(&user.clone().acc())
+  } else if let Some(hash) = val.read_lock::<BlockHash>() {
+    format!("{:?}", *hash)
+  } else if let Some(hash) = val.read_lock::<H160>() {
+    format!("{:?}", *hash)
+  } else if let Some(num) = val.read_lock::<U256>() {
+    num.to_string()
+  } else if let Some(event) = val.read_lock::<EventRecord>() {
+    format!("{:?}", *event)
+  } else if let Some(events) = val.read_lock::<EventRecords>() {
+    format!("{:?}", *events)
+  } else if let Ok(dec) = val.as_decimal() {
+    dec.to_string()
+  } else {
+    val.to_string()
+  }
+}
+
+fn pretty_value(out: &mut String, val: &Dynamic, depth: usize) {
+  if let Some(arr) = val.read_lock::<Array>() {
+    if as_bytes(&arr).is_none() {
+      let items = arr.iter().map(|v| (None, v)).collect::<Vec<_>>();
+      return pretty_items(out, ("[", "]"), items, depth);
+    }
+  } else if let Some(map) = val.read_lock::<RMap>() {
+    let items = map
+      .iter()
+      .map(|(k, v)| (Some(k.as_str()), v))
+      .collect::<Vec<_>>();
+    return pretty_items(out, ("{", "}"), items, depth);
+  }
+  out.push_str(&pretty_scalar(val));
+}
+
+fn pretty_items(
+  out: &mut String,
+  (open, close): (&str, &str),
+  items: Vec<(Option<&str>, &Dynamic)>,
+  depth: usize,
+) {
+  if items.is_empty() {
+    let _ = write!(out, "{}{}", open, close);
+    return;
+  }
+  // Keep short lists of simple values on one line.
+  if !items.iter().any(|(_, v)| is_nested(v)) {
+    let line = items
+      .iter()
+      .map(|(k, v)| match k {
+        Some(k) => format!("{}: {}", k, pretty_scalar(v)),
+        None => pretty_scalar(v),
+      })
+      .collect::<Vec<_>>()
+      .join(", ");
+    if line.len() <= MAX_INLINE_LEN && !line.contains('\n') {
+      let _ = write!(out, "{}{}{}", open, line, close);
+      return;
+    }
+  }
+  out.push_str(open);
+  out.push('\n');
+  for (key, val) in items {
+    for _ in 0..=depth {
+      out.push_str(INDENT);
+    }
+    if let Some(key) = key {
+      let _ = write!(out, "{}: ", key);
+    }
+    pretty_value(out, val, depth + 1);
+    out.push_str(",\n");
+  }
+  for _ in 0..depth {
+    out.push_str(INDENT);
+  }
+  out.push_str(close);
+}
+
+/// Render a value with indentation, hex byte arrays and SS58 addresses.
+pub fn pretty(val: &Dynamic) -> String {
+  let mut out = String::new();
+  pretty_value(&mut out, val, 0);
+  out
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_fn("pretty", |val: Dynamic| pretty(&val))
+    .register_fn("to_pretty", |val: &mut Dynamic| pretty(val));
+}
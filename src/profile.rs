@@ -0,0 +1,243 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+/// Opt-in profiling of scripts and RPC calls.
+static PROFILE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  // Self time of each stack: `main;scan_blocks;rpc:chain_getBlock` -> time.
+  static ref SAMPLES: Mutex<HashMap<String, Duration>> = Mutex::new(HashMap::new());
+}
+
+struct Frame {
+  name: String,
+  start: Instant,
+  children: Duration,
+}
+
+thread_local! {
+  static PROFILE_STACK: RefCell<Vec<Frame>> = RefCell::new(Vec::new());
+}
+
+pub fn set_profile(enabled: bool) {
+  PROFILE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_profile() -> bool {
+  PROFILE.load(Ordering::Relaxed)
+}
+
+/// Time `func` as a named frame nested in the current thread's frames.
+pub fn scope<N, F, R>(name: N, func: F) -> R
+where
+  N: FnOnce() -> String,
+  F: FnOnce() -> R,
+{
+  if !is_profile() {
+    return func();
+  }
+  PROFILE_STACK.with(|s| {
+    s.borrow_mut().push(Frame {
+      name: name(),
+      start: Instant::now(),
+      children: Duration::default(),
+    })
+  });
+  let res = func();
+  PROFILE_STACK.with(|s| {
+    let mut stack = s.borrow_mut();
+    if let Some(frame) = stack.pop() {
+      let elapsed = frame.start.elapsed();
+      let mut path = stack
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+      if !path.is_empty() {
+        path.push(';');
+      }
+      path.push_str(&frame.name);
+      let self_time = elapsed.checked_sub(frame.children).unwrap_or_default();
+      *SAMPLES.lock().unwrap().entry(path).or_default() += self_time;
+      if let Some(parent) = stack.last_mut() {
+        parent.children += elapsed;
+      }
+    }
+  });
+  res
+}
+
+/// Write the samples in the "folded stacks" format (microseconds) used by flame graph tools.
+pub fn write_folded(path: &str) -> std::io::Result<()> {
+  let samples = SAMPLES.lock().unwrap();
+  let mut stacks = samples.iter().collect::<Vec<_>>();
+  stacks.sort_by(|a, b| a.0.cmp(b.0));
+
+  let mut out = BufWriter::new(File::create(path)?);
+  for (stack, time) in stacks {
+    writeln!(out, "{} {}", stack, time.as_micros())?;
+  }
+  out.flush()
+}
+
+/// Self time per frame name, most expensive first.
+pub fn totals() -> Vec<(String, Duration)> {
+  let samples = SAMPLES.lock().unwrap();
+  let mut totals: HashMap<&str, Duration> = HashMap::new();
+  for (stack, time) in samples.iter() {
+    let name = stack.rsplit(';').next().unwrap_or(stack);
+    *totals.entry(name).or_default() += *time;
+  }
+  let mut totals = totals
+    .into_iter()
+    .map(|(name, time)| (name.to_string(), time))
+    .collect::<Vec<_>>();
+  totals.sort_by(|a, b| b.1.cmp(&a.1));
+  totals
+}
+
+enum Token<'a> {
+  Ident(&'a str),
+  Punct(char),
+}
+
+/// Split a script into identifiers and punctuation, skipping strings and comments.
+fn tokenize(source: &str) -> Vec<(usize, Token<'_>)> {
+  let bytes = source.as_bytes();
+  let mut tokens = Vec::new();
+  let mut idx = 0;
+  while idx < bytes.len() {
+    let c = bytes[idx];
+    match c {
+      b'/' if bytes.get(idx + 1) == Some(&b'/') => {
+        while idx < bytes.len() && bytes[idx] != b'\n' {
+          idx += 1;
+        }
+      }
+      b'/' if bytes.get(idx + 1) == Some(&b'*') => {
+        // Block comments can be nested.
+        let mut depth = 0;
+        while idx < bytes.len() {
+          if bytes[idx] == b'/' && bytes.get(idx + 1) == Some(&b'*') {
+            depth += 1;
+            idx += 2;
+          } else if bytes[idx] == b'*' && bytes.get(idx + 1) == Some(&b'/') {
+            depth -= 1;
+            idx += 2;
+            if depth == 0 {
+              break;
+            }
+          } else {
+            idx += 1;
+          }
+        }
+      }
+      b'"' | b'\'' | b'`' => {
+        idx += 1;
+        while idx < bytes.len() && bytes[idx] != c {
+          if bytes[idx] == b'\\' {
+            idx += 1;
+          }
+          idx += 1;
+        }
+        idx += 1;
+      }
+      c if c == b'_' || c.is_ascii_alphabetic() => {
+        let start = idx;
+        while idx < bytes.len() && (bytes[idx] == b'_' || bytes[idx].is_ascii_alphanumeric()) {
+          idx += 1;
+        }
+        tokens.push((start, Token::Ident(&source[start..idx])));
+      }
+      c => {
+        if c.is_ascii_punctuation() {
+          tokens.push((idx, Token::Punct(c as char)));
+        }
+        idx += 1;
+      }
+    }
+  }
+  tokens
+}
+
+/// Wrap the body of each script function in a `profile("fn:<name>", || { .. })` call, so
+/// script functions are timed without changing the script.
+///
+/// Functions using `this` are skipped, closures can't access it.  Edits don't add lines,
+/// so error line numbers stay the same.
+pub fn instrument_script(source: &str) -> String {
+  let tokens = tokenize(source);
+  // (offset, text) to insert.
+  let mut edits = Vec::new();
+  let mut depth = 0;
+  let mut idx = 0;
+  while idx < tokens.len() {
+    match tokens[idx].1 {
+      Token::Punct('{') => depth += 1,
+      Token::Punct('}') => depth -= 1,
+      Token::Ident("fn") if depth == 0 => {
+        let name = match tokens.get(idx + 1) {
+          Some((_, Token::Ident(name))) => *name,
+          _ => {
+            idx += 1;
+            continue;
+          }
+        };
+        // Find the body's opening brace.
+        let mut open = idx + 2;
+        while open < tokens.len() && !matches!(tokens[open].1, Token::Punct('{')) {
+          open += 1;
+        }
+        let mut body_depth = 0;
+        let mut uses_this = false;
+        let mut close = open;
+        while close < tokens.len() {
+          match tokens[close].1 {
+            Token::Punct('{') => body_depth += 1,
+            Token::Punct('}') => {
+              body_depth -= 1;
+              if body_depth == 0 {
+                break;
+              }
+            }
+            Token::Ident("this") => uses_this = true,
+            _ => (),
+          }
+          close += 1;
+        }
+        if close >= tokens.len() {
+          // Unbalanced, let the parser report it.
+          break;
+        }
+        if !uses_this {
+          edits.push((
+            tokens[open].0 + 1,
+            format!(" return profile(\"fn:{}\", || {{", name),
+          ));
+          edits.push((tokens[close].0, "}); ".to_string()));
+        }
+        idx = close + 1;
+        continue;
+      }
+      _ => (),
+    }
+    idx += 1;
+  }
+
+  let mut out = String::with_capacity(source.len() + edits.len() * 32);
+  let mut last = 0;
+  for (offset, text) in edits {
+    out.push_str(&source[last..offset]);
+    out.push_str(&text);
+    last = offset;
+  }
+  out.push_str(&source[last..]);
+  out
+}
@@ -1,6 +1,16 @@
-use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+#[cfg(feature = "light")]
+use std::collections::HashMap;
+use std::collections::HashSet;
+#[cfg(unix)]
+use std::io::{BufReader, Write};
+#[cfg(feature = "light")]
+use std::num::NonZeroU32;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{from_value, json, Value};
@@ -8,10 +18,18 @@ use serde_json::{from_value, json, Value};
 use dashmap::DashMap;
 
 use rhai::serde::from_dynamic;
-use rhai::{Dynamic, Engine, EvalAltResult};
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use crate::client::BlockHash;
+use crate::engine::EngineOptions;
+use crate::error::{error_message, ErrorKind, ScriptError};
+use crate::profile;
+use crate::summary;
 
 use ws::{Factory, Handler, Message, WebSocket};
 
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+
 pub type ConnectionId = u16;
 pub type RequestId = u32;
 
@@ -30,6 +48,8 @@ pub enum ResponseEvent {
   Update(Option<Value>),
   Error(RpcError),
   Closed,
+  /// The connection was re-established, the request was lost and wasn't retried.
+  Reconnected,
 }
 
 impl ResponseEvent {
@@ -45,13 +65,6 @@ pub struct ResponseMessage {
 }
 
 impl ResponseMessage {
-  fn reply(token: RequestToken, result: Option<Value>) -> Self {
-    Self {
-      token,
-      event: ResponseEvent::Reply(result),
-    }
-  }
-
   fn update(token: RequestToken, result: Option<Value>) -> Self {
     Self {
       token,
@@ -66,13 +79,6 @@ impl ResponseMessage {
     }
   }
 
-  fn error(token: RequestToken, err: RpcError) -> Self {
-    Self {
-      token,
-      event: ResponseEvent::Error(err),
-    }
-  }
-
   fn to_string(&mut self) -> String {
     format!("{:?}", self)
   }
@@ -81,10 +87,55 @@ impl ResponseMessage {
 type RespSender = crossbeam_channel::Sender<ResponseMessage>;
 type RespReceiver = crossbeam_channel::Receiver<ResponseMessage>;
 
+/// Error returned when a request doesn't get a response in time.
+#[derive(Debug, Clone)]
+pub struct RpcTimeout {
+  pub method: String,
+  pub timeout: Duration,
+}
+
+// Transaction pool error codes.
+const POOL_INVALID_TX: i64 = 1010;
+const POOL_TOO_LOW_PRIORITY: i64 = 1014;
+
+impl RpcError {
+  fn into_error(self) -> Box<EvalAltResult> {
+    let message = match &self.data {
+      Some(Value::String(data)) => format!("{} (code {}): {}", self.message, self.code, data),
+      _ => format!("{} (code {})", self.message, self.code),
+    };
+    ScriptError::new(ErrorKind::Rpc, message).into()
+  }
+}
+
+/// Check if the node rejected an extrinsic because of its nonce (stale, future or
+/// already used by another extrinsic in the pool).
+pub fn is_nonce_error(err: &EvalAltResult) -> bool {
+  let message = error_message(err);
+  if message.contains(&format!("(code {})", POOL_TOO_LOW_PRIORITY)) {
+    return true;
+  }
+  message.contains(&format!("(code {})", POOL_INVALID_TX))
+    && (message.contains("outdated") || message.contains("in the future"))
+}
+
+impl RpcTimeout {
+  fn into_error(self) -> Box<EvalAltResult> {
+    let message = format!(
+      "{}: no response after {} ms",
+      self.method,
+      self.timeout.as_millis()
+    );
+    ScriptError::new(ErrorKind::Timeout, message).into()
+  }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RpcError {
   pub code: i64,
   pub message: String,
+  #[serde(default)]
+  pub data: Option<Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -107,6 +158,26 @@ struct RpcResp {
   params: Option<RpcRespParams>,
 }
 
+/// Read-only methods.  Identical in-flight calls of these methods share one request.
+const IDEMPOTENT_METHODS: &[&str] = &[
+  "chain_getBlock",
+  "chain_getBlockHash",
+  "chain_getFinalizedHead",
+  "chain_getHeader",
+  "state_call",
+  "state_getKeysPaged",
+  "state_getMetadata",
+  "state_getReadProof",
+  "state_getRuntimeVersion",
+  "state_getStorage",
+  "state_queryStorageAt",
+  "system_chain",
+  "system_health",
+  "system_name",
+  "system_properties",
+  "system_version",
+];
+
 struct RpcRequest {
   method: String,
   params: Value,
@@ -146,7 +217,18 @@ impl RpcRequest {
     }
   }
 
-  fn into_request(self, id: RequestId) -> (String, RequestData) {
+  /// Key used to find identical in-flight requests.
+  fn dedup_key(&self) -> Option<String> {
+    if self.is_subscription || self.resp_tx.is_none() {
+      return None;
+    }
+    if !IDEMPOTENT_METHODS.contains(&self.method.as_str()) {
+      return None;
+    }
+    Some(format!("{}:{}", self.method, self.params))
+  }
+
+  fn into_request(self, id: RequestId, dedup_key: Option<String>) -> (String, RequestData) {
     let msg = json!({
       "jsonrpc": "2.0",
       "id": id,
@@ -159,6 +241,8 @@ impl RpcRequest {
       unsub: self.unsub,
       topic: None,
       resp_tx: self.resp_tx,
+      dedup_key,
+      followers: Vec::new(),
     };
     (msg.to_string(), data)
   }
@@ -169,6 +253,9 @@ struct RequestData {
   unsub: Option<String>,
   topic: Option<String>,
   resp_tx: Option<RespSender>,
+  dedup_key: Option<String>,
+  // Identical requests waiting for the response to this request.
+  followers: Vec<(RequestId, RespSender)>,
 }
 
 impl RequestData {
@@ -179,6 +266,183 @@ impl RequestData {
       false
     }
   }
+
+  /// Send the final response to this request and the requests waiting on it.
+  fn send_all(&self, conn_id: ConnectionId, id: RequestId, event: ResponseEvent) {
+    for (follower_id, resp_tx) in &self.followers {
+      let _ = resp_tx.send(ResponseMessage {
+        token: RequestToken(conn_id, *follower_id),
+        event: event.clone(),
+      });
+    }
+    self.send(ResponseMessage {
+      token: RequestToken(conn_id, id),
+      event,
+    });
+  }
+}
+
+/// TLS settings used for `wss://` connections.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+  /// CA bundle (PEM) used to verify the server certificate.
+  pub ca_file: Option<String>,
+  /// Client certificate chain (PEM).
+  pub cert_file: Option<String>,
+  /// Client private key (PEM).
+  pub key_file: Option<String>,
+  /// Skip verification of the server certificate.
+  pub insecure: bool,
+}
+
+impl TlsOptions {
+  fn connector(&self) -> Result<SslConnector, openssl::error::ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    if let Some(ca_file) = &self.ca_file {
+      builder.set_ca_file(ca_file)?;
+    }
+    if let Some(cert_file) = &self.cert_file {
+      builder.set_certificate_chain_file(cert_file)?;
+    }
+    if let Some(key_file) = &self.key_file {
+      builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+    }
+    if self.insecure {
+      builder.set_verify(SslVerifyMode::NONE);
+    }
+    Ok(builder.build())
+  }
+}
+
+/// WebSocket frame and message limits.
+#[derive(Debug, Clone, Copy)]
+pub struct WsLimits {
+  /// Maximum size of a (reassembled) message, in bytes.
+  pub max_message_size: usize,
+  /// Maximum size of a single incoming frame, in bytes (0 for no limit).
+  pub max_fragment_size: usize,
+  /// Outgoing messages larger than this are split into frames, in bytes.
+  pub fragment_size: usize,
+}
+
+impl Default for WsLimits {
+  fn default() -> Self {
+    Self {
+      max_message_size: 64 * 1024 * 1024,
+      max_fragment_size: 0,
+      fragment_size: u16::MAX as usize,
+    }
+  }
+}
+
+impl WsLimits {
+  fn settings(&self) -> ws::Settings {
+    ws::Settings {
+      in_buffer_capacity_hard_limit: self.max_message_size,
+      out_buffer_capacity_hard_limit: self.max_message_size,
+      max_fragment_size: match self.max_fragment_size {
+        0 => usize::MAX,
+        size => size,
+      },
+      fragment_size: self.fragment_size.max(1),
+      ..ws::Settings::default()
+    }
+  }
+}
+
+/// Per-connection settings parsed from the connection url's query string.
+///
+/// Example: `wss://node:443?timeout=10s&retries=3&name=relay&pool=4&dedup=off`
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+  /// Url without the connection options.
+  pub url: String,
+  /// Optional name, can be used instead of the url to get the connection.
+  pub name: Option<String>,
+  /// Timeout when waiting for a method call response.
+  pub timeout: Option<Duration>,
+  /// Number of times to reconnect after the connection fails or is closed.
+  pub retries: u32,
+  /// Number of parallel connections to open.
+  pub pool: Option<usize>,
+  /// First JSON-RPC request id.  Useful when a proxy multiplexes connections.
+  pub id_start: RequestId,
+  /// Share one request between identical in-flight read-only calls.
+  pub dedup: bool,
+}
+
+impl ConnectionOptions {
+  pub fn parse(uri: &str) -> Result<Self, Box<EvalAltResult>> {
+    let mut url = url::Url::parse(uri).map_err(|e| format!("Invalid url '{}': {}", uri, e))?;
+    let mut opts = Self {
+      id_start: 1,
+      dedup: true,
+      ..Default::default()
+    };
+    let mut query = Vec::new();
+    for (key, val) in url.query_pairs() {
+      match key.as_ref() {
+        "name" => {
+          opts.name = Some(val.to_string());
+        }
+        "timeout" => {
+          opts.timeout = Some(parse_duration(&val)?);
+        }
+        "retries" => {
+          opts.retries = val
+            .parse()
+            .map_err(|e| format!("Invalid connection retries '{}': {:?}", val, e))?;
+        }
+        "pool" => {
+          opts.pool = Some(
+            val
+              .parse()
+              .map_err(|e| format!("Invalid connection pool size '{}': {:?}", val, e))?,
+          );
+        }
+        "id_start" => {
+          opts.id_start = val
+            .parse()
+            .map_err(|e| format!("Invalid request id start '{}': {:?}", val, e))?;
+        }
+        "dedup" => {
+          opts.dedup = match val.as_ref() {
+            "1" | "true" | "on" => true,
+            "0" | "false" | "off" => false,
+            _ => Err(format!("Invalid dedup option '{}'", val))?,
+          };
+        }
+        _ => {
+          // Keep unknown query parameters.
+          query.push((key.to_string(), val.to_string()));
+        }
+      }
+    }
+    if query.len() > 0 {
+      url.query_pairs_mut().clear().extend_pairs(query);
+    } else {
+      url.set_query(None);
+    }
+    opts.url = url.into();
+    Ok(opts)
+  }
+}
+
+/// Parse a duration like `10s`, `500ms` or `2m`.  Defaults to seconds.
+fn parse_duration(val: &str) -> Result<Duration, Box<EvalAltResult>> {
+  let idx = val
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(val.len());
+  let (num, unit) = val.split_at(idx);
+  let num: u64 = num
+    .parse()
+    .map_err(|e| format!("Invalid duration '{}': {:?}", val, e))?;
+  match unit {
+    "ms" => Ok(Duration::from_millis(num)),
+    "" | "s" => Ok(Duration::from_secs(num)),
+    "m" => Ok(Duration::from_secs(num * 60)),
+    _ => Err(format!("Invalid duration unit '{}'", unit))?,
+  }
 }
 
 pub struct Subscription {
@@ -186,23 +450,78 @@ pub struct Subscription {
   pub unsub: String,
 }
 
+/// Outgoing side of a connection.
+enum RpcSender {
+  Ws(ws::Sender),
+  /// `ipc:///path/node.sock`.  Requests are newline delimited.
+  #[cfg(unix)]
+  Ipc(Mutex<UnixStream>),
+  /// `light://chainspec.json`, requests go to an embedded smoldot client.
+  #[cfg(feature = "light")]
+  Light(Arc<Mutex<LightClient>>, smoldot_light::ChainId),
+}
+
+#[cfg(feature = "light")]
+type LightClient = smoldot_light::Client<Arc<smoldot_light::platform::default::DefaultPlatform>>;
+
+#[cfg(feature = "light")]
+lazy_static::lazy_static! {
+  /// Light clients by chain specs.  Connections to the same chains share a client, each
+  /// connection adds the chains again (smoldot shares identical chains) to get it's own
+  /// JSON-RPC responses.
+  static ref LIGHT_CLIENTS: Mutex<HashMap<String, Arc<Mutex<LightClient>>>> =
+    Mutex::new(HashMap::new());
+}
+
+impl RpcSender {
+  fn send(&self, msg: String) -> Result<(), String> {
+    match self {
+      Self::Ws(out) => out.send(msg).map_err(|e| e.to_string()),
+      #[cfg(unix)]
+      Self::Ipc(out) => {
+        let mut out = out.lock().unwrap();
+        out
+          .write_all(msg.as_bytes())
+          .and_then(|_| out.write_all(b"\n"))
+          .map_err(|e| e.to_string())
+      }
+      #[cfg(feature = "light")]
+      Self::Light(client, chain_id) => client
+        .lock()
+        .unwrap()
+        .json_rpc_request(msg, *chain_id)
+        .map_err(|e| format!("Light client request failed: {:?}", e)),
+    }
+  }
+}
+
 pub struct InnerRpcConnection {
   id: ConnectionId,
-  url: String,
+  opts: ConnectionOptions,
+  tls: TlsOptions,
+  limits: WsLimits,
   next_id: AtomicU32,
   requests: DashMap<RequestId, RequestData>,
   subscriptions: DashMap<String, RequestId>,
-  out: RwLock<Option<ws::Sender>>,
+  // Dedup key -> id of the in-flight request.
+  inflight: DashMap<String, RequestId>,
+  // Deduplicated request id -> id of the request that was sent.
+  followers: DashMap<RequestId, RequestId>,
+  out: RwLock<Option<RpcSender>>,
 }
 
 impl InnerRpcConnection {
-  fn new(id: ConnectionId, url: &str) -> Arc<Self> {
+  fn new(id: ConnectionId, opts: ConnectionOptions, tls: TlsOptions, limits: WsLimits) -> Arc<Self> {
     Arc::new(Self {
       id: id,
-      url: url.into(),
-      next_id: 1.into(),
+      tls,
+      limits,
+      next_id: opts.id_start.into(),
+      opts,
       requests: DashMap::new(),
       subscriptions: DashMap::new(),
+      inflight: DashMap::new(),
+      followers: DashMap::new(),
       out: RwLock::new(None),
     })
   }
@@ -214,11 +533,66 @@ impl InnerRpcConnection {
   fn add_request(&self, req: RpcRequest) -> (String, RequestToken) {
     let id = self.get_next_id();
     let token = RequestToken(self.id, id);
-    let (msg, data) = req.into_request(id);
+    let dedup_key = if self.opts.dedup { req.dedup_key() } else { None };
+    if let Some(key) = &dedup_key {
+      self.inflight.insert(key.clone(), id);
+    }
+    let (msg, data) = req.into_request(id, dedup_key);
     self.requests.insert(id, data);
     (msg, token)
   }
 
+  /// Wait on an identical in-flight request instead of sending a new one.
+  fn join_inflight(&self, req: &RpcRequest) -> Option<RequestToken> {
+    if !self.opts.dedup {
+      return None;
+    }
+    let key = req.dedup_key()?;
+    let resp_tx = req.resp_tx.clone()?;
+    let leader_id = *self.inflight.get(&key)?;
+    // The leader is removed from `requests` before its response is sent.
+    let mut leader = self.requests.get_mut(&leader_id)?;
+    let id = self.get_next_id();
+    leader.followers.push((id, resp_tx));
+    self.followers.insert(id, leader_id);
+    log::debug!("Dedup request {} -> {}", id, leader_id);
+    Some(RequestToken(self.id, id))
+  }
+
+  /// Cleanup after a request has been removed.
+  fn finish_request(&self, id: RequestId, req: &RequestData) {
+    if let Some(key) = &req.dedup_key {
+      self.inflight.remove_if(key, |_, leader_id| *leader_id == id);
+    }
+    for (follower_id, _) in &req.followers {
+      self.followers.remove(follower_id);
+    }
+  }
+
+  /// Stop waiting for a deduplicated request.  Returns false if `id` isn't a follower.
+  fn remove_follower(&self, id: RequestId) -> bool {
+    match self.followers.remove(&id) {
+      Some((_, leader_id)) => {
+        if let Some(mut leader) = self.requests.get_mut(&leader_id) {
+          leader.followers.retain(|(follower_id, _)| *follower_id != id);
+        }
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Keep a request that other requests are waiting on, but stop sending responses to it.
+  fn detach_leader(&self, id: RequestId) -> bool {
+    match self.requests.get_mut(&id) {
+      Some(mut req) if !req.followers.is_empty() => {
+        req.resp_tx = None;
+        true
+      }
+      _ => false,
+    }
+  }
+
   fn unsubscribe(&self, unsub: &str, topic: &str) -> Result<RequestToken, Box<EvalAltResult>> {
     let req = RpcRequest::unsubscribe(unsub, topic);
     self.send(req)
@@ -226,9 +600,21 @@ impl InnerRpcConnection {
 
   fn close_request(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
     let id = token.req_id();
+    if self.remove_follower(id) {
+      return Ok(());
+    }
+    if let Some(req) = self.requests.get(&id) {
+      if !req.followers.is_empty() {
+        req.send(ResponseMessage::closed(token));
+      }
+    }
+    if self.detach_leader(id) {
+      return Ok(());
+    }
     match self.requests.remove(&id) {
       Some((_, req)) => {
         log::debug!("Close Request: {:?}", token);
+        self.finish_request(id, &req);
         req.send(ResponseMessage::closed(token));
         // Make sure to cleanup any subscriptions.
         if let Some(topic) = req.topic {
@@ -245,13 +631,37 @@ impl InnerRpcConnection {
     Ok(())
   }
 
+  /// Drop a request without sending a `Closed` event.
+  fn cancel_request(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
+    let id = token.req_id();
+    if self.remove_follower(id) || self.detach_leader(id) {
+      return Ok(());
+    }
+    if let Some((_, req)) = self.requests.remove(&id) {
+      log::debug!("Cancel Request: {:?}", token);
+      self.finish_request(id, &req);
+      // Make sure to cleanup any subscriptions.
+      if let Some(topic) = req.topic {
+        self.subscriptions.remove(&topic);
+        if let Some(unsub) = req.unsub {
+          self.unsubscribe(&unsub, &topic)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
   fn send(&self, req: RpcRequest) -> Result<RequestToken, Box<EvalAltResult>> {
+    if let Some(token) = self.join_inflight(&req) {
+      return Ok(token);
+    }
     let (msg, token) = self.add_request(req);
+    summary::rpc_call();
     log::debug!("send_msg({:?})", msg);
     let out = self.out.read().unwrap();
     match &*out {
       Some(out) => {
-        out.send(msg).map_err(|e| e.to_string())?;
+        out.send(msg)?;
       }
       None => {
         log::error!("Not connected yet.");
@@ -260,9 +670,37 @@ impl InnerRpcConnection {
     Ok(token)
   }
 
-  fn set_out(&self, ws: ws::Sender) {
+  /// Close all active subscriptions.
+  fn close_subscriptions(&self) -> usize {
+    let ids: Vec<RequestId> = self
+      .requests
+      .iter()
+      .filter(|r| r.topic.is_some())
+      .map(|r| *r.key())
+      .collect();
+    for id in &ids {
+      if let Err(err) = self.close_request(RequestToken(self.id, *id)) {
+        log::warn!("Failed to close subscription {}: {:?}", id, err);
+      }
+    }
+    ids.len()
+  }
+
+  /// Close all pending requests with `event` (`Closed` or `Reconnected`).
+  fn close_all(&self, event: ResponseEvent) {
+    let ids: Vec<RequestId> = self.requests.iter().map(|r| *r.key()).collect();
+    for id in ids {
+      if let Some((_, req)) = self.requests.remove(&id) {
+        self.finish_request(id, &req);
+        req.send_all(self.id, id, event.clone());
+      }
+    }
+    self.subscriptions.clear();
+  }
+
+  fn set_out(&self, sender: RpcSender) {
     let mut out = self.out.write().unwrap();
-    *out = Some(ws);
+    *out = Some(sender);
   }
 
   fn get_subscription_id(&self, topic: Option<&str>) -> Option<RequestId> {
@@ -272,11 +710,11 @@ impl InnerRpcConnection {
   }
 
   fn request_error(&self, id: RequestId, error: RpcError) -> Result<(), ws::Error> {
-    let token = RequestToken(self.id, id);
     match self.requests.remove(&id) {
       Some((_, req)) => {
         log::error!("Request error: {:?}", error);
-        req.send(ResponseMessage::error(token, error));
+        self.finish_request(id, &req);
+        req.send_all(self.id, id, ResponseEvent::Error(error));
         // Make sure to cleanup any subscriptions.
         if let Some(topic) = req.topic {
           self.subscriptions.remove(&topic);
@@ -290,7 +728,6 @@ impl InnerRpcConnection {
   }
 
   fn request_reply(&self, id: RequestId, result: Option<Value>) -> Result<(), ws::Error> {
-    let token = RequestToken(self.id, id);
     match self.requests.get_mut(&id) {
       Some(mut req) if req.is_subscription => {
         log::debug!("Subscription started: {:?}", result);
@@ -306,10 +743,13 @@ impl InnerRpcConnection {
       }
       Some(req) => {
         log::debug!("Request reply: {:?}", result);
-        req.send(ResponseMessage::reply(token, result));
         // Drop reference `req` so we can remove it without deadlocking.
         drop(req);
-        self.requests.remove(&id);
+        // Remove the request before replying, so no more requests can wait on it.
+        if let Some((_, req)) = self.requests.remove(&id) {
+          self.finish_request(id, &req);
+          req.send_all(self.id, id, ResponseEvent::Reply(result));
+        }
       }
       None => {
         log::warn!("Unknown request id: {}", id);
@@ -391,20 +831,227 @@ impl std::ops::Deref for RpcConnection {
 }
 
 impl RpcConnection {
-  pub fn new(id: ConnectionId, url: &str) -> Result<Self, Box<EvalAltResult>> {
-    let client = Self(InnerRpcConnection::new(id, url));
+  pub fn new(
+    id: ConnectionId,
+    opts: ConnectionOptions,
+    tls: TlsOptions,
+    limits: WsLimits,
+  ) -> Result<Self, Box<EvalAltResult>> {
+    let client = Self(InnerRpcConnection::new(id, opts, tls, limits));
     client.spawn().map_err(|e| e.to_string())?;
     Ok(client)
   }
 
+  fn connect(&self, url: &url::Url) -> Result<WebSocket<Self>, ws::Error> {
+    let mut ws = ws::Builder::new()
+      .with_settings(self.limits.settings())
+      .build(self.clone())?;
+    self.set_out(RpcSender::Ws(ws.broadcaster()));
+    ws.connect(url.clone())?;
+    Ok(ws)
+  }
+
+  fn run(&self, mut ws: WebSocket<Self>, url: url::Url) {
+    let mut retries = 0;
+    loop {
+      if let Err(err) = ws.run() {
+        log::error!("RpcConnection({}) error: {:?}", url, err);
+      }
+      if retries >= self.opts.retries {
+        break;
+      }
+      retries += 1;
+      log::warn!(
+        "RpcConnection({}) reconnecting: attempt {} of {}",
+        url,
+        retries,
+        self.opts.retries
+      );
+      // Pending requests will not get a response from the new connection.
+      self.close_all(ResponseEvent::Reconnected);
+      thread::sleep(Duration::from_secs(1));
+      ws = match self.connect(&url) {
+        Ok(ws) => ws,
+        Err(err) => {
+          log::error!("RpcConnection({}) failed to reconnect: {:?}", url, err);
+          break;
+        }
+      };
+    }
+    self.close_all(ResponseEvent::Closed);
+  }
+
+  #[cfg(unix)]
+  fn connect_ipc(&self, url: &url::Url) -> Result<UnixStream, ws::Error> {
+    let stream = UnixStream::connect(url.path())?;
+    self.set_out(RpcSender::Ipc(Mutex::new(stream.try_clone()?)));
+    Ok(stream)
+  }
+
+  /// Read responses from an IPC socket until it is closed.
+  #[cfg(unix)]
+  fn read_ipc(&self, stream: UnixStream) -> Result<(), ws::Error> {
+    let resps = serde_json::Deserializer::from_reader(BufReader::new(stream)).into_iter::<RpcResp>();
+    for resp in resps {
+      let resp = resp.map_err(|e| new_error(e.to_string()))?;
+      log::debug!("on_ipc_msg({:?})", resp);
+      self.on_resp(resp)?;
+    }
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  fn run_ipc(&self, mut stream: UnixStream, url: url::Url) {
+    let mut retries = 0;
+    loop {
+      if let Err(err) = self.read_ipc(stream) {
+        log::error!("RpcConnection({}) error: {:?}", url, err);
+      }
+      if retries >= self.opts.retries {
+        break;
+      }
+      retries += 1;
+      log::warn!(
+        "RpcConnection({}) reconnecting: attempt {} of {}",
+        url,
+        retries,
+        self.opts.retries
+      );
+      self.close_all(ResponseEvent::Reconnected);
+      thread::sleep(Duration::from_secs(1));
+      stream = match self.connect_ipc(&url) {
+        Ok(stream) => stream,
+        Err(err) => {
+          log::error!("RpcConnection({}) failed to reconnect: {:?}", url, err);
+          break;
+        }
+      };
+    }
+    self.close_all(ResponseEvent::Closed);
+  }
+
+  /// Start a light client for the chain specs in a `light://` url.
+  ///
+  /// `light://relay.json,para.json`: the last spec is the chain to use, the others are
+  /// its potential relay chains.
+  #[cfg(feature = "light")]
+  fn connect_light(&self, url: &url::Url) -> Result<smoldot_light::JsonRpcResponses, ws::Error> {
+    use smoldot_light::{AddChainConfig, AddChainConfigJsonRpc};
+
+    let spec_files = self
+      .opts
+      .url
+      .strip_prefix("light://")
+      .map(|s| s.split('?').next().unwrap_or(s))
+      .ok_or_else(|| new_error(format!("Invalid light client url: {}", url)))?;
+    let specs = spec_files
+      .split(',')
+      .map(|file| {
+        std::fs::read_to_string(file)
+          .map_err(|e| new_error(format!("Failed to read chain spec {:?}: {}", file, e)))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let shared = LIGHT_CLIENTS
+      .lock()
+      .unwrap()
+      .entry(spec_files.to_string())
+      .or_insert_with(|| {
+        let platform = smoldot_light::platform::default::DefaultPlatform::new(
+          env!("CARGO_PKG_NAME").into(),
+          env!("CARGO_PKG_VERSION").into(),
+        );
+        Arc::new(Mutex::new(LightClient::new(platform)))
+      })
+      .clone();
+    let mut client = shared.lock().unwrap();
+    let mut relay_chains = Vec::new();
+    let last = specs.len().saturating_sub(1);
+    for (idx, spec) in specs.iter().enumerate() {
+      let json_rpc = if idx == last {
+        AddChainConfigJsonRpc::Enabled {
+          max_pending_requests: NonZeroU32::new(u32::MAX).unwrap(),
+          max_subscriptions: u32::MAX,
+        }
+      } else {
+        AddChainConfigJsonRpc::Disabled
+      };
+      let chain = client
+        .add_chain(AddChainConfig {
+          user_data: (),
+          specification: spec,
+          database_content: "",
+          potential_relay_chains: relay_chains.clone().into_iter(),
+          json_rpc,
+        })
+        .map_err(|e| new_error(format!("Failed to add chain to light client: {:?}", e)))?;
+      if idx == last {
+        let responses = chain
+          .json_rpc_responses
+          .ok_or_else(|| new_error(format!("Light client has no JSON-RPC for {}", url)))?;
+        drop(client);
+        self.set_out(RpcSender::Light(shared, chain.chain_id));
+        return Ok(responses);
+      }
+      relay_chains.push(chain.chain_id);
+    }
+    Err(new_error(format!("No chain spec in light client url: {}", url)))
+  }
+
+  /// Pass the light client's responses to the waiting requests.
+  #[cfg(feature = "light")]
+  fn run_light(&self, mut responses: smoldot_light::JsonRpcResponses, url: url::Url) {
+    futures::executor::block_on(async {
+      while let Some(msg) = responses.next().await {
+        log::debug!("on_light_msg({:?})", msg);
+        let res = serde_json::from_str::<RpcResp>(&msg)
+          .map_err(|e| new_error(e.to_string()))
+          .and_then(|resp| self.on_resp(resp));
+        if let Err(err) = res {
+          log::error!("RpcConnection({}) error: {:?}", url, err);
+        }
+      }
+    });
+    self.close_all(ResponseEvent::Closed);
+  }
+
   fn spawn(&self) -> Result<(), ws::Error> {
-    let mut ws = WebSocket::new(self.clone())?;
-    let url = url::Url::parse(&self.url).map_err(|e| new_error(e.to_string()))?;
-    self.set_out(ws.broadcaster());
-    ws.connect(url)?;
+    let url = url::Url::parse(&self.opts.url).map_err(|e| new_error(e.to_string()))?;
+    let name = match &self.opts.name {
+      Some(name) => format!("RpcConnection({})", name),
+      None => "RpcConnection".into(),
+    };
+    let conn = self.clone();
+    if url.scheme() == "light" {
+      #[cfg(feature = "light")]
+      {
+        let responses = self.connect_light(&url)?;
+        thread::Builder::new()
+          .name(name)
+          .spawn(move || conn.run_light(responses, url))?;
+        return Ok(());
+      }
+      #[cfg(not(feature = "light"))]
+      return Err(new_error(format!(
+        "Can't use light client {}: built without the `light` feature",
+        url
+      )));
+    }
+    if url.scheme() == "ipc" {
+      #[cfg(unix)]
+      {
+        let stream = self.connect_ipc(&url)?;
+        thread::Builder::new()
+          .name(name)
+          .spawn(move || conn.run_ipc(stream, url))?;
+        return Ok(());
+      }
+      #[cfg(not(unix))]
+      return Err(new_error(format!("IPC connections are only supported on unix: {}", url)));
+    }
+    let ws = self.connect(&url)?;
     thread::Builder::new()
-      .name("RpcConnection".into())
-      .spawn(move || ws.run())?;
+      .name(name)
+      .spawn(move || conn.run(ws, url))?;
     Ok(())
   }
 }
@@ -413,78 +1060,320 @@ impl Handler for RpcConnection {
   fn on_message(&mut self, msg: Message) -> Result<(), ws::Error> {
     self.0.on_message(msg)
   }
+
+  fn upgrade_ssl_client(
+    &mut self,
+    stream: ws::util::TcpStream,
+    url: &url::Url,
+  ) -> Result<SslStream<ws::util::TcpStream>, ws::Error> {
+    let domain = url
+      .domain()
+      .ok_or_else(|| new_error(format!("Unable to parse domain from {}. Needed for SSL.", url)))?;
+    let connector = self
+      .tls
+      .connector()
+      .map_err(|e| new_error(format!("Failed to upgrade client to SSL: {}", e)))?;
+    let mut config = connector
+      .configure()
+      .map_err(|e| new_error(format!("Failed to upgrade client to SSL: {}", e)))?;
+    if self.tls.insecure {
+      config.set_verify_hostname(false);
+    }
+    config.connect(domain, stream).map_err(ws::Error::from)
+  }
 }
 
 impl Factory for RpcConnection {
   type Handler = RpcConnection;
 
   fn connection_made(&mut self, ws: ws::Sender) -> RpcConnection {
-    self.set_out(ws);
+    self.set_out(RpcSender::Ws(ws));
     self.clone()
   }
 }
 
+/// Upper bounds (ms) of the latency histogram buckets.  Slower requests go in the last bucket.
+const LATENCY_BUCKETS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Clone, Debug, Default)]
+pub struct MethodStats {
+  pub requests: u64,
+  pub errors: u64,
+  /// Requests with a response (not subscriptions or cancelled requests).
+  pub responses: u64,
+  pub total: Duration,
+  pub max: Duration,
+  pub histogram: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl MethodStats {
+  fn record(&mut self, latency: Duration, ok: bool) {
+    self.responses += 1;
+    if !ok {
+      self.errors += 1;
+    }
+    self.total += latency;
+    self.max = self.max.max(latency);
+    let ms = latency.as_millis() as u64;
+    let idx = LATENCY_BUCKETS
+      .iter()
+      .position(|max| ms <= *max)
+      .unwrap_or(LATENCY_BUCKETS.len());
+    self.histogram[idx] += 1;
+  }
+
+  fn avg(&self) -> Duration {
+    match self.responses {
+      0 => Duration::default(),
+      count => self.total / count as u32,
+    }
+  }
+
+  fn to_map(&self) -> RMap {
+    let mut histogram = RMap::new();
+    for (idx, count) in self.histogram.iter().enumerate() {
+      let name = match LATENCY_BUCKETS.get(idx) {
+        Some(max) => format!("<={}ms", max),
+        None => format!(">{}ms", LATENCY_BUCKETS[LATENCY_BUCKETS.len() - 1]),
+      };
+      histogram.insert(name.into(), Dynamic::from(*count as INT));
+    }
+    let mut map = RMap::new();
+    map.insert("requests".into(), Dynamic::from(self.requests as INT));
+    map.insert("errors".into(), Dynamic::from(self.errors as INT));
+    map.insert("avg_ms".into(), Dynamic::from(self.avg().as_millis() as INT));
+    map.insert("max_ms".into(), Dynamic::from(self.max.as_millis() as INT));
+    map.insert("histogram".into(), Dynamic::from(histogram));
+    map
+  }
+}
+
+/// Per-method request counts, errors and latencies.
+#[derive(Default)]
+pub struct RpcStats {
+  methods: DashMap<String, MethodStats>,
+  // Requests waiting for a response.
+  pending: DashMap<RequestToken, (String, Instant)>,
+}
+
+impl RpcStats {
+  fn start(&self, token: RequestToken, method: &str) {
+    self.count(method);
+    self.pending.insert(token, (method.into(), Instant::now()));
+  }
+
+  fn count(&self, method: &str) {
+    self.methods.entry(method.into()).or_default().requests += 1;
+  }
+
+  fn finish(&self, token: RequestToken, ok: bool) {
+    if let Some((_, (method, start))) = self.pending.remove(&token) {
+      self
+        .methods
+        .entry(method)
+        .or_default()
+        .record(start.elapsed(), ok);
+    }
+  }
+
+  fn cancel(&self, token: RequestToken) {
+    self.pending.remove(&token);
+  }
+
+  /// Method of a pending request.
+  fn method(&self, token: RequestToken) -> Option<String> {
+    self.pending.get(&token).map(|entry| entry.0.clone())
+  }
+
+  pub fn reset(&self) {
+    self.methods.clear();
+  }
+
+  /// Stats by method name, sorted by total time.
+  pub fn methods(&self) -> Vec<(String, MethodStats)> {
+    let mut methods = self
+      .methods
+      .iter()
+      .map(|m| (m.key().clone(), m.value().clone()))
+      .collect::<Vec<_>>();
+    methods.sort_by(|a, b| b.1.total.cmp(&a.1.total));
+    methods
+  }
+
+  pub fn to_map(&self) -> RMap {
+    self
+      .methods()
+      .into_iter()
+      .map(|(name, stats)| (name.into(), Dynamic::from(stats.to_map())))
+      .collect()
+  }
+
+  /// Table of the stats, for printing at exit.
+  pub fn report(&self) -> String {
+    let mut out = format!(
+      "{:<36} {:>8} {:>7} {:>10} {:>10} {:>12}\n",
+      "method", "requests", "errors", "avg", "max", "total"
+    );
+    for (name, stats) in self.methods() {
+      out.push_str(&format!(
+        "{:<36} {:>8} {:>7} {:>10.3?} {:>10.3?} {:>12.3?}\n",
+        name,
+        stats.requests,
+        stats.errors,
+        stats.avg(),
+        stats.max,
+        stats.total
+      ));
+    }
+    out
+  }
+}
+
 pub struct InnerRpcHandler {
-  conn: RpcConnection,
+  conns: Arc<Vec<RpcConnection>>,
+  next_conn: AtomicUsize,
+  timeout: Option<Duration>,
   // TODO: Move these into a `thread_local` struct.
   // Each thread gets their own channel for waiting for responses and updates map.
   resp_tx: RespSender,
   resp_rx: Mutex<RespReceiver>,
   updates: DashMap<RequestToken, ResponseEvent>,
+  stats: Arc<RpcStats>,
 }
 
 impl InnerRpcHandler {
-  fn new(conn: RpcConnection) -> Arc<Self> {
+  fn new(
+    conns: Arc<Vec<RpcConnection>>,
+    timeout: Option<Duration>,
+    stats: Arc<RpcStats>,
+  ) -> Arc<Self> {
     let (resp_tx, resp_rx) = crossbeam_channel::unbounded();
     Arc::new(Self {
-      conn,
+      conns,
+      next_conn: 0.into(),
+      timeout,
       resp_tx,
       resp_rx: Mutex::new(resp_rx),
       updates: DashMap::new(),
+      stats,
     })
   }
 
+  /// Round-robin over the pooled connections.
+  fn next_conn(&self) -> &RpcConnection {
+    let idx = self.next_conn.fetch_add(1, Ordering::Relaxed);
+    &self.conns[idx % self.conns.len()]
+  }
+
+  fn get_conn(&self, token: RequestToken) -> Result<&RpcConnection, Box<EvalAltResult>> {
+    Ok(
+      self
+        .conns
+        .iter()
+        .find(|c| c.id == token.0)
+        .ok_or_else(|| format!("Unknown connection for request: {:?}", token))?,
+    )
+  }
+
   fn send(&self, req: RpcRequest) -> Result<RequestToken, Box<EvalAltResult>> {
-    self.conn.send(req)
+    self.next_conn().send(req)
   }
 
+  /// Close a request (or subscription).  Drops any unread responses and its pending stats.
   pub fn close_request(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
-    self.conn.close_request(token)
+    self.updates.remove(&token);
+    self.stats.cancel(token);
+    self.get_conn(token)?.close_request(token)
+  }
+
+  /// Cancel a request.  Any response that arrives later is dropped.
+  pub fn cancel(&self, token: RequestToken) -> Result<(), Box<EvalAltResult>> {
+    self.get_conn(token)?.cancel_request(token)?;
+    self.updates.remove(&token);
+    self.stats.cancel(token);
+    Ok(())
   }
 
-  pub fn get_response(&self, token: RequestToken) -> Result<ResponseEvent, Box<EvalAltResult>> {
+  pub fn stats(&self) -> &RpcStats {
+    &self.stats
+  }
+
+  pub fn get_response(
+    &self,
+    token: RequestToken,
+    timeout: Option<Duration>,
+  ) -> Result<ResponseEvent, Box<EvalAltResult>> {
+    let res = self.wait_for_response(token, timeout);
+    let ok = matches!(res, Ok(ResponseEvent::Reply(_)) | Ok(ResponseEvent::Update(_)));
+    self.stats.finish(token, ok);
+    res
+  }
+
+  fn wait_for_response(
+    &self,
+    token: RequestToken,
+    timeout: Option<Duration>,
+  ) -> Result<ResponseEvent, Box<EvalAltResult>> {
     // Check if the response has already arrived.
     if let Some((_, resp)) = self.updates.remove(&token) {
       log::debug!("------ response was already received: {:?}", token);
       return Ok(resp);
     }
     log::debug!("------ get updates.");
-    self.wait_for_update(token)
+    match self.wait_for_update(token, timeout.map(|t| Instant::now() + t)) {
+      Ok(Some(resp)) => Ok(resp),
+      Ok(None) => {
+        let method = self.stats.method(token).unwrap_or_default();
+        // Record the timeout before `cancel` drops the pending request.
+        self.stats.finish(token, false);
+        self.cancel(token)?;
+        Err(
+          RpcTimeout {
+            method,
+            timeout: timeout.unwrap_or_default(),
+          }
+          .into_error(),
+        )
+      }
+      Err(err) => Err(err),
+    }
   }
 
   fn get_sender(&self) -> RespSender {
     self.resp_tx.clone()
   }
 
+  /// Wait for a response.  Returns `None` if the deadline is reached.
   fn wait_for_update(
     &self,
     wait_for: RequestToken,
-  ) -> Result<ResponseEvent, Box<EvalAltResult>> {
+    deadline: Option<Instant>,
+  ) -> Result<Option<ResponseEvent>, Box<EvalAltResult>> {
     loop {
       let resp_rx = self.resp_rx.lock().unwrap();
       // We need to check again for the response after acquiring the lock.
       if let Some((_, resp)) = self.updates.remove(&wait_for) {
         log::debug!("------ response was already received: {:?}", wait_for);
-        return Ok(resp);
+        return Ok(Some(resp));
       }
 
       // Wait for an update from the RPC connection.
-      let resp = resp_rx
+      let resp = match deadline {
+        Some(deadline) => match resp_rx.recv_deadline(deadline) {
+          Ok(resp) => resp,
+          Err(e) if e.is_timeout() => {
+            log::warn!("Request timed out: {:?}", wait_for);
+            return Ok(None);
+          }
+          Err(_) => Err(ScriptError::new(ErrorKind::Rpc, "RpcConnection closed"))?,
+        },
+        None => resp_rx
           .recv()
-          .map_err(|_| format!("RpcConnection closed"))?;
+          .map_err(|_| ScriptError::new(ErrorKind::Rpc, "RpcConnection closed"))?,
+      };
       if wait_for == resp.token {
         log::debug!("------ got response we wanted: {:?}", resp.token);
-        return Ok(resp.event);
+        return Ok(Some(resp.event));
       }
       log::debug!("------ cache response: {:?}", resp.token);
       self.updates.insert(resp.token, resp.event);
@@ -503,8 +1392,12 @@ impl std::ops::Deref for RpcHandler {
 }
 
 impl RpcHandler {
-  pub fn new(conn: RpcConnection) -> Self {
-    Self(InnerRpcHandler::new(conn))
+  pub fn new(
+    conns: Arc<Vec<RpcConnection>>,
+    timeout: Option<Duration>,
+    stats: Arc<RpcStats>,
+  ) -> Self {
+    Self(InnerRpcHandler::new(conns, timeout, stats))
   }
 
   pub fn async_call_method(
@@ -513,7 +1406,9 @@ impl RpcHandler {
     params: Value,
   ) -> Result<RequestToken, Box<EvalAltResult>> {
     let req = RpcRequest::call_method(method, params, self.get_sender());
-    self.0.send(req)
+    let token = self.0.send(req)?;
+    self.stats.start(token, method);
+    Ok(token)
   }
 
   /// Get response to method call.
@@ -521,7 +1416,7 @@ impl RpcHandler {
     &self,
     token: RequestToken,
   ) -> Result<Option<T>, Box<EvalAltResult>> {
-    match self.0.get_response(token)? {
+    match self.0.get_response(token, self.timeout)? {
       ResponseEvent::Reply(Some(reply)) => {
         let res: T = from_value(reply).map_err(|e| e.to_string())?;
         Ok(Some(res))
@@ -530,8 +1425,15 @@ impl RpcHandler {
       ResponseEvent::Update(_) => Err(format!(
         "Got invalid subscription update event for an method call."
       ))?,
-      ResponseEvent::Error(err) => Err(format!("{:?}", err))?,
-      ResponseEvent::Closed => Err(format!("Request closed without response."))?,
+      ResponseEvent::Error(err) => Err(err.into_error())?,
+      ResponseEvent::Closed => Err(ScriptError::new(
+        ErrorKind::Rpc,
+        "Request closed without response.",
+      ))?,
+      ResponseEvent::Reconnected => Err(ScriptError::new(
+        ErrorKind::Rpc,
+        "Request lost when the connection was re-established, it can be retried.",
+      ))?,
     }
   }
 
@@ -540,13 +1442,13 @@ impl RpcHandler {
     &self,
     token: RequestToken,
   ) -> Result<Option<T>, Box<EvalAltResult>> {
-    match self.0.get_response(token)? {
+    match self.0.get_response(token, None)? {
       ResponseEvent::Update(Some(reply)) => {
         let res: T = from_value(reply).map_err(|e| e.to_string())?;
         Ok(Some(res))
       }
       ResponseEvent::Update(None) => Ok(None),
-      ResponseEvent::Error(err) => Err(format!("{:?}", err))?,
+      ResponseEvent::Error(err) => Err(err.into_error())?,
       resp => {
         self.close_request(token)?;
         Err(format!("Unexpected response event: {:?}", resp))?
@@ -560,8 +1462,13 @@ impl RpcHandler {
     method: &str,
     params: Value,
   ) -> Result<Option<T>, Box<EvalAltResult>> {
-    let token = self.async_call_method(method, params)?;
-    self.get_response(token)
+    profile::scope(
+      || format!("rpc:{}", method),
+      || {
+        let token = self.async_call_method(method, params)?;
+        self.get_response(token)
+      },
+    )
   }
 
   /// Get response to multiple requests.
@@ -579,13 +1486,199 @@ impl RpcHandler {
     unsub: &str,
   ) -> Result<RequestToken, Box<EvalAltResult>> {
     let req = RpcRequest::subscribe(method, params, self.get_sender(), unsub);
-    self.0.send(req)
+    let token = self.0.send(req)?;
+    self.stats.count(method);
+    Ok(token)
+  }
+
+  /// Call a method that must return a value of type `T`.
+  pub fn call_typed<T: DeserializeOwned>(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> Result<T, Box<EvalAltResult>> {
+    let reply: Value = self
+      .call_method(method, params)?
+      .ok_or_else(|| format!("{}: empty response", method))?;
+    Ok(from_value(reply).map_err(|e| format!("{}: unexpected response: {}", method, e))?)
+  }
+
+  pub fn system_health(&self) -> Result<SystemHealth, Box<EvalAltResult>> {
+    self.call_typed("system_health", json!([]))
+  }
+
+  pub fn system_sync_state(&self) -> Result<SyncState, Box<EvalAltResult>> {
+    self.call_typed("system_syncState", json!([]))
+  }
+
+  pub fn system_peers(&self) -> Result<Vec<PeerInfo>, Box<EvalAltResult>> {
+    self.call_typed("system_peers", json!([]))
+  }
+}
+
+struct InnerSubscription {
+  rpc: RpcHandler,
+  token: RequestToken,
+  closed: AtomicBool,
+}
+
+impl InnerSubscription {
+  fn close(&self) -> Result<(), Box<EvalAltResult>> {
+    if !self.closed.swap(true, Ordering::Relaxed) {
+      self.rpc.close_request(self.token)?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for InnerSubscription {
+  fn drop(&mut self) {
+    if let Err(err) = self.close() {
+      log::warn!("Failed to close subscription {:?}: {}", self.token, err);
+    }
+  }
+}
+
+/// Script handle to a node subscription.  Closed when the last handle is dropped.
+#[derive(Clone)]
+pub struct RpcSubscription(Arc<InnerSubscription>);
+
+impl RpcSubscription {
+  pub fn subscribe(
+    rpc: &RpcHandler,
+    method: &str,
+    params: Value,
+    unsub: &str,
+  ) -> Result<Self, Box<EvalAltResult>> {
+    let token = rpc.subscribe(method, params, unsub)?;
+    Ok(Self(Arc::new(InnerSubscription {
+      rpc: rpc.clone(),
+      token,
+      closed: AtomicBool::new(false),
+    })))
+  }
+
+  /// Wait for the next update.  Returns `()` on timeout or once the subscription is closed.
+  pub fn next_update(&self, timeout: Option<Duration>) -> Result<Dynamic, Box<EvalAltResult>> {
+    let inner = &self.0;
+    if inner.closed.load(Ordering::Relaxed) {
+      return Ok(Dynamic::UNIT);
+    }
+    let deadline = timeout.map(|t| Instant::now() + t);
+    match inner.rpc.wait_for_update(inner.token, deadline)? {
+      None => Ok(Dynamic::UNIT),
+      Some(ResponseEvent::Update(Some(update))) => {
+        Ok(from_value(update).map_err(|e| e.to_string())?)
+      }
+      Some(ResponseEvent::Update(None)) => Ok(Dynamic::UNIT),
+      Some(ResponseEvent::Closed) => {
+        inner.closed.store(true, Ordering::Relaxed);
+        Ok(Dynamic::UNIT)
+      }
+      Some(ResponseEvent::Reconnected) => {
+        inner.closed.store(true, Ordering::Relaxed);
+        Err(ScriptError::new(
+          ErrorKind::Rpc,
+          "Subscription lost when the connection was re-established.",
+        ))?
+      }
+      Some(ResponseEvent::Error(err)) => Err(err.into_error()),
+      Some(resp) => {
+        inner.close()?;
+        Err(format!("Unexpected response event: {:?}", resp))?
+      }
+    }
+  }
+
+  fn next(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.next_update(None)
+  }
+
+  fn next_timeout(&mut self, timeout_ms: INT) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.next_update(Some(Duration::from_millis(timeout_ms.max(0) as u64)))
+  }
+
+  fn close(&mut self) -> Result<(), Box<EvalAltResult>> {
+    self.0.close()
+  }
+
+  fn is_closed(&mut self) -> bool {
+    self.0.closed.load(Ordering::Relaxed)
+  }
+
+  fn token(&mut self) -> RequestToken {
+    self.0.token
+  }
+}
+
+/// Response of `system_health`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemHealth {
+  pub peers: u64,
+  pub is_syncing: bool,
+  pub should_have_peers: bool,
+}
+
+/// Response of `system_syncState`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncState {
+  pub starting_block: u64,
+  pub current_block: u64,
+  pub highest_block: Option<u64>,
+}
+
+/// An entry of the `system_peers` response.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+  pub peer_id: String,
+  pub roles: String,
+  pub best_hash: BlockHash,
+  pub best_number: u64,
+}
+
+#[cfg(feature = "async")]
+impl RpcHandler {
+  /// Make a rpc call without blocking the async runtime.
+  pub async fn call_method_async<T: DeserializeOwned + Send + 'static>(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> Result<Option<T>, Box<EvalAltResult>> {
+    let token = self.async_call_method(method, params)?;
+    self.get_response_async(token).await
   }
+
+  /// Wait for the response to a method call without blocking the async runtime.
+  pub async fn get_response_async<T: DeserializeOwned + Send + 'static>(
+    &self,
+    token: RequestToken,
+  ) -> Result<Option<T>, Box<EvalAltResult>> {
+    let handler = self.clone();
+    tokio::task::spawn_blocking(move || handler.get_response(token))
+      .await
+      .map_err(|err| format!("Failed to join task: {:?}", err))?
+  }
+}
+
+/// Pooled connections to a url.
+struct RpcPool {
+  conns: Arc<Vec<RpcConnection>>,
+  /// Response timeout from the url's options, all connections of the pool use the same options.
+  timeout: Option<Duration>,
 }
 
 struct InnerRpcManager {
+  opts: EngineOptions,
   next_id: AtomicU16,
-  connections: DashMap<String, RpcConnection>,
+  tls: TlsOptions,
+  limits: WsLimits,
+  pool_size: usize,
+  timeout: Option<Duration>,
+  connections: DashMap<String, Arc<RpcPool>>,
+  stats: Arc<RpcStats>,
 }
 
 impl InnerRpcManager {
@@ -598,26 +1691,93 @@ impl InnerRpcManager {
 pub struct RpcManager(Arc<InnerRpcManager>);
 
 impl RpcManager {
-  pub fn new() -> Self {
+  pub fn new(opts: &EngineOptions) -> Self {
+    let tls = TlsOptions {
+      ca_file: opts.tls_ca_file.clone(),
+      cert_file: opts.tls_cert_file.clone(),
+      key_file: opts.tls_key_file.clone(),
+      insecure: opts.tls_insecure,
+    };
+    let timeout = match opts.rpc_timeout {
+      0 => None,
+      secs => Some(Duration::from_secs(secs)),
+    };
+    let limits = WsLimits {
+      max_message_size: opts.ws_max_message_size,
+      max_fragment_size: opts.ws_max_fragment_size,
+      fragment_size: opts.ws_fragment_size,
+    };
+    let pool_size = opts.rpc_pool_size;
     Self(Arc::new(InnerRpcManager {
+      opts: opts.clone(),
       next_id: 1.into(),
+      tls,
+      limits,
+      pool_size: pool_size.max(1),
+      timeout,
       connections: DashMap::new(),
+      stats: Default::default(),
     }))
   }
 
-  fn get_connection(&self, url: &str) -> Result<RpcConnection, Box<EvalAltResult>> {
-    if let Some(connection) = self.0.connections.get(url) {
-      return Ok(connection.clone());
+  fn get_connections(&self, url: &str) -> Result<Arc<RpcPool>, Box<EvalAltResult>> {
+    // Pools are keyed by the url with its options, urls with different options get
+    // their own connections.
+    if let Some(conns) = self.0.connections.get(url) {
+      return Ok(conns.clone());
+    }
+    let opts = ConnectionOptions::parse(url)?;
+    let pool_size = opts.pool.unwrap_or(self.0.pool_size).max(1);
+    let conns = (0..pool_size)
+      .map(|_| {
+        let id = self.0.get_next_id();
+        RpcConnection::new(id, opts.clone(), self.0.tls.clone(), self.0.limits)
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let conns = Arc::new(RpcPool {
+      conns: Arc::new(conns),
+      timeout: opts.timeout,
+    });
+    self.0.connections.insert(url.into(), conns.clone());
+    // Connections can also be looked up by name.
+    if let Some(name) = opts.name {
+      self.0.connections.insert(name, conns.clone());
+    }
+    Ok(conns)
+  }
+
+  /// Close the active subscriptions of all connections.
+  ///
+  /// Returns the number of subscriptions closed.
+  pub fn close_subscriptions(&self) -> usize {
+    // The same pool can be registered under multiple names.
+    let mut seen = HashSet::new();
+    let mut count = 0;
+    for pool in self.0.connections.iter() {
+      for conn in pool.value().conns.iter() {
+        if seen.insert(conn.id) {
+          count += conn.close_subscriptions();
+        }
+      }
     }
-    let id = self.0.get_next_id();
-    let connection = RpcConnection::new(id, url)?;
-    self.0.connections.insert(url.into(), connection.clone());
-    Ok(connection)
+    count
+  }
+
+  /// Request stats of all connections.
+  pub fn stats(&self) -> &RpcStats {
+    &self.0.stats
+  }
+
+  /// The options used to create the engine.
+  pub fn engine_opts(&self) -> &EngineOptions {
+    &self.0.opts
   }
 
   pub fn get_client(&self, url: &str) -> Result<RpcHandler, Box<EvalAltResult>> {
-    let conn = self.get_connection(url)?;
-    Ok(RpcHandler::new(conn))
+    let pool = self.get_connections(url)?;
+    // The url's timeout overrides the default.
+    let timeout = pool.timeout.or(self.0.timeout);
+    Ok(RpcHandler::new(pool.conns.clone(), timeout, self.0.stats.clone()))
   }
 }
 
@@ -625,7 +1785,7 @@ fn new_error(msg: String) -> ws::Error {
   ws::Error::new(ws::ErrorKind::Internal, msg)
 }
 
-pub fn init_engine(engine: &mut Engine) -> Result<RpcManager, Box<EvalAltResult>> {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<RpcConnection>("RpcConnection")
     .register_type_with_name::<RequestToken>("RequestToken")
@@ -656,6 +1816,13 @@ pub fn init_engine(engine: &mut Engine) -> Result<RpcManager, Box<EvalAltResult>
         client.subscribe(method, params, unsub)
       },
     )
+    .register_result_fn(
+      "subscription",
+      |client: &mut RpcHandler, method: &str, params: Dynamic, unsub: &str| {
+        let params: Value = from_dynamic(&params)?;
+        RpcSubscription::subscribe(client, method, params, unsub)
+      },
+    )
     .register_result_fn(
       "get_response",
       |client: &mut RpcHandler, token: RequestToken| client.get_response::<Dynamic>(token),
@@ -668,11 +1835,62 @@ pub fn init_engine(engine: &mut Engine) -> Result<RpcManager, Box<EvalAltResult>
       "close_request",
       |client: &mut RpcHandler, token: RequestToken| client.close_request(token),
     )
+    .register_result_fn(
+      "cancel",
+      |client: &mut RpcHandler, token: RequestToken| client.cancel(token),
+    )
+    .register_fn("stats", |client: &mut RpcHandler| client.stats().to_map())
+    .register_fn("reset_stats", |client: &mut RpcHandler| client.stats().reset())
+    .register_result_fn("system_health", |client: &mut RpcHandler| client.system_health())
+    .register_result_fn("system_sync_state", |client: &mut RpcHandler| {
+      client.system_sync_state()
+    })
+    .register_result_fn("system_peers", |client: &mut RpcHandler| {
+      Ok(
+        client
+          .system_peers()?
+          .into_iter()
+          .map(Dynamic::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+    .register_type_with_name::<RpcSubscription>("Subscription")
+    .register_result_fn("next", RpcSubscription::next)
+    .register_result_fn("next", RpcSubscription::next_timeout)
+    .register_result_fn("close", RpcSubscription::close)
+    .register_get("is_closed", RpcSubscription::is_closed)
+    .register_get("token", RpcSubscription::token)
+    .register_type_with_name::<SystemHealth>("SystemHealth")
+    .register_fn("to_string", |h: &mut SystemHealth| format!("{:?}", h))
+    .register_get("peers", |h: &mut SystemHealth| h.peers as INT)
+    .register_get("is_syncing", |h: &mut SystemHealth| h.is_syncing)
+    .register_get("should_have_peers", |h: &mut SystemHealth| h.should_have_peers)
+    .register_type_with_name::<SyncState>("SyncState")
+    .register_fn("to_string", |s: &mut SyncState| format!("{:?}", s))
+    .register_get("starting_block", |s: &mut SyncState| s.starting_block as INT)
+    .register_get("current_block", |s: &mut SyncState| s.current_block as INT)
+    .register_get("highest_block", |s: &mut SyncState| {
+      s.highest_block
+        .map(|n| Dynamic::from(n as INT))
+        .unwrap_or(Dynamic::UNIT)
+    })
+    .register_type_with_name::<PeerInfo>("PeerInfo")
+    .register_fn("to_string", |p: &mut PeerInfo| format!("{:?}", p))
+    .register_get("peer_id", |p: &mut PeerInfo| p.peer_id.clone())
+    .register_get("roles", |p: &mut PeerInfo| p.roles.clone())
+    .register_get("best_hash", |p: &mut PeerInfo| p.best_hash)
+    .register_get("best_number", |p: &mut PeerInfo| p.best_number as INT)
     .register_type_with_name::<RpcManager>("RpcManager")
     .register_result_fn("get_client", |rpc: &mut RpcManager, url: &str| {
       rpc.get_client(url)
     });
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  opts: &EngineOptions,
+) -> Result<RpcManager, Box<EvalAltResult>> {
+  register(engine);
 
-  let rpc = RpcManager::new();
-  Ok(rpc)
+  Ok(RpcManager::new(opts))
 }
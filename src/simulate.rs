@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use parity_scale_codec::{Decode, Encode};
+use sp_core::{
+  storage::{well_known_keys, Storage, StorageKey},
+  traits::{CallInWasm, MissingHostFunctions},
+};
+use sp_externalities::Externalities;
+use sp_runtime::{generic, transaction_validity::TransactionSource, ApplyExtrinsicResult};
+use sp_state_machine::BasicExternalities;
+use sp_wasm_interface::HostFunctions;
+
+use sc_executor::{WasmExecutionMethod, WasmExecutor};
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use crate::client::{
+  block_number_from_int, decode_hex, dynamic_to_hash, BlockHash, BlockHeader, Client,
+  EventRecords,
+};
+use crate::types::{TypeLookup, TypeRef};
+
+/// Number of keys to request per `state_getKeysPaged` call.
+const KEYS_PAGE_SIZE: u32 = 1000;
+
+/// Fetch the full state of a block.
+fn fetch_state(client: &Client, hash: BlockHash) -> Result<Storage, Box<EvalAltResult>> {
+  let prefix = StorageKey(vec![]);
+  let mut top = BTreeMap::new();
+  let mut start_key = None;
+  loop {
+    let keys =
+      client.get_storage_keys_paged(&prefix, KEYS_PAGE_SIZE, start_key.as_ref(), Some(hash))?;
+    let values = client.get_storage_by_keys(&keys, Some(hash))?;
+    for (key, value) in keys.iter().zip(values) {
+      if let Some(value) = value {
+        top.insert(key.0.clone(), value.0);
+      }
+    }
+    if keys.len() < KEYS_PAGE_SIZE as usize {
+      break;
+    }
+    start_key = keys.last().cloned();
+  }
+  log::info!("Fetched {} storage keys for simulation at {:?}", top.len(), hash);
+  Ok(Storage {
+    top,
+    children_default: Default::default(),
+  })
+}
+
+pub struct InnerSimulator {
+  block_hash: BlockHash,
+  block_number: u64,
+  // Block number type of the chain's `Header`.
+  block_number_ty: Option<TypeRef>,
+  events_key: Vec<u8>,
+  event_records: TypeRef,
+  executor: WasmExecutor,
+  state: Storage,
+  ext: Mutex<BasicExternalities>,
+}
+
+impl InnerSimulator {
+  fn call(&self, method: &str, data: &[u8]) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    let mut ext = self.ext.lock().unwrap();
+    let code = ext
+      .storage(well_known_keys::CODE)
+      .ok_or_else(|| format!("Missing runtime code in state"))?;
+    Ok(
+      self
+        .executor
+        .call_in_wasm(
+          &code,
+          None,
+          method,
+          data,
+          &mut *ext,
+          MissingHostFunctions::Allow,
+        )
+        .map_err(|e| format!("{} failed: {}", method, e))?,
+    )
+  }
+
+  fn set_storage(&self, key: Vec<u8>, value: Vec<u8>) {
+    let mut ext = self.ext.lock().unwrap();
+    ext.set_storage(key, value);
+  }
+
+  fn events(&self) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let ext = self.ext.lock().unwrap();
+    match ext.storage(&self.events_key) {
+      Some(data) => Ok(self.event_records.decode(data)?.try_cast().unwrap_or_default()),
+      None => Ok(vec![]),
+    }
+  }
+}
+
+/// Run runtime calls locally against the fetched state of a block.
+///
+/// Extrinsics are applied directly on top of the block's state, `Core_initialize_block`
+/// is only run when `initialize_block` is called first (`on_initialize` hooks are skipped
+/// otherwise).
+#[derive(Clone)]
+pub struct Simulator(Arc<InnerSimulator>);
+
+impl Simulator {
+  pub fn new(
+    client: &Client,
+    event_records: TypeRef,
+    block_hash: BlockHash,
+  ) -> Result<Self, Box<EvalAltResult>> {
+    let events_key = client
+      .get_metadata()
+      .get_storage("System", "Events")?
+      .get_value_key()?
+      .0;
+    let block_number = client
+      .get_block(Some(block_hash))?
+      .ok_or_else(|| format!("Block not found: {:?}", block_hash))?
+      .header()
+      .number;
+    let state = fetch_state(client, block_hash)?;
+    let executor = WasmExecutor::new(
+      WasmExecutionMethod::Compiled,
+      None,
+      sp_io::SubstrateHostFunctions::host_functions(),
+      1,
+      None,
+    );
+    Ok(Self(Arc::new(InnerSimulator {
+      block_hash,
+      block_number,
+      block_number_ty: client.block_number_type(),
+      events_key,
+      event_records,
+      executor,
+      ext: Mutex::new(BasicExternalities::new(state.clone())),
+      state,
+    })))
+  }
+
+  /// Drop all changes made by simulated extrinsics.
+  pub fn reset(&mut self) {
+    let mut ext = self.0.ext.lock().unwrap();
+    *ext = BasicExternalities::new(self.0.state.clone());
+  }
+
+  /// Replace the runtime code, for testing runtime upgrades against old state.
+  pub fn set_code(&mut self, code: Vec<u8>) {
+    self.0.set_storage(well_known_keys::CODE.to_vec(), code);
+  }
+
+  /// Run `Core_initialize_block`.
+  pub fn initialize_block(&mut self, header: &BlockHeader) -> Result<(), Box<EvalAltResult>> {
+    let header = header.encode_with(self.0.block_number_ty.as_ref())?;
+    self.0.call("Core_initialize_block", &header)?;
+    Ok(())
+  }
+
+  /// Run `Core_initialize_block` for a new block on top of the simulated block.
+  pub fn initialize_next_block(&mut self) -> Result<(), Box<EvalAltResult>> {
+    let header = BlockHeader {
+      parent_hash: self.0.block_hash,
+      number: self.0.block_number + 1,
+      state_root: Default::default(),
+      extrinsics_root: Default::default(),
+      digest: Default::default(),
+    };
+    self.initialize_block(&header)
+  }
+
+  /// Run `TaggedTransactionQueue_validate_transaction`.
+  pub fn validate(&mut self, xt: Vec<u8>) -> Result<String, Box<EvalAltResult>> {
+    let data = (TransactionSource::External, RawExtrinsic(xt), self.0.block_hash).encode();
+    let res = self
+      .0
+      .call("TaggedTransactionQueue_validate_transaction", &data)?;
+    let validity = sp_runtime::transaction_validity::TransactionValidity::decode(&mut &res[..])
+      .map_err(|e| e.to_string())?;
+    Ok(format!("{:?}", validity))
+  }
+
+  /// Run `BlockBuilder_apply_extrinsic` and collect the new events.
+  pub fn apply(&mut self, xt: Vec<u8>) -> Result<RMap, Box<EvalAltResult>> {
+    let old_events = self.0.events()?.len();
+    let res = self
+      .0
+      .call("BlockBuilder_apply_extrinsic", &RawExtrinsic(xt).encode())?;
+    let res = ApplyExtrinsicResult::decode(&mut &res[..]).map_err(|e| e.to_string())?;
+    let events = self.0.events()?.into_iter().skip(old_events).collect::<Vec<_>>();
+
+    let mut map = RMap::new();
+    map.insert("is_success".into(), Dynamic::from(matches!(res, Ok(Ok(())))));
+    map.insert("result".into(), Dynamic::from(format!("{:?}", res)));
+    map.insert(
+      "events".into(),
+      Dynamic::from(EventRecords::from_dynamic(events.into())?),
+    );
+    Ok(map)
+  }
+}
+
+/// Replay extrinsic `index` of a block on top of the parent block's state.
+///
+/// The block is initialized and all earlier extrinsics of the block are applied first.
+/// Like a block import, the seal is removed from the header before initializing the block.
+/// An optional runtime `code` can be used instead of the block's runtime.
+pub fn replay_extrinsic(
+  client: &Client,
+  event_records: TypeRef,
+  hash: BlockHash,
+  index: usize,
+  code: Option<Vec<u8>>,
+) -> Result<RMap, Box<EvalAltResult>> {
+  let block = client
+    .get_block(Some(hash))?
+    .ok_or_else(|| format!("Block not found: {:?}", hash))?;
+  let xts = block
+    .extrinsics()
+    .iter()
+    .map(|xt| decode_hex(xt))
+    .collect::<Result<Vec<_>, _>>()?;
+  if index >= xts.len() {
+    Err(format!(
+      "Extrinsic index {} out of range, block has {} extrinsics",
+      index,
+      xts.len()
+    ))?;
+  }
+  let mut header = block.header().clone();
+  header
+    .digest
+    .logs
+    .retain(|log| !matches!(log, generic::DigestItem::Seal(..)));
+  let mut sim = Simulator::new(client, event_records, header.parent_hash)?;
+  if let Some(code) = code {
+    sim.set_code(code);
+  }
+  sim.initialize_block(&header)?;
+  for xt in &xts[..index] {
+    sim.apply(xt.clone())?;
+  }
+  sim.apply(xts[index].clone())
+}
+
+/// Already encoded extrinsic (including the length prefix).
+struct RawExtrinsic(Vec<u8>);
+
+impl Encode for RawExtrinsic {
+  fn size_hint(&self) -> usize {
+    self.0.len()
+  }
+
+  fn encode_to<T: parity_scale_codec::Output + ?Sized>(&self, dest: &mut T) {
+    dest.write(&self.0)
+  }
+}
+
+/// Get the block hash from a block number or hash.
+fn block_to_hash(client: &Client, block: Dynamic) -> Result<BlockHash, Box<EvalAltResult>> {
+  match block.as_int() {
+    Ok(num) => Ok(
+      client
+        .get_block_hash(block_number_from_int(num)?)?
+        .ok_or_else(|| format!("Block not found: {}", num))?,
+    ),
+    Err(_) => dynamic_to_hash(block),
+  }
+}
+
+pub fn init_engine(engine: &mut Engine, lookup: &TypeLookup) {
+  let event_records = lookup.resolve("EventRecords");
+  let replay_records = event_records.clone();
+  let replay_code_records = event_records.clone();
+  engine
+    .register_type_with_name::<Simulator>("Simulator")
+    .register_result_fn("simulator", move |client: &mut Client, hash: Dynamic| {
+      let hash = block_to_hash(client, hash)?;
+      Simulator::new(client, event_records.clone(), hash)
+    })
+    .register_result_fn(
+      "replay_extrinsic",
+      move |client: &mut Client, block: Dynamic, index: INT| {
+        let hash = block_to_hash(client, block)?;
+        replay_extrinsic(client, replay_records.clone(), hash, index as usize, None)
+      },
+    )
+    .register_result_fn(
+      "replay_extrinsic",
+      move |client: &mut Client, block: Dynamic, index: INT, code: Vec<u8>| {
+        let hash = block_to_hash(client, block)?;
+        replay_extrinsic(
+          client,
+          replay_code_records.clone(),
+          hash,
+          index as usize,
+          Some(code),
+        )
+      },
+    )
+    .register_fn("reset", Simulator::reset)
+    .register_fn("set_code", Simulator::set_code)
+    .register_result_fn("initialize_block", Simulator::initialize_next_block)
+    .register_result_fn("validate", |sim: &mut Simulator, xthex: &str| {
+      sim.validate(decode_hex(xthex)?)
+    })
+    .register_result_fn("apply", |sim: &mut Simulator, xthex: &str| {
+      sim.apply(decode_hex(xthex)?)
+    });
+}
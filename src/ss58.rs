@@ -0,0 +1,113 @@
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+
+use lazy_static::lazy_static;
+
+use rhai::{Dynamic, Engine, Map as RMap, INT};
+
+/// Bundled copy of the SS58 address format registry:
+/// https://github.com/paritytech/ss58-registry
+const SS58_REGISTRY_JSON: &str = include_str!("../ss58-registry.json");
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ss58Network {
+  pub prefix: u16,
+  pub network: String,
+  pub display_name: String,
+  pub symbols: Vec<String>,
+  pub decimals: Vec<u32>,
+  pub standard_account: Option<String>,
+  pub website: Option<String>,
+}
+
+impl Ss58Network {
+  fn to_map(&self) -> RMap {
+    let mut map = RMap::new();
+    map.insert("prefix".into(), Dynamic::from(self.prefix as INT));
+    map.insert("network".into(), Dynamic::from(self.network.clone()));
+    map.insert(
+      "display_name".into(),
+      Dynamic::from(self.display_name.clone()),
+    );
+    map.insert(
+      "symbols".into(),
+      Dynamic::from(
+        self
+          .symbols
+          .iter()
+          .map(|s| Dynamic::from(s.clone()))
+          .collect::<Vec<_>>(),
+      ),
+    );
+    map.insert(
+      "decimals".into(),
+      Dynamic::from(
+        self
+          .decimals
+          .iter()
+          .map(|d| Dynamic::from(*d as INT))
+          .collect::<Vec<_>>(),
+      ),
+    );
+    map.insert(
+      "standard_account".into(),
+      self
+        .standard_account
+        .clone()
+        .map(Dynamic::from)
+        .unwrap_or(Dynamic::UNIT),
+    );
+    map.insert(
+      "website".into(),
+      self.website.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT),
+    );
+    map
+  }
+}
+
+#[derive(Deserialize)]
+struct Ss58Registry {
+  registry: Vec<Ss58Network>,
+}
+
+lazy_static! {
+  static ref SS58_NETWORKS: Vec<Ss58Network> = serde_json::from_str::<Ss58Registry>(SS58_REGISTRY_JSON)
+    .expect("Bundled ss58 registry is invalid")
+    .registry;
+}
+
+/// Find a network by its address prefix.
+pub fn find_by_prefix(prefix: u16) -> Option<&'static Ss58Network> {
+  SS58_NETWORKS.iter().find(|n| n.prefix == prefix)
+}
+
+/// Find a network by name (case insensitive), e.g. `polkadot`.
+pub fn find_by_network(name: &str) -> Option<&'static Ss58Network> {
+  SS58_NETWORKS
+    .iter()
+    .find(|n| n.network.eq_ignore_ascii_case(name))
+}
+
+fn info_to_dynamic(network: Option<&Ss58Network>) -> Dynamic {
+  network
+    .map(|n| Dynamic::from(n.to_map()))
+    .unwrap_or(Dynamic::UNIT)
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_fn("ss58_info", |prefix: INT| {
+      info_to_dynamic(u16::try_from(prefix).ok().and_then(find_by_prefix))
+    })
+    .register_fn("ss58_info", |name: &str| {
+      info_to_dynamic(find_by_network(name))
+    })
+    .register_fn("ss58_networks", || {
+      SS58_NETWORKS
+        .iter()
+        .map(|n| Dynamic::from(n.to_map()))
+        .collect::<Vec<_>>()
+    });
+}
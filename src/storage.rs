@@ -1,14 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use rhai::{Dynamic, Engine, EvalAltResult, INT};
 
 use sp_core::storage::StorageKey;
 
-use crate::client::Client;
+use crate::client::{block_number_from_int, decode_hex, BlockHash, Client};
 use crate::metadata::*;
 
+/// Number of keys to fetch per request when counting storage entries.
+const COUNT_PAGE_SIZE: u32 = 1000;
+
+fn key_to_hex(key: &StorageKey) -> String {
+  format!("0x{}", hex::encode(&key.0))
+}
+
+fn hex_to_key(key: &str) -> Result<StorageKey, Box<EvalAltResult>> {
+  Ok(StorageKey(decode_hex(key)?))
+}
+
+/// Prefix of child trie storage keys.
+const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:";
+/// Prefix of default child tries.
+const DEFAULT_CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Paged iteration over storage.  Pages are decoded values, or
+/// the keys (hex) for raw iteration without metadata.
 #[derive(Clone)]
 pub struct StorageKeysPaged {
   client: Client,
-  md: StorageMetadata,
+  md: Option<StorageMetadata>,
+  // Decode values that are missing as the metadata default, like `Storage` does.
+  use_defaults: bool,
+  // Child trie to iterate instead of the main trie.
+  child: Option<StorageKey>,
   prefix: StorageKey,
   count: u32,
   start_key: Option<StorageKey>,
@@ -16,10 +41,12 @@ pub struct StorageKeysPaged {
 }
 
 impl StorageKeysPaged {
-  fn new(client: &Client, md: &StorageMetadata, prefix: StorageKey) -> Self {
+  fn new(client: &Client, md: Option<&StorageMetadata>, prefix: StorageKey) -> Self {
     Self {
       client: client.clone(),
-      md: md.clone(),
+      md: md.cloned(),
+      use_defaults: false,
+      child: None,
       prefix,
       count: 100,
       start_key: None,
@@ -44,10 +71,19 @@ impl StorageKeysPaged {
       // No more pages.
       return Ok(Dynamic::UNIT);
     }
-    let keys =
-      self
-        .client
-        .get_storage_keys_paged(&self.prefix, self.count, self.start_key.as_ref())?;
+    let start_key = self.start_key.as_ref();
+    let keys = match &self.child {
+      Some(child) => {
+        self
+          .client
+          .get_child_storage_keys_paged(child, &self.prefix, self.count, start_key)?
+      }
+      None => {
+        self
+          .client
+          .get_storage_keys_paged(&self.prefix, self.count, start_key, None)?
+      }
+    };
     if keys.len() < self.count as usize {
       self.finished = true;
       if keys.len() == 0 {
@@ -58,12 +94,20 @@ impl StorageKeysPaged {
       self.start_key = keys.last().cloned();
     }
 
+    let md = match &self.md {
+      Some(md) => md,
+      None => {
+        let keys = keys.iter().map(|k| Dynamic::from(key_to_hex(k))).collect::<Vec<_>>();
+        return Ok(Dynamic::from(keys));
+      }
+    };
     let result: Vec<Dynamic> = self
       .client
       .get_storage_by_keys(&keys, None)?
       .into_iter()
       .map(|val| match val {
-        Some(val) => self.md.decode_value(val.0),
+        Some(val) => md.decode_value(val.0),
+        None if self.use_defaults => md.decode_default(),
         None => Ok(Dynamic::UNIT),
       })
       .collect::<Result<_, _>>()?;
@@ -75,6 +119,8 @@ impl StorageKeysPaged {
 pub struct Storage {
   client: Client,
   metadata: Metadata,
+  // Shared between the copies of `STORAGE`.
+  use_defaults: Arc<AtomicBool>,
 }
 
 impl Storage {
@@ -82,6 +128,25 @@ impl Storage {
     Self {
       client,
       metadata: metadata.clone(),
+      use_defaults: Arc::new(AtomicBool::new(true)),
+    }
+  }
+
+  /// Return `()` instead of the metadata default for empty storage.
+  fn set_use_defaults(&mut self, use_defaults: bool) {
+    self.use_defaults.store(use_defaults, Ordering::Relaxed);
+  }
+
+  fn use_defaults(&mut self) -> bool {
+    self.use_defaults.load(Ordering::Relaxed)
+  }
+
+  /// Value for an empty storage entry.
+  fn empty_value(&self, md: &StorageMetadata) -> Result<Dynamic, Box<EvalAltResult>> {
+    if self.use_defaults.load(Ordering::Relaxed) {
+      md.decode_default()
+    } else {
+      Ok(Dynamic::UNIT)
     }
   }
 
@@ -89,10 +154,11 @@ impl Storage {
     &self,
     md: &StorageMetadata,
     key: StorageKey,
+    at: Option<BlockHash>,
   ) -> Result<Dynamic, Box<EvalAltResult>> {
-    match self.client.get_storage_by_key(key, None)? {
+    match self.client.get_storage_by_key(key, at)? {
       Some(value) => md.decode_value(value.0),
-      None => Ok(Dynamic::UNIT),
+      None => self.empty_value(md),
     }
   }
 
@@ -107,7 +173,7 @@ impl Storage {
       .into_iter()
       .map(|val| match val {
         Some(val) => md.decode_value(val.0),
-        None => Ok(Dynamic::UNIT),
+        None => self.empty_value(md),
       })
       .collect()
   }
@@ -117,7 +183,96 @@ impl Storage {
     md: &StorageMetadata,
     prefix: StorageKey,
   ) -> Result<StorageKeysPaged, Box<EvalAltResult>> {
-    Ok(StorageKeysPaged::new(&self.client, &md, prefix))
+    let mut paged = StorageKeysPaged::new(&self.client, Some(md), prefix);
+    paged.use_defaults = self.use_defaults.load(Ordering::Relaxed);
+    Ok(paged)
+  }
+
+  /// Call `f` with each page of keys under `prefix`, without loading the values.
+  fn for_each_key_page<F>(&self, prefix: &StorageKey, mut f: F) -> Result<(), Box<EvalAltResult>>
+  where
+    F: FnMut(&[StorageKey]) -> Result<(), Box<EvalAltResult>>,
+  {
+    let mut start_key: Option<StorageKey> = None;
+    loop {
+      let keys = self
+        .client
+        .get_storage_keys_paged(prefix, COUNT_PAGE_SIZE, start_key.as_ref(), None)?;
+      f(&keys)?;
+      if keys.len() < COUNT_PAGE_SIZE as usize {
+        return Ok(());
+      }
+      start_key = keys.last().cloned();
+    }
+  }
+
+  /// Number of entries in a storage map.
+  pub fn count(&mut self, mod_name: &str, storage_name: &str) -> Result<INT, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let prefix = md.get_map_prefix()?;
+    let mut count = 0;
+    self.for_each_key_page(&prefix, |keys| {
+      count += keys.len();
+      Ok(())
+    })?;
+    Ok(count as INT)
+  }
+
+  /// Total size in bytes of the encoded values of a storage item (all entries for maps).
+  pub fn size(&mut self, mod_name: &str, storage_name: &str) -> Result<INT, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    if md.key_hasher.is_none() {
+      let key = md.get_value_key()?;
+      let size = self.client.get_storage_sizes(&[key], None)?;
+      return Ok(size.into_iter().flatten().sum::<u64>() as INT);
+    }
+    let prefix = md.get_map_prefix()?;
+    let mut size = 0u64;
+    self.for_each_key_page(&prefix, |keys| {
+      size += self
+        .client
+        .get_storage_sizes(keys, None)?
+        .into_iter()
+        .flatten()
+        .sum::<u64>();
+      Ok(())
+    })?;
+    Ok(size as INT)
+  }
+
+  /// Raw value (hex) of a storage key.  Returns `()` if the storage is empty.
+  pub fn raw_get(&mut self, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    match self.client.get_storage_by_key(hex_to_key(key)?, None)? {
+      Some(value) => Ok(Dynamic::from(format!("0x{}", hex::encode(&value.0)))),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Iterate over the storage keys (hex) starting with `prefix`.
+  pub fn raw_keys_paged(&mut self, prefix: &str) -> Result<StorageKeysPaged, Box<EvalAltResult>> {
+    Ok(StorageKeysPaged::new(&self.client, None, hex_to_key(prefix)?))
+  }
+
+  /// Storage key (hex) for a value, map key or array of keys (double maps and NMaps).
+  pub fn key_hex(
+    &mut self,
+    mod_name: &str,
+    storage_name: &str,
+    key: Dynamic,
+  ) -> Result<String, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let hashers = md.key_hasher.as_ref().map_or(0, |h| h.type_hashers.len());
+    let key = match hashers {
+      0 => md.get_value_key()?,
+      1 => md.get_map_key(key)?,
+      _ => {
+        let keys = key
+          .try_cast::<Vec<Dynamic>>()
+          .ok_or_else(|| format!("Expected an array of {} keys", hashers))?;
+        md.get_nmap_key(keys)?
+      }
+    };
+    Ok(key_to_hex(&key))
   }
 
   pub fn get_value(
@@ -127,7 +282,7 @@ impl Storage {
   ) -> Result<Dynamic, Box<EvalAltResult>> {
     let md = self.metadata.get_storage(mod_name, storage_name)?;
     let key = md.get_value_key()?;
-    self.get_by_key(md, key)
+    self.get_by_key(md, key, None)
   }
 
   pub fn get_map(
@@ -138,7 +293,7 @@ impl Storage {
   ) -> Result<Dynamic, Box<EvalAltResult>> {
     let md = self.metadata.get_storage(mod_name, storage_name)?;
     let key = md.get_map_key(key)?;
-    self.get_by_key(md, key)
+    self.get_by_key(md, key, None)
   }
 
   pub fn get_map_paged(
@@ -174,7 +329,29 @@ impl Storage {
   ) -> Result<Dynamic, Box<EvalAltResult>> {
     let md = self.metadata.get_storage(mod_name, storage_name)?;
     let key = md.get_double_map_key(key1, key2)?;
-    self.get_by_key(md, key)
+    self.get_by_key(md, key, None)
+  }
+
+  /// Get the values for many `[key1, key2]` pairs with one batch of requests.
+  pub fn get_double_map_keys(
+    &mut self,
+    mod_name: &str,
+    storage_name: &str,
+    keys: Vec<Dynamic>,
+  ) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let keys = keys
+      .into_iter()
+      .map(|pair| {
+        let pair = pair
+          .try_cast::<Vec<Dynamic>>()
+          .filter(|pair| pair.len() == 2)
+          .ok_or_else(|| format!("Expected an array of [key1, key2] pairs"))?;
+        let mut pair = pair.into_iter();
+        md.get_double_map_key(pair.next().unwrap(), pair.next().unwrap())
+      })
+      .collect::<Result<Vec<_>, Box<EvalAltResult>>>()?;
+    self.get_by_keys(md, &keys)
   }
 
   pub fn get_double_paged(
@@ -187,21 +364,173 @@ impl Storage {
     let prefix = md.get_double_map_prefix(key1)?;
     self.get_keys_paged(md, prefix)
   }
+
+  pub fn get_nmap(
+    &mut self,
+    mod_name: &str,
+    storage_name: &str,
+    keys: Vec<Dynamic>,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = md.get_nmap_key(keys)?;
+    self.get_by_key(md, key, None)
+  }
+
+  /// Value of a storage item at block number `number`.
+  pub fn get_value_at_number(
+    &mut self,
+    number: INT,
+    mod_name: &str,
+    storage_name: &str,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let at = self.client.block_hash_at(block_number_from_int(number)?)?;
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = md.get_value_key()?;
+    self.get_by_key(md, key, Some(at))
+  }
+
+  pub fn get_map_at_number(
+    &mut self,
+    number: INT,
+    mod_name: &str,
+    storage_name: &str,
+    key: Dynamic,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let at = self.client.block_hash_at(block_number_from_int(number)?)?;
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = md.get_map_key(key)?;
+    self.get_by_key(md, key, Some(at))
+  }
+
+  pub fn get_double_map_at_number(
+    &mut self,
+    number: INT,
+    mod_name: &str,
+    storage_name: &str,
+    key1: Dynamic,
+    key2: Dynamic,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let at = self.client.block_hash_at(block_number_from_int(number)?)?;
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = md.get_double_map_key(key1, key2)?;
+    self.get_by_key(md, key, Some(at))
+  }
+
+  pub fn get_nmap_at_number(
+    &mut self,
+    number: INT,
+    mod_name: &str,
+    storage_name: &str,
+    keys: Vec<Dynamic>,
+  ) -> Result<Dynamic, Box<EvalAltResult>> {
+    let at = self.client.block_hash_at(block_number_from_int(number)?)?;
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let key = md.get_nmap_key(keys)?;
+    self.get_by_key(md, key, Some(at))
+  }
+
+  /// Iterate over the NMap values matching the first `keys.len()` keys.
+  pub fn get_nmap_paged(
+    &mut self,
+    mod_name: &str,
+    storage_name: &str,
+    keys: Vec<Dynamic>,
+  ) -> Result<StorageKeysPaged, Box<EvalAltResult>> {
+    let md = self.metadata.get_storage(mod_name, storage_name)?;
+    let prefix = md.get_nmap_prefix(keys)?;
+    self.get_keys_paged(md, prefix)
+  }
 }
 
-pub fn init_engine(engine: &mut Engine, client: &Client, metadata: &Metadata) -> Storage {
+/// Raw access to a child trie (e.g. crowdloan funds, contracts).
+#[derive(Clone)]
+pub struct ChildStorage {
+  client: Client,
+  child_key: StorageKey,
+}
+
+impl ChildStorage {
+  /// `child_key` is the prefixed child key or the id of a default child trie (hex).
+  pub fn new(client: &Client, child_key: &str) -> Result<Self, Box<EvalAltResult>> {
+    let mut key = decode_hex(child_key)?;
+    if !key.starts_with(CHILD_STORAGE_PREFIX) {
+      key.splice(0..0, DEFAULT_CHILD_STORAGE_PREFIX.iter().cloned());
+    }
+    Ok(Self {
+      client: client.clone(),
+      child_key: StorageKey(key),
+    })
+  }
+
+  fn child_key(&mut self) -> String {
+    key_to_hex(&self.child_key)
+  }
+
+  /// Raw value (hex) of a key in the child trie.
+  fn get(&mut self, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    let value = self
+      .client
+      .get_child_storage_by_key(&self.child_key, hex_to_key(key)?, None)?;
+    match value {
+      Some(value) => Ok(Dynamic::from(format!("0x{}", hex::encode(&value.0)))),
+      None => Ok(Dynamic::UNIT),
+    }
+  }
+
+  /// Iterate over the keys (hex) in the child trie starting with `prefix`.
+  fn keys_paged(&mut self, prefix: &str) -> Result<StorageKeysPaged, Box<EvalAltResult>> {
+    let mut paged = StorageKeysPaged::new(&self.client, None, hex_to_key(prefix)?);
+    paged.child = Some(self.child_key.clone());
+    Ok(paged)
+  }
+
+  fn to_string(&mut self) -> String {
+    format!("ChildStorage: {}", self.child_key())
+  }
+}
+
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<Storage>("Storage")
+    .register_fn("set_use_defaults", Storage::set_use_defaults)
+    .register_get("use_defaults", Storage::use_defaults)
     .register_result_fn("value", Storage::get_value)
     .register_result_fn("map", Storage::get_map)
     .register_result_fn("map_keys", Storage::get_map_keys)
     .register_result_fn("double_map", Storage::get_double_map)
+    .register_result_fn("double_map_keys", Storage::get_double_map_keys)
     .register_result_fn("map_paged", Storage::get_map_paged)
     .register_result_fn("double_paged", Storage::get_double_paged)
+    .register_result_fn("nmap", Storage::get_nmap)
+    .register_result_fn("nmap_paged", Storage::get_nmap_paged)
+    .register_result_fn("value_at_number", Storage::get_value_at_number)
+    .register_result_fn("map_at_number", Storage::get_map_at_number)
+    .register_result_fn("double_map_at_number", Storage::get_double_map_at_number)
+    .register_result_fn("nmap_at_number", Storage::get_nmap_at_number)
+    .register_result_fn("count", Storage::count)
+    .register_result_fn("raw_get", Storage::raw_get)
+    .register_result_fn("raw_keys_paged", Storage::raw_keys_paged)
+    .register_result_fn("key_hex", |storage: &mut Storage, mod_name: &str, name: &str| {
+      storage.key_hex(mod_name, name, Dynamic::UNIT)
+    })
+    .register_result_fn("key_hex", Storage::key_hex)
+    .register_result_fn("child", |storage: &mut Storage, child_key: &str| {
+      ChildStorage::new(&storage.client, child_key)
+    })
+    .register_type_with_name::<ChildStorage>("ChildStorage")
+    .register_get("child_key", ChildStorage::child_key)
+    .register_result_fn("get", ChildStorage::get)
+    .register_result_fn("keys_paged", ChildStorage::keys_paged)
+    .register_fn("to_string", ChildStorage::to_string)
+    .register_result_fn("size", Storage::size)
     .register_type_with_name::<StorageKeysPaged>("StorageKeysPaged")
     .register_get("is_finished", StorageKeysPaged::is_finished)
     .register_get("has_more", StorageKeysPaged::has_more)
     .register_fn("set_page_count", StorageKeysPaged::set_page_count)
     .register_result_fn("next", StorageKeysPaged::next);
+}
+
+pub fn init_engine(engine: &mut Engine, client: &Client, metadata: &Metadata) -> Storage {
+  register(engine);
   Storage::new(client.clone(), metadata)
 }
@@ -0,0 +1,251 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
+
+use crate::client::{BlockHash, Client, ExtrinsicCallResult};
+use crate::metadata::EncodedCall;
+use crate::users::SharedUser;
+
+/// How often to check for a new block while waiting for a submission slot.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Substrate's default maximum block weight (2 seconds of compute), used if the
+/// metadata doesn't have the `System.BlockWeights` constant.
+const DEFAULT_MAX_WEIGHT: u64 = 2_000_000_000_000;
+
+/// Compute time of a decoded weight: a plain integer or a `Weight` with `ref_time`.
+fn weight_ref_time(weight: &Dynamic) -> Option<u64> {
+  if let Ok(weight) = weight.as_int() {
+    return Some(weight as u64);
+  }
+  if let Ok(weight) = weight.as_decimal() {
+    return weight.to_u64();
+  }
+  weight
+    .read_lock::<RMap>()
+    .and_then(|weight| weight.get("ref_time").and_then(weight_ref_time))
+}
+
+/// Maximum block weight from the `System.BlockWeights` (or older `System.MaximumBlockWeight`)
+/// constant.
+fn max_block_weight(client: &Client) -> Result<u64, Box<EvalAltResult>> {
+  let metadata = client.get_metadata();
+  if let Ok(md) = metadata.get_constant("System", "BlockWeights") {
+    let weights = md.decode_value()?;
+    if let Some(max) = weights
+      .read_lock::<RMap>()
+      .and_then(|weights| weights.get("max_block").and_then(weight_ref_time))
+    {
+      return Ok(max);
+    }
+  }
+  if let Ok(md) = metadata.get_constant("System", "MaximumBlockWeight") {
+    if let Some(max) = weight_ref_time(&md.decode_value()?) {
+      return Ok(max);
+    }
+  }
+  Ok(DEFAULT_MAX_WEIGHT)
+}
+
+/// Convert a ratio (`0.8` or `80` percent) to permill.
+fn ratio_to_permill(val: &Dynamic) -> Result<u64, Box<EvalAltResult>> {
+  let ratio = if let Ok(percent) = val.as_int() {
+    Decimal::from(percent) / Decimal::from(100)
+  } else if let Ok(dec) = val.as_decimal() {
+    dec
+  } else {
+    Err(format!("Expected a ratio (0.8) or percent (80): {:?}", val))?
+  };
+  let permill = (ratio * Decimal::from(1_000_000))
+    .to_u64()
+    .ok_or_else(|| format!("Invalid ratio: {}", ratio))?;
+  Ok(permill.min(1_000_000))
+}
+
+struct RateState {
+  block: Option<BlockHash>,
+  // Extrinsics allowed per block.
+  rate: u64,
+  // Extrinsics submitted since the last block.
+  submitted: u64,
+  // Fullness of the last block in permill.
+  fullness: u64,
+  blocks: u64,
+  total_submitted: u64,
+}
+
+pub struct InnerSubmitter {
+  client: Client,
+  target: u64,
+  min_rate: u64,
+  max_rate: u64,
+  max_weight: u64,
+  state: Mutex<RateState>,
+}
+
+impl InnerSubmitter {
+  fn block_weight(&self, hash: BlockHash) -> Result<u64, Box<EvalAltResult>> {
+    let md = self
+      .client
+      .get_metadata()
+      .get_storage("System", "BlockWeight")?
+      .clone();
+    let data = match self.client.get_storage_by_key(md.get_value_key()?, Some(hash))? {
+      Some(data) => data,
+      None => return Ok(0),
+    };
+    // `PerDispatchClass<Weight>`: normal, operational, mandatory.
+    let weights = md
+      .decode_value(data.0)?
+      .try_cast::<RMap>()
+      .ok_or_else(|| format!("Expected a map for System.BlockWeight"))?;
+    Ok(weights.values().filter_map(weight_ref_time).sum())
+  }
+
+  /// Adjust the rate to the fullness of a new block.
+  fn new_block(&self, state: &mut RateState, hash: BlockHash, weight: u64) {
+    let fullness = (weight as u128 * 1_000_000 / self.max_weight.max(1) as u128) as u64;
+    let rate = if fullness < self.target {
+      // Slowly ramp up.
+      state.rate + (state.rate / 10).max(1)
+    } else {
+      // Backoff proportional to how much the target was overshot.
+      (state.rate as u128 * self.target as u128 / fullness.max(1) as u128) as u64
+    };
+    state.rate = rate.max(self.min_rate).min(self.max_rate);
+    log::debug!(
+      "Block {:?}: weight={}, fullness={} permill, rate={}",
+      hash,
+      weight,
+      fullness,
+      state.rate
+    );
+    state.block = Some(hash);
+    state.fullness = fullness;
+    state.submitted = 0;
+    state.blocks += 1;
+  }
+
+  /// Wait until the rate limit allows another submission.
+  ///
+  /// The state isn't locked during the RPC calls, so other threads can check their slots.
+  fn wait_for_slot(&self) -> Result<(), Box<EvalAltResult>> {
+    loop {
+      let best = self.client.get_best_block_hash()?;
+      let new_best = best.filter(|best| self.state.lock().unwrap().block != Some(*best));
+      let weight = match new_best {
+        Some(best) => Some((best, self.block_weight(best)?)),
+        None => None,
+      };
+      {
+        let mut state = self.state.lock().unwrap();
+        if let Some((best, weight)) = weight {
+          // Another thread might have handled the block already.
+          if state.block != Some(best) {
+            self.new_block(&mut state, best, weight);
+          }
+        }
+        if state.submitted < state.rate {
+          state.submitted += 1;
+          state.total_submitted += 1;
+          return Ok(());
+        }
+      }
+      thread::sleep(POLL_INTERVAL);
+    }
+  }
+}
+
+/// Submit extrinsics at a rate that keeps blocks at a target fullness.
+#[derive(Clone)]
+pub struct BulkSubmitter(Arc<InnerSubmitter>);
+
+impl BulkSubmitter {
+  pub fn new(client: &Client, opts: RMap) -> Result<Self, Box<EvalAltResult>> {
+    let get_int = |name: &str, default: u64| -> Result<u64, Box<EvalAltResult>> {
+      match opts.get(name) {
+        Some(val) => Ok(val.as_int().map_err(|_| format!("Expected integer for {}", name))? as u64),
+        None => Ok(default),
+      }
+    };
+    let target = match opts.get("target") {
+      Some(val) => ratio_to_permill(val)?,
+      None => 800_000,
+    };
+    let min_rate = get_int("min_rate", 1)?.max(1);
+    let max_rate = get_int("max_rate", 1_000)?.max(min_rate);
+    let max_weight = match opts.get("max_weight") {
+      Some(_) => get_int("max_weight", 0)?,
+      None => max_block_weight(client)?,
+    };
+    let rate = get_int("rate", min_rate)?.max(min_rate).min(max_rate);
+    Ok(Self(Arc::new(InnerSubmitter {
+      client: client.clone(),
+      target,
+      min_rate,
+      max_rate,
+      max_weight,
+      state: Mutex::new(RateState {
+        block: None,
+        rate,
+        submitted: 0,
+        fullness: 0,
+        blocks: 0,
+        total_submitted: 0,
+      }),
+    })))
+  }
+
+  pub fn submit(
+    &mut self,
+    mut user: SharedUser,
+    call: EncodedCall,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    self.0.wait_for_slot()?;
+    user.submit_call(call)
+  }
+
+  fn rate(&mut self) -> INT {
+    self.0.state.lock().unwrap().rate as INT
+  }
+
+  fn fullness(&mut self) -> Dynamic {
+    let fullness = self.0.state.lock().unwrap().fullness;
+    Dynamic::from_decimal(Decimal::new(fullness as i64, 6))
+  }
+
+  fn stats(&mut self) -> RMap {
+    let state = self.0.state.lock().unwrap();
+    let mut map = RMap::new();
+    map.insert("rate".into(), Dynamic::from(state.rate as INT));
+    map.insert(
+      "fullness".into(),
+      Dynamic::from_decimal(Decimal::new(state.fullness as i64, 6)),
+    );
+    map.insert("blocks".into(), Dynamic::from(state.blocks as INT));
+    map.insert(
+      "submitted".into(),
+      Dynamic::from(state.total_submitted as INT),
+    );
+    map
+  }
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<BulkSubmitter>("BulkSubmitter")
+    .register_result_fn("bulk_submitter", |client: &mut Client| {
+      BulkSubmitter::new(client, RMap::new())
+    })
+    .register_result_fn("bulk_submitter", |client: &mut Client, opts: RMap| {
+      BulkSubmitter::new(client, opts)
+    })
+    .register_result_fn("submit", BulkSubmitter::submit)
+    .register_get("rate", BulkSubmitter::rate)
+    .register_get("fullness", BulkSubmitter::fullness)
+    .register_fn("stats", BulkSubmitter::stats);
+}
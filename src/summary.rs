@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use lazy_static::lazy_static;
+
+static RPC_CALLS: AtomicU64 = AtomicU64::new(0);
+static XT_SUBMITTED: AtomicU64 = AtomicU64::new(0);
+static XT_SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+static XT_FAILED: AtomicU64 = AtomicU64::new(0);
+static DECODE_ERRORS: AtomicU64 = AtomicU64::new(0);
+static WARNINGS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+  static ref STARTED: Instant = Instant::now();
+  // Wall-clock time of each phase, in run order.
+  static ref PHASES: Mutex<Vec<(String, Duration)>> = Mutex::new(Vec::new());
+}
+
+pub fn rpc_call() {
+  RPC_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn extrinsic_submitted() {
+  XT_SUBMITTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn extrinsic_result(success: bool) {
+  if success {
+    XT_SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+  } else {
+    XT_FAILED.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+pub fn decode_error() {
+  DECODE_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Time a phase of the run (e.g. `init`, `script`).
+pub fn phase<F, R>(name: &str, func: F) -> R
+where
+  F: FnOnce() -> R,
+{
+  lazy_static::initialize(&STARTED);
+  let start = Instant::now();
+  let res = func();
+  PHASES
+    .lock()
+    .unwrap()
+    .push((name.to_string(), start.elapsed()));
+  res
+}
+
+/// Logger wrapper that counts warnings and errors for the summary.
+pub struct CountingLogger<L: log::Log>(pub L);
+
+impl<L: log::Log> log::Log for CountingLogger<L> {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    self.0.enabled(metadata)
+  }
+
+  fn log(&self, record: &log::Record) {
+    match record.level() {
+      log::Level::Warn => {
+        WARNINGS.fetch_add(1, Ordering::Relaxed);
+      }
+      log::Level::Error => {
+        ERRORS.fetch_add(1, Ordering::Relaxed);
+      }
+      _ => (),
+    }
+    self.0.log(record)
+  }
+
+  fn flush(&self) {
+    self.0.flush()
+  }
+}
+
+fn millis(time: Duration) -> f64 {
+  time.as_secs_f64() * 1000.0
+}
+
+/// Build the run summary.  `error` is the script error, if the script failed.
+pub fn summary(script: &str, error: Option<String>) -> Value {
+  let submitted = XT_SUBMITTED.load(Ordering::Relaxed);
+  let succeeded = XT_SUCCEEDED.load(Ordering::Relaxed);
+  let failed = XT_FAILED.load(Ordering::Relaxed);
+  let phases = PHASES
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(name, time)| json!({ "name": name, "ms": millis(*time) }))
+    .collect::<Vec<_>>();
+  json!({
+    "script": script,
+    "success": error.is_none(),
+    "error": error,
+    "rpc_calls": RPC_CALLS.load(Ordering::Relaxed),
+    "extrinsics": {
+      "submitted": submitted,
+      "succeeded": succeeded,
+      "failed": failed,
+      // Results that the script never checked.
+      "unknown": submitted.saturating_sub(succeeded + failed),
+    },
+    "decode_errors": DECODE_ERRORS.load(Ordering::Relaxed),
+    "warnings": WARNINGS.load(Ordering::Relaxed),
+    "errors": ERRORS.load(Ordering::Relaxed),
+    "phases": phases,
+    "total_ms": millis(STARTED.elapsed()),
+  })
+}
+
+pub fn write_summary(path: &str, script: &str, error: Option<String>) -> std::io::Result<()> {
+  let file = File::create(path)?;
+  serde_json::to_writer_pretty(BufWriter::new(file), &summary(script, error))?;
+  Ok(())
+}
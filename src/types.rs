@@ -1,9 +1,14 @@
 use std::any::TypeId;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
-use std::fs::File;
-use std::io::BufReader;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
 use parity_scale_codec::{Compact, Decode, Encode, Error as PError, Input};
 use serde_json::{Map, Value};
@@ -16,17 +21,25 @@ use scale_info::{
 };
 
 use sp_core::crypto::Ss58Codec;
+use sp_core::{H160, H256, U256};
 use sp_runtime::{generic::Era, MultiSignature};
 
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use rust_decimal::{
+  prelude::{FromPrimitive, ToPrimitive},
+  Decimal,
+};
 
-use rhai::{Array, Dynamic, Engine, EvalAltResult, ImmutableString, Map as RMap};
+use rhai::serde::from_dynamic;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, ImmutableString, Map as RMap, INT};
 use smartstring::{LazyCompact, SmartString};
 
 use indexmap::map::IndexMap;
 
+use super::client::decode_hex;
 use super::engine::EngineOptions;
+use super::error::{ErrorKind, ScriptError};
 use super::metadata::EncodedArgs;
+use super::summary;
 use super::users::{AccountId, SharedUser};
 
 #[cfg(feature = "v14")]
@@ -41,20 +54,14 @@ pub fn is_type_compact(ty: &Type<PortableForm>) -> bool {
 pub fn get_type_name(ty: &Type<PortableForm>, types: &PortableRegistry, full: bool) -> String {
   let name = match ty.type_def() {
     TypeDef::Sequence(s) => {
-      let elm_ty = types.resolve(s.type_param().id())
-        .expect("Failed to resolve sequence element type");
-      format!("Vec<{}>", get_type_name(elm_ty, types, full))
+      format!("Vec<{}>", get_type_id_name(s.type_param().id(), types, full))
     }
     TypeDef::Array(a) => {
-      let elm_ty = types.resolve(a.type_param().id())
-        .expect("Failed to resolve array element type");
-      format!("[{}; {}]", get_type_name(elm_ty, types, full), a.len())
+      format!("[{}; {}]", get_type_id_name(a.type_param().id(), types, full), a.len())
     }
     TypeDef::Tuple(t) => {
       let fields = t.fields().iter().map(|f| {
-        let f_ty = types.resolve(f.id())
-          .expect("Failed to resolve tuple element type");
-        get_type_name(f_ty, types, full)
+        get_type_id_name(f.id(), types, full)
       }).collect::<Vec<_>>();
       format!("({})", fields.join(","))
     }
@@ -79,15 +86,13 @@ pub fn get_type_name(ty: &Type<PortableForm>, types: &PortableRegistry, full: bo
       }
     }
     TypeDef::Compact(c) => {
-      let elm_ty = types.resolve(c.type_param().id())
-        .expect("Failed to resolve Compact type");
-      format!("Compact<{}>", get_type_name(elm_ty, types, full))
+      format!("Compact<{}>", get_type_id_name(c.type_param().id(), types, full))
     }
     _ => {
       if full {
         format!("{}", ty.path())
       } else {
-        ty.path().ident().expect("Missing type name")
+        ty.path().ident().unwrap_or_else(|| format!("{}", ty.path()))
       }
     }
   };
@@ -95,11 +100,7 @@ pub fn get_type_name(ty: &Type<PortableForm>, types: &PortableRegistry, full: bo
   if ty_params.len() > 0 {
     let params = ty_params.iter().map(|p| {
       match p.ty() {
-        Some(ty) => {
-          let p_ty = types.resolve(ty.id())
-            .expect("Failed to resolve type parameter");
-          get_type_name(p_ty, types, full)
-        }
+        Some(ty) => get_type_id_name(ty.id(), types, full),
         None => p.name().clone()
       }
     }).collect::<Vec<_>>();
@@ -109,6 +110,267 @@ pub fn get_type_name(ty: &Type<PortableForm>, types: &PortableRegistry, full: bo
   }
 }
 
+/// Opt-in codec tracing.  Logs each nested type with byte offsets.
+static CODEC_TRACE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct CodecTrace {
+  path: Vec<String>,
+  start_len: usize,
+  // Nesting of the types being encoded/decoded.
+  depth: usize,
+}
+
+thread_local! {
+  static CODEC_TRACE_STATE: RefCell<CodecTrace> = RefCell::new(CodecTrace::default());
+}
+
+pub fn set_codec_trace(enabled: bool) {
+  CODEC_TRACE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_codec_trace() -> bool {
+  CODEC_TRACE.load(Ordering::Relaxed)
+}
+
+/// Track the nesting of traced types.  The outermost type of an encode/decode
+/// (from any entry point) records the input length used for decode offsets.
+struct TraceScope(bool);
+
+impl TraceScope {
+  fn enter<F: FnOnce() -> usize>(start_len: F) -> Self {
+    if !is_codec_trace() {
+      return Self(false);
+    }
+    CODEC_TRACE_STATE.with(|t| {
+      let mut t = t.borrow_mut();
+      if t.depth == 0 {
+        t.start_len = start_len();
+      }
+      t.depth += 1;
+    });
+    Self(true)
+  }
+}
+
+impl Drop for TraceScope {
+  fn drop(&mut self) {
+    if self.0 {
+      CODEC_TRACE_STATE.with(|t| {
+        let mut t = t.borrow_mut();
+        t.depth = t.depth.saturating_sub(1);
+      });
+    }
+  }
+}
+
+/// Current type path, e.g. `data.free` or `args[2].kind`.
+pub fn trace_path() -> String {
+  CODEC_TRACE_STATE.with(|t| {
+    let t = t.borrow();
+    let mut path = String::new();
+    for seg in &t.path {
+      if !path.is_empty() && !seg.starts_with('[') {
+        path.push('.');
+      }
+      path.push_str(seg);
+    }
+    path
+  })
+}
+
+/// Push a path segment while encoding/decoding a nested type.
+struct TraceSegment(bool);
+
+impl TraceSegment {
+  fn enter<F: FnOnce() -> String>(segment: F) -> Self {
+    if !is_codec_trace() {
+      return Self(false);
+    }
+    CODEC_TRACE_STATE.with(|t| t.borrow_mut().path.push(segment()));
+    Self(true)
+  }
+}
+
+impl Drop for TraceSegment {
+  fn drop(&mut self) {
+    if self.0 {
+      CODEC_TRACE_STATE.with(|t| {
+        t.borrow_mut().path.pop();
+      });
+    }
+  }
+}
+
+/// Prefix of encode errors that have a value path.
+const ENCODE_ERROR_PREFIX: &str = "Encode error at `";
+
+/// Add a path segment to an encode error.  Segments are added from the innermost value out.
+fn encode_error_path(err: Box<EvalAltResult>, segment: &str) -> Box<EvalAltResult> {
+  match *err {
+    EvalAltResult::ErrorRuntime(msg, pos) if msg.is::<ImmutableString>() => {
+      let msg = msg.cast::<ImmutableString>();
+      let msg = match msg.strip_prefix(ENCODE_ERROR_PREFIX) {
+        Some(rest) => {
+          let sep = if rest.starts_with('[') { "" } else { "." };
+          format!("{}{}{}{}", ENCODE_ERROR_PREFIX, segment, sep, rest)
+        }
+        None => format!("{}{}`: {}", ENCODE_ERROR_PREFIX, segment, msg),
+      };
+      Box::new(EvalAltResult::ErrorRuntime(msg.into(), pos))
+    }
+    err => Box::new(err),
+  }
+}
+
+/// Encode a nested value.  Errors will include the path to the value,
+/// e.g. "Encode error at `args[2].portfolio.kind`: ...".
+pub fn encode_nested<S, F>(segment: S, func: F) -> Result<(), Box<EvalAltResult>>
+where
+  S: Fn() -> String,
+  F: FnOnce() -> Result<(), Box<EvalAltResult>>,
+{
+  let _seg = TraceSegment::enter(&segment);
+  func().map_err(|err| encode_error_path(err, &segment()))
+}
+
+/// Encode a map entry into `entries`, keyed by the encoded key.  Duplicate keys keep the
+/// last value.
+fn encode_map_entry(
+  key_ref: &TypeRef,
+  val_ref: &TypeRef,
+  key: Dynamic,
+  value: Dynamic,
+  entries: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+) -> Result<(), Box<EvalAltResult>> {
+  let mut key_data = EncodedArgs::new();
+  key_ref.encode_value(key, &mut key_data)?;
+  let mut val_data = EncodedArgs::new();
+  val_ref.encode_value(value, &mut val_data)?;
+  entries.insert(key_data.into_inner(), val_data.into_inner());
+  Ok(())
+}
+
+fn trace_encode(meta: &TypeMeta, offset: usize) -> TraceScope {
+  let scope = TraceScope::enter(|| 0);
+  if is_codec_trace() {
+    log::trace!(
+      target: "codec",
+      "encode {} at offset={}: {}",
+      trace_path(),
+      offset,
+      meta.kind_name()
+    );
+  }
+  scope
+}
+
+fn trace_decode<I: Input>(meta: &TypeMeta, input: &mut I) -> TraceScope {
+  let remaining = if is_codec_trace() {
+    input.remaining_len().ok().flatten().unwrap_or(0)
+  } else {
+    0
+  };
+  let scope = TraceScope::enter(|| remaining);
+  if is_codec_trace() {
+    let start_len = CODEC_TRACE_STATE.with(|t| t.borrow().start_len);
+    log::trace!(
+      target: "codec",
+      "decode {} at offset={} (remaining={}): {}",
+      trace_path(),
+      start_len.saturating_sub(remaining),
+      remaining,
+      meta.kind_name()
+    );
+  }
+  scope
+}
+
+/// Maximum nesting of types when decoding a value.  Guards against stack overflows
+/// from malformed data in recursive types.
+const MAX_DECODE_DEPTH: usize = 256;
+
+thread_local! {
+  static DECODE_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Track the decode nesting depth, fails when `MAX_DECODE_DEPTH` is exceeded.
+struct DecodeDepth;
+
+impl DecodeDepth {
+  fn enter() -> Result<Self, PError> {
+    let depth = DECODE_DEPTH.with(|d| {
+      let depth = d.get() + 1;
+      d.set(depth);
+      depth
+    });
+    // Create the guard first, so the depth is restored on error.
+    let guard = Self;
+    if depth > MAX_DECODE_DEPTH {
+      if is_codec_trace() {
+        log::error!(
+          target: "codec",
+          "decode depth limit ({}) exceeded at: {}",
+          MAX_DECODE_DEPTH,
+          trace_path()
+        );
+      }
+      Err("Error decoding value, maximum nesting depth exceeded.")?;
+    }
+    Ok(guard)
+  }
+}
+
+impl Drop for DecodeDepth {
+  fn drop(&mut self) {
+    DECODE_DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+  }
+}
+
+/// Longest collection of zero-sized items (`Vec<()>`) accepted when decoding.
+const MAX_ZERO_SIZE_LEN: u64 = 1024 * 1024;
+
+/// Decode a `Compact<u64>` length prefix.
+///
+/// Each item needs at least one byte, so a length larger than the remaining input
+/// can only come from bad data.  Zero-sized items don't use any input, their length
+/// is limited by `MAX_ZERO_SIZE_LEN` instead.
+fn decode_len<I: Input>(input: &mut I, zero_size: bool) -> Result<usize, PError> {
+  let len = Compact::<u64>::decode(input)?.0;
+  if zero_size {
+    if len > MAX_ZERO_SIZE_LEN {
+      log::debug!("invalid length of zero-sized items: {}", len);
+      Err("Error decoding length, too many zero-sized items.")?;
+    }
+  } else if let Some(remaining) = input.remaining_len()? {
+    if len > remaining as u64 {
+      log::debug!("invalid length: {}, remaining: {}", len, remaining);
+      if is_codec_trace() {
+        log::error!(
+          target: "codec",
+          "invalid length {} at: {}",
+          len,
+          trace_path()
+        );
+      }
+      Err("Error decoding length, larger than the remaining input.")?;
+    }
+  }
+  usize::try_from(len).map_err(|_| "Error decoding length, too large.".into())
+}
+
+/// Get the type name for a registry type id.  Missing types get a placeholder name.
+#[cfg(feature = "v14")]
+pub fn get_type_id_name(id: u32, types: &PortableRegistry, full: bool) -> String {
+  match types.resolve(id) {
+    Some(ty) => get_type_name(ty, types, full),
+    None => {
+      log::warn!("Missing registry type: {}", id);
+      format!("MissingType{}", id)
+    }
+  }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct EnumVariant {
   idx: u8,
@@ -116,6 +378,16 @@ pub struct EnumVariant {
   type_ref: Option<TypeRef>,
 }
 
+impl EnumVariant {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn type_ref(&self) -> Option<&TypeRef> {
+    self.type_ref.as_ref()
+  }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct EnumVariants {
   variants: Vec<Option<EnumVariant>>,
@@ -297,16 +569,17 @@ impl TypeRef {
 
   pub fn encode(&self, value: Dynamic) -> Result<Vec<u8>, Box<EvalAltResult>> {
     let mut data = EncodedArgs::new();
-    self.encode_value(value, &mut data)?;
+    self
+      .encode_value(value, &mut data)
+      .map_err(|e| ScriptError::wrap(ErrorKind::Encode, e))?;
     Ok(data.into_inner())
   }
 
   pub fn decode(&self, data: Vec<u8>) -> Result<Dynamic, Box<EvalAltResult>> {
-    Ok(
-      self
-        .decode_value(&mut &data[..], false)
-        .map_err(|e| e.to_string())?,
-    )
+    Ok(self.decode_value(&mut &data[..], false).map_err(|e| {
+      summary::decode_error();
+      ScriptError::new(ErrorKind::Decode, e.to_string())
+    })?)
   }
 
   pub fn encode_mut(&mut self, value: Dynamic) -> Result<Vec<u8>, Box<EvalAltResult>> {
@@ -324,8 +597,206 @@ impl TypeRef {
       _ => false,
     }
   }
+
+  /// Check if values of the type are encoded as zero bytes (`()`, `[u8; 0]`, empty tuples).
+  pub fn is_zero_size(&self) -> bool {
+    fn zero_size(type_ref: &TypeRef, depth: usize) -> bool {
+      // Recursive type definitions aren't zero-sized.
+      if depth > MAX_PARSE_DEPTH {
+        return false;
+      }
+      match &*type_ref.0.read().unwrap() {
+        TypeMeta::Unit => true,
+        TypeMeta::Slice(len, type_ref) => *len == 0 || zero_size(type_ref, depth + 1),
+        TypeMeta::Tuple(types) => types.iter().all(|t| zero_size(t, depth + 1)),
+        TypeMeta::Struct(fields) => fields.values().all(|t| zero_size(t, depth + 1)),
+        TypeMeta::NewType(_, type_ref) | TypeMeta::Box(type_ref) => zero_size(type_ref, depth + 1),
+        _ => false,
+      }
+    }
+    zero_size(self, 0)
+  }
+
+  /// Check if the type (or the type it wraps) is unresolved.
+  pub fn is_unresolved(&self) -> bool {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::Unresolved(_) => true,
+      TypeMeta::NewType(_, type_ref)
+      | TypeMeta::Compact(type_ref)
+      | TypeMeta::Vector(type_ref)
+      | TypeMeta::Option(type_ref)
+      | TypeMeta::Slice(_, type_ref) => type_ref.is_unresolved(),
+      _ => false,
+    }
+  }
+
+  /// Check if the type is an `Option<T>` (looks through `NewType` wrappers).
+  pub fn is_option(&self) -> bool {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::Option(_) | TypeMeta::OptionBool => true,
+      TypeMeta::NewType(_, type_ref) => type_ref.is_option(),
+      _ => false,
+    }
+  }
+
+  /// Check if the type is an integer (looks through `NewType`/`Compact` wrappers).
+  pub fn is_integer(&self) -> bool {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::Integer(_, _) => true,
+      TypeMeta::Compact(type_ref) | TypeMeta::NewType(_, type_ref) => type_ref.is_integer(),
+      _ => false,
+    }
+  }
+
+  /// Variants of an enum type (looks through `NewType`/custom wrappers).
+  pub fn enum_variants(&self) -> Option<EnumVariants> {
+    fn variants(meta: &TypeMeta) -> Option<EnumVariants> {
+      match meta {
+        TypeMeta::Enum(variants) => Some(variants.clone()),
+        TypeMeta::NewType(_, type_ref) => type_ref.enum_variants(),
+        TypeMeta::CustomType(custom) => variants(&custom.type_meta),
+        _ => None,
+      }
+    }
+    variants(&self.0.read().unwrap())
+  }
+
+  /// Type of a struct field (looks through `NewType`/`Box`/custom wrappers).
+  pub fn field_type(&self, name: &str) -> Option<TypeRef> {
+    fn field(meta: &TypeMeta, name: &str) -> Option<TypeRef> {
+      match meta {
+        TypeMeta::Struct(fields) => fields.get(name).cloned(),
+        TypeMeta::NewType(_, type_ref) | TypeMeta::Box(type_ref) => type_ref.field_type(name),
+        TypeMeta::CustomType(custom) => field(&custom.type_meta, name),
+        _ => None,
+      }
+    }
+    field(&self.0.read().unwrap(), name)
+  }
+
+  /// Check if values are decoded by a custom decoder (e.g. the token scaling of `Balance`).
+  pub fn has_custom_decode(&self) -> bool {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::CustomType(custom) => custom.decode.is_some(),
+      TypeMeta::NewType(_, type_ref) | TypeMeta::Compact(type_ref) => type_ref.has_custom_decode(),
+      _ => false,
+    }
+  }
+
+  /// Check if the type (or the type it wraps) has custom encoding or decoding.
+  pub fn is_custom(&self) -> bool {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::CustomType(_) => true,
+      TypeMeta::NewType(_, type_ref) | TypeMeta::Compact(type_ref) => type_ref.is_custom(),
+      _ => false,
+    }
+  }
+
+  /// Check if both types are the same integer type (looks through `NewType`/`Compact` wrappers).
+  pub fn is_same_integer(&self, other: &TypeRef) -> bool {
+    match (self.integer_kind(), other.integer_kind()) {
+      (Some(a), Some(b)) => a == b,
+      _ => false,
+    }
+  }
+
+  /// Compact flag, width and signedness of an integer type.
+  fn integer_kind(&self) -> Option<(bool, u8, bool)> {
+    let inner = match &*self.0.read().unwrap() {
+      TypeMeta::Compact(type_ref) => Some(type_ref.clone()),
+      _ => None,
+    };
+    let (compact, type_ref) = match inner {
+      Some(type_ref) => (true, type_ref),
+      None => (false, self.clone()),
+    };
+    match type_ref.integer_meta()? {
+      TypeMeta::Integer(width, signed) => Some((compact, width, signed)),
+      _ => None,
+    }
+  }
+
+  /// Encode an already scaled integer, skipping custom encoders (e.g. the token scaling
+  /// of `Balance`).  Looks through `Compact` wrappers.
+  pub fn encode_raw_integer(
+    &self,
+    raw: Decimal,
+    data: &mut EncodedArgs,
+  ) -> Result<(), Box<EvalAltResult>> {
+    let compact = match &*self.0.read().unwrap() {
+      TypeMeta::Compact(type_ref) => Some(type_ref.clone()),
+      _ => None,
+    };
+    if let Some(type_ref) = compact {
+      let old = data.is_compact();
+      data.set_compact(true);
+      let res = type_ref.encode_raw_integer(raw, data);
+      data.set_compact(old);
+      return res;
+    }
+    let int_meta = self
+      .integer_meta()
+      .ok_or_else(|| format!("Expected an integer type: {:?}", self))?;
+    int_meta.encode_value(Dynamic::from_decimal(raw), data)
+  }
+
+  /// The integer type behind `NewType` and custom encoding wrappers.
+  fn integer_meta(&self) -> Option<TypeMeta> {
+    let self_meta = self.0.read().unwrap();
+    match &*self_meta {
+      TypeMeta::Integer(width, signed) => Some(TypeMeta::Integer(*width, *signed)),
+      TypeMeta::NewType(_, type_ref) => type_ref.integer_meta(),
+      TypeMeta::CustomType(custom) => match &*custom.type_meta {
+        TypeMeta::Integer(width, signed) => Some(TypeMeta::Integer(*width, *signed)),
+        TypeMeta::NewType(_, type_ref) => type_ref.integer_meta(),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
 }
 
+/// Split generic type parameters on top-level commas: `K, Vec<(A, B)>` -> [`K`, `Vec<(A, B)>`]
+fn split_type_params(params: &str) -> Vec<&str> {
+  let mut parts = Vec::new();
+  let mut depth = 0;
+  let mut start = 0;
+  for (idx, c) in params.char_indices() {
+    match c {
+      '<' | '(' | '[' => depth += 1,
+      '>' | ')' | ']' => depth -= 1,
+      ',' if depth == 0 => {
+        parts.push(params[start..idx].trim());
+        start = idx + 1;
+      }
+      _ => (),
+    }
+  }
+  parts.push(params[start..].trim());
+  parts
+}
+
+/// Split a generic type into it's name and parameters: `BTreeMap<K, V>` -> (`BTreeMap`, [`K`, `V`])
+fn split_generic(def: &str) -> Option<(&str, Vec<&str>)> {
+  let (wrap, params) = def.strip_suffix('>')?.split_once('<')?;
+  Some((wrap.trim(), split_type_params(params)))
+}
+
+/// Bounded types are encoded the same as their unbounded type.
+///
+/// (bounded name, unbounded name, number of parameters to keep)
+const BOUNDED_TYPES: &[(&str, &str, usize)] = &[
+  ("BoundedVec", "Vec", 1),
+  ("WeakBoundedVec", "Vec", 1),
+  ("BoundedBTreeMap", "BTreeMap", 2),
+  ("BoundedBTreeSet", "BTreeSet", 1),
+];
+
 impl From<TypeMeta> for TypeRef {
   fn from(meta: TypeMeta) -> Self {
     TypeRef(Arc::new(RwLock::new(meta.clone())))
@@ -356,6 +827,8 @@ pub enum TypeMeta {
   /// (ok, err)
   Result(TypeRef, TypeRef),
   Vector(TypeRef),
+  /// `BTreeMap<K, V>`/`HashMap<K, V>` (key, value)
+  Map(TypeRef, TypeRef),
   /// Fixed length.
   Slice(usize, TypeRef),
   String,
@@ -383,6 +856,32 @@ impl TypeMeta {
     format!("TypeMeta: {:?}", self)
   }
 
+  /// Short name of the type kind, used for codec tracing.
+  pub fn kind_name(&self) -> String {
+    match self {
+      TypeMeta::Unit => "Unit".into(),
+      TypeMeta::Integer(len, signed) => {
+        format!("{}{}", if *signed { "i" } else { "u" }, *len as usize * 8)
+      }
+      TypeMeta::Bool => "Bool".into(),
+      TypeMeta::Option(_) => "Option".into(),
+      TypeMeta::Box(_) => "Box".into(),
+      TypeMeta::OptionBool => "OptionBool".into(),
+      TypeMeta::Result(_, _) => "Result".into(),
+      TypeMeta::Vector(_) => "Vector".into(),
+      TypeMeta::Map(_, _) => "Map".into(),
+      TypeMeta::Slice(len, _) => format!("Slice({})", len),
+      TypeMeta::String => "String".into(),
+      TypeMeta::Tuple(types) => format!("Tuple({})", types.len()),
+      TypeMeta::Struct(fields) => format!("Struct({})", fields.len()),
+      TypeMeta::Enum(_) => "Enum".into(),
+      TypeMeta::Compact(_) => "Compact".into(),
+      TypeMeta::NewType(name, _) => format!("NewType({})", name),
+      TypeMeta::Unresolved(name) => format!("Unresolved({})", name),
+      TypeMeta::CustomType(_) => "CustomType".into(),
+    }
+  }
+
   fn make_custom_type(&mut self) {
     match self {
       TypeMeta::CustomType(_) => {
@@ -421,6 +920,7 @@ impl TypeMeta {
     data: &mut EncodedArgs,
   ) -> Result<(), Box<EvalAltResult>> {
     log::debug!("encode TypeMeta: {:?}", self);
+    let _trace = trace_encode(self, data.len());
     match self {
       TypeMeta::Unit => (),
       TypeMeta::Integer(len, signed) => {
@@ -440,6 +940,10 @@ impl TypeMeta {
             _ => Err(format!("Unsupported integer type: {:?}", self))?,
           }
         } else if let Some(dec) = value.as_decimal().ok() {
+          // Don't silently drop the fraction, scaled types have their own encoders.
+          if !dec.fract().is_zero() {
+            Err(format!("Expected an integer, got decimal {}", dec))?;
+          }
           match (len, signed) {
             (_, false) if data.is_compact() => {
               let num = dec
@@ -512,29 +1016,78 @@ impl TypeMeta {
         }
       }
       TypeMeta::Bool => data.encode(value.as_bool()?),
-      TypeMeta::Option(type_ref) => {
-        if value.is::<()>() {
-          // None
-          data.encode(0u8);
-        } else {
-          // Some
+      TypeMeta::Option(type_ref) => match OptionValue::from_dynamic(value) {
+        None => data.encode(0u8),
+        Some(value) => {
           data.encode(1u8);
           type_ref.encode_value(value, data)?
         }
+      },
+      TypeMeta::OptionBool => {
+        data.encode(OptionValue::from_dynamic(value).and_then(|v| v.as_bool().ok()))
       }
-      TypeMeta::OptionBool => data.encode(value.as_bool().ok()),
       TypeMeta::Vector(type_ref) => {
         if value.is::<Array>() {
           let values = value.cast::<Array>();
           // Encode vector length.
           data.encode(Compact::<u64>(values.len() as u64));
-          for value in values.into_iter() {
-            type_ref.encode_value(value, data)?
+          for (idx, value) in values.into_iter().enumerate() {
+            encode_nested(|| format!("[{}]", idx), || type_ref.encode_value(value, data))?;
           }
         } else {
           Err(format!("Expected a vector, got {:?}", value.type_id()))?;
         }
       }
+      TypeMeta::Map(key_ref, val_ref) => {
+        // Sort and dedup the entries by their encoded keys, so the encoding doesn't depend on
+        // the order of the script's keys.
+        let mut entries = BTreeMap::new();
+        if value.is::<RMap>() {
+          let map = value.cast::<RMap>();
+          for (key, value) in map.into_iter() {
+            encode_nested(
+              || format!("[{}]", key),
+              || {
+                // Rhai map keys are strings, convert them for integer keys.
+                let key = if key_ref.is_integer() {
+                  let num = key
+                    .parse::<INT>()
+                    .map_err(|e| format!("Expected integer map key '{}': {:?}", key, e))?;
+                  Dynamic::from_int(num)
+                } else {
+                  Dynamic::from(key.to_string())
+                };
+                encode_map_entry(key_ref, val_ref, key, value, &mut entries)
+              },
+            )?;
+          }
+        } else if value.is::<Array>() {
+          // Array of `[key, value]` pairs.
+          let pairs = value.cast::<Array>();
+          for (idx, pair) in pairs.into_iter().enumerate() {
+            encode_nested(
+              || format!("[{}]", idx),
+              || {
+                let mut pair = pair
+                  .try_cast::<Array>()
+                  .filter(|p| p.len() == 2)
+                  .ok_or_else(|| format!("Expected `[key, value]` pair for map entry"))?;
+                let value = pair.pop().unwrap_or_default();
+                let key = pair.pop().unwrap_or_default();
+                encode_map_entry(key_ref, val_ref, key, value, &mut entries)
+              },
+            )?;
+          }
+        } else {
+          Err(format!("Expected a map, got {:?}", value.type_id()))?;
+        }
+        // Encode map length.
+        data.encode(Compact::<u64>(entries.len() as u64));
+        for (key, value) in entries {
+          data.write(&key);
+          data.write(&value);
+        }
+      }
       TypeMeta::Slice(len, type_ref) => {
         if value.is::<Array>() {
           let values = value.cast::<Array>();
@@ -545,8 +1098,8 @@ impl TypeMeta {
               values.len()
             ))?;
           }
-          for value in values.into_iter() {
-            type_ref.encode_value(value, data)?
+          for (idx, value) in values.into_iter().enumerate() {
+            encode_nested(|| format!("[{}]", idx), || type_ref.encode_value(value, data))?;
           }
           return Ok(());
         } else if type_ref.is_u8() {
@@ -605,8 +1158,8 @@ impl TypeMeta {
               values.len()
             ))?;
           }
-          for (type_ref, value) in types.iter().zip(values.into_iter()) {
-            type_ref.encode_value(value, data)?
+          for (idx, (type_ref, value)) in types.iter().zip(values.into_iter()).enumerate() {
+            encode_nested(|| format!("[{}]", idx), || type_ref.encode_value(value, data))?;
           }
         } else {
           Err(format!("Expected a Tuple, got {:?}", value.type_id()))?;
@@ -618,7 +1171,7 @@ impl TypeMeta {
           for (name, type_ref) in fields {
             let name: SmartString<LazyCompact> = name.into();
             if let Some(value) = map.get(&name) {
-              type_ref.encode_value(value.clone(), data)?;
+              encode_nested(|| name.to_string(), || type_ref.encode_value(value.clone(), data))?;
             } else {
               Err(format!("Missing field `{}` in Struct", name))?;
             }
@@ -628,7 +1181,16 @@ impl TypeMeta {
         }
       }
       TypeMeta::Enum(variants) => {
-        if value.is::<RMap>() {
+        if value.is::<EnumValue>() {
+          let value = value.cast::<EnumValue>();
+          let variant = variants
+            .get_by_name(&value.name)
+            .ok_or_else(|| format!("Unknown Enum variant: {}.", value.name))?;
+          data.encode(variant.idx);
+          if let Some(type_ref) = &variant.type_ref {
+            encode_nested(|| variant.name.clone(), || type_ref.encode_value(value.value, data))?;
+          }
+        } else if value.is::<RMap>() {
           let map = value.cast::<RMap>();
           let mut encoded = false;
           for (name, value) in map.into_iter() {
@@ -641,7 +1203,7 @@ impl TypeMeta {
               // Encode enum variant idx.
               data.encode(variant.idx);
               if let Some(type_ref) = &variant.type_ref {
-                type_ref.encode_value(value, data)?;
+                encode_nested(|| variant.name.clone(), || type_ref.encode_value(value, data))?;
               }
             } else {
               Err(format!("Unknown Enum variant: {}.", name))?;
@@ -651,10 +1213,47 @@ impl TypeMeta {
           if !encoded {
             Err(format!("Enum is empty, must provide at least one variant."))?;
           }
+        } else if value.is::<ImmutableString>() {
+          let name = value.cast::<ImmutableString>();
+          // Unit variant by name: `"Affirmed"` instead of `#{Affirmed: ()}`.
+          let variant = variants
+            .get_by_name(name.as_str())
+            .ok_or_else(|| format!("Unknown Enum variant: {}.", name))?;
+          if variant.type_ref.is_some() {
+            Err(format!(
+              "Enum variant {} has a value, use `#{{{}: value}}`.",
+              name, name
+            ))?;
+          }
+          data.encode(variant.idx);
         } else {
           Err(format!("Expected a Enum, got {:?}", value.type_id()))?;
         }
       }
+      TypeMeta::Result(ok_ref, err_ref) => {
+        let (name, value) = if value.is::<EnumValue>() {
+          let value = value.cast::<EnumValue>();
+          (value.name, value.value)
+        } else if let Some(map) = value.read_lock::<RMap>() {
+          match map.iter().next() {
+            Some((name, value)) if map.len() == 1 => (name.to_string(), value.clone()),
+            _ => Err(format!("Expected a Result map with one key `Ok` or `Err`: {:?}", map))?,
+          }
+        } else {
+          Err(format!("Expected a Result, got {:?}", value.type_id()))?
+        };
+        match name.as_str() {
+          "Ok" => {
+            data.encode(0u8);
+            encode_nested(|| "Ok".into(), || ok_ref.encode_value(value, data))?;
+          }
+          "Err" => {
+            data.encode(1u8);
+            encode_nested(|| "Err".into(), || err_ref.encode_value(value, data))?;
+          }
+          _ => Err(format!("Unknown Result variant: {}, expected `Ok` or `Err`.", name))?,
+        }
+      }
 
       TypeMeta::Compact(type_ref) => {
         let old = data.is_compact();
@@ -675,6 +1274,8 @@ impl TypeMeta {
   }
 
   pub fn decode_value<I: Input>(&self, input: &mut I, is_compact: bool) -> Result<Dynamic, PError> {
+    let _depth = DecodeDepth::enter()?;
+    let _trace = trace_decode(self, input);
     let val = match self {
       TypeMeta::Unit => Dynamic::UNIT,
       TypeMeta::Integer(len, signed) => match (len, signed) {
@@ -750,16 +1351,29 @@ impl TypeMeta {
         Dynamic::from(map)
       }
       TypeMeta::Vector(type_ref) => {
-        let len = Compact::<u64>::decode(input)?.0;
+        let len = decode_len(input, type_ref.is_zero_size())?;
         let mut vec = Vec::new();
-        for _ in 0..len {
+        for idx in 0..len {
+          let _seg = TraceSegment::enter(|| format!("[{}]", idx));
           vec.push(type_ref.decode_value(input, false)?);
         }
         Dynamic::from(vec)
       }
+      TypeMeta::Map(key_ref, val_ref) => {
+        let len = decode_len(input, key_ref.is_zero_size() && val_ref.is_zero_size())?;
+        let mut map = RMap::new();
+        for idx in 0..len {
+          let _seg = TraceSegment::enter(|| format!("[{}]", idx));
+          let key = key_ref.decode_value(input, false)?;
+          let value = val_ref.decode_value(input, false)?;
+          map.insert(key.to_string().into(), value);
+        }
+        Dynamic::from(map)
+      }
       TypeMeta::Slice(len, type_ref) => {
         let mut vec = Vec::with_capacity(*len as usize);
-        for _ in 0..*len {
+        for idx in 0..*len {
+          let _seg = TraceSegment::enter(|| format!("[{}]", idx));
           vec.push(type_ref.decode_value(input, false)?);
         }
         Dynamic::from(vec)
@@ -771,7 +1385,8 @@ impl TypeMeta {
 
       TypeMeta::Tuple(types) => {
         let mut vec = Vec::with_capacity(types.len());
-        for type_ref in types {
+        for (idx, type_ref) in types.iter().enumerate() {
+          let _seg = TraceSegment::enter(|| format!("[{}]", idx));
           vec.push(type_ref.decode_value(input, false)?);
         }
         Dynamic::from(vec)
@@ -780,6 +1395,7 @@ impl TypeMeta {
         let mut map = RMap::new();
         for (name, type_ref) in fields {
           log::debug!("decode Struct field: {}", name);
+          let _seg = TraceSegment::enter(|| name.clone());
           map.insert(name.into(), type_ref.decode_value(input, false)?);
         }
         Dynamic::from(map)
@@ -792,6 +1408,7 @@ impl TypeMeta {
             log::debug!("decode Enum variant: {}", name);
             let mut map = RMap::new();
             if let Some(type_ref) = &variant.type_ref {
+              let _seg = TraceSegment::enter(|| name.clone());
               map.insert(name.into(), type_ref.decode_value(input, false)?);
             } else {
               map.insert(name.into(), Dynamic::UNIT);
@@ -805,6 +1422,14 @@ impl TypeMeta {
               input.remaining_len()?,
               variants
             );
+            if is_codec_trace() {
+              log::error!(
+                target: "codec",
+                "invalid Enum variant {} at: {}",
+                val,
+                trace_path()
+              );
+            }
             Err("Error decoding Enum, invalid variant.")?
           }
         }
@@ -825,55 +1450,327 @@ impl TypeMeta {
   }
 }
 
+/// Schema files larger than this are rejected.
+const MAX_SCHEMA_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Downloaded schemas older than this are downloaded again.
+const SCHEMA_CACHE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Ordered map from `[name, type]` pairs.
+fn pairs_to_map(pairs: &[Value]) -> Result<Map<String, Value>, Box<EvalAltResult>> {
+  pairs
+    .iter()
+    .map(|pair| match pair.as_array().map(|p| p.as_slice()) {
+      Some([name, ty]) => {
+        let name = name
+          .as_str()
+          .ok_or_else(|| format!("Expected a name, got: {}", name))?;
+        Ok((name.to_string(), ty.clone()))
+      }
+      _ => Err(format!("Expected a [name, type] pair, got: {}", pair).into()),
+    })
+    .collect()
+}
+
+/// Download a schema into the cache directory and return the cached file.
+///
+/// A cached copy is used until it is older than `SCHEMA_CACHE_MAX_AGE`, or
+/// if the download fails.
+fn fetch_schema(url: &str, cache_dir: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+  let name = format!("{}.json", hex::encode(sp_core::blake2_128(url.as_bytes())));
+  let path = Path::new(cache_dir).join(name);
+  let age = fs::metadata(&path)
+    .and_then(|meta| meta.modified())
+    .ok()
+    .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+  if let Some(age) = age {
+    if age < SCHEMA_CACHE_MAX_AGE {
+      log::debug!("Using cached schema {} from {:?}", url, path);
+      return Ok(path);
+    }
+  }
+  let res = ureq::get(url)
+    .call()
+    .map_err(|e| e.to_string())
+    .and_then(|resp| {
+      let mut body = Vec::new();
+      resp
+        .into_reader()
+        .take(MAX_SCHEMA_SIZE + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| e.to_string())?;
+      if body.len() as u64 > MAX_SCHEMA_SIZE {
+        return Err(format!("larger than {} bytes", MAX_SCHEMA_SIZE));
+      }
+      Ok(body)
+    });
+  match res {
+    Ok(body) => {
+      fs::create_dir_all(cache_dir)
+        .and_then(|_| fs::write(&path, body))
+        .map_err(|e| format!("Failed to cache schema {} in {:?}: {}", url, path, e))?;
+      log::info!("Downloaded schema {} to {:?}", url, path);
+    }
+    Err(err) if path.exists() => {
+      log::warn!("Failed to download schema {} ({}), using cached {:?}", url, err, path);
+    }
+    Err(err) => Err(format!("Failed to download schema {}: {}", url, err))?,
+  }
+  Ok(path)
+}
+
+/// Longest type definition accepted from a schema.
+const MAX_TYPE_DEF_LEN: usize = 1024;
+
+/// Maximum nesting of generics/tuples/slices in a type definition.
+const MAX_PARSE_DEPTH: usize = 32;
+
+/// Largest fixed size array (`[T; N]`) accepted from a schema.
+const MAX_SLICE_LEN: usize = 1024 * 1024;
+
+/// Maximum number of errors listed when a schema has bad entries.
+const MAX_SCHEMA_ERRORS: usize = 50;
+
+/// Type definitions that only apply to a range of spec versions
+/// (polkadot-js style `versioned` schema entries).
+#[derive(Clone, Debug)]
+struct VersionedTypes {
+  min: Option<u32>,
+  max: Option<u32>,
+  types: Map<String, Value>,
+}
+
+impl VersionedTypes {
+  /// Parse `{ "minmax": [min, max], "types": { ... } }`.  `null` is an open bound.
+  fn from_json(val: &Value) -> Result<Self, Box<EvalAltResult>> {
+    let bound = |idx: usize| -> Option<u32> {
+      val
+        .get("minmax")
+        .and_then(|m| m.get(idx))
+        .and_then(|b| b.as_u64())
+        .map(|b| b as u32)
+    };
+    let types = val
+      .get("types")
+      .and_then(|t| t.as_object())
+      .ok_or_else(|| format!("Versioned types need a `types` object: {}", val))?;
+    Ok(Self {
+      min: bound(0),
+      max: bound(1),
+      types: types.clone(),
+    })
+  }
+
+  fn matches(&self, spec_version: u32) -> bool {
+    self.min.map_or(true, |min| spec_version >= min)
+      && self.max.map_or(true, |max| spec_version <= max)
+  }
+}
+
 #[derive(Clone)]
 pub struct Types {
   types: IndexMap<String, TypeRef>,
+  // Schema definitions, used to restore types overridden by versioned types.
+  schema_types: Map<String, Value>,
+  // Definitions of versioned-only types (not in `schema_types`) before their first
+  // override, restored when no version range matches.
+  versioned_base: HashMap<String, TypeMeta>,
+  versioned: Vec<VersionedTypes>,
+  spec_version: Option<u32>,
+  // Replace existing definitions, instead of keeping the first one.
+  redefine: bool,
+  // Current nesting depth of `parse_type`.
+  parse_depth: usize,
+  // Pallet name -> namespace of the pallet's types: `Balances` -> `pallet_balances`.
+  namespaces: HashMap<String, String>,
+  // V14 portable registry type id -> type.
+  type_ids: HashMap<u32, TypeRef>,
+  // Integer types encoded from token amounts: type name -> decimals.
+  decimals: HashMap<String, u32>,
 }
 
 impl Types {
   pub fn new() -> Self {
     Self {
       types: IndexMap::new(),
+      schema_types: Map::new(),
+      versioned_base: HashMap::new(),
+      versioned: Vec::new(),
+      spec_version: None,
+      redefine: false,
+      parse_depth: 0,
+      namespaces: HashMap::new(),
+      type_ids: HashMap::new(),
+      decimals: HashMap::new(),
+    }
+  }
+
+  /// Get a type by it's V14 portable registry id.
+  pub fn resolve_type_id(&self, id: u32) -> Option<TypeRef> {
+    self.type_ids.get(&id).cloned()
+  }
+
+  /// Map a pallet name to the namespace of it's types.
+  pub fn add_namespace(&mut self, pallet: &str, namespace: &str) {
+    self.namespaces.insert(pallet.into(), namespace.into());
+  }
+
+  /// Expand a pallet namespace: `Balances::Event` -> `pallet_balances::Event`
+  fn expand_namespace(&self, name: String) -> String {
+    if !self.types.contains_key(&name) {
+      if let Some((pallet, ty)) = name.split_once("::") {
+        if let Some(namespace) = self.namespaces.get(pallet) {
+          return format!("{}::{}", namespace, ty);
+        }
+      }
+    }
+    name
+  }
+
+  /// Resolve a type in a namespace (pallet name or crate name).
+  ///
+  /// Falls back to the global type name.
+  pub fn resolve_in(&mut self, namespace: &str, name: &str) -> TypeRef {
+    let namespace = self
+      .namespaces
+      .get(namespace)
+      .map(|s| s.as_str())
+      .unwrap_or(namespace);
+    let full_name = format!("{}::{}", namespace, name);
+    if self.types.contains_key(&full_name) {
+      self.resolve(&full_name)
+    } else {
+      self.resolve(name)
+    }
+  }
+
+  /// Check if a type is defined (not just referenced).
+  fn is_defined(&self, name: &str) -> bool {
+    match self.types.get(name) {
+      Some(type_ref) => !matches!(&*type_ref.0.read().unwrap(), TypeMeta::Unresolved(_)),
+      None => false,
     }
   }
 
+  /// Load a comma-separated list of schema files and `http(s)://` URLs, in order.
+  pub fn load_schemas(&mut self, schemas: &str, cache_dir: &str) -> Result<(), Box<EvalAltResult>> {
+    for schema in schemas.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+      if schema.starts_with("https://") || schema.starts_with("http://") {
+        let path = fetch_schema(schema, cache_dir)?;
+        self.load_schema(&path.to_string_lossy())?;
+      } else {
+        self.load_schema(schema)?;
+      }
+    }
+    Ok(())
+  }
+
   pub fn load_schema(&mut self, filename: &str) -> Result<(), Box<EvalAltResult>> {
-    let file = File::open(filename).map_err(|e| e.to_string())?;
+    let file = File::open(filename).map_err(|e| format!("Failed to open schema {}: {}", filename, e))?;
+    let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+    if size > MAX_SCHEMA_SIZE {
+      Err(format!(
+        "Schema {} is too large: {} bytes (max {})",
+        filename, size, MAX_SCHEMA_SIZE
+      ))?;
+    }
 
-    let schema: serde_json::Value =
-      serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let schema: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+      .map_err(|e| format!("Failed to parse schema {}: {}", filename, e))?;
 
     let schema = schema
       .as_object()
-      .expect("Invalid schema, expected object.");
+      .ok_or_else(|| format!("Invalid schema {}, expected object.", filename))?;
 
     let types = match schema.get("types") {
       Some(val) => val.as_object().unwrap_or(schema),
       _ => schema,
     };
-    self.parse_schema_types(types)?;
+    self
+      .parse_schema_types(types)
+      .map_err(|e| format!("Invalid schema {}: {}", filename, e))?;
+    self.schema_types.extend(types.clone());
+
+    if let Some(versioned) = schema.get("versioned") {
+      let versioned = versioned
+        .as_array()
+        .ok_or_else(|| format!("Invalid schema {}, `versioned` must be an array.", filename))?;
+      for val in versioned {
+        self.versioned.push(VersionedTypes::from_json(val)?);
+      }
+      if let Some(spec_version) = self.spec_version {
+        self.set_spec_version(spec_version)?;
+      }
+    }
 
     Ok(())
   }
 
+  /// Apply the versioned types for `spec_version`.  Types without a matching
+  /// version range use the schema's default definition, or the definition they had
+  /// before the first versioned override if the schema doesn't define them.
+  pub fn set_spec_version(&mut self, spec_version: u32) -> Result<(), Box<EvalAltResult>> {
+    let mut defs = Map::new();
+    let mut versioned_only = Vec::new();
+    for name in self.versioned.iter().flat_map(|v| v.types.keys()) {
+      if let Some(def) = self.schema_types.get(name) {
+        defs.insert(name.clone(), def.clone());
+      } else {
+        versioned_only.push(name.clone());
+      }
+    }
+    for name in &versioned_only {
+      if !self.versioned_base.contains_key(name) {
+        let meta = match self.types.get(name) {
+          Some(type_ref) => type_ref.0.read().unwrap().clone(),
+          None => TypeMeta::Unresolved(name.clone()),
+        };
+        self.versioned_base.insert(name.clone(), meta);
+      }
+    }
+    for versioned in self.versioned.iter().filter(|v| v.matches(spec_version)) {
+      defs.extend(versioned.types.clone());
+    }
+    for name in versioned_only.iter().filter(|name| !defs.contains_key(*name)) {
+      if let (Some(type_ref), Some(meta)) = (self.types.get(name), self.versioned_base.get(name)) {
+        *type_ref.0.write().unwrap() = meta.clone();
+      }
+    }
+    self.spec_version = Some(spec_version);
+    if defs.is_empty() {
+      return Ok(());
+    }
+    log::debug!("Apply {} versioned type(s) for spec version {}", defs.len(), spec_version);
+    self.redefine = true;
+    let res = self.parse_schema_types(&defs);
+    self.redefine = false;
+    res.map_err(|e| format!("Invalid versioned types: {}", e).into())
+  }
+
+  /// Parse all types of a schema.  Bad entries are skipped and reported together.
   fn parse_schema_types(&mut self, types: &Map<String, Value>) -> Result<(), Box<EvalAltResult>> {
+    let mut errors = Vec::new();
     for (name, val) in types.iter() {
-      match val {
-        Value::String(val) => {
-          self.parse_named_type(name, val)?;
-        }
+      let res = match val {
+        Value::String(val) => self.parse_named_type(name, val).map(|_| ()),
         Value::Object(map) => {
           if let Some(variants) = map.get("_enum") {
-            self.parse_enum(name, variants)?;
+            self.parse_enum(name, variants)
           } else {
-            self.parse_struct(name, map)?;
+            self.parse_struct(name, map)
           }
         }
-        _ => {
-          eprintln!("UNHANDLED JSON VALUE: {} => {:?}", name, val);
-        }
+        _ => Err(format!("Expected a type definition, got: {}", val).into()),
+      };
+      if let Err(err) = res {
+        errors.push(format!("{}: {}", name, err));
       }
     }
+    if !errors.is_empty() {
+      let count = errors.len();
+      errors.truncate(MAX_SCHEMA_ERRORS);
+      Err(format!("{} bad type(s):\n  {}", count, errors.join("\n  ")))?;
+    }
     Ok(())
   }
 
@@ -894,9 +1791,15 @@ impl Types {
               )),
             }
           })?;
+        if arr.len() > 256 {
+          Err(format!("Enum {} has too many variants: {}", name, arr.len()))?;
+        }
         self.insert_meta(name, TypeMeta::Enum(variants));
       }
       Value::Object(obj) => {
+        if obj.len() > 256 {
+          Err(format!("Enum {} has too many variants: {}", name, obj.len()))?;
+        }
         let variants = obj.iter().try_fold(
           EnumVariants::new(),
           |mut variants, (var_name, val)| -> Result<_, Box<EvalAltResult>> {
@@ -951,18 +1854,70 @@ impl Types {
     Ok(())
   }
 
+  /// Define a type from a script.  Existing definitions are replaced.
+  ///
+  /// `def` is a type name, a struct (object of field types) or
+  /// an array of `[field, type]` pairs (keeps the field order).
+  pub fn define(&mut self, name: &str, def: &Value) -> Result<TypeRef, Box<EvalAltResult>> {
+    self.redefine = true;
+    let res = match def {
+      Value::String(def) => self.parse_named_type(name, def).map(|_| ()),
+      Value::Object(fields) => self.parse_struct(name, fields),
+      Value::Array(pairs) => {
+        pairs_to_map(pairs).and_then(|fields| self.parse_struct(name, &fields))
+      }
+      _ => Err(format!("Expected a type definition, got: {}", def).into()),
+    };
+    self.redefine = false;
+    res?;
+    Ok(self.resolve(name))
+  }
+
+  /// Define an enum from a script: an array of variant names, an object of variant types
+  /// or an array of `[variant, type]` pairs (keeps the variant order).
+  pub fn define_enum(&mut self, name: &str, variants: &Value) -> Result<TypeRef, Box<EvalAltResult>> {
+    let variants = match variants {
+      Value::Array(pairs) if pairs.iter().all(|p| p.is_array()) => {
+        Value::Object(pairs_to_map(pairs)?)
+      }
+      _ => variants.clone(),
+    };
+    self.redefine = true;
+    let res = self.parse_enum(name, &variants);
+    self.redefine = false;
+    res?;
+    Ok(self.resolve(name))
+  }
+
   pub fn parse_named_type(&mut self, name: &str, def: &str) -> Result<TypeRef, Box<EvalAltResult>> {
+    if def.trim() == name {
+      Err(format!("Type {} is defined as itself", name))?;
+    }
     let type_ref = self.parse_type(def)?;
 
     Ok(self.insert_meta(name, TypeMeta::NewType(name.into(), type_ref)))
   }
 
   pub fn parse_type(&mut self, name: &str) -> Result<TypeRef, Box<EvalAltResult>> {
+    if name.len() > MAX_TYPE_DEF_LEN {
+      Err(format!("Type definition too long: {} chars", name.len()))?;
+    }
+    if self.parse_depth >= MAX_PARSE_DEPTH {
+      Err(format!("Type definition nested too deep: {}", name))?;
+    }
+    self.parse_depth += 1;
+    let res = self.parse_type_inner(name);
+    self.parse_depth -= 1;
+    res
+  }
+
+  fn parse_type_inner(&mut self, name: &str) -> Result<TypeRef, Box<EvalAltResult>> {
     let name = name
       .trim()
       .replace("\r", "")
       .replace("\n", "")
       .replace("T::", "");
+    let name = self.expand_namespace(name);
     // Try to resolve the type.
     let type_ref = self.resolve(&name);
     let mut type_meta = type_ref.0.write().unwrap();
@@ -982,49 +1937,66 @@ impl Types {
   fn parse(&mut self, def: &str) -> Result<TypeMeta, Box<EvalAltResult>> {
     match def.chars().last() {
       Some('>') => {
-        // Handle: Vec<T>, Option<T>, Compact<T>
-        let (wrap, ty) = def
-          .strip_suffix('>')
-          .and_then(|s| s.split_once('<'))
-          .map(|(wrap, ty)| (wrap.trim(), ty.trim()))
-          .ok_or_else(|| format!("Failed to parse Vec/Option/Compact: {}", def))?;
-        match wrap {
-          "Vec" => {
+        // Handle: Vec<T>, Option<T>, Compact<T>, BTreeMap<K, V>, ...
+        let (full_wrap, params) = split_generic(def)
+          .ok_or_else(|| format!("Failed to parse generic type: {}", def))?;
+        // Strip the path: `sp_std::marker::PhantomData<T>` -> `PhantomData<T>`
+        let wrap = full_wrap.rsplit("::").next().unwrap_or(full_wrap);
+        // Strip the bound from bounded types: `BoundedVec<T, S>` -> `Vec<T>`
+        if let Some((_, unbounded, count)) = BOUNDED_TYPES.iter().find(|(name, ..)| *name == wrap) {
+          if params.len() > *count {
+            let def = format!("{}<{}>", unbounded, params[..*count].join(", "));
+            return self.parse(&def);
+          }
+        }
+        match (wrap, &params[..]) {
+          ("Vec", [ty]) => {
             let wrap_ref = self.parse_type(ty)?;
             Ok(TypeMeta::Vector(wrap_ref))
           }
-          "Option" => {
+          ("Option", [ty]) => {
             let wrap_ref = self.parse_type(ty)?;
             Ok(TypeMeta::Option(wrap_ref))
           }
-          "Compact" => {
+          ("Compact", [ty]) => {
             let wrap_ref = self.parse_type(ty)?;
             Ok(TypeMeta::Compact(wrap_ref))
           }
-          "Box" => {
+          ("Box", [ty]) => {
             let wrap_ref = self.parse_type(ty)?;
             Ok(TypeMeta::Box(wrap_ref))
           }
-          "Result" => {
-            let (ok_ref, err_ref) = match ty.split_once(',') {
-              Some((ok_ty, err_ty)) => {
-                let ok_ref = self.parse_type(ok_ty)?;
-                let err_ref = self.parse_type(err_ty)?;
-                (ok_ref, err_ref)
-              }
-              None => {
-                let ok_ref = self.parse_type(ty)?;
-                let err_ref = self.parse_type("Error")?;
-                (ok_ref, err_ref)
-              }
-            };
+          ("Result", [ok_ty, err_ty]) => {
+            let ok_ref = self.parse_type(ok_ty)?;
+            let err_ref = self.parse_type(err_ty)?;
             Ok(TypeMeta::Result(ok_ref, err_ref))
           }
-          "PhantomData" | "sp_std::marker::PhantomData" => Ok(TypeMeta::Unit),
-          generic => {
+          ("Result", [ok_ty]) => {
+            let ok_ref = self.parse_type(ok_ty)?;
+            let err_ref = self.parse_type("Error")?;
+            Ok(TypeMeta::Result(ok_ref, err_ref))
+          }
+          ("BTreeMap" | "HashMap", [key_ty, val_ty]) => {
+            let key_ref = self.parse_type(key_ty)?;
+            let val_ref = self.parse_type(val_ty)?;
+            Ok(TypeMeta::Map(key_ref, val_ref))
+          }
+          ("BTreeSet" | "HashSet", [ty]) => {
+            // Sets are encoded the same as `Vec<T>`.
+            let wrap_ref = self.parse_type(ty)?;
+            Ok(TypeMeta::Vector(wrap_ref))
+          }
+          ("PhantomData", _) => Ok(TypeMeta::Unit),
+          ("Vec" | "Option" | "Compact" | "Box" | "Result" | "BTreeMap" | "HashMap"
+            | "BTreeSet" | "HashSet", _) => {
+            Err(format!("Wrong number of type parameters: {}", def))?
+          }
+          _ => {
             // Some generic type.
-            if self.types.contains_key(generic) {
-              Ok(TypeMeta::NewType(generic.into(), self.resolve(generic)))
+            if self.types.contains_key(full_wrap) {
+              Ok(TypeMeta::NewType(full_wrap.into(), self.resolve(full_wrap)))
+            } else if self.types.contains_key(wrap) {
+              Ok(TypeMeta::NewType(wrap.into(), self.resolve(wrap)))
             } else {
               Ok(TypeMeta::Unresolved(def.into()))
             }
@@ -1032,32 +2004,17 @@ impl Types {
         }
       }
       Some(')') => {
-        let mut broken_type = None;
-        let defs = def
-          .trim_matches(|c| c == '(' || c == ')')
-          .split_terminator(',')
-          .filter_map(|s| {
-            let s = match broken_type.take() {
-              Some(s1) => format!("{}, {}", s1, s),
-              None => s.to_string(),
-            }.trim().to_string();
-            // Check for broken type.
-            let left = s.chars().filter(|c| *c == '<').count();
-            let right = s.chars().filter(|c| *c == '>').count();
-            if left != right {
-              broken_type = Some(s);
-              return None;
-            }
-            if s != "" {
-              Some(s)
-            } else {
-              None
-            }
-          })
+        let params = match def.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+          Some(params) => split_type_params(params),
+          None => return Ok(TypeMeta::Unresolved(def.into())),
+        };
+        let defs = params
+          .into_iter()
+          .filter(|s| *s != "")
           .try_fold(
             Vec::new(),
             |mut vec, val| -> Result<_, Box<EvalAltResult>> {
-              let type_ref = self.parse_type(&val)?;
+              let type_ref = self.parse_type(val)?;
               vec.push(type_ref);
               Ok(vec)
             },
@@ -1074,6 +2031,9 @@ impl Types {
             len.trim().parse::<usize>().ok().map(|l| (ty.trim(), l))
           })
           .ok_or_else(|| format!("Failed to parse slice: {}", def))?;
+        if slice_len > MAX_SLICE_LEN {
+          Err(format!("Slice too large: {}", def))?;
+        }
         // Handle slices.
         let slice_ref = self.parse_type(slice_ty)?;
         Ok(TypeMeta::Slice(slice_len, slice_ref))
@@ -1104,6 +2064,10 @@ impl Types {
           TypeMeta::Unresolved(_) => {
             *old_meta = TypeMeta::NewType(name.into(), type_ref.clone());
           }
+          // Don't make the type wrap itself.
+          _ if self.redefine && !Arc::ptr_eq(&old_ref.0, &type_ref.0) => {
+            *old_meta = TypeMeta::NewType(name.into(), type_ref.clone());
+          }
           _ => {
             eprintln!("REDEFINE TYPE: {}", name);
           }
@@ -1119,7 +2083,8 @@ impl Types {
 
   #[cfg(feature = "v14")]
   fn import_v14_type(&mut self, id: u32, ty: &Type<PortableForm>, id_to_ref: &HashMap<u32, TypeRef>) -> Result<(), Box<EvalAltResult>> {
-    let type_ref = id_to_ref.get(&id).unwrap();
+    let type_ref = id_to_ref.get(&id)
+      .ok_or_else(|| format!("Missing registry type: {}", id))?;
     log::debug!("import_v14_type: {}", ty.path());
     let type_meta = match ty.type_def() {
       TypeDef::Composite(s) => {
@@ -1131,7 +2096,7 @@ impl Types {
           });
           let field_ty = id_to_ref.get(&f.ty().id())
             .cloned()
-            .expect("Failed to resolve Composite field type");
+            .ok_or_else(|| format!("Failed to resolve Composite field type: {}", ty.path()))?;
           fields.insert(name.to_string(), field_ty);
         }
         TypeMeta::Struct(fields)
@@ -1143,7 +2108,7 @@ impl Types {
           let mut fields = var.fields().into_iter()
             .map(|ty| id_to_ref.get(&ty.ty().id()).cloned())
             .collect::<Option<Vec<_>>>()
-            .expect("Failed to resolve Enum variant field type");
+            .ok_or_else(|| format!("Failed to resolve Enum variant field type: {}", ty.path()))?;
           if fields.len() == 0 {
             variants.insert_at(var.index(), var.name(), None);
           } else if fields.len() == 1 {
@@ -1157,20 +2122,20 @@ impl Types {
       TypeDef::Sequence(s) => {
         let elm_ty = id_to_ref.get(&s.type_param().id())
           .cloned()
-          .expect("Failed to resolve Sequence element type");
+          .ok_or_else(|| format!("Failed to resolve Sequence element type"))?;
         TypeMeta::Vector(elm_ty)
       }
       TypeDef::Array(a) => {
         let elm_ty = id_to_ref.get(&a.type_param().id())
           .cloned()
-          .expect("Failed to resolve Array element type");
+          .ok_or_else(|| format!("Failed to resolve Array element type"))?;
         TypeMeta::Slice(a.len() as usize, elm_ty)
       }
       TypeDef::Tuple(t) => {
         let defs = t.fields().into_iter()
           .map(|ty| id_to_ref.get(&ty.id()).cloned())
           .collect::<Option<Vec<_>>>()
-          .expect("Failed to resolve Tuple field type");
+          .ok_or_else(|| format!("Failed to resolve Tuple field type"))?;
         TypeMeta::Tuple(defs)
       }
       TypeDef::Primitive(p) => {
@@ -1196,11 +2161,12 @@ impl Types {
       TypeDef::Compact(c) => {
         let elm_ty = id_to_ref.get(&c.type_param().id())
           .cloned()
-          .expect("Failed to resolve Compact type");
+          .ok_or_else(|| format!("Failed to resolve Compact type"))?;
         TypeMeta::Compact(elm_ty)
       }
       _ => {
-        todo!("Handle TypeDef");
+        log::warn!("Unsupported TypeDef: {}", ty.path());
+        TypeMeta::Unresolved(format!("{}", ty.path()))
       }
     };
     // Resolve type.
@@ -1220,8 +2186,64 @@ impl Types {
     }
 
     for ty in types.types() {
-      self.import_v14_type(ty.id(), ty.ty(), &id_to_ref)?;
+      if let Err(err) = self.import_v14_type(ty.id(), ty.ty(), &id_to_ref) {
+        // Leave the type unresolved, only pallets using it will be skipped.
+        log::warn!("Failed to import type {}: {}", ty.ty().path(), err);
+      }
     }
+
+    // Alias types by their namespaced name (`pallet_balances::Event`) and short name (`Event`).
+    // Only unique names are aliased, ambiguous names need the namespace.
+    let mut aliases: HashMap<String, Vec<u32>> = HashMap::new();
+    for ty in types.types() {
+      let path = ty.ty().path();
+      let namespace = match path.namespace().first() {
+        Some(namespace) => namespace,
+        None => continue,
+      };
+      let mut names = vec![get_type_name(ty.ty(), types, false)];
+      if let Some(ident) = path.ident() {
+        if ident != names[0] {
+          names.push(ident);
+        }
+      }
+      for name in names {
+        aliases
+          .entry(format!("{}::{}", namespace, name))
+          .or_default()
+          .push(ty.id());
+        aliases.entry(name).or_default().push(ty.id());
+      }
+    }
+    for (name, ids) in aliases {
+      match &ids[..] {
+        [id] => {
+          if !self.is_defined(&name) {
+            if let Some(type_ref) = id_to_ref.get(id) {
+              self.insert(&name, type_ref.clone());
+            }
+          }
+        }
+        _ => {
+          log::debug!("Ambiguous type name '{}' used by {} types", name, ids.len());
+        }
+      }
+    }
+
+    // Alias runtime types that the client needs by their short name.
+    for ty in types.types() {
+      let ident = ty.ty().path().ident();
+      match ident.as_deref() {
+        Some("SessionKeys") => {
+          if let Some(type_ref) = id_to_ref.get(&ty.id()) {
+            self.insert("SessionKeys", type_ref.clone());
+          }
+        }
+        _ => (),
+      }
+    }
+
+    self.type_ids = id_to_ref;
     Ok(())
   }
 
@@ -1269,6 +2291,59 @@ impl Types {
     type_ref.custom_decode(func);
     Ok(())
   }
+
+  pub fn decimals(&self, name: &str) -> Option<u32> {
+    self.decimals.get(name).copied()
+  }
+
+  /// Scale values of the integer type `name` by `10^decimals`.
+  ///
+  /// Scripts then use token amounts (`1.5`), which are encoded as `1.5 * 10^decimals`.
+  pub fn set_decimals(&mut self, name: &str, decimals: u32) -> Result<(), Box<EvalAltResult>> {
+    let int_meta = self
+      .parse_type(name)?
+      .integer_meta()
+      .ok_or_else(|| format!("Type {} isn't an integer type", name))?;
+    let scale = 10u128
+      .checked_pow(decimals)
+      .and_then(Decimal::from_u128)
+      .ok_or_else(|| format!("Too many decimals for {}: {}", name, decimals))?;
+    for type_id in &[
+      TypeId::of::<INT>(),
+      TypeId::of::<Decimal>(),
+      TypeId::of::<ImmutableString>(),
+    ] {
+      let int_meta = int_meta.clone();
+      let type_name = name.to_string();
+      self.custom_encode(name, *type_id, move |value, data| {
+        let amount = fixed_point_value(value)?;
+        let raw = amount
+          .checked_mul(scale)
+          .ok_or_else(|| format!("{} too large: {}", type_name, amount))?;
+        if !raw.fract().is_zero() {
+          Err(format!(
+            "{} {} has more than {} decimals",
+            type_name, amount, decimals
+          ))?;
+        }
+        int_meta.encode_value(Dynamic::from_decimal(raw), data)
+      })?;
+    }
+    for (name, is_compact) in &[(name.to_string(), false), (format!("Compact<{}>", name), true)] {
+      let int_meta = int_meta.clone();
+      let is_compact = *is_compact;
+      self.custom_decode(name, move |mut input| {
+        let raw = int_meta.decode_value(&mut input, is_compact)?;
+        let raw = match raw.as_int() {
+          Ok(num) => Decimal::from(num),
+          Err(_) => raw.as_decimal().map_err(|_| "Expected integer")?,
+        };
+        Ok(Dynamic::from_decimal(raw / scale))
+      })?;
+    }
+    self.decimals.insert(name.into(), decimals);
+    Ok(())
+  }
 }
 
 #[derive(Clone)]
@@ -1294,6 +2369,18 @@ impl TypeLookup {
     t.parse_named_type(name, def)
   }
 
+  pub fn define(&self, name: &str, def: Dynamic) -> Result<TypeRef, Box<EvalAltResult>> {
+    let def: Value = from_dynamic(&def)?;
+    let mut t = self.types.write().unwrap();
+    t.define(name, &def)
+  }
+
+  pub fn define_enum(&self, name: &str, variants: Dynamic) -> Result<TypeRef, Box<EvalAltResult>> {
+    let variants: Value = from_dynamic(&variants)?;
+    let mut t = self.types.write().unwrap();
+    t.define_enum(name, &variants)
+  }
+
   pub fn parse_type(&self, def: &str) -> Result<TypeRef, Box<EvalAltResult>> {
     let mut t = self.types.write().unwrap();
     t.parse_type(def)
@@ -1304,6 +2391,80 @@ impl TypeLookup {
     t.resolve(name)
   }
 
+  pub fn resolve_type_id(&self, id: u32) -> Option<TypeRef> {
+    self.types.read().unwrap().resolve_type_id(id)
+  }
+
+  /// Resolve a V14 type by it's portable registry id, falling back to the type name
+  /// when the id isn't in the registry.
+  ///
+  /// The named type is kept when it has custom encoding (`AccountId`, `MultiAddress`)
+  /// or is an integer alias of the same integer type (`Balance`), so custom encoders
+  /// added later (`set_decimals`) still apply to it.
+  pub fn parse_type_with_id(&self, name: &str, id: u32) -> Result<TypeRef, Box<EvalAltResult>> {
+    let id_ref = match self.resolve_type_id(id) {
+      Some(id_ref) => id_ref,
+      None => return self.parse_type(name),
+    };
+    let type_ref = self.parse_type(name)?;
+    if !type_ref.is_unresolved() && (type_ref.is_custom() || type_ref.is_same_integer(&id_ref)) {
+      return Ok(type_ref);
+    }
+    log::debug!("Using registry type {} for type: {}", id, name);
+    Ok(id_ref)
+  }
+
+  /// Parse a type name for encoding/decoding.  Fails if the type can't be resolved.
+  fn resolve_codec_type(&self, name: &str) -> Result<TypeRef, Box<EvalAltResult>> {
+    let type_ref = self.parse_type(name)?;
+    if type_ref.is_unresolved() {
+      Err(format!("Unknown type: {}", name))?;
+    }
+    Ok(type_ref)
+  }
+
+  /// SCALE encode a value as the named type.  Returns a `0x` prefixed hex string.
+  pub fn encode_hex(&mut self, name: &str, value: Dynamic) -> Result<String, Box<EvalAltResult>> {
+    let data = self.resolve_codec_type(name)?.encode(value)?;
+    Ok(format!("0x{}", hex::encode(data)))
+  }
+
+  /// Decode a hex string (with or without `0x`) as the named type.
+  pub fn decode_hex(&mut self, name: &str, data: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    let type_ref = self.resolve_codec_type(name)?;
+    let data = hex::decode(data.strip_prefix("0x").unwrap_or(data))
+      .map_err(|e| format!("Invalid hex data: {}", e))?;
+    type_ref.decode(data)
+  }
+
+  pub fn resolve_in(&self, namespace: &str, name: &str) -> TypeRef {
+    let mut t = self.types.write().unwrap();
+    t.resolve_in(namespace, name)
+  }
+
+  pub fn set_spec_version(&self, spec_version: u32) -> Result<(), Box<EvalAltResult>> {
+    let mut t = self.types.write().unwrap();
+    t.set_spec_version(spec_version)
+  }
+
+  pub fn spec_version(&self) -> Option<u32> {
+    self.types.read().unwrap().spec_version
+  }
+
+  pub fn decimals(&self, name: &str) -> Option<u32> {
+    self.types.read().unwrap().decimals(name)
+  }
+
+  pub fn set_decimals(&self, name: &str, decimals: u32) -> Result<(), Box<EvalAltResult>> {
+    let mut t = self.types.write().unwrap();
+    t.set_decimals(name, decimals)
+  }
+
+  pub fn add_namespace(&self, pallet: &str, namespace: &str) {
+    let mut t = self.types.write().unwrap();
+    t.add_namespace(pallet, namespace)
+  }
+
   pub fn insert_meta(&self, name: &str, type_meta: TypeMeta) -> TypeRef {
     let mut t = self.types.write().unwrap();
     t.insert_meta(name, type_meta)
@@ -1350,11 +2511,228 @@ impl TypeLookup {
   }
 }
 
-pub fn init_engine(
-  engine: &mut Engine,
-  opts: &EngineOptions,
-) -> Result<TypeLookup, Box<EvalAltResult>> {
+/// Fixed-point and per-thing types: (name, integer width, signed, scale).
+const FIXED_POINT_TYPES: &[(&str, u8, bool, u64)] = &[
+  ("Percent", 1, false, 100),
+  ("Permill", 4, false, 1_000_000),
+  ("Perbill", 4, false, 1_000_000_000),
+  ("Perquintill", 8, false, 1_000_000_000_000_000_000),
+  ("FixedI64", 8, true, 1_000_000_000),
+  ("FixedU128", 16, false, 1_000_000_000_000_000_000),
+  ("FixedI128", 16, true, 1_000_000_000_000_000_000),
+];
+
+/// Convert an integer, decimal or string (`"0.5"` or `"50%"`) into a decimal.
+pub fn fixed_point_value(value: Dynamic) -> Result<Decimal, Box<EvalAltResult>> {
+  if let Ok(num) = value.as_int() {
+    Ok(Decimal::from(num))
+  } else if let Ok(dec) = value.as_decimal() {
+    Ok(dec)
+  } else {
+    let s = value.into_immutable_string()?;
+    let s = s.trim();
+    match s.strip_suffix('%') {
+      Some(percent) => {
+        let dec = Decimal::from_str(percent.trim()).map_err(|e| e.to_string())?;
+        Ok(dec / Decimal::from(100))
+      }
+      None => Ok(Decimal::from_str(s).map_err(|e| e.to_string())?),
+    }
+  }
+}
+
+fn register_fixed_point(types: &mut Types) -> Result<(), Box<EvalAltResult>> {
+  for (name, width, signed, scale) in FIXED_POINT_TYPES {
+    let (width, signed, scale) = (*width, *signed, Decimal::from(*scale));
+    let int_meta = TypeMeta::Integer(width, signed);
+    // Integers are the raw parts (`Perbill` 500_000_000 is 50%), like on-chain values.
+    let raw_meta = int_meta.clone();
+    types.custom_encode(name, TypeId::of::<INT>(), move |value, data| {
+      raw_meta.encode_value(value, data)
+    })?;
+    for type_id in &[TypeId::of::<Decimal>(), TypeId::of::<ImmutableString>()] {
+      let int_meta = int_meta.clone();
+      let type_name = name.to_string();
+      types.custom_encode(name, *type_id, move |value, data| {
+        let amount = fixed_point_value(value)?;
+        let raw = amount
+          .checked_mul(scale)
+          .ok_or_else(|| format!("{} too large: {}", type_name, amount))?;
+        if !raw.fract().is_zero() {
+          Err(format!("{} {} has more precision than 1/{}", type_name, amount, scale))?;
+        }
+        int_meta.encode_value(Dynamic::from_decimal(raw), data)
+      })?;
+    }
+    for (name, is_compact) in &[(name.to_string(), false), (format!("Compact<{}>", name), true)] {
+      let int_meta = int_meta.clone();
+      let is_compact = *is_compact;
+      types.custom_decode(name, move |mut input| {
+        let raw = int_meta.decode_value(&mut input, is_compact)?;
+        let raw = match raw.as_int() {
+          Ok(num) => Decimal::from(num),
+          Err(_) => raw.as_decimal().map_err(|_| "Expected integer")?,
+        };
+        Ok(Dynamic::from_decimal(raw / scale))
+      })?;
+    }
+  }
+  Ok(())
+}
+
+/// Explicit `Option` value: `Some(x)` or `None`.
+///
+/// Without it `()` is encoded as `None` and any other value as `Some`.
+#[derive(Clone, Debug)]
+pub struct OptionValue(pub Option<Dynamic>);
+
+impl OptionValue {
+  /// Unwrap an `Option` value.  `()` is `None`, other values are `Some`.
+  pub fn from_dynamic(value: Dynamic) -> Option<Dynamic> {
+    if value.is::<OptionValue>() {
+      value.cast::<OptionValue>().0
+    } else if value.is::<()>() {
+      None
+    } else {
+      Some(value)
+    }
+  }
+
+  fn is_some(&mut self) -> bool {
+    self.0.is_some()
+  }
+
+  fn is_none(&mut self) -> bool {
+    self.0.is_none()
+  }
+
+  fn value(&mut self) -> Dynamic {
+    self.0.clone().unwrap_or(Dynamic::UNIT)
+  }
+
+  fn to_string(&mut self) -> String {
+    match &self.0 {
+      Some(value) => format!("Some({})", value),
+      None => "None".into(),
+    }
+  }
+}
+
+/// Explicit enum variant: `variant("Ticker", value)`.
+///
+/// The enum is the type it is encoded as.
+#[derive(Clone, Debug)]
+pub struct EnumValue {
+  pub name: String,
+  pub value: Dynamic,
+}
+
+impl EnumValue {
+  pub fn new(name: &str, value: Dynamic) -> Self {
+    Self {
+      name: name.into(),
+      value,
+    }
+  }
+
+  fn name(&mut self) -> String {
+    self.name.clone()
+  }
+
+  fn value(&mut self) -> Dynamic {
+    self.value.clone()
+  }
+
+  fn to_string(&mut self) -> String {
+    if self.value.is::<()>() {
+      self.name.clone()
+    } else {
+      format!("{}({})", self.name, self.value)
+    }
+  }
+}
+
+/// Parse a hex string into a fixed-size hash (`H256`, `H160`).
+fn parse_hash<H>(hex: &str, len: usize, from_slice: fn(&[u8]) -> H) -> Result<H, Box<EvalAltResult>> {
+  let bytes = decode_hex(hex)?;
+  if bytes.len() != len {
+    Err(format!("Expected {} byte hash, got {} bytes", len, bytes.len()))?;
+  }
+  Ok(from_slice(&bytes))
+}
+
+/// Parse a `U256` from a hex (`0x` prefix) or decimal string.
+fn parse_u256(val: &str) -> Result<U256, Box<EvalAltResult>> {
+  let res = match val.strip_prefix("0x") {
+    Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| format!("{:?}", e)),
+    None => U256::from_dec_str(val).map_err(|e| format!("{:?}", e)),
+  };
+  Ok(res.map_err(|e| format!("Invalid U256 '{}': {}", val, e))?)
+}
+
+fn int_to_u256(val: INT) -> Result<U256, Box<EvalAltResult>> {
+  if val < 0 {
+    Err(format!("U256 can't be negative: {}", val))?;
+  }
+  Ok(U256::from(val as u64))
+}
+
+fn u256_op(
+  a: U256,
+  b: U256,
+  op: &str,
+  func: fn(U256, U256) -> Option<U256>,
+) -> Result<U256, Box<EvalAltResult>> {
+  Ok(func(a, b).ok_or_else(|| format!("U256 overflow: {} {} {}", a, op, b))?)
+}
+
+fn register_hashes(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<H256>("H256")
+    .register_result_fn("H256", |hex: &str| parse_hash(hex, 32, H256::from_slice))
+    .register_fn("to_string", |hash: &mut H256| format!("{:?}", hash))
+    .register_fn("to_debug", |hash: &mut H256| format!("{:?}", hash))
+    .register_fn("==", |a: H256, b: H256| a == b)
+    .register_fn("!=", |a: H256, b: H256| a != b)
+    .register_type_with_name::<H160>("H160")
+    .register_result_fn("H160", |hex: &str| parse_hash(hex, 20, H160::from_slice))
+    .register_fn("to_string", |hash: &mut H160| format!("{:?}", hash))
+    .register_fn("to_debug", |hash: &mut H160| format!("{:?}", hash))
+    .register_fn("==", |a: H160, b: H160| a == b)
+    .register_fn("!=", |a: H160, b: H160| a != b)
+    .register_type_with_name::<U256>("U256")
+    .register_result_fn("U256", |val: &str| parse_u256(val))
+    .register_result_fn("U256", int_to_u256)
+    .register_fn("to_string", |val: &mut U256| val.to_string())
+    .register_fn("to_debug", |val: &mut U256| val.to_string())
+    .register_fn("to_hex", |val: &mut U256| format!("{:#x}", val))
+    .register_result_fn("+", |a: U256, b: U256| u256_op(a, b, "+", U256::checked_add))
+    .register_result_fn("-", |a: U256, b: U256| u256_op(a, b, "-", U256::checked_sub))
+    .register_result_fn("*", |a: U256, b: U256| u256_op(a, b, "*", U256::checked_mul))
+    .register_result_fn("/", |a: U256, b: U256| u256_op(a, b, "/", U256::checked_div))
+    .register_fn("==", |a: U256, b: U256| a == b)
+    .register_fn("!=", |a: U256, b: U256| a != b)
+    .register_fn("<", |a: U256, b: U256| a < b)
+    .register_fn("<=", |a: U256, b: U256| a <= b)
+    .register_fn(">", |a: U256, b: U256| a > b)
+    .register_fn(">=", |a: U256, b: U256| a >= b);
+}
+
+pub fn register(engine: &mut Engine) {
+  register_hashes(engine);
   engine
+    .register_type_with_name::<OptionValue>("Option")
+    .register_fn("Some", |value: Dynamic| OptionValue(Some(value)))
+    .register_get("is_some", OptionValue::is_some)
+    .register_get("is_none", OptionValue::is_none)
+    .register_get("value", OptionValue::value)
+    .register_fn("to_string", OptionValue::to_string)
+    .register_type_with_name::<EnumValue>("EnumVariant")
+    .register_fn("variant", |name: &str| EnumValue::new(name, Dynamic::UNIT))
+    .register_fn("variant", |name: &str, value: Dynamic| EnumValue::new(name, value))
+    .register_get("name", EnumValue::name)
+    .register_get("value", EnumValue::value)
+    .register_fn("to_string", EnumValue::to_string)
     .register_type_with_name::<TypeLookup>("TypeLookup")
     .register_fn("dump_types", TypeLookup::dump_types)
     .register_fn("dump_unresolved", TypeLookup::dump_unresolved)
@@ -1364,12 +2742,52 @@ pub fn init_engine(
         TypeLookup::parse_named_type(lookup, name, def)
       },
     )
+    .register_result_fn("define", |lookup: &mut TypeLookup, name: &str, def: Dynamic| {
+      lookup.define(name, def)
+    })
+    .register_result_fn(
+      "define_enum",
+      |lookup: &mut TypeLookup, name: &str, variants: Dynamic| lookup.define_enum(name, variants),
+    )
     .register_result_fn("parse_type", |lookup: &mut TypeLookup, def: &str| {
       TypeLookup::parse_type(lookup, def)
     })
     .register_fn("resolve", |lookup: &mut TypeLookup, name: &str| {
       TypeLookup::resolve(lookup, name)
     })
+    .register_fn(
+      "resolve_in",
+      |lookup: &mut TypeLookup, namespace: &str, name: &str| {
+        TypeLookup::resolve_in(lookup, namespace, name)
+      },
+    )
+    .register_result_fn("set_spec_version", |lookup: &mut TypeLookup, spec_version: INT| {
+      let spec_version = u32::try_from(spec_version)
+        .map_err(|_| format!("Invalid spec version: {}", spec_version))?;
+      lookup.set_spec_version(spec_version)
+    })
+    .register_get("spec_version", |lookup: &mut TypeLookup| {
+      lookup
+        .spec_version()
+        .map_or(Dynamic::UNIT, |v| Dynamic::from(v as INT))
+    })
+    .register_result_fn(
+      "set_decimals",
+      |lookup: &mut TypeLookup, name: &str, decimals: INT| {
+        let decimals = u32::try_from(decimals).map_err(|_| format!("Invalid decimals: {}", decimals))?;
+        lookup.set_decimals(name, decimals)
+      },
+    )
+    .register_fn("decimals", |lookup: &mut TypeLookup, name: &str| {
+      lookup
+        .decimals(name)
+        .map_or(Dynamic::UNIT, |v| Dynamic::from(v as INT))
+    })
+    .register_result_fn("encode_hex", TypeLookup::encode_hex)
+    .register_result_fn("decode_hex", TypeLookup::decode_hex)
+    .register_fn("set_codec_trace", |_lookup: &mut TypeLookup, enabled: bool| {
+      set_codec_trace(enabled)
+    })
     .register_type_with_name::<Types>("Types")
     .register_type_with_name::<TypeMeta>("TypeMeta")
     .register_fn("to_string", TypeMeta::to_string)
@@ -1384,6 +2802,33 @@ pub fn init_engine(
     })
     .register_fn("encode", |era: &mut Era| era.encode())
     .register_fn("to_string", |era: &mut Era| format!("{:?}", era));
+
+  #[cfg(feature = "libp2p")]
+  {
+    use libp2p_core::{PeerId, Multiaddr};
+
+    engine
+      .register_type_with_name::<PeerId>("PeerId")
+      .register_fn("to_string", |id: &mut PeerId| id.to_base58())
+      .register_fn("to_debug", |id: &mut PeerId| id.to_base58())
+      .register_type_with_name::<Multiaddr>("Multiaddr")
+      .register_fn("to_string", |m: &mut Multiaddr| format!("{}", m))
+      .register_fn("to_debug", |m: &mut Multiaddr| format!("{:?}", m));
+  }
+}
+
+pub fn init_engine(
+  engine: &mut Engine,
+  opts: &EngineOptions,
+) -> Result<TypeLookup, Box<EvalAltResult>> {
+  register(engine);
+  set_codec_trace(opts.codec_trace);
+
+  new_lookup(opts)
+}
+
+/// Load the standard and custom schemas into a new type lookup.
+pub fn new_lookup(opts: &EngineOptions) -> Result<TypeLookup, Box<EvalAltResult>> {
   let mut types = Types::new();
 
   // Primitive types.
@@ -1400,13 +2845,17 @@ pub fn init_engine(
   types.insert_meta("bool", TypeMeta::Bool);
   types.insert_meta("Text", TypeMeta::String);
   types.insert_meta("Option<bool>", TypeMeta::OptionBool);
+  types.parse_named_type("H160", "[u8; 20]")?;
+  types.parse_named_type("U256", "[u8; 32]")?;
 
   // Load standard substrate types.
-  types.load_schema(&opts.substrate_types)?;
+  types.load_schemas(&opts.substrate_types, &opts.schema_cache_dir)?;
   // Load custom chain types.
-  types.load_schema(&opts.custom_types)?;
+  types.load_schemas(&opts.custom_types, &opts.schema_cache_dir)?;
 
   // Custom encodings.
+  register_fixed_point(&mut types)?;
+
   types.custom_encode("Era", TypeId::of::<Era>(), |value, data| {
     let era = value.cast::<Era>();
     data.encode(era);
@@ -1417,6 +2866,36 @@ pub fn init_engine(
     Ok(Dynamic::from(era))
   })?;
 
+  types.custom_encode("H256", TypeId::of::<H256>(), |value, data| {
+    data.encode(value.cast::<H256>());
+    Ok(())
+  })?;
+  types.custom_decode("H256", |mut input| {
+    Ok(Dynamic::from(H256::decode(&mut input)?))
+  })?;
+  types.custom_encode("H160", TypeId::of::<H160>(), |value, data| {
+    data.encode(value.cast::<H160>());
+    Ok(())
+  })?;
+  types.custom_decode("H160", |mut input| {
+    Ok(Dynamic::from(H160::decode(&mut input)?))
+  })?;
+  types.custom_encode("U256", TypeId::of::<U256>(), |value, data| {
+    data.encode(value.cast::<U256>());
+    Ok(())
+  })?;
+  types.custom_encode("U256", TypeId::of::<INT>(), |value, data| {
+    data.encode(int_to_u256(value.cast::<INT>())?);
+    Ok(())
+  })?;
+  types.custom_encode("U256", TypeId::of::<ImmutableString>(), |value, data| {
+    data.encode(parse_u256(&value.cast::<ImmutableString>())?);
+    Ok(())
+  })?;
+  types.custom_decode("U256", |mut input| {
+    Ok(Dynamic::from(U256::decode(&mut input)?))
+  })?;
+
   types.custom_encode("AccountId", TypeId::of::<SharedUser>(), |value, data| {
     let user = value.cast::<SharedUser>();
     data.encode(user.public());
@@ -1447,6 +2926,30 @@ pub fn init_engine(
     data.encode(user.public());
     Ok(())
   })?;
+  types.custom_encode("MultiAddress", TypeId::of::<AccountId>(), |value, data| {
+    data.encode(0u8); // MultiAddress::Id
+    data.encode(value.cast::<AccountId>());
+    Ok(())
+  })?;
+  types.custom_encode(
+    "MultiAddress",
+    TypeId::of::<ImmutableString>(),
+    |value, data| {
+      let val = value.cast::<ImmutableString>();
+      let acc = AccountId::from_string(&val).map_err(|e| format!("{:?}", e))?;
+      data.encode(0u8); // MultiAddress::Id
+      data.encode(acc);
+      Ok(())
+    },
+  )?;
+  // Small integers are account indices.
+  types.custom_encode("MultiAddress", TypeId::of::<INT>(), |value, data| {
+    let index = value.cast::<INT>();
+    let index = u32::try_from(index).map_err(|_| format!("Invalid account index: {}", index))?;
+    data.encode(1u8); // MultiAddress::Index
+    data.encode(Compact(index));
+    Ok(())
+  })?;
 
   types.custom_encode(
     "MultiSignature",
@@ -1461,14 +2964,6 @@ pub fn init_engine(
   {
     use libp2p_core::{PeerId, Multiaddr};
 
-    engine
-      .register_type_with_name::<PeerId>("PeerId")
-      .register_fn("to_string", |id: &mut PeerId| id.to_base58())
-      .register_fn("to_debug", |id: &mut PeerId| id.to_base58())
-      .register_type_with_name::<Multiaddr>("Multiaddr")
-      .register_fn("to_string", |m: &mut Multiaddr| format!("{}", m))
-      .register_fn("to_debug", |m: &mut Multiaddr| format!("{:?}", m));
-
     types.custom_decode("OpaquePeerId", |mut input| {
       let opaque_bytes: Vec<u8> = Decode::decode(&mut input)?;
       let data: Vec<u8> = Decode::decode(&mut &opaque_bytes[..])?;
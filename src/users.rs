@@ -1,17 +1,227 @@
-use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
 
-use sp_core::{sr25519, Pair};
-use sp_runtime::{AccountId32, MultiSignature};
+use sp_core::{
+  crypto::{Ss58AddressFormat, Ss58Codec},
+  ed25519, sr25519, Pair,
+};
+use sp_runtime::{traits::Verify, AccountId32, MultiSignature};
+
+use parity_scale_codec::Decode;
 
 use dashmap::DashMap;
 
-use rhai::{Dynamic, Engine, EvalAltResult, INT};
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RMap, INT};
 
-use crate::client::{Client, ExtrinsicCallResult};
-use crate::metadata::EncodedCall;
+use crate::client::{
+  decode_hex, Client, Extra, ExtrinsicCallResult, ExtrinsicV4, OfflineSignOptions, RetryPolicy,
+  TxHash, WaitFor,
+};
+use crate::engine::is_cancelled;
+use crate::metadata::{EncodedCall, FuncMetadata, Metadata};
+use crate::rpc::RequestToken;
 
 pub type AccountId = AccountId32;
 
+/// Default number of in-flight extrinsics for `submit_batch`.
+const DEFAULT_BATCH_CONCURRENCY: usize = 16;
+
+/// Well-known dev accounts, also available as globals (`ALICE`, `BOB`, ...).
+pub const DEV_ACCOUNTS: &[&str] = &["Alice", "Bob", "Charlie", "Dave", "Eve", "Ferdie"];
+
+/// Default time limit for `vanity`, in seconds.
+const DEFAULT_VANITY_TIMEOUT: u64 = 60;
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Get an account from an `AccountId`, `User` or SS58 address.
+pub fn dynamic_to_account(val: Dynamic) -> Result<AccountId, Box<EvalAltResult>> {
+  if val.is::<AccountId>() {
+    Ok(val.cast::<AccountId>())
+  } else if val.is::<SharedUser>() {
+    Ok(val.cast::<SharedUser>().acc())
+  } else if let Some(addr) = val.read_lock::<rhai::ImmutableString>() {
+    parse_account(addr.as_str())
+  } else {
+    Err(format!("Expected an address, AccountId or User: {}", val.type_name()))?
+  }
+}
+
+fn ss58_format(prefix: INT) -> Result<Ss58AddressFormat, Box<EvalAltResult>> {
+  Ok(
+    u16::try_from(prefix)
+      .ok()
+      .and_then(|prefix| Ss58AddressFormat::try_from(prefix).ok())
+      .ok_or_else(|| format!("Invalid ss58 prefix: {}", prefix))?,
+  )
+}
+
+/// Parse an SS58 address.  The address must use a known or the chain's prefix.
+pub fn parse_account(addr: &str) -> Result<AccountId, Box<EvalAltResult>> {
+  Ok(AccountId::from_string(addr).map_err(|e| format!("Invalid address {}: {:?}", addr, e))?)
+}
+
+/// Parse an SS58 address that must use `prefix`.
+pub fn parse_account_with_prefix(addr: &str, prefix: INT) -> Result<AccountId, Box<EvalAltResult>> {
+  let expected = ss58_format(prefix)?;
+  let (acc, format) = AccountId::from_string_with_version(addr)
+    .map_err(|e| format!("Invalid address {}: {:?}", addr, e))?;
+  if format != expected {
+    Err(format!(
+      "Address {} has prefix {:?}, expected {:?}",
+      addr, format, expected
+    ))?;
+  }
+  Ok(acc)
+}
+
+/// A random keypair: address, account and secret seed.
+type VanityKey = (String, AccountId, [u8; 32]);
+
+fn generate_key(scheme: &str) -> Result<fn() -> VanityKey, Box<EvalAltResult>> {
+  fn sr25519_key() -> VanityKey {
+    let (pair, seed) = sr25519::Pair::generate();
+    let acc = AccountId::new(pair.public().0);
+    (acc.to_ss58check(), acc, seed)
+  }
+  fn ed25519_key() -> VanityKey {
+    let (pair, seed) = ed25519::Pair::generate();
+    let acc = AccountId::new(pair.public().0);
+    (acc.to_ss58check(), acc, seed)
+  }
+  match scheme {
+    "sr25519" => Ok(sr25519_key),
+    "ed25519" => Ok(ed25519_key),
+    _ => Err(format!("Unsupported key scheme '{}', expected sr25519 or ed25519", scheme))?,
+  }
+}
+
+/// Search for a keypair with an SS58 address starting with `prefix`.
+///
+/// The network's leading character can be left out of `prefix` (e.g. "ABC" matches "5ABC...").
+/// Options: `threads` (default: number of CPUs) and `timeout` in seconds.
+/// Returns `()` if nothing was found before the timeout.
+fn vanity(prefix: &str, scheme: &str, opts: RMap) -> Result<Dynamic, Box<EvalAltResult>> {
+  if let Some(c) = prefix.chars().find(|c| !BASE58_ALPHABET.contains(*c)) {
+    Err(format!("Vanity prefix can't contain '{}', it isn't a base58 character", c))?;
+  }
+  let generate = generate_key(scheme)?;
+  let int_opt = |name: &str, default: u64| -> Result<u64, Box<EvalAltResult>> {
+    match opts.get(name) {
+      Some(val) => Ok(
+        val
+          .as_int()
+          .map_err(|_| format!("Expected integer for {}", name))?
+          .max(1) as u64,
+      ),
+      None => Ok(default),
+    }
+  };
+  let cpus = std::thread::available_parallelism().map_or(4, |n| n.get());
+  let threads = int_opt("threads", cpus as u64)?;
+  let deadline = Instant::now() + Duration::from_secs(int_opt("timeout", DEFAULT_VANITY_TIMEOUT)?);
+
+  let found = Arc::new(Mutex::new(None));
+  let done = Arc::new(AtomicBool::new(false));
+  let attempts = Arc::new(AtomicU64::new(0));
+  let workers = (0..threads)
+    .map(|_| {
+      let prefix = prefix.to_string();
+      let found = found.clone();
+      let done = done.clone();
+      let attempts = attempts.clone();
+      spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+          let key = generate();
+          attempts.fetch_add(1, Ordering::Relaxed);
+          let matched = key.0.starts_with(&prefix) || key.0[1..].starts_with(&prefix);
+          if matched && !done.swap(true, Ordering::Relaxed) {
+            *found.lock().unwrap() = Some(key);
+          } else if Instant::now() >= deadline || is_cancelled() {
+            break;
+          }
+        }
+      })
+    })
+    .collect::<Vec<_>>();
+  for worker in workers {
+    worker
+      .join()
+      .map_err(|err| format!("Vanity worker failed: {:?}", err))?;
+  }
+
+  let found = found.lock().unwrap().take();
+  Ok(match found {
+    Some((address, acc, seed)) => {
+      let mut map = RMap::new();
+      map.insert("address".into(), Dynamic::from(address));
+      map.insert("acc".into(), Dynamic::from(acc));
+      // The hex seed can be used as a secret URI.
+      map.insert("seed".into(), Dynamic::from(format!("0x{}", hex::encode(seed))));
+      map.insert("scheme".into(), Dynamic::from(scheme.to_string()));
+      map.insert(
+        "attempts".into(),
+        Dynamic::from(attempts.load(Ordering::Relaxed) as INT),
+      );
+      Dynamic::from(map)
+    }
+    None => Dynamic::UNIT,
+  })
+}
+
+/// Get the `wait` level from submit options.
+fn wait_option(opts: &RMap) -> Result<WaitFor, Box<EvalAltResult>> {
+  match opts.get("wait") {
+    Some(wait) => WaitFor::parse(
+      wait
+        .read_lock::<rhai::ImmutableString>()
+        .ok_or_else(|| format!("Expected string for wait"))?
+        .as_str(),
+    ),
+    None => Ok(WaitFor::InBlock),
+  }
+}
+
+/// Get bytes from a `Blob` or a string (`0x` prefixed hex or UTF-8 text).
+pub fn dynamic_to_bytes(val: Dynamic) -> Result<Vec<u8>, Box<EvalAltResult>> {
+  if let Some(data) = val.read_lock::<Vec<u8>>() {
+    Ok(data.clone())
+  } else if let Some(s) = val.read_lock::<rhai::ImmutableString>() {
+    if s.starts_with("0x") {
+      decode_hex(s.as_str())
+    } else {
+      Ok(s.as_bytes().to_vec())
+    }
+  } else {
+    Err(format!("Expected a Blob or string: {}", val.type_name()))?
+  }
+}
+
+/// Get a `MultiSignature` from a signature or its SCALE encoding.
+fn dynamic_to_signature(val: Dynamic) -> Result<MultiSignature, Box<EvalAltResult>> {
+  if val.is::<MultiSignature>() {
+    return Ok(val.cast::<MultiSignature>());
+  }
+  let data = dynamic_to_bytes(val)?;
+  Ok(MultiSignature::decode(&mut &data[..]).map_err(|e| format!("Invalid signature: {}", e))?)
+}
+
+/// Verify an sr25519, ed25519 or ecdsa signature of `message` by `account`.
+pub fn verify_signature(
+  account: Dynamic,
+  message: Dynamic,
+  signature: Dynamic,
+) -> Result<bool, Box<EvalAltResult>> {
+  let account = dynamic_to_account(account)?;
+  let message = dynamic_to_bytes(message)?;
+  let signature = dynamic_to_signature(signature)?;
+  Ok(signature.verify(&message[..], &account))
+}
+
 #[derive(Clone)]
 pub struct User {
   pub pair: sr25519::Pair,
@@ -19,10 +229,12 @@ pub struct User {
   pub name: String,
   account: AccountId,
   client: Client,
+  users: Weak<InnerUsers>,
 }
 
 impl User {
-  fn new(client: Client, name: &str) -> Result<Self, Box<EvalAltResult>> {
+  /// The `name` can include a derivation path, e.g. `Alice//stash` or `Alice//0/1`.
+  fn new(client: Client, users: Weak<InnerUsers>, name: &str) -> Result<Self, Box<EvalAltResult>> {
     log::info!("New user: {}", name);
     let seed = format!("//{}", name);
     let pair = sr25519::Pair::from_string(&seed, None).map_err(|e| format!("{:?}", e))?;
@@ -33,9 +245,19 @@ impl User {
       account,
       nonce: 0u32,
       client,
+      users,
     })
   }
 
+  /// Derive a new user using a hard (`//`) or soft (`/`) derivation path.
+  fn derive(&self, path: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    if !path.starts_with('/') {
+      Err(format!("Invalid derivation path '{}', expected '//hard' or '/soft'", path))?;
+    }
+    let users = self.users.upgrade().ok_or("Users have been dropped")?;
+    users.get_user(format!("{}{}", self.name, path))
+  }
+
   pub fn public(&self) -> sr25519::Public {
     self.pair.public()
   }
@@ -52,6 +274,12 @@ impl User {
     MultiSignature::Sr25519(self.pair.sign(&data[..]))
   }
 
+  /// Sign a call without using the node.  Returns the hex encoded extrinsic.
+  pub fn sign_call(&self, call: EncodedCall, opts: OfflineSignOptions) -> String {
+    let extra = Extra::new(opts.era, opts.nonce);
+    ExtrinsicV4::sign_call(self, extra, opts.additional, call).to_hex()
+  }
+
   pub fn submit_call(
     &mut self,
     call: EncodedCall,
@@ -68,6 +296,27 @@ impl User {
     Ok(res)
   }
 
+  /// Submit a call without tracking it, for load generation.  Returns the transaction hash.
+  pub fn submit_nowait(&mut self, call: EncodedCall) -> Result<TxHash, Box<EvalAltResult>> {
+    if self.nonce == 0u32 {
+      self.nonce = self.client.get_nonce(self.acc())?.unwrap_or(0);
+    }
+    let hash = self.client.submit_nowait(self, call)?;
+    self.nonce += 1;
+    Ok(hash)
+  }
+
+  /// Reload the nonce from the chain, then sign and submit the call again.
+  fn resubmit_call(
+    &mut self,
+    call: EncodedCall,
+  ) -> Result<(RequestToken, String), Box<EvalAltResult>> {
+    self.nonce = self.client.get_nonce(self.acc())?.unwrap_or(0);
+    let res = self.client.inner().submit_call(self, call)?;
+    self.nonce += 1;
+    Ok(res)
+  }
+
   fn to_string(&self) -> String {
     self.name.clone()
   }
@@ -93,6 +342,44 @@ impl SharedUser {
     self.0.read().unwrap().sign_data(data)
   }
 
+  pub fn sign_call(
+    &mut self,
+    call: EncodedCall,
+    opts: RMap,
+  ) -> Result<String, Box<EvalAltResult>> {
+    let opts = OfflineSignOptions::from_map(opts)?;
+    Ok(self.0.read().unwrap().sign_call(call, opts))
+  }
+
+  /// Build the payload that would be signed for `call`, without signing or submitting it.
+  ///
+  /// Missing `extra` fields (see `sign_call`) default to the values used by `submit`.
+  pub fn signing_payload(
+    &mut self,
+    call: EncodedCall,
+    mut extra: RMap,
+  ) -> Result<RMap, Box<EvalAltResult>> {
+    let user = self.0.read().unwrap();
+    let (spec_version, tx_version, genesis_hash, ..) = user.client.get_signed_extra();
+    if !extra.contains_key("nonce") {
+      let nonce = match user.nonce {
+        0 => user.client.get_nonce(user.acc())?.unwrap_or(0),
+        nonce => nonce,
+      };
+      extra.insert("nonce".into(), Dynamic::from(nonce as INT));
+    }
+    let defaults = [
+      ("spec_version", Dynamic::from(spec_version as INT)),
+      ("transaction_version", Dynamic::from(tx_version as INT)),
+      ("genesis_hash", Dynamic::from(genesis_hash)),
+    ];
+    for (name, val) in defaults {
+      extra.entry(name.into()).or_insert(val);
+    }
+    let opts = OfflineSignOptions::from_map(extra)?;
+    user.client.signing_payload(&call, &opts)
+  }
+
   pub fn submit_call(
     &mut self,
     call: EncodedCall,
@@ -100,6 +387,83 @@ impl SharedUser {
     self.0.write().unwrap().submit_call(call)
   }
 
+  pub fn submit_nowait(&mut self, call: EncodedCall) -> Result<TxHash, Box<EvalAltResult>> {
+    self.0.write().unwrap().submit_nowait(call)
+  }
+
+  /// Submit a call with options.
+  ///
+  /// * `wait`: `"in_block"` (default) or `"finalized"`.
+  /// * `retries`: re-submit with a fresh nonce when the extrinsic is dropped, usurped, invalid
+  ///   or rejected by the pool because of its nonce.
+  pub fn submit_call_opts(
+    &mut self,
+    call: EncodedCall,
+    opts: RMap,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let wait = wait_option(&opts)?;
+    let retries = match opts.get("retries") {
+      Some(val) => val
+        .as_int()
+        .map_err(|_| "Expected integer for retries")?
+        .max(0) as u32,
+      None => 0,
+    };
+    let mut res = self.submit_call(call.clone())?;
+    res.set_wait(wait);
+    if retries > 0 {
+      let user = self.clone();
+      res.set_retry(RetryPolicy {
+        max_retries: retries,
+        resubmit: Arc::new(move || user.0.write().unwrap().resubmit_call(call.clone())),
+      });
+    }
+    Ok(res)
+  }
+
+  /// Submit calls with sequential nonces, without waiting for each one to be included.
+  ///
+  /// At most `concurrency` extrinsics are waiting for inclusion at any time.
+  /// Returns the results once all extrinsics reached the `wait` level (or were dropped).
+  pub fn submit_batch(
+    &mut self,
+    calls: Vec<Dynamic>,
+    opts: RMap,
+  ) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let concurrency = match opts.get("concurrency") {
+      Some(val) => val
+        .as_int()
+        .map_err(|_| format!("Expected integer for concurrency"))?
+        .max(1) as usize,
+      None => DEFAULT_BATCH_CONCURRENCY,
+    };
+    let wait = wait_option(&opts)?;
+    let mut results = Vec::with_capacity(calls.len());
+    let mut pending: VecDeque<ExtrinsicCallResult> = VecDeque::with_capacity(concurrency);
+    for call in calls {
+      let call = call
+        .try_cast::<EncodedCall>()
+        .ok_or_else(|| format!("Expected an encoded call"))?;
+      if pending.len() >= concurrency {
+        if let Some(mut res) = pending.pop_front() {
+          res.is_in_block()?;
+        }
+      }
+      let mut res = self.submit_call(call)?;
+      res.set_wait(wait);
+      pending.push_back(res.clone());
+      results.push(Dynamic::from(res));
+    }
+    for mut res in pending {
+      res.is_in_block()?;
+    }
+    Ok(results)
+  }
+
+  pub fn derive(&mut self, path: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    self.0.read().unwrap().derive(path)
+  }
+
   fn to_string(&mut self) -> String {
     self.0.read().unwrap().to_string()
   }
@@ -124,14 +488,14 @@ impl InnerUsers {
     self.account_map.get(&acc).as_deref().cloned().unwrap_or(Dynamic::UNIT)
   }
 
-  fn get_user(&self, name: String) -> Result<Dynamic, Box<EvalAltResult>> {
+  fn get_user(self: &Arc<Self>, name: String) -> Result<Dynamic, Box<EvalAltResult>> {
     // Try save user.  If another thread generated the user first, then use that user.
     use dashmap::mapref::entry::Entry;
     Ok(match self.users.entry(name) {
       Entry::Occupied(entry) => entry.get().clone(),
       Entry::Vacant(entry) => {
         // Generate new user.
-        let user = User::new(self.client.clone(), entry.key())?;
+        let user = User::new(self.client.clone(), Arc::downgrade(self), entry.key())?;
         let acc = user.acc();
         // Create a shared wrapper for the user.
         let shared = Dynamic::from(SharedUser(Arc::new(RwLock::new(user))));
@@ -142,6 +506,49 @@ impl InnerUsers {
       }
     })
   }
+
+  /// Fund a test account from Alice.
+  ///
+  /// When Alice is the sudo key, `Balances.set_balance` sets the free balance to `amount`,
+  /// otherwise `amount` is transferred from Alice.
+  fn fund(
+    self: &Arc<Self>,
+    account: Dynamic,
+    amount: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    let who = Dynamic::from(dynamic_to_account(account)?);
+    let mut alice = self.get_user("Alice".into())?.cast::<SharedUser>();
+    let md = self.client.get_metadata();
+    let call = match self.sudo_set_balance(&md, &alice.acc()) {
+      Some(func) => {
+        let mut args = vec![who, amount];
+        // Older runtimes also set the reserved balance.
+        if func.required_args() == 3 {
+          args.push(Dynamic::from(0 as INT));
+        }
+        let call = func.encode_args(args)?;
+        md.get_func("Sudo", "sudo")?.encode_args(vec![Dynamic::from(call)])?
+      }
+      None => md
+        .get_func("Balances", "transfer")
+        .or_else(|_| md.get_func("Balances", "transfer_allow_death"))?
+        .encode_args(vec![who, amount])?,
+    };
+    alice.submit_call(call)
+  }
+
+  /// `Balances.set_balance` (or `force_set_balance`), if `sudo_key` can call it.
+  fn sudo_set_balance<'a>(&self, md: &'a Metadata, sudo_key: &AccountId) -> Option<&'a FuncMetadata> {
+    let key = md.get_storage("Sudo", "Key").ok()?;
+    let value = self.client.get_storage_by_key(key.get_value_key().ok()?, None).ok()??;
+    let key = key.decode_value(value.0).ok()?.try_cast::<AccountId>()?;
+    if &key != sudo_key {
+      return None;
+    }
+    md.get_func("Balances", "set_balance")
+      .or_else(|_| md.get_func("Balances", "force_set_balance"))
+      .ok()
+  }
 }
 
 #[derive(Clone)]
@@ -156,25 +563,69 @@ impl Users {
     self.0.find_by_account(acc)
   }
 
-  fn get_user(&mut self, name: String) -> Result<Dynamic, Box<EvalAltResult>> {
+  pub fn get_user(&mut self, name: String) -> Result<Dynamic, Box<EvalAltResult>> {
     self.0.get_user(name)
   }
+
+  pub fn fund(
+    &mut self,
+    account: Dynamic,
+    amount: Dynamic,
+  ) -> Result<ExtrinsicCallResult, Box<EvalAltResult>> {
+    self.0.fund(account, amount)
+  }
 }
 
-pub fn init_engine(engine: &mut Engine, client: &Client) -> Users {
+pub fn register(engine: &mut Engine) {
   engine
     .register_type_with_name::<SharedUser>("User")
     .register_get("acc", SharedUser::acc)
     .register_get("nonce", SharedUser::nonce)
     .register_fn("to_string", SharedUser::to_string)
     .register_fn("sign", SharedUser::sign_data)
+    .register_result_fn("sign_call", SharedUser::sign_call)
+    .register_result_fn("signing_payload", SharedUser::signing_payload)
+    .register_result_fn("signing_payload", |user: &mut SharedUser, call: EncodedCall| {
+      user.signing_payload(call, RMap::new())
+    })
     .register_result_fn("submit", SharedUser::submit_call)
+    .register_result_fn("submit", SharedUser::submit_call_opts)
+    .register_result_fn(
+      "submit_nowait",
+      |_client: &mut Client, mut user: SharedUser, call: EncodedCall| user.submit_nowait(call),
+    )
+    .register_result_fn("derive", SharedUser::derive)
+    .register_result_fn("submit_batch", SharedUser::submit_batch)
+    .register_result_fn("submit_batch", |user: &mut SharedUser, calls: Vec<Dynamic>| {
+      user.submit_batch(calls, RMap::new())
+    })
     .register_type_with_name::<AccountId>("AccountId")
     .register_fn("to_string", |acc: &mut AccountId| acc.to_string())
+    .register_result_fn("to_ss58", |acc: &mut AccountId, prefix: INT| {
+      Ok(acc.to_ss58check_with_version(ss58_format(prefix)?))
+    })
+    .register_result_fn("account", parse_account)
+    .register_result_fn("account", parse_account_with_prefix)
     .register_fn("==", |acc1: AccountId, acc2: AccountId| acc1 == acc2)
+    .register_result_fn("verify_signature", verify_signature)
     .register_type_with_name::<Users>("Users")
     .register_fn("new_users", Users::new)
     .register_fn("find_by_account", Users::find_by_account)
+    .register_result_fn("fund", Users::fund)
+    .register_result_fn("vanity", |_: &mut Users, prefix: &str| {
+      vanity(prefix, "sr25519", RMap::new())
+    })
+    .register_result_fn("vanity", |_: &mut Users, prefix: &str, scheme: &str| {
+      vanity(prefix, scheme, RMap::new())
+    })
+    .register_result_fn(
+      "vanity",
+      |_: &mut Users, prefix: &str, scheme: &str, opts: RMap| vanity(prefix, scheme, opts),
+    )
     .register_indexer_get_result(Users::get_user);
+}
+
+pub fn init_engine(engine: &mut Engine, client: &Client) -> Users {
+  register(engine);
   Users::new(client.clone())
 }
@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+
+use serde_json::json;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+
+use crate::client::{BlockHeader, Client, EventRecord, EventRecords};
+use crate::engine::{current_script, is_cancelled, SharedEngine};
+use crate::rpc::RequestToken;
+
+/// Check an event name against a pattern.
+///
+/// Patterns: `*` (all events), `Pallet` or `Pallet.*` (all events of a pallet)
+/// and `Pallet.Event`.
+fn event_matches(pattern: &str, name: &str) -> bool {
+  if pattern == "*" {
+    return true;
+  }
+  let pallet = pattern.strip_suffix(".*").unwrap_or(pattern);
+  if !pallet.contains('.') {
+    return name.split('.').next() == Some(pallet);
+  }
+  pattern == name
+}
+
+struct InnerWatcher {
+  pattern: String,
+  client: Client,
+  token: RequestToken,
+  stopped: AtomicBool,
+  matched: AtomicU64,
+  last_block: Mutex<Option<u64>>,
+}
+
+impl InnerWatcher {
+  fn is_stopped(&self) -> bool {
+    self.stopped.load(Ordering::Relaxed) || is_cancelled()
+  }
+
+  /// Call the callback for the matching events of blocks up to `head`.
+  ///
+  /// Returns `false` if the callback asked to stop.
+  fn process_blocks(
+    &self,
+    engine: &SharedEngine,
+    ast: &AST,
+    func: &FnPtr,
+    head: u64,
+  ) -> Result<bool, Box<EvalAltResult>> {
+    let from = self.last_block.lock().unwrap().map_or(head, |b| b + 1);
+    for number in from..=head {
+      let hash = match self.client.get_block_hash(number)? {
+        Some(hash) => hash,
+        None => continue,
+      };
+      let events = EventRecords::to_records(self.client.get_events(Some(hash))?)?;
+      for event in events {
+        if self.is_stopped() {
+          return Ok(false);
+        }
+        let name = match event.read_lock::<EventRecord>() {
+          Some(ev) => ev.name.clone(),
+          None => continue,
+        };
+        if !event_matches(&self.pattern, &name) {
+          continue;
+        }
+        self.matched.fetch_add(1, Ordering::Relaxed);
+        let res = engine.call_fn_ptr(ast, func, (event, Dynamic::from(number as INT)))?;
+        if res.as_bool() == Ok(false) {
+          return Ok(false);
+        }
+      }
+      *self.last_block.lock().unwrap() = Some(number);
+    }
+    Ok(true)
+  }
+
+  /// Follow finalized blocks until stopped.
+  fn run(&self, engine: SharedEngine, ast: AST, func: FnPtr) -> Result<(), Box<EvalAltResult>> {
+    let rpc = self.client.rpc();
+    // Each watcher gets it's own engine, so it doesn't block the script.
+    let engine = engine.new_task_engine()?;
+    while !self.is_stopped() {
+      let header = match rpc.get_update::<BlockHeader>(self.token) {
+        Ok(Some(header)) => header,
+        Ok(None) => break,
+        // `stop` closes the subscription.
+        Err(_) if self.is_stopped() => break,
+        Err(err) => return Err(err),
+      };
+      if !self.process_blocks(&engine, &ast, &func, header.number)? {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  fn stop(&self) -> Result<(), Box<EvalAltResult>> {
+    if !self.stopped.swap(true, Ordering::Relaxed) {
+      // Wake up the watcher thread.
+      self.client.rpc().close_request(self.token)?;
+    }
+    Ok(())
+  }
+}
+
+/// Handle to a background task calling a script closure for matching events.
+#[derive(Clone)]
+pub struct EventWatcher {
+  inner: Arc<InnerWatcher>,
+  handle: Arc<Mutex<Option<JoinHandle<Result<(), Box<EvalAltResult>>>>>>,
+}
+
+impl EventWatcher {
+  /// Start watching finalized blocks for events matching `pattern`.
+  ///
+  /// The callback gets the `EventRecord` and block number.  Returning `false` stops the watcher.
+  pub fn start(client: &Client, pattern: &str, func: FnPtr) -> Result<Self, Box<EvalAltResult>> {
+    let (engine, ast) =
+      current_script().ok_or_else(|| format!("on_event: no script running on this thread"))?;
+    let token = client.rpc().subscribe(
+      "chain_subscribeFinalizedHeads",
+      json!([]),
+      "chain_unsubscribeFinalizedHeads",
+    )?;
+    let inner = Arc::new(InnerWatcher {
+      pattern: pattern.into(),
+      client: client.clone(),
+      token,
+      stopped: AtomicBool::new(false),
+      matched: AtomicU64::new(0),
+      last_block: Mutex::new(None),
+    });
+    let watcher = inner.clone();
+    let handle = spawn(move || {
+      let res = watcher.run(engine, ast, func);
+      if let Err(err) = &res {
+        log::error!("Event watcher '{}' failed: {}", watcher.pattern, err);
+      }
+      // Make sure the subscription is closed.
+      watcher.stop()?;
+      res
+    });
+    Ok(Self {
+      inner,
+      handle: Arc::new(Mutex::new(Some(handle))),
+    })
+  }
+
+  /// Stop the watcher and wait for the background task to finish.
+  pub fn stop(&mut self) -> Result<(), Box<EvalAltResult>> {
+    self.inner.stop()?;
+    self.join()
+  }
+
+  /// Wait for the watcher to stop.  Returns the callback's error, if it failed.
+  pub fn join(&mut self) -> Result<(), Box<EvalAltResult>> {
+    match self.handle.lock().unwrap().take() {
+      Some(handle) => handle
+        .join()
+        .map_err(|err| format!("Failed to join event watcher: {:?}", err))?,
+      None => Ok(()),
+    }
+  }
+
+  fn is_running(&mut self) -> bool {
+    !self.inner.stopped.load(Ordering::Relaxed)
+  }
+
+  fn matched(&mut self) -> INT {
+    self.inner.matched.load(Ordering::Relaxed) as INT
+  }
+
+  fn last_block(&mut self) -> Dynamic {
+    match *self.inner.last_block.lock().unwrap() {
+      Some(block) => Dynamic::from(block as INT),
+      None => Dynamic::UNIT,
+    }
+  }
+}
+
+pub fn init_engine(engine: &mut Engine) {
+  engine
+    .register_type_with_name::<EventWatcher>("EventWatcher")
+    .register_result_fn(
+      "on_event",
+      |client: &mut Client, pattern: &str, func: FnPtr| EventWatcher::start(client, pattern, func),
+    )
+    .register_result_fn("stop", EventWatcher::stop)
+    .register_result_fn("join", EventWatcher::join)
+    .register_get("is_running", EventWatcher::is_running)
+    .register_get("matched", EventWatcher::matched)
+    .register_get("last_block", EventWatcher::last_block);
+}